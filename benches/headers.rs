@@ -0,0 +1,75 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use htp::{bstr::Bstr, config::Config, parse_request_message, table::Table};
+use std::{borrow::Cow, time::Duration};
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().measurement_time(Duration::new(2, 0)).sample_size(50).without_plots();
+    targets = short_headers, folded_headers, bstr_construction, header_lookup
+}
+criterion_main!(benches);
+
+/// A request with many short header values, as typically seen from browsers.
+fn short_header_request() -> Vec<u8> {
+    let mut request = b"GET / HTTP/1.1\r\nHost: www.example.com\r\n".to_vec();
+    for i in 0..30 {
+        request.extend_from_slice(format!("X-Header-{}: value{}\r\n", i, i).as_bytes());
+    }
+    request.extend_from_slice(b"\r\n");
+    request
+}
+
+pub fn short_headers(c: &mut Criterion) {
+    let cfg = Config::default();
+    let request = short_header_request();
+    c.bench_function("short_headers", |b| {
+        b.iter(|| parse_request_message(black_box(&request), &cfg).unwrap())
+    });
+}
+
+pub fn folded_headers(c: &mut Criterion) {
+    let cfg = Config::default();
+    let mut request = b"GET / HTTP/1.1\r\nHost: www.example.com\r\n".to_vec();
+    for i in 0..30 {
+        request.extend_from_slice(format!("X-Header-{}: value\r\n  folded{}\r\n", i, i).as_bytes());
+    }
+    request.extend_from_slice(b"\r\n");
+    c.bench_function("folded_headers", |b| {
+        b.iter(|| parse_request_message(black_box(&request), &cfg).unwrap())
+    });
+}
+
+pub fn bstr_construction(c: &mut Criterion) {
+    let value = b"text/html; charset=utf-8";
+    c.bench_function("bstr_from_slice", |b| {
+        b.iter(|| Bstr::from(black_box(&value[..])))
+    });
+    c.bench_function("bstr_from_owned_cow", |b| {
+        b.iter_with_setup(
+            || value.to_vec(),
+            |owned| Bstr::from_cow(Cow::Owned(black_box(owned))),
+        )
+    });
+}
+
+pub fn header_lookup(c: &mut Criterion) {
+    let mut headers = Table::with_capacity(64);
+    for i in 0..60 {
+        headers.add(Bstr::from(format!("X-Header-{}", i).as_str()), i);
+    }
+    headers.add(Bstr::from("Content-Type"), 60);
+    c.bench_function("header_lookup_nocase", |b| {
+        b.iter(|| headers.get_nocase(black_box("content-type")))
+    });
+    c.bench_function("header_lookup_nocase_nozero", |b| {
+        b.iter(|| headers.get_nocase_nozero(black_box("content-type")))
+    });
+    // The same lookup comparing every key, as done before keys were folded.
+    c.bench_function("header_lookup_scan", |b| {
+        b.iter(|| {
+            headers
+                .iter()
+                .find(|(key, _)| key.eq_nocase_nozero(black_box("content-type")))
+        })
+    });
+}
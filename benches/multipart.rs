@@ -0,0 +1,33 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use htp::{config::Config, multipart::Parser};
+use std::{sync::Arc, time::Duration};
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().measurement_time(Duration::new(2, 0)).sample_size(50).without_plots();
+    targets = large_file_part
+}
+criterion_main!(benches);
+
+/// A file part of binary data with occasional line endings and a long boundary.
+pub fn large_file_part(c: &mut Criterion) {
+    let boundary = b"----WebKitFormBoundary7MA4YWxkTrZu0gW";
+    let mut body = [&b"--"[..], boundary, b"\r\n"].concat();
+    body.extend_from_slice(
+        b"Content-Disposition: form-data; name=\"file\"; filename=\"a.bin\"\r\n\r\n",
+    );
+    for i in 0..(1 << 20) {
+        body.push(if i % 97 == 0 { b'\n' } else { (i % 251) as u8 });
+    }
+    body.extend_from_slice(&[&b"\r\n--"[..], boundary, b"--\r\n"].concat());
+    let cfg = Arc::new(Config::default());
+    c.bench_function("multipart_large_file_part", |b| {
+        b.iter(|| {
+            let mut parser = Parser::new(&cfg, boundary, 0);
+            for chunk in body.chunks(16384) {
+                parser.parse(black_box(chunk));
+            }
+            parser.finalize().unwrap();
+        })
+    });
+}
@@ -0,0 +1,262 @@
+//! Driver for processing many independent connections in parallel.
+
+use crate::{
+    config::Config,
+    connection_parser::{ConnectionParser, HtpStreamState},
+};
+use std::{
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    sync::{mpsc, Arc, Mutex},
+    thread,
+};
+
+/// A chunk of connection data, tagged with its direction.
+#[derive(Clone, Debug)]
+pub enum Chunk {
+    /// Data sent by the client.
+    Request(Vec<u8>),
+    /// Data sent by the server.
+    Response(Vec<u8>),
+}
+
+/// A single connection to be processed.
+#[derive(Clone, Debug, Default)]
+pub struct Input {
+    /// Index of the configuration to parse this connection with.
+    pub config: usize,
+    /// Connection data in the order it was seen on the wire.
+    pub chunks: Vec<Chunk>,
+}
+
+impl Input {
+    /// Create a new input parsed with the first configuration.
+    pub fn new(chunks: Vec<Chunk>) -> Self {
+        Self { config: 0, chunks }
+    }
+}
+
+/// Statistics aggregated over all processed connections.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Stats {
+    /// Number of connections processed.
+    pub connections: usize,
+    /// Number of transactions seen.
+    pub transactions: usize,
+    /// Number of request bytes processed.
+    pub request_bytes: u64,
+    /// Number of response bytes processed.
+    pub response_bytes: u64,
+    /// Number of connections on which the parser reported an error.
+    pub errors: usize,
+}
+
+impl Stats {
+    fn merge(&mut self, other: &Stats) {
+        self.connections += other.connections;
+        self.transactions += other.transactions;
+        self.request_bytes += other.request_bytes;
+        self.response_bytes += other.response_bytes;
+        self.errors += other.errors;
+    }
+}
+
+/// Reasons an input produced no result.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum InputError {
+    /// The input refers to a configuration index that was not supplied.
+    MissingConfig(usize),
+    /// Parsing the input or running the callback panicked.
+    Panicked,
+}
+
+impl fmt::Display for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InputError::MissingConfig(index) => write!(f, "no configuration at index {}", index),
+            InputError::Panicked => write!(f, "parsing or the callback panicked"),
+        }
+    }
+}
+
+impl std::error::Error for InputError {}
+
+/// The results of a batch run.
+#[derive(Debug)]
+pub struct Batch<R> {
+    /// One entry per input, in input order: the callback result, or the reason
+    /// the input produced none.
+    pub results: Vec<Result<R, InputError>>,
+    /// Statistics aggregated over the inputs that produced a result.
+    pub stats: Stats,
+}
+
+/// Parse a single connection to completion.
+fn process_one(cfg: &Arc<Config>, input: &Input) -> (ConnectionParser, Stats) {
+    let mut connp = ConnectionParser::new(Arc::clone(cfg));
+    let mut stats = Stats {
+        connections: 1,
+        ..Default::default()
+    };
+    let mut error = false;
+    connp.open(None, None, None, None, None);
+    for chunk in &input.chunks {
+        let rc = match chunk {
+            Chunk::Request(data) => {
+                stats.request_bytes += data.len() as u64;
+                connp.request_data(data.into(), None)
+            }
+            Chunk::Response(data) => {
+                stats.response_bytes += data.len() as u64;
+                connp.response_data(data.into(), None)
+            }
+        };
+        if rc == HtpStreamState::ERROR {
+            error = true;
+        }
+    }
+    connp.close(None);
+    stats.transactions = connp.tx_size();
+    if error {
+        stats.errors = 1;
+    }
+    (connp, stats)
+}
+
+/// Process many independent connections across threads, each with its own parser.
+///
+/// Every input is parsed with `configs[input.config]`. Once a connection has been
+/// parsed and closed, `callback` is invoked with the input index and the parser.
+/// The batch holds one entry per input, in input order: the value the callback
+/// returned, or the reason there is none. An input referring to a missing
+/// configuration is not parsed, and an input whose parsing or callback panics does
+/// not stop the others. Statistics are aggregated over the inputs with a result.
+pub fn process_many<F, R>(configs: &[Arc<Config>], inputs: Vec<Input>, callback: F) -> Batch<R>
+where
+    F: Fn(usize, &ConnectionParser) -> R + Sync,
+    R: Send,
+{
+    let count = inputs.len();
+    let queue = Mutex::new(inputs.into_iter().enumerate());
+    let threads = thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+    let (sender, receiver) = mpsc::channel();
+    let mut stats = Stats::default();
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                let (callback, queue, sender) = (&callback, &queue, sender.clone());
+                scope.spawn(move || {
+                    let mut stats = Stats::default();
+                    loop {
+                        let next = queue.lock().ok().and_then(|mut queue| queue.next());
+                        let (index, input) = if let Some(next) = next {
+                            next
+                        } else {
+                            break;
+                        };
+                        let result = if let Some(cfg) = configs.get(input.config) {
+                            panic::catch_unwind(AssertUnwindSafe(|| {
+                                let (connp, connp_stats) = process_one(cfg, &input);
+                                (callback(index, &connp), connp_stats)
+                            }))
+                            .map(|(result, connp_stats)| {
+                                stats.merge(&connp_stats);
+                                result
+                            })
+                            .map_err(|_| InputError::Panicked)
+                        } else {
+                            Err(InputError::MissingConfig(input.config))
+                        };
+                        // The receiver outlives the workers.
+                        let _ = sender.send((index, result));
+                    }
+                    stats
+                })
+            })
+            .collect();
+        for worker in workers {
+            match worker.join() {
+                Ok(worker_stats) => stats.merge(&worker_stats),
+                // Panics of the parser and callbacks are caught per input.
+                Err(panic) => panic::resume_unwind(panic),
+            }
+        }
+    });
+    drop(sender);
+
+    let mut received: Vec<(usize, Result<R, InputError>)> = receiver.iter().collect();
+    received.sort_by_key(|(index, _)| *index);
+    debug_assert_eq!(count, received.len());
+    Batch {
+        results: received.into_iter().map(|(_, result)| result).collect(),
+        stats,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ProcessMany() {
+        let mut inputs = Vec::new();
+        for i in 0..20 {
+            inputs.push(Input::new(vec![
+                Chunk::Request(format!("GET /{} HTTP/1.1\r\nHost: a\r\n\r\n", i).into_bytes()),
+                Chunk::Response(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nOK".to_vec()),
+            ]));
+        }
+        // An input referring to a missing configuration is reported, in its place.
+        inputs.insert(
+            5,
+            Input {
+                config: 1,
+                chunks: Vec::new(),
+            },
+        );
+        let cfg = Arc::new(Config::default());
+        let prefix = String::from("/");
+        let batch = process_many(&[cfg], inputs, |_, connp| {
+            connp
+                .tx(0)
+                .and_then(|tx| tx.request_uri.as_ref())
+                .map(|uri| uri.to_string().trim_start_matches(&prefix).to_string())
+        });
+        assert_eq!(21, batch.results.len());
+        assert_eq!(Err(InputError::MissingConfig(1)), batch.results[5]);
+        let uris: Vec<_> = batch
+            .results
+            .iter()
+            .filter_map(|result| result.as_ref().ok())
+            .collect();
+        for (i, uri) in uris.into_iter().enumerate() {
+            assert_eq!(&Some(i.to_string()), uri);
+        }
+        assert_eq!(20, batch.stats.connections);
+        assert_eq!(20, batch.stats.transactions);
+        assert_eq!(0, batch.stats.errors);
+        assert_eq!(20 * 40, batch.stats.response_bytes);
+    }
+
+    #[test]
+    fn ProcessManyPanic() {
+        let inputs = (0..4)
+            .map(|i| {
+                Input::new(vec![Chunk::Request(
+                    format!("GET /{} HTTP/1.1\r\nHost: a\r\n\r\n", i).into_bytes(),
+                )])
+            })
+            .collect();
+        let batch = process_many(&[Arc::new(Config::default())], inputs, |index, _| {
+            assert_ne!(2, index);
+            index
+        });
+        assert_eq!(
+            vec![Ok(0), Ok(1), Err(InputError::Panicked), Ok(3)],
+            batch.results
+        );
+        assert_eq!(3, batch.stats.connections);
+    }
+}
@@ -1,6 +1,9 @@
 use bstr::{BString, ByteSlice};
 use core::cmp::Ordering;
-use std::ops::{Deref, DerefMut};
+use std::{
+    borrow::Cow,
+    ops::{Deref, DerefMut},
+};
 
 /// Bstr is a convenience wrapper around binary data that adds string-like functions.
 #[derive(Clone, Debug, PartialEq)]
@@ -34,6 +37,15 @@ impl Bstr {
         }
     }
 
+    /// Make a new owned Bstr from data that may already be owned. Owned data is
+    /// moved in without copying; borrowed data is copied once, without spare capacity.
+    pub fn from_cow(src: Cow<'_, [u8]>) -> Self {
+        match src {
+            Cow::Borrowed(src) => src.into(),
+            Cow::Owned(src) => src.into(),
+        }
+    }
+
     /// Split the Bstr into a a collection of substrings, seperated by the given byte string.
     /// Each element yielded is guaranteed not to include the splitter substring.
     /// Returns a Vector of the substrings.
@@ -166,6 +178,12 @@ impl From<Vec<u8>> for Bstr {
     }
 }
 
+impl From<Cow<'_, [u8]>> for Bstr {
+    fn from(src: Cow<'_, [u8]>) -> Self {
+        Self::from_cow(src)
+    }
+}
+
 /// Compare a Bstr to a &str byte for byte
 impl PartialEq<&str> for Bstr {
     fn eq(&self, rhs: &&str) -> bool {
@@ -439,3 +457,15 @@ fn IndexOfNocaseNozero() {
     assert_eq!(None, b.index_of_nocase_nozero("Hi"));
     assert_eq!(None, b.index_of_nocase_nozero("ghi"));
 }
+
+#[test]
+fn FromCow() {
+    let data = b"value".to_vec();
+    let ptr = data.as_ptr();
+    let b = Bstr::from_cow(Cow::Owned(data));
+    assert!(b.eq("value"));
+    assert_eq!(ptr, b.as_ptr());
+    let b: Bstr = Cow::Borrowed(&b"value"[..]).into();
+    assert!(b.eq("value"));
+    assert_eq!(5, b.capacity());
+}
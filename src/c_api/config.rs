@@ -1,7 +1,14 @@
 #![deny(missing_docs)]
 use crate::{
-    config::{Config, HtpServerPersonality, HtpUrlEncodingHandling},
-    hook::{DataExternalCallbackFn, LogExternalCallbackFn, TxExternalCallbackFn},
+    config::{
+        Config, HtpHeaderNul, HtpParamDuplicates, HtpServerPersonality, HtpTrailerPolicy,
+        HtpUrlEncodingHandling,
+    },
+    hook::{
+        ConnectionFlagExternalCallbackFn, DataExternalCallbackFn, LogExternalCallbackFn,
+        TxExternalCallbackFn, VhostSelectExternalCallbackFn,
+    },
+    unicode_bestfit_map::{HtpBestfitCodepage, UnicodeBestfitMap},
     HtpStatus,
 };
 
@@ -80,6 +87,28 @@ pub unsafe extern "C" fn htp_config_register_request_line(
         .map(|cfg| cfg.hook_request_line.register_extern(cbk_fn));
 }
 
+/// Registers a REQUEST_URI_NORMALIZED callback. Returning HTP_STATUS_STOP from it
+/// skips the processing of the transaction's bodies.
+#[no_mangle]
+pub unsafe extern "C" fn htp_config_register_request_uri_normalized(
+    cfg: *mut Config,
+    cbk_fn: TxExternalCallbackFn,
+) {
+    cfg.as_mut()
+        .map(|cfg| cfg.hook_request_uri_normalized.register_extern(cbk_fn));
+}
+
+/// Registers a VHOST_SELECT callback, which may return the configuration to use for the
+/// rest of the transaction once the request hostname is known, or NULL to decline.
+#[no_mangle]
+pub unsafe extern "C" fn htp_config_register_vhost_select(
+    cfg: *mut Config,
+    cbk_fn: VhostSelectExternalCallbackFn,
+) {
+    cfg.as_mut()
+        .map(|cfg| cfg.hook_vhost_select.register_extern(cbk_fn));
+}
+
 /// Registers a REQUEST_START callback, which is invoked every time a new
 /// request begins and before any parsing is done.
 #[no_mangle]
@@ -191,6 +220,16 @@ pub unsafe extern "C" fn htp_config_register_transaction_complete(
         .map(|cfg| cfg.hook_transaction_complete.register_extern(cbk_fn));
 }
 
+/// Registers a CONNECTION_FLAG callback.
+#[no_mangle]
+pub unsafe extern "C" fn htp_config_register_connection_flag(
+    cfg: *mut Config,
+    cbk_fn: ConnectionFlagExternalCallbackFn,
+) {
+    cfg.as_mut()
+        .map(|cfg| cfg.hook_connection_flag.register_extern(cbk_fn));
+}
+
 /// Configures whether backslash characters are treated as path segment separators. They
 /// are not on Unix systems, but are on Windows systems. If this setting is enabled, a path
 /// such as "/one\two/three" will be converted to "/one/two/three".
@@ -212,6 +251,47 @@ pub unsafe extern "C" fn htp_config_set_bestfit_replacement_byte(cfg: *mut Confi
         .map(|cfg| cfg.set_bestfit_replacement_byte(b as u8));
 }
 
+/// Configures the built-in best-fit map of a code page, keeping the current
+/// replacement byte.
+#[no_mangle]
+pub unsafe extern "C" fn htp_config_set_bestfit_codepage(
+    cfg: *mut Config,
+    codepage: HtpBestfitCodepage,
+) {
+    if let Some(cfg) = cfg.as_mut() {
+        cfg.set_bestfit_codepage(codepage);
+    }
+}
+
+/// Loads a best-fit map from text in the format of the Microsoft bestfitNNNN.txt
+/// files. The current replacement byte is kept.
+///
+/// Returns HTP_STATUS_ERROR if the text is malformed.
+#[no_mangle]
+pub unsafe extern "C" fn htp_config_set_bestfit_map_data(
+    cfg: *mut Config,
+    data: *const u8,
+    len: libc::size_t,
+) -> HtpStatus {
+    if data.is_null() {
+        return HtpStatus::ERROR;
+    }
+    let data = std::slice::from_raw_parts(data, len);
+    cfg.as_mut()
+        .map(|cfg| {
+            UnicodeBestfitMap::from_bytes(data)
+                .map(|map| {
+                    let replacement_byte = cfg.decoder_cfg.bestfit_map.replacement_byte;
+                    cfg.set_bestfit_map(UnicodeBestfitMap {
+                        replacement_byte,
+                        ..map
+                    })
+                })
+                .into()
+        })
+        .unwrap_or(HtpStatus::ERROR)
+}
+
 /// Configures the maximum compression bomb size LibHTP will decompress.
 #[no_mangle]
 pub unsafe extern "C" fn htp_config_set_compression_bomb_limit(
@@ -296,6 +376,17 @@ pub unsafe extern "C" fn htp_config_set_parse_request_cookies(
         .map(|cfg| cfg.set_parse_request_cookies(parse_request_cookies == 1));
 }
 
+/// Configures whether request cookies are validated against the RFC 6265 syntax.
+#[no_mangle]
+pub unsafe extern "C" fn htp_config_set_request_cookies_strict(
+    cfg: *mut Config,
+    strict: libc::c_int,
+) {
+    if let Some(cfg) = cfg.as_mut() {
+        cfg.set_request_cookies_strict(strict == 1);
+    }
+}
+
 /// Configures whether consecutive path segment separators will be compressed. When enabled, a path
 /// such as "/one//two" will be normalized to "/one/two". Backslash conversion and path segment separator
 /// decoding are carried out before compression. For example, the path "/one\\/two\/%5cthree/%2f//four"
@@ -375,6 +466,56 @@ pub unsafe extern "C" fn htp_config_set_u_encoding_decode(cfg: *mut Config, enab
         .map(|cfg| cfg.set_u_encoding_decode(enabled == 1));
 }
 
+/// Configures the normalization steps applied to request parameter names, a
+/// combination of ParamNameNormalization values.
+#[no_mangle]
+pub unsafe extern "C" fn htp_config_set_param_name_normalization(cfg: *mut Config, steps: u64) {
+    if let Some(cfg) = cfg.as_mut() {
+        cfg.set_param_name_normalization(steps);
+    }
+}
+
+/// Configures how request parameters with a name that was already seen are handled.
+#[no_mangle]
+pub unsafe extern "C" fn htp_config_set_param_duplicates(
+    cfg: *mut Config,
+    duplicates: HtpParamDuplicates,
+) {
+    if let Some(cfg) = cfg.as_mut() {
+        cfg.set_param_duplicates(duplicates);
+    }
+}
+
+/// Configures how the trailer fields of chunked requests and responses are handled.
+#[no_mangle]
+pub unsafe extern "C" fn htp_config_set_trailer_policy(cfg: *mut Config, policy: HtpTrailerPolicy) {
+    if let Some(cfg) = cfg.as_mut() {
+        cfg.set_trailer_policy(policy);
+    }
+}
+
+/// Configures how NUL bytes in request header values are handled.
+#[no_mangle]
+pub unsafe extern "C" fn htp_config_set_request_header_nul(
+    cfg: *mut Config,
+    handling: HtpHeaderNul,
+) {
+    if let Some(cfg) = cfg.as_mut() {
+        cfg.set_request_header_nul(handling);
+    }
+}
+
+/// Configures how NUL bytes in response header values are handled.
+#[no_mangle]
+pub unsafe extern "C" fn htp_config_set_response_header_nul(
+    cfg: *mut Config,
+    handling: HtpHeaderNul,
+) {
+    if let Some(cfg) = cfg.as_mut() {
+        cfg.set_response_header_nul(handling);
+    }
+}
+
 /// Configures how the server handles to invalid URL encoding.
 #[no_mangle]
 pub unsafe extern "C" fn htp_config_set_url_encoding_invalid_handling(
@@ -1,9 +1,10 @@
 #![deny(missing_docs)]
 use crate::{
+    c_api::UserData,
     config::Config,
     connection::Connection,
     connection_parser::{ConnectionParser, Data, HtpStreamState},
-    transaction::Transaction,
+    transaction::{Transaction, TxId},
 };
 use chrono::{DateTime, NaiveDateTime, Utc};
 use std::{convert::TryFrom, ffi::CStr};
@@ -64,8 +65,8 @@ pub unsafe extern "C" fn htp_connp_connection(connp: *const ConnectionParser) ->
 pub unsafe extern "C" fn htp_connp_user_data(connp: *const ConnectionParser) -> *mut libc::c_void {
     connp
         .as_ref()
-        .and_then(|val| val.user_data::<*mut libc::c_void>())
-        .map(|val| *val)
+        .and_then(|val| val.user_data::<UserData>())
+        .map(|val| val.get())
         .unwrap_or(std::ptr::null_mut())
 }
 
@@ -77,7 +78,7 @@ pub unsafe extern "C" fn htp_connp_set_user_data(
 ) {
     connp
         .as_mut()
-        .map(|connp| connp.set_user_data(Box::new(user_data)));
+        .map(|connp| connp.set_user_data(Box::new(UserData::new(user_data))));
 }
 
 /// Opens connection.
@@ -144,6 +145,25 @@ pub unsafe extern "C" fn htp_connp_request_close(
     });
 }
 
+/// Closes the outbound (response) direction of the connection associated with the
+/// supplied parser.
+///
+/// timestamp is optional
+#[no_mangle]
+pub unsafe extern "C" fn htp_connp_response_close(
+    connp: *mut ConnectionParser,
+    timestamp: *const libc::timeval,
+) {
+    connp.as_mut().map(|connp| {
+        connp.response_close(timestamp.as_ref().map(|val| {
+            DateTime::<Utc>::from_utc(
+                NaiveDateTime::from_timestamp(val.tv_sec, val.tv_usec as u32),
+                Utc,
+            )
+        }))
+    });
+}
+
 /// Process a chunk of inbound client request data
 ///
 /// timestamp is optional
@@ -235,6 +255,21 @@ pub unsafe extern "C" fn htp_connp_tx(
         .unwrap_or(std::ptr::null())
 }
 
+/// Get a transaction by its stable id.
+///
+/// Returns the transaction or NULL if it does not exist or has been destroyed.
+#[no_mangle]
+pub unsafe extern "C" fn htp_connp_tx_by_id(
+    connp: *const ConnectionParser,
+    id: u64,
+) -> *const Transaction {
+    connp
+        .as_ref()
+        .and_then(|connp| connp.tx_by_id(TxId(id)))
+        .map(|tx| tx as *const Transaction)
+        .unwrap_or(std::ptr::null())
+}
+
 /// Retrieves the pointer to the active response transaction. In connection
 /// parsing mode there can be many open transactions, and up to 2 active
 /// transactions at any one time. This is due to HTTP pipelining. Can be NULL.
@@ -301,3 +336,25 @@ pub unsafe extern "C" fn htp_connp_response_data_consumed(connp: *const Connecti
         .map(|connp| connp.response_data_consumed())
         .unwrap_or(-1)
 }
+
+/// Returns the state of the inbound stream, or HTP_STREAM_STATE_ERROR if connp is NULL.
+#[no_mangle]
+pub unsafe extern "C" fn htp_connp_request_stream_state(
+    connp: *const ConnectionParser,
+) -> HtpStreamState {
+    connp
+        .as_ref()
+        .map(|connp| connp.request_stream_state())
+        .unwrap_or(HtpStreamState::ERROR)
+}
+
+/// Returns the state of the outbound stream, or HTP_STREAM_STATE_ERROR if connp is NULL.
+#[no_mangle]
+pub unsafe extern "C" fn htp_connp_response_stream_state(
+    connp: *const ConnectionParser,
+) -> HtpStreamState {
+    connp
+        .as_ref()
+        .map(|connp| connp.response_stream_state())
+        .unwrap_or(HtpStreamState::ERROR)
+}
@@ -36,6 +36,30 @@ pub unsafe extern "C" fn htp_log_code(log: *const Log) -> HtpLogCode {
         .unwrap_or(HtpLogCode::ERROR)
 }
 
+/// Get the id of the transaction a log's message was issued for
+///
+/// Returns the id or -1 if it is not known
+#[no_mangle]
+pub unsafe extern "C" fn htp_log_tx_id(log: *const Log) -> i64 {
+    log.as_ref()
+        .and_then(|log| log.msg.position)
+        .and_then(|position| position.tx_id)
+        .map(|id| id.0 as i64)
+        .unwrap_or(-1)
+}
+
+/// Get the offset in its direction's stream at which a log's message was issued
+///
+/// Returns the offset or -1 if it is not known
+#[no_mangle]
+pub unsafe extern "C" fn htp_log_offset(log: *const Log) -> i64 {
+    log.as_ref()
+        .and_then(|log| log.msg.position)
+        .and_then(|position| position.offset)
+        .map(|offset| offset as i64)
+        .unwrap_or(-1)
+}
+
 /// Free log
 #[no_mangle]
 pub unsafe extern "C" fn htp_log_free(log: *mut Log) {
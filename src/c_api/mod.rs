@@ -21,6 +21,24 @@ pub mod transaction;
 /// Functions for working with request uri.
 pub mod uri;
 
+/// User data pointer supplied through the C API. Only its address is kept: the
+/// pointer is never dereferenced on the Rust side, and the caller is responsible
+/// for synchronizing access to what it points to.
+#[derive(Copy, Clone)]
+pub(crate) struct UserData(usize);
+
+impl UserData {
+    /// Keeps the address of `user_data`.
+    pub(crate) fn new(user_data: *mut libc::c_void) -> Self {
+        Self(user_data as usize)
+    }
+
+    /// Returns the pointer that was supplied.
+    pub(crate) fn get(self) -> *mut libc::c_void {
+        self.0 as *mut libc::c_void
+    }
+}
+
 /// Returns the LibHTP version string.
 #[no_mangle]
 pub unsafe extern "C" fn htp_get_version() -> *const libc::c_char {
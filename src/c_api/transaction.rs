@@ -1,15 +1,23 @@
 use crate::{
-    bstr::Bstr, c_api::header::htp_headers_get, config::Config,
-    connection_parser::ConnectionParser, decompressors::HtpContentEncoding,
-    hook::DataExternalCallbackFn, request::HtpMethod, transaction::*, uri::Uri,
+    bstr::Bstr,
+    c_api::{header::htp_headers_get, UserData},
+    config::Config,
+    connection_parser::ConnectionParser,
+    decompressors::HtpContentEncoding,
+    hook::DataExternalCallbackFn,
+    request::HtpMethod,
+    transaction::*,
+    uri::Uri,
 };
-use std::{convert::TryFrom, rc::Rc};
+use std::{convert::TryFrom, sync::Arc};
 
 /// Destroys the supplied transaction.
 #[no_mangle]
 pub unsafe extern "C" fn htp_tx_destroy(connp: *mut ConnectionParser, tx: *const Transaction) {
     match (connp.as_mut(), tx.as_ref()) {
-        (Some(connp), Some(tx)) => connp.remove_tx(tx.index),
+        (Some(connp), Some(tx)) => {
+            connp.remove_tx(tx.index);
+        }
         _ => {}
     }
 }
@@ -40,23 +48,57 @@ pub unsafe extern "C" fn htp_tx_normalized_uri(tx: *const Transaction) -> *const
 #[no_mangle]
 pub unsafe extern "C" fn htp_tx_cfg(tx: *const Transaction) -> *const Config {
     tx.as_ref()
-        .map(|tx| Rc::as_ptr(&tx.cfg))
+        .map(|tx| Arc::as_ptr(&tx.cfg))
         .unwrap_or(std::ptr::null())
 }
 
+/// Replaces the configuration used for the rest of the transaction.
+/// Note the provided config will be copied into the transaction. Therefore, subsequent
+/// modification to the original config will have no effect.
+///
+/// tx: Transaction pointer.
+/// cfg: Configuration pointer.
+#[no_mangle]
+pub unsafe extern "C" fn htp_tx_set_config(tx: *mut Transaction, cfg: *const Config) {
+    if let (Some(tx), Some(cfg)) = (tx.as_mut(), cfg.as_ref()) {
+        tx.set_config(Arc::new(cfg.clone()));
+    }
+}
+
+/// Stops processing the request body of the transaction, apart from its framing.
+///
+/// tx: Transaction pointer.
+#[no_mangle]
+pub unsafe extern "C" fn htp_tx_set_request_body_skip(tx: *mut Transaction) {
+    if let Some(tx) = tx.as_mut() {
+        tx.set_request_body_skip();
+    }
+}
+
+/// Stops processing the response body of the transaction, apart from its framing.
+///
+/// tx: Transaction pointer.
+#[no_mangle]
+pub unsafe extern "C" fn htp_tx_set_response_body_skip(tx: *mut Transaction) {
+    if let Some(tx) = tx.as_mut() {
+        tx.set_response_body_skip();
+    }
+}
+
 /// Returns the user data associated with this transaction or NULL on error.
 #[no_mangle]
 pub unsafe extern "C" fn htp_tx_user_data(tx: *const Transaction) -> *mut libc::c_void {
     tx.as_ref()
-        .and_then(|val| val.user_data::<*mut libc::c_void>())
-        .map(|val| *val)
+        .and_then(|val| val.user_data::<UserData>())
+        .map(|val| val.get())
         .unwrap_or(std::ptr::null_mut())
 }
 
 /// Associates user data with this transaction.
 #[no_mangle]
 pub unsafe extern "C" fn htp_tx_set_user_data(tx: *mut Transaction, user_data: *mut libc::c_void) {
-    tx.as_mut().map(|tx| tx.set_user_data(Box::new(user_data)));
+    tx.as_mut()
+        .map(|tx| tx.set_user_data(Box::new(UserData::new(user_data))));
 }
 
 /// Get a transaction's request line.
@@ -588,6 +630,18 @@ pub unsafe extern "C" fn htp_tx_index(tx: *const Transaction) -> isize {
         .unwrap_or(-1)
 }
 
+/// Get the transaction's stable id.
+///
+/// tx: Transaction pointer.
+///
+/// Returns an id or -1 on error.
+#[no_mangle]
+pub unsafe extern "C" fn htp_tx_id(tx: *const Transaction) -> i64 {
+    tx.as_ref()
+        .map(|tx| i64::try_from(tx.id.0).unwrap_or(-1))
+        .unwrap_or(-1)
+}
+
 /// Register callback for the transaction-specific RESPONSE_BODY_DATA hook.
 #[no_mangle]
 pub unsafe extern "C" fn htp_tx_register_response_body_data(
@@ -604,7 +658,8 @@ pub unsafe extern "C" fn htp_tx_register_response_body_data(
 #[no_mangle]
 pub unsafe extern "C" fn htp_tx_data_tx(data: *const Data) -> *const Transaction {
     data.as_ref()
-        .map(|data| data.tx() as *const Transaction)
+        .and_then(|data| data.tx())
+        .map(|tx| tx as *const Transaction)
         .unwrap_or(std::ptr::null())
 }
 
@@ -636,6 +691,37 @@ pub unsafe extern "C" fn htp_tx_data_is_last(data: *const Data) -> bool {
     data.as_ref().map(|data| data.is_last()).unwrap_or(false)
 }
 
+/// Get the offset of the data in its direction of the connection stream.
+///
+/// Returns the offset or -1 if the data was not taken from the stream or on error.
+#[no_mangle]
+pub unsafe extern "C" fn htp_tx_data_stream_offset(data: *const Data) -> i64 {
+    data.as_ref()
+        .and_then(|data| data.stream_offset())
+        .and_then(|offset| i64::try_from(offset).ok())
+        .unwrap_or(-1)
+}
+
+/// Get the offset of the data among the data of the same kind passed for the transaction.
+///
+/// Returns the offset or -1 on error.
+#[no_mangle]
+pub unsafe extern "C" fn htp_tx_data_offset(data: *const Data) -> i64 {
+    data.as_ref()
+        .and_then(|data| i64::try_from(data.offset()).ok())
+        .unwrap_or(-1)
+}
+
+/// Get the sequence number of the data chunk within its transaction and direction.
+///
+/// Returns the sequence number or -1 on error.
+#[no_mangle]
+pub unsafe extern "C" fn htp_tx_data_sequence(data: *const Data) -> i64 {
+    data.as_ref()
+        .and_then(|data| i64::try_from(data.sequence()).ok())
+        .unwrap_or(-1)
+}
+
 /// Get whether this data is empty.
 ///
 /// Returns true if data is NULL or zero-length.
@@ -1,8 +1,16 @@
-use crate::log::{Log, Message};
+use crate::{
+    log::{HtpLogCode, HtpLogLevel, Log, LogCounts, LogLimits, Message},
+    sniff::HtpSniffedProtocol,
+    transaction::{AltService, HtpNtlmMessageType},
+};
 use chrono::{DateTime, Utc};
 use std::{
+    collections::HashMap,
     net::IpAddr,
-    sync::mpsc::{channel, Receiver, Sender},
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Arc,
+    },
     time::SystemTime,
 };
 
@@ -17,6 +25,153 @@ impl Flags {
     pub const PIPELINED: u8 = 0x01;
     /// Seen extra data after a HTTP 0.9 communication.
     pub const HTTP_0_9_EXTRA: u8 = 0x02;
+    /// Stream does not carry HTTP.
+    pub const NOT_HTTP: u8 = 0x04;
+    /// The request parser recovered from an error by skipping to the next request.
+    pub const RESYNCED: u8 = 0x08;
+    /// More empty lines than allowed preceded a request line.
+    pub const IGNORED_LINES: u8 = 0x10;
+}
+
+/// Groups the transactions of a connection by the credentials and session
+/// cookies they present.
+#[derive(Debug, Default)]
+pub struct Actors {
+    /// Maps identifying keys to actor ids.
+    keys: HashMap<Vec<u8>, usize>,
+    /// Number of actors identified so far.
+    count: usize,
+}
+
+impl Actors {
+    /// Identifies the actor presenting the given keys. If any key was seen before,
+    /// the id of its actor is reused, otherwise a new id is allocated. All keys are
+    /// associated with the returned id from then on.
+    ///
+    /// Returns None if no keys are given.
+    pub fn identify(&mut self, keys: &[Vec<u8>]) -> Option<usize> {
+        if keys.is_empty() {
+            return None;
+        }
+        let id = keys
+            .iter()
+            .find_map(|key| self.keys.get(key).copied())
+            .unwrap_or_else(|| {
+                self.count += 1;
+                self.count - 1
+            });
+        for key in keys {
+            self.keys.entry(key.clone()).or_insert(id);
+        }
+        Some(id)
+    }
+
+    /// Returns the number of actors identified so far.
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    /// Returns true if no actors have been identified.
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Forgets all actors.
+    fn clear(&mut self) {
+        self.keys.clear();
+        self.count = 0;
+    }
+}
+
+/// Running counters for a connection, updated as its transactions are parsed so
+/// they can be exported without walking the transactions.
+#[derive(Debug)]
+pub struct ConnectionStats {
+    /// Number of requests parsed to completion.
+    pub requests: u64,
+    /// Number of responses parsed to completion.
+    pub responses: u64,
+    /// Number of request bytes seen, including data in gaps.
+    pub request_bytes: u64,
+    /// Number of response bytes seen, including data in gaps.
+    pub response_bytes: u64,
+    /// Number of request body bytes produced by decompression.
+    pub request_decompressed_bytes: u64,
+    /// Number of response body bytes produced by decompression.
+    pub response_decompressed_bytes: u64,
+    /// Number of completed transactions that raised each transaction flag bit.
+    flags: [u64; 64],
+    /// Number of messages logged at each level.
+    pub(crate) log_counts: Arc<LogCounts>,
+    /// Number of messages suppressed by the log limits.
+    pub(crate) log_limits: Arc<LogLimits>,
+}
+
+impl Default for ConnectionStats {
+    fn default() -> Self {
+        Self {
+            requests: 0,
+            responses: 0,
+            request_bytes: 0,
+            response_bytes: 0,
+            request_decompressed_bytes: 0,
+            response_decompressed_bytes: 0,
+            flags: [0; 64],
+            log_counts: Arc::new(LogCounts::default()),
+            log_limits: Arc::new(LogLimits::default()),
+        }
+    }
+}
+
+impl ConnectionStats {
+    /// Returns the number of completed transactions that raised the given
+    /// transaction flag. Composite flags are counted by their most significant bit,
+    /// e.g. REQUEST_MISSING_BYTES rather than MISSING_BYTES.
+    pub fn flag_count(&self, flag: u64) -> u64 {
+        if flag == 0 {
+            return 0;
+        }
+        self.flags[63 - flag.leading_zeros() as usize]
+    }
+
+    /// Returns the number of messages logged at the given level. Messages below
+    /// the configured log level are discarded and not counted.
+    pub fn log_count(&self, level: HtpLogLevel) -> u64 {
+        self.log_counts.get(level)
+    }
+
+    /// Returns the number of messages with the given code that were suppressed by
+    /// the log limits. See Config::set_log_limit_per_code.
+    pub fn log_suppressed(&self, code: HtpLogCode) -> u64 {
+        self.log_limits.suppressed(code)
+    }
+
+    /// Sets all counters back to zero. The log counts and limits are shared with
+    /// the logger, which resets them.
+    fn reset(&mut self) {
+        let log_counts = Arc::clone(&self.log_counts);
+        let log_limits = Arc::clone(&self.log_limits);
+        *self = Self {
+            requests: 0,
+            responses: 0,
+            request_bytes: 0,
+            response_bytes: 0,
+            request_decompressed_bytes: 0,
+            response_decompressed_bytes: 0,
+            flags: [0; 64],
+            log_counts,
+            log_limits,
+        };
+    }
+
+    /// Counts the flags raised by a completed transaction.
+    pub(crate) fn track_flags(&mut self, flags: u64) {
+        for (bit, count) in self.flags.iter_mut().enumerate() {
+            if flags & (1 << bit) != 0 {
+                *count += 1;
+            }
+        }
+    }
 }
 
 /// Stores information about the session.
@@ -39,10 +194,27 @@ pub struct Connection {
     pub open_timestamp: DateTime<Utc>,
     /// When was this connection closed?
     pub close_timestamp: DateTime<Utc>,
+    /// When did the client close its side of the connection? None while it is open.
+    pub request_close_timestamp: Option<DateTime<Utc>>,
+    /// When did the server close its side of the connection? None while it is open.
+    pub response_close_timestamp: Option<DateTime<Utc>>,
     /// Inbound data counter.
     pub request_data_counter: i64,
     /// Outbound data counter.
     pub response_data_counter: i64,
+    /// The most recent NTLM message seen on this connection. NTLM authenticates
+    /// the connection rather than individual requests, so the handshake spans
+    /// several transactions.
+    pub ntlm_handshake: Option<HtpNtlmMessageType>,
+    /// Alternative services most recently advertised with Alt-Svc on this connection,
+    /// which the client may migrate to, for example over HTTP/3.
+    pub alt_svc: Vec<AltService>,
+    /// Actors seen on this connection, when actor tracking is enabled.
+    pub actors: Actors,
+    /// The protocol recognized when the stream was found not to carry HTTP.
+    pub sniffed_protocol: Option<HtpSniffedProtocol>,
+    /// Running counters for this connection.
+    pub stats: ConnectionStats,
 }
 
 impl Default for Connection {
@@ -57,8 +229,15 @@ impl Default for Connection {
             flags: 0,
             open_timestamp: DateTime::<Utc>::from(SystemTime::now()),
             close_timestamp: DateTime::<Utc>::from(SystemTime::now()),
+            request_close_timestamp: None,
+            response_close_timestamp: None,
             request_data_counter: 0,
             response_data_counter: 0,
+            ntlm_handshake: None,
+            alt_svc: Vec::new(),
+            actors: Actors::default(),
+            sniffed_protocol: None,
+            stats: ConnectionStats::default(),
         }
     }
 }
@@ -85,24 +264,84 @@ impl Connection {
         }
     }
 
+    /// Returns the connection to its initial state so that it can describe a new
+    /// connection, discarding any logs that were not retrieved.
+    pub(crate) fn reset(&mut self) {
+        let Self {
+            client_addr,
+            client_port,
+            server_addr,
+            server_port,
+            log_channel,
+            flags,
+            open_timestamp,
+            close_timestamp,
+            request_close_timestamp,
+            response_close_timestamp,
+            request_data_counter,
+            response_data_counter,
+            ntlm_handshake,
+            alt_svc,
+            actors,
+            sniffed_protocol,
+            stats,
+        } = self;
+        while log_channel.1.try_recv().is_ok() {}
+        *client_addr = None;
+        *client_port = None;
+        *server_addr = None;
+        *server_port = None;
+        *flags = 0;
+        *open_timestamp = DateTime::<Utc>::from(SystemTime::now());
+        *close_timestamp = *open_timestamp;
+        *request_close_timestamp = None;
+        *response_close_timestamp = None;
+        *request_data_counter = 0;
+        *response_data_counter = 0;
+        *ntlm_handshake = None;
+        alt_svc.clear();
+        actors.clear();
+        *sniffed_protocol = None;
+        stats.reset();
+    }
+
     /// Closes the connection.
     pub fn close(&mut self, timestamp: Option<DateTime<Utc>>) {
         // Update timestamp.
         if let Some(timestamp) = timestamp {
             self.close_timestamp = timestamp;
         }
+        let close_timestamp = self.close_timestamp;
+        self.request_close_timestamp.get_or_insert(close_timestamp);
+        self.response_close_timestamp.get_or_insert(close_timestamp);
+    }
+
+    /// Records that the client closed its side of the connection. Only the first
+    /// close is recorded; the current time is used when no timestamp is given.
+    pub fn request_close(&mut self, timestamp: Option<DateTime<Utc>>) {
+        self.request_close_timestamp
+            .get_or_insert_with(|| timestamp.unwrap_or_else(|| SystemTime::now().into()));
+    }
+
+    /// Records that the server closed its side of the connection. Only the first
+    /// close is recorded; the current time is used when no timestamp is given.
+    pub fn response_close(&mut self, timestamp: Option<DateTime<Utc>>) {
+        self.response_close_timestamp
+            .get_or_insert_with(|| timestamp.unwrap_or_else(|| SystemTime::now().into()));
     }
 
     /// Keeps track of inbound packets and data.
     pub fn track_inbound_data(&mut self, len: usize) {
         self.request_data_counter =
             (self.request_data_counter as u64).wrapping_add(len as u64) as i64;
+        self.stats.request_bytes = self.stats.request_bytes.wrapping_add(len as u64);
     }
 
     /// Keeps track of outbound packets and data.
     pub fn track_outbound_data(&mut self, len: usize) {
         self.response_data_counter =
             (self.response_data_counter as u64).wrapping_add(len as u64) as i64;
+        self.stats.response_bytes = self.stats.response_bytes.wrapping_add(len as u64);
     }
 
     /// Return the log channel sender
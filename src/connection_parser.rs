@@ -1,17 +1,20 @@
 use crate::{
     bstr::Bstr,
-    config::{Config, HtpServerPersonality},
+    config::{Config, HtpServerPersonality, MultipartConfig},
     connection::{Connection, Flags},
-    error::Result,
+    error::{Error, ErrorContext, Result},
+    event::{Direction, Event},
     hook::DataHook,
-    log::Logger,
-    transaction::Transaction,
-    transactions::Transactions,
-    util::{File, FlagOperations},
+    log::{HtpLogCode, LogContext, LogLimits, LogPosition, Logger},
+    sniff::HtpSniffedProtocol,
+    trace::{Segment, Trace},
+    transaction::{ConditionalExchange, Transaction, TxId},
+    transactions::{Transactions, TransactionsIter},
+    util::{ExtractionQuota, File, FlagOperations},
     HtpStatus,
 };
 use chrono::{DateTime, Utc};
-use std::{any::Any, io::Cursor, net::IpAddr, rc::Rc, time::SystemTime};
+use std::{any::Any, collections::VecDeque, io::Cursor, net::IpAddr, sync::Arc, time::SystemTime};
 
 /// Enumerates parsing state.
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -77,6 +80,18 @@ pub enum HtpStreamState {
     STOP,
     /// State when all current data in the stream has been processed.
     DATA,
+    /// State when a callback paused a transaction. Parsing continues when
+    /// ConnectionParser::resume() is called.
+    PAUSED,
+}
+
+/// The outcome of supplying an owned data chunk to request_feed or response_feed.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Feed {
+    /// The stream state after processing the chunk.
+    pub state: HtpStreamState,
+    /// How many bytes of the chunk were consumed.
+    pub consumed: usize,
 }
 
 #[derive(Debug, Clone)]
@@ -203,17 +218,47 @@ impl<'a> From<(*const u8, usize)> for Data<'a> {
     }
 }
 
+/// A chunk of data supplied to the parser: borrowed from the caller, or handed
+/// over so that the parser can keep it without a copy.
+pub(crate) enum Chunk<'a> {
+    /// Data borrowed from the caller.
+    Borrowed(Data<'a>),
+    /// Data handed over to the parser.
+    Owned(Vec<u8>),
+}
+
+impl<'a> Chunk<'a> {
+    /// Returns a view of the data.
+    pub(crate) fn data(&self) -> Data<'_> {
+        match self {
+            Chunk::Borrowed(data) => data.clone(),
+            Chunk::Owned(data) => Data::from(data),
+        }
+    }
+
+    /// Returns the data, copying it only if it is borrowed.
+    pub(crate) fn into_vec(self) -> Vec<u8> {
+        match self {
+            Chunk::Borrowed(data) => data.as_slice().to_vec(),
+            Chunk::Owned(data) => data,
+        }
+    }
+}
+
 /// Stores information about the parsing process and associated transactions.
 pub struct ConnectionParser {
     // General fields
     /// The logger structure associated with this parser
     pub logger: Logger,
+    /// Direction of the data the parser is working on, attached to the messages it
+    /// logs. None until the parser has seen data.
+    log_direction: Option<Direction>,
     /// A reference to the current parser configuration structure.
-    pub cfg: Rc<Config>,
+    pub cfg: Arc<Config>,
     /// The connection structure associated with this parser.
     pub conn: Connection,
     /// Opaque user data associated with this parser.
-    pub user_data: Option<Box<dyn Any>>,
+    pub user_data: Option<Box<dyn Any + Send + Sync>>,
     // Request parser fields
     /// Parser inbound status. Starts as OK, but may turn into ERROR.
     pub request_status: HtpStreamState,
@@ -231,6 +276,8 @@ pub struct ConnectionParser {
     /// data (e.g., complete headers) is sent to appropriate callbacks (e.g.,
     /// request_header_data).
     pub request_current_receiver_offset: u64,
+    /// Number of bytes sent to the current request data receiver hook.
+    pub request_receiver_data_offset: u64,
     /// How many data chunks does the inbound connection stream consist of?
     pub request_chunk_count: usize,
     /// The index of the first chunk used in the current request.
@@ -240,6 +287,8 @@ pub struct ConnectionParser {
     /// Stores the current value of a folded request header. Such headers span
     /// multiple lines, and are processed only when all data is available.
     pub request_header: Option<Bstr>,
+    /// Offset in the inbound stream of the first byte of request_header.
+    pub(crate) request_header_offset: Option<u64>,
     /// The request body length declared in a valid request header. The key here
     /// is "valid". This field will not be populated if the request contains both
     /// a Transfer-Encoding header and a Content-Length header.
@@ -251,12 +300,34 @@ pub struct ConnectionParser {
     /// Holds the amount of data that needs to be read from the
     /// current data chunk. Only used with chunked request bodies.
     pub request_chunked_length: Option<i32>,
+    /// Set when a gap disrupted the request chunked framing and the parser is
+    /// looking for the next plausible chunk-size line.
+    pub request_chunked_resync: bool,
+    /// Set after a request error while the parser is looking for the next request
+    /// line. Only used with Config::request_error_recovery.
+    pub request_resync: bool,
+    /// Whether the data discarded while looking for the next request line ended
+    /// with a line feed.
+    pub(crate) request_resync_line_start: bool,
+    /// Chunk number and offset at which parsing last resumed after an error.
+    pub(crate) request_resync_resumed: Option<(usize, u64)>,
     /// Current request parser state.
     pub request_state: State,
     /// Previous request parser state. Used to detect state changes.
     pub request_state_previous: State,
     /// The hook that should be receiving raw connection data.
     pub request_data_receiver_hook: Option<DataHook>,
+    /// Set when a paused transaction left part of the current inbound data
+    /// chunk unprocessed.
+    pub request_paused: bool,
+    /// The outbound data counter at the time request_feed yielded with DATA_OTHER.
+    pub request_data_other_mark: Option<i64>,
+    /// Inbound data kept while waiting for outbound data.
+    pub request_pending: Vec<u8>,
+    /// Number of bytes consumed from the most recent inbound data chunk.
+    pub(crate) request_consumed: usize,
+    /// Why the inbound stream failed, once it is in the ERROR state.
+    pub(crate) request_error: Option<Error>,
 
     // Response parser fields
     /// The time when the last response data chunk was received.
@@ -267,11 +338,15 @@ pub struct ConnectionParser {
     /// data (e.g., complete headers) is sent to appropriate callbacks (e.g.,
     /// response_header_data).
     pub response_current_receiver_offset: u64,
+    /// Number of bytes sent to the current response data receiver hook.
+    pub response_receiver_data_offset: u64,
     /// Used to buffer a line of outbound data when buffering cannot be avoided.
     pub response_buf: Bstr,
     /// Stores the current value of a folded response header. Such headers span
     /// multiple lines, and are processed only when all data is available.
     pub response_header: Option<Bstr>,
+    /// Offset in the outbound stream of the first byte of response_header.
+    pub(crate) response_header_offset: Option<u64>,
     /// The length of the current response body as presented in the
     /// Content-Length response header.
     pub response_content_length: i64,
@@ -280,14 +355,39 @@ pub struct ConnectionParser {
     /// Holds the amount of data that needs to be read from the
     /// current response data chunk. Only used with chunked response bodies.
     pub response_chunked_length: Option<i32>,
+    /// Set when a gap disrupted the response chunked framing and the parser is
+    /// looking for the next plausible chunk-size line.
+    pub response_chunked_resync: bool,
     /// Current response parser state.
     pub response_state: State,
     /// Previous response parser state.
     pub response_state_previous: State,
     /// The hook that should be receiving raw connection data.
     pub response_data_receiver_hook: Option<DataHook>,
+    /// Set when a paused transaction left part of the current outbound data
+    /// chunk unprocessed.
+    pub response_paused: bool,
+    /// The inbound data counter at the time response_feed yielded with DATA_OTHER.
+    pub response_data_other_mark: Option<i64>,
+    /// Outbound data kept while waiting for inbound data.
+    pub response_pending: Vec<u8>,
+    /// Number of bytes consumed from the most recent outbound data chunk.
+    pub(crate) response_consumed: usize,
+    /// Why the outbound stream failed, once it is in the ERROR state.
+    pub(crate) response_error: Option<Error>,
     /// On request body data, this field contains additional file data.
     pub request_file: Option<File>,
+    /// Quota on the file data extracted from this connection.
+    pub request_extraction_quota: Option<Arc<ExtractionQuota>>,
+    /// Events waiting to be retrieved with drain_events.
+    events: VecDeque<Event>,
+    /// Number of events dropped because the event queue was full.
+    pub events_dropped: u64,
+    /// Number of body bytes analyzed on this connection, counted against
+    /// Config::connection_body_budget.
+    pub body_analysis_used: u64,
+    /// Calls made to this parser since start_recording.
+    recorder: Option<Trace>,
 
     /// Transactions processed by this parser
     transactions: Transactions,
@@ -304,15 +404,50 @@ impl std::fmt::Debug for ConnectionParser {
     }
 }
 
+impl LogContext for ConnectionParser {
+    /// Returns the current transaction and offset of the parser in the direction
+    /// it is working on.
+    fn log_position(&self) -> Option<LogPosition> {
+        let direction = self.log_direction?;
+        let (curr_data, counter, index) = match direction {
+            Direction::Request => (
+                &self.request_curr_data,
+                self.conn.request_data_counter,
+                self.request_index(),
+            ),
+            Direction::Response => (
+                &self.response_curr_data,
+                self.conn.response_data_counter,
+                self.response_index(),
+            ),
+        };
+        let offset = (counter as u64)
+            .wrapping_sub(curr_data.get_ref().len() as u64)
+            .wrapping_add(curr_data.position());
+        Some(LogPosition {
+            direction,
+            tx_id: self.tx(index).map(|tx| tx.id),
+            offset: Some(offset),
+        })
+    }
+}
+
 impl ConnectionParser {
     /// Creates a new ConnectionParser with a preconfigured `Config` struct.
-    pub fn new(cfg: Config) -> Self {
-        let cfg = Rc::new(cfg);
-        let conn = Connection::default();
-        let logger = Logger::new(conn.get_sender(), cfg.log_level);
+    ///
+    /// The configuration is never modified by the parser, so a single `Arc<Config>`
+    /// can be shared by any number of parsers instead of being copied into each.
+    pub fn new<C: Into<Arc<Config>>>(cfg: C) -> Self {
+        let cfg = cfg.into();
+        let mut conn = Connection::default();
+        let mut logger = Logger::new(conn.get_sender(), cfg.log_level);
+        logger.limits = Arc::new(LogLimits::new(cfg.log_limit_per_code, cfg.log_limit));
+        conn.stats.log_counts = Arc::clone(&logger.counts);
+        conn.stats.log_limits = Arc::clone(&logger.limits);
         Self {
             logger: logger.clone(),
-            cfg: Rc::clone(&cfg),
+            log_direction: None,
+            cfg: Arc::clone(&cfg),
             conn,
             user_data: None,
             request_status: HtpStreamState::NEW,
@@ -321,32 +456,204 @@ impl ConnectionParser {
             request_timestamp: DateTime::<Utc>::from(SystemTime::now()),
             request_curr_data: Cursor::new(Vec::new()),
             request_current_receiver_offset: 0,
+            request_receiver_data_offset: 0,
             request_chunk_count: 0,
             request_chunk_request_index: 0,
             request_buf: Bstr::new(),
             request_header: None,
+            request_header_offset: None,
             request_content_length: 0,
             request_body_data_left: 0,
             request_chunked_length: None,
+            request_chunked_resync: false,
+            request_resync: false,
+            request_resync_line_start: false,
+            request_resync_resumed: None,
             request_state: State::IDLE,
             request_state_previous: State::NONE,
             request_data_receiver_hook: None,
+            request_paused: false,
+            request_data_other_mark: None,
+            request_pending: Vec::new(),
+            request_consumed: 0,
+            request_error: None,
             response_timestamp: DateTime::<Utc>::from(SystemTime::now()),
             response_curr_data: Cursor::new(Vec::new()),
             response_current_receiver_offset: 0,
+            response_receiver_data_offset: 0,
             response_buf: Bstr::new(),
             response_header: None,
+            response_header_offset: None,
             response_content_length: 0,
             response_body_data_left: 0,
             response_chunked_length: None,
+            response_chunked_resync: false,
             response_state: State::IDLE,
             response_state_previous: State::NONE,
             response_data_receiver_hook: None,
+            response_paused: false,
+            response_data_other_mark: None,
+            response_pending: Vec::new(),
+            response_consumed: 0,
+            response_error: None,
             request_file: None,
+            request_extraction_quota: cfg
+                .multipart_cfg
+                .extract_connection_bytes_limit
+                .map(|limit| Arc::new(ExtractionQuota::new(limit))),
+            events: VecDeque::new(),
+            events_dropped: 0,
+            body_analysis_used: 0,
+            recorder: None,
             transactions: Transactions::new(&cfg, &logger),
         }
     }
 
+    /// Prepares the parser for a new connection, as if it had just been created
+    /// with the same configuration and opened with the given parameters. The
+    /// transactions, logs and user data of the previous connection are discarded,
+    /// but buffers and tables keep their allocations, which makes reusing a parser
+    /// cheaper than creating one for every short-lived connection.
+    pub fn reset_for_new_connection(
+        &mut self,
+        client_addr: Option<IpAddr>,
+        client_port: Option<u16>,
+        server_addr: Option<IpAddr>,
+        server_port: Option<u16>,
+        timestamp: Option<DateTime<Utc>>,
+    ) {
+        // Every field is listed so that new fields cannot be forgotten here.
+        let Self {
+            logger,
+            log_direction,
+            cfg,
+            conn,
+            user_data,
+            request_status,
+            response_status,
+            response_data_other_at_tx_end,
+            request_timestamp,
+            request_curr_data,
+            request_current_receiver_offset,
+            request_receiver_data_offset,
+            request_chunk_count,
+            request_chunk_request_index,
+            request_buf,
+            request_header,
+            request_header_offset,
+            request_content_length,
+            request_body_data_left,
+            request_chunked_length,
+            request_chunked_resync,
+            request_resync,
+            request_resync_line_start,
+            request_resync_resumed,
+            request_state,
+            request_state_previous,
+            request_data_receiver_hook,
+            request_paused,
+            request_data_other_mark,
+            request_pending,
+            request_consumed,
+            request_error,
+            response_timestamp,
+            response_curr_data,
+            response_current_receiver_offset,
+            response_receiver_data_offset,
+            response_buf,
+            response_header,
+            response_header_offset,
+            response_content_length,
+            response_body_data_left,
+            response_chunked_length,
+            response_chunked_resync,
+            response_state,
+            response_state_previous,
+            response_data_receiver_hook,
+            response_paused,
+            response_data_other_mark,
+            response_pending,
+            response_consumed,
+            response_error,
+            request_file,
+            request_extraction_quota,
+            events,
+            events_dropped,
+            body_analysis_used,
+            recorder,
+            transactions,
+        } = self;
+        logger.reset();
+        *log_direction = None;
+        conn.reset();
+        *user_data = None;
+        *request_status = HtpStreamState::NEW;
+        *response_status = HtpStreamState::NEW;
+        *response_data_other_at_tx_end = false;
+        *request_timestamp = DateTime::<Utc>::from(SystemTime::now());
+        request_curr_data.get_mut().clear();
+        request_curr_data.set_position(0);
+        *request_current_receiver_offset = 0;
+        *request_receiver_data_offset = 0;
+        *request_chunk_count = 0;
+        *request_chunk_request_index = 0;
+        request_buf.clear();
+        *request_header = None;
+        *request_header_offset = None;
+        *request_content_length = 0;
+        *request_body_data_left = 0;
+        *request_chunked_length = None;
+        *request_chunked_resync = false;
+        *request_resync = false;
+        *request_resync_line_start = false;
+        *request_resync_resumed = None;
+        *request_state = State::IDLE;
+        *request_state_previous = State::NONE;
+        *request_data_receiver_hook = None;
+        *request_paused = false;
+        *request_data_other_mark = None;
+        request_pending.clear();
+        *request_consumed = 0;
+        *request_error = None;
+        *response_timestamp = DateTime::<Utc>::from(SystemTime::now());
+        response_curr_data.get_mut().clear();
+        response_curr_data.set_position(0);
+        *response_current_receiver_offset = 0;
+        *response_receiver_data_offset = 0;
+        response_buf.clear();
+        *response_header = None;
+        *response_header_offset = None;
+        *response_content_length = 0;
+        *response_body_data_left = 0;
+        *response_chunked_length = None;
+        *response_chunked_resync = false;
+        *response_state = State::IDLE;
+        *response_state_previous = State::NONE;
+        *response_data_receiver_hook = None;
+        *response_paused = false;
+        *response_data_other_mark = None;
+        response_pending.clear();
+        *response_consumed = 0;
+        *response_error = None;
+        *request_file = None;
+        *request_extraction_quota = cfg
+            .multipart_cfg
+            .extract_connection_bytes_limit
+            .map(|limit| Arc::new(ExtractionQuota::new(limit)));
+        events.clear();
+        *events_dropped = 0;
+        *body_analysis_used = 0;
+        *recorder = None;
+        transactions.clear();
+        self.open(
+            client_addr,
+            client_port,
+            server_addr,
+            server_port,
+            timestamp,
+        );
+    }
+
     /// Get the current request transaction
     pub fn request(&mut self) -> &Transaction {
         self.transactions.request()
@@ -372,11 +679,24 @@ impl ConnectionParser {
     pub fn request_next(&mut self) -> usize {
         // Detect pipelining.
         if self.transactions.request_index() > self.transactions.response_index() {
-            self.conn.flags.set(Flags::PIPELINED)
+            self.set_connection_flag(Flags::PIPELINED)
         }
         self.transactions.request_next()
     }
 
+    /// Sets a connection flag. The first time a flag is set on the connection, the
+    /// CONNECTION_FLAG callbacks are invoked with the flag and the index of the
+    /// current request transaction.
+    pub fn set_connection_flag(&mut self, flag: u8) {
+        if self.conn.flags.is_set(flag) {
+            return;
+        }
+        self.conn.flags.set(flag);
+        let tx_index = self.request_index();
+        // The flags are raised where parsing cannot be stopped.
+        let _ = self.cfg.hook_connection_flag.run_all(self, flag, tx_index);
+    }
+
     /// Advance to the next response
     /// Returns the next response transaction id
     pub fn response_next(&mut self) -> usize {
@@ -398,6 +718,59 @@ impl ConnectionParser {
         self.transactions.size()
     }
 
+    /// Checks whether a stream starts with a protocol that is clearly not HTTP,
+    /// given the first bytes seen in either direction. If so, the connection is
+    /// flagged and both directions switch to pass-through, so that no transaction
+    /// is created for the data.
+    ///
+    /// Returns true if the stream is not HTTP.
+    pub fn sniff_non_http(&mut self, data: &[u8]) -> bool {
+        if self.tx_size() > 0 {
+            return false;
+        }
+        if let Some(protocol) = HtpSniffedProtocol::sniff(data) {
+            htp_warn!(
+                self.logger,
+                HtpLogCode::NOT_HTTP,
+                format!("Stream does not carry HTTP: {:?}", protocol)
+            );
+            self.set_connection_flag(Flags::NOT_HTTP);
+            self.conn.sniffed_protocol = Some(protocol);
+            self.request_status = HtpStreamState::TUNNEL;
+            self.response_status = HtpStreamState::TUNNEL;
+            return true;
+        }
+        false
+    }
+
+    /// Returns the quotas that files extracted from this connection are counted
+    /// against, under the given configuration.
+    pub(crate) fn extraction_quotas(&self, cfg: &MultipartConfig) -> Vec<Arc<ExtractionQuota>> {
+        self.request_extraction_quota
+            .iter()
+            .chain(cfg.extract_global_quota.iter())
+            .cloned()
+            .collect()
+    }
+
+    /// Returns the offset in the request stream of the given position in the
+    /// current request chunk.
+    pub fn request_stream_offset(&self, position: u64) -> u64 {
+        let chunk_len = self.request_curr_data.get_ref().len() as u64;
+        (self.conn.request_data_counter as u64)
+            .wrapping_sub(chunk_len)
+            .wrapping_add(position)
+    }
+
+    /// Returns the offset in the response stream of the given position in the
+    /// current response chunk.
+    pub fn response_stream_offset(&self, position: u64) -> u64 {
+        let chunk_len = self.response_curr_data.get_ref().len() as u64;
+        (self.conn.response_data_counter as u64)
+            .wrapping_sub(chunk_len)
+            .wrapping_add(position)
+    }
+
     /// Get a specific transaction
     pub fn tx(&self, index: usize) -> Option<&Transaction> {
         self.transactions.get(index)
@@ -408,9 +781,62 @@ impl ConnectionParser {
         self.transactions.get_mut(index)
     }
 
+    /// Lists the conditional requests among the transactions still held by the
+    /// parser, with how they were answered and which earlier response supplied
+    /// the validator they present. Requests are matched with earlier responses by
+    /// their normalized URI.
+    pub fn conditional_exchanges(&self) -> Vec<ConditionalExchange> {
+        let txs: Vec<&Transaction> = self.transactions().collect();
+        txs.iter()
+            .enumerate()
+            .filter(|(_, tx)| tx.request_is_conditional())
+            .map(|(i, tx)| {
+                let uri = tx.partial_normalized_uri.as_ref();
+                let validator_tx = txs[..i]
+                    .iter()
+                    .rev()
+                    .find(|earlier| {
+                        earlier.partial_normalized_uri.as_ref() == uri
+                            && earlier.response_validates(tx)
+                    })
+                    .map(|earlier| earlier.index);
+                ConditionalExchange {
+                    tx_index: tx.index,
+                    not_modified: tx.response_status_number.eq_num(304),
+                    validator_tx,
+                }
+            })
+            .collect()
+    }
+
+    /// Iterate over the transactions still held by the parser, in order. Unlike
+    /// an index loop up to tx_size(), transactions already destroyed are skipped.
+    pub fn transactions(&self) -> TransactionsIter<'_> {
+        self.transactions.iter()
+    }
+
+    /// Iterate over the transactions still held by the parser that have any of
+    /// the given HtpFlags set.
+    pub fn flagged_transactions(&self, mask: u64) -> impl Iterator<Item = &Transaction> {
+        self.transactions().flagged(mask)
+    }
+
+    /// Get a specific transaction by its stable id. Returns None if the
+    /// transaction has already been destroyed.
+    pub fn tx_by_id(&self, id: TxId) -> Option<&Transaction> {
+        self.transactions.get_by_id(id)
+    }
+
+    /// Get a specific transaction by its stable id. Returns None if the
+    /// transaction has already been destroyed.
+    pub fn tx_by_id_mut(&mut self, id: TxId) -> Option<&mut Transaction> {
+        self.transactions.get_by_id_mut(id)
+    }
+
     /// Handle the current state to be processed.
     pub fn handle_request_state(&mut self, data: &mut Data) -> Result<()> {
         data.set_position(self.request_curr_data.position() as usize);
+        self.log_direction = Some(Direction::Request);
         match self.request_state {
             State::NONE => Err(HtpStatus::ERROR),
             State::IDLE => self.request_idle(),
@@ -422,6 +848,16 @@ impl ConnectionParser {
             State::CONNECT_CHECK => self.request_connect_check(),
             State::CONNECT_PROBE_DATA => self.request_connect_probe_data(data.as_slice()),
             State::BODY_DETERMINE => self.request_body_determine(),
+            State::BODY_CHUNKED_DATA
+            | State::BODY_CHUNKED_LENGTH
+            | State::BODY_CHUNKED_DATA_END
+                if data.is_gap() =>
+            {
+                self.request_body_chunked_gap(data)
+            }
+            State::BODY_CHUNKED_LENGTH if self.request_chunked_resync => {
+                self.request_body_chunked_resync(data.as_slice())
+            }
             State::BODY_CHUNKED_DATA => self.request_body_chunked_data(data.as_slice()),
             State::BODY_CHUNKED_LENGTH => self.request_body_chunked_length(data.as_slice()),
             State::BODY_CHUNKED_DATA_END => self.request_body_chunked_data_end(data.as_slice()),
@@ -435,12 +871,23 @@ impl ConnectionParser {
     /// Handle the current state to be processed.
     pub fn handle_response_state(&mut self, data: &mut Data) -> Result<()> {
         data.set_position(self.response_curr_data.position() as usize);
+        self.log_direction = Some(Direction::Response);
         match self.response_state {
             State::NONE => Err(HtpStatus::ERROR),
             State::IDLE => self.response_idle(),
             State::LINE => self.response_line(data.as_slice()),
             State::HEADERS => self.response_headers(data.as_slice()),
             State::BODY_DETERMINE => self.response_body_determine(),
+            State::BODY_CHUNKED_DATA
+            | State::BODY_CHUNKED_LENGTH
+            | State::BODY_CHUNKED_DATA_END
+                if data.is_gap() =>
+            {
+                self.response_body_chunked_gap(data)
+            }
+            State::BODY_CHUNKED_LENGTH if self.response_chunked_resync => {
+                self.response_body_chunked_resync(data.as_slice())
+            }
             State::BODY_CHUNKED_DATA => self.response_body_chunked_data(data.as_slice()),
             State::BODY_CHUNKED_LENGTH => self.response_body_chunked_length(data.as_slice()),
             State::BODY_CHUNKED_DATA_END => self.response_body_chunked_data_end(data.as_slice()),
@@ -455,7 +902,7 @@ impl ConnectionParser {
     /// The function used for request line parsing. Depends on the personality.
     pub fn parse_request_line(&mut self, request_line: &[u8]) -> Result<()> {
         self.request_mut().request_line = Some(Bstr::from(request_line));
-        if self.cfg.server_personality == HtpServerPersonality::APACHE_2 {
+        if self.request().cfg.server_personality == HtpServerPersonality::APACHE_2 {
             self.parse_request_line_generic_ex(request_line, true)
         } else {
             self.parse_request_line_generic_ex(request_line, false)
@@ -478,8 +925,14 @@ impl ConnectionParser {
         self.process_response_headers_generic(data)
     }
 
-    /// Closes the connection associated with the supplied parser.
+    /// Closes the inbound (request) direction of the connection, as when the
+    /// client sends a FIN while the server can still respond.
     pub fn request_close(&mut self, timestamp: Option<DateTime<Utc>>) {
+        self.record(Segment::RequestClose);
+        let recorder = self.recorder.take();
+        self.conn.request_close(timestamp);
+        // Parse kept data while the stream is still open
+        self.request_process_pending();
         // Update internal flags
         if self.request_status != HtpStreamState::ERROR {
             self.request_status = HtpStreamState::CLOSED
@@ -487,12 +940,37 @@ impl ConnectionParser {
         // Call the parsers one last time, which will allow them
         // to process the events that depend on stream closure
         self.request_data(Data::default(), timestamp);
+        self.recorder = recorder;
+    }
+
+    /// Closes the outbound (response) direction of the connection, as when the
+    /// server sends a FIN while the client can still send. A response body that
+    /// is delimited by the connection closing is complete once this is called.
+    pub fn response_close(&mut self, timestamp: Option<DateTime<Utc>>) {
+        self.record(Segment::ResponseClose);
+        let recorder = self.recorder.take();
+        self.conn.response_close(timestamp);
+        // Parse kept data while the stream is still open
+        self.response_process_pending();
+        // Update internal flags
+        if self.response_status != HtpStreamState::ERROR {
+            self.response_status = HtpStreamState::CLOSED
+        }
+        // Call the parsers one last time, which will allow them
+        // to process the events that depend on stream closure
+        self.response_data(Data::default(), timestamp);
+        self.recorder = recorder;
     }
 
     /// Closes the connection associated with the supplied parser.
     pub fn close(&mut self, timestamp: Option<DateTime<Utc>>) {
+        self.record(Segment::Close);
+        let recorder = self.recorder.take();
         // Close the underlying connection.
         self.conn.close(timestamp);
+        // Parse kept data while the streams are still open
+        self.request_process_pending();
+        self.response_process_pending();
         // Update internal flags
         if self.request_status != HtpStreamState::ERROR {
             self.request_status = HtpStreamState::CLOSED
@@ -504,6 +982,35 @@ impl ConnectionParser {
         // to process the events that depend on stream closure
         self.request_data(Data::default(), timestamp);
         self.response_data(Data::default(), timestamp);
+        self.logger.log_suppressed_summary();
+        self.recorder = recorder;
+    }
+
+    /// Starts recording the calls made to this parser, discarding any previous
+    /// recording. Replaying the recorded Trace on a new parser reproduces the exact
+    /// segmentation of the data, which makes segmentation-dependent problems
+    /// reproducible.
+    pub fn start_recording(&mut self) {
+        self.recorder = Some(Trace::new());
+    }
+
+    /// Stops recording and returns the calls recorded since start_recording.
+    pub fn take_trace(&mut self) -> Option<Trace> {
+        self.recorder.take()
+    }
+
+    /// Records a call, if recording.
+    pub(crate) fn record(&mut self, segment: Segment) {
+        if let Some(trace) = &mut self.recorder {
+            trace.segments.push(segment);
+        }
+    }
+
+    /// Records a request_data or response_data call, if recording.
+    pub(crate) fn record_data(&mut self, request: bool, chunk: &Data) {
+        if let Some(trace) = &mut self.recorder {
+            trace.push_data(request, chunk);
+        }
     }
 
     /// This function is most likely not used and/or not needed.
@@ -513,18 +1020,291 @@ impl ConnectionParser {
         self.request_chunk_request_index = self.request_chunk_count;
     }
 
-    /// Returns the number of bytes consumed from the current data chunks so far.
+    /// Returns true if the event stream is enabled in the configuration.
+    pub fn events_enabled(&self) -> bool {
+        self.cfg.event_queue_limit > 0
+    }
+
+    /// Queues an event, preceded by any log messages issued since the last event.
+    /// Does nothing unless the event stream is enabled.
+    pub fn push_event(&mut self, event: Event) {
+        if !self.events_enabled() {
+            return;
+        }
+        self.queue_logs();
+        self.queue_event(event);
+    }
+
+    /// Returns all queued events in the order they were produced, leaving the
+    /// queue empty.
+    pub fn drain_events(&mut self) -> Vec<Event> {
+        if self.events_enabled() {
+            self.queue_logs();
+        }
+        self.events.drain(..).collect()
+    }
+
+    fn queue_logs(&mut self) {
+        while let Some(log) = self.conn.get_next_log() {
+            self.queue_event(Event::Log(log));
+        }
+    }
+
+    fn queue_event(&mut self, event: Event) {
+        if self.events.len() < self.cfg.event_queue_limit {
+            self.events.push_back(event);
+        } else {
+            self.events_dropped = self.events_dropped.wrapping_add(1);
+        }
+    }
+
+    /// Returns true if a callback paused the transaction with the given index.
+    pub fn tx_paused(&self, index: usize) -> bool {
+        self.tx(index).map(|tx| tx.paused).unwrap_or(false)
+    }
+
+    /// Resumes parsing after a callback paused a transaction by returning
+    /// HtpStatus::PAUSE. The data that was left unprocessed in each direction is
+    /// parsed from the position at which it stopped. The consumed counts then refer
+    /// to the resumed chunks.
+    ///
+    /// Returns the resulting request and response stream states.
+    pub fn resume(&mut self) -> (HtpStreamState, HtpStreamState) {
+        self.record(Segment::Resume);
+        for tx in &mut self.transactions {
+            tx.paused = false;
+        }
+        let request_status = if self.request_paused {
+            self.request_paused = false;
+            let data = self.request_curr_data.get_ref().clone();
+            let rc = self.request_process(&mut Data::from(&data));
+            self.request_consumed = if self.request_paused {
+                data.len()
+            } else {
+                self.request_curr_data.position() as usize
+            };
+            rc
+        } else {
+            self.request_status
+        };
+        let response_status = if self.response_paused {
+            self.response_paused = false;
+            let data = self.response_curr_data.get_ref().clone();
+            let rc = self.response_process(&mut Data::from(&data));
+            self.response_consumed = if self.response_paused {
+                data.len()
+            } else {
+                self.response_curr_data.position() as usize
+            };
+            rc
+        } else {
+            self.response_status
+        };
+        (request_status, response_status)
+    }
+
+    /// Process an owned chunk of inbound data, such as a Vec<u8> or a Bytes buffer.
+    /// The chunk is moved into the parser without a copy, and the parser keeps
+    /// whatever it has not processed yet, so the chunk only needs to be supplied
+    /// again when the returned consumed count is short of its length: from that
+    /// offset after DATA_OTHER, or entirely when the parser was paused and
+    /// refused it.
+    pub fn request_feed<B: Into<Vec<u8>>>(
+        &mut self,
+        data: B,
+        timestamp: Option<DateTime<Utc>>,
+    ) -> Feed {
+        let state = self.request_input(Chunk::Owned(data.into()), timestamp);
+        self.request_data_other_mark = None;
+        if state == HtpStreamState::DATA_OTHER {
+            self.request_data_other_mark = Some(self.conn.response_data_counter);
+        }
+        Feed {
+            state,
+            consumed: self.request_data_consumed() as usize,
+        }
+    }
+
+    /// Process an owned chunk of outbound data. See request_feed.
+    pub fn response_feed<B: Into<Vec<u8>>>(
+        &mut self,
+        data: B,
+        timestamp: Option<DateTime<Utc>>,
+    ) -> Feed {
+        let state = self.response_input(Chunk::Owned(data.into()), timestamp);
+        self.response_data_other_mark = None;
+        if state == HtpStreamState::DATA_OTHER {
+            self.response_data_other_mark = Some(self.conn.request_data_counter);
+        }
+        Feed {
+            state,
+            consumed: self.response_data_consumed() as usize,
+        }
+    }
+
+    /// Returns the state of the inbound stream. The two directions are tracked
+    /// separately: an outbound failure leaves the inbound stream in its own state.
+    pub fn request_stream_state(&self) -> HtpStreamState {
+        if self.request_paused {
+            HtpStreamState::PAUSED
+        } else {
+            self.request_status
+        }
+    }
+
+    /// Returns the state of the outbound stream. See request_stream_state.
+    pub fn response_stream_state(&self) -> HtpStreamState {
+        if self.response_paused {
+            HtpStreamState::PAUSED
+        } else {
+            self.response_status
+        }
+    }
+
+    /// Returns true if the inbound parser can make progress with more data. It
+    /// cannot while it is paused, finished, tunnelling, or waiting for outbound
+    /// data after DATA_OTHER, unless the outbound stream failed.
+    pub fn request_wants_data(&self) -> bool {
+        if self.request_paused {
+            return false;
+        }
+        match self.request_status {
+            HtpStreamState::NEW | HtpStreamState::OPEN | HtpStreamState::DATA => true,
+            HtpStreamState::DATA_OTHER if self.response_status == HtpStreamState::ERROR => true,
+            HtpStreamState::DATA_OTHER => self
+                .request_data_other_mark
+                .map(|mark| mark != self.conn.response_data_counter)
+                .unwrap_or(true),
+            _ => false,
+        }
+    }
+
+    /// Returns true if the outbound parser can make progress with more data. See
+    /// request_wants_data.
+    pub fn response_wants_data(&self) -> bool {
+        if self.response_paused {
+            return false;
+        }
+        match self.response_status {
+            HtpStreamState::NEW | HtpStreamState::OPEN | HtpStreamState::DATA => true,
+            HtpStreamState::DATA_OTHER => self
+                .response_data_other_mark
+                .map(|mark| mark != self.conn.request_data_counter)
+                .unwrap_or(true),
+            _ => false,
+        }
+    }
+
+    /// Returns the number of bytes consumed from the chunk passed to the most recent
+    /// request_data() call. The value is accurate whatever the returned state: zero
+    /// when the chunk was refused (for example in the STOP, ERROR or PAUSED states),
+    /// the offset at which parsing stopped after DATA_OTHER, STOP or ERROR, and the
+    /// full length when the parser keeps the remainder itself.
     pub fn request_data_consumed(&self) -> i64 {
-        self.request_curr_data.position() as i64
+        self.request_consumed as i64
     }
 
     /// Returns the number of bytes consumed from the most recent outbound data chunk. Normally, an invocation
     /// of response_data() will consume all data from the supplied buffer, but there are circumstances
     /// where only partial consumption is possible. In such cases DATA_OTHER will be returned.
     /// Consumed bytes are no longer necessary, but the remainder of the buffer will be saved
-    /// for later.
+    /// for later. See request_data_consumed.
     pub fn response_data_consumed(&self) -> i64 {
-        self.response_curr_data.position() as i64
+        self.response_consumed as i64
+    }
+
+    /// Returns the part of `data`, the chunk passed to the most recent request_data()
+    /// call, that the parser did not consume and needs to be supplied again.
+    pub fn request_unprocessed<'a>(&self, data: &'a [u8]) -> &'a [u8] {
+        &data[std::cmp::min(self.request_consumed, data.len())..]
+    }
+
+    /// Returns the part of `data`, the chunk passed to the most recent response_data()
+    /// call, that the parser did not consume and needs to be supplied again.
+    pub fn response_unprocessed<'a>(&self, data: &'a [u8]) -> &'a [u8] {
+        &data[std::cmp::min(self.response_consumed, data.len())..]
+    }
+
+    /// Returns why the inbound stream failed, if it is in the ERROR state.
+    pub fn request_error(&self) -> Option<&Error> {
+        self.request_error.as_ref()
+    }
+
+    /// Returns why the outbound stream failed, if it is in the ERROR state.
+    pub fn response_error(&self) -> Option<&Error> {
+        self.response_error.as_ref()
+    }
+
+    /// Same as request_data(), but describes a failure with an Error instead of
+    /// returning HtpStreamState::ERROR. Once the stream has failed, every call
+    /// returns the error that caused it.
+    pub fn try_request_data(
+        &mut self,
+        chunk: Data,
+        timestamp: Option<DateTime<Utc>>,
+    ) -> std::result::Result<HtpStreamState, Error> {
+        match self.request_data(chunk, timestamp) {
+            HtpStreamState::ERROR => Err(self
+                .request_error
+                .clone()
+                .unwrap_or_else(|| self.stream_error(Direction::Request, HtpStatus::ERROR))),
+            rc => Ok(rc),
+        }
+    }
+
+    /// Same as response_data(), but describes a failure with an Error. See
+    /// try_request_data.
+    pub fn try_response_data(
+        &mut self,
+        chunk: Data,
+        timestamp: Option<DateTime<Utc>>,
+    ) -> std::result::Result<HtpStreamState, Error> {
+        match self.response_data(chunk, timestamp) {
+            HtpStreamState::ERROR => Err(self
+                .response_error
+                .clone()
+                .unwrap_or_else(|| self.stream_error(Direction::Response, HtpStatus::ERROR))),
+            rc => Ok(rc),
+        }
+    }
+
+    /// Describes the failure of a stream, using the error most recently logged by
+    /// the parser.
+    pub(crate) fn stream_error(&self, direction: Direction, status: HtpStatus) -> Error {
+        let (state, received, data, pending) = match direction {
+            Direction::Request => (
+                self.request_state,
+                self.conn.stats.request_bytes,
+                &self.request_curr_data,
+                self.request_pending.len(),
+            ),
+            Direction::Response => (
+                self.response_state,
+                self.conn.stats.response_bytes,
+                &self.response_curr_data,
+                self.response_pending.len(),
+            ),
+        };
+        let unconsumed = (data.get_ref().len() as u64).saturating_sub(data.position());
+        let context = |code, message, file, line| ErrorContext {
+            direction,
+            status,
+            state,
+            offset: received.saturating_sub(unconsumed + pending as u64),
+            code,
+            message,
+            file,
+            line,
+        };
+        match self.logger.take_last_error() {
+            Some(error) => Error::Parse(context(error.code, error.msg, error.file, error.line)),
+            None => Error::Aborted(context(
+                HtpLogCode::UNKNOWN,
+                String::new(),
+                String::new(),
+                0,
+            )),
+        }
     }
 
     /// Opens connection.
@@ -558,7 +1338,7 @@ impl ConnectionParser {
     }
 
     /// Set the user data.
-    pub fn set_user_data(&mut self, data: Box<dyn Any + 'static>) {
+    pub fn set_user_data(&mut self, data: Box<dyn Any + Send + Sync + 'static>) {
         self.user_data = Some(data);
     }
 
@@ -586,6 +1366,18 @@ impl ConnectionParser {
             .request_process_body_data(unsafe { &mut *connp_ptr }, data)
     }
 
+    /// Consumes request body data taken as is from the current request chunk,
+    /// at the position of the cursor.
+    pub(crate) fn request_process_body_chunk(&mut self, data: &[u8]) -> Result<()> {
+        let stream_offset = self.request_stream_offset(self.request_curr_data.position());
+        let connp_ptr: *mut Self = self as *mut Self;
+        self.request_mut().request_process_body_data_at(
+            unsafe { &mut *connp_ptr },
+            Some(data),
+            Some(stream_offset),
+        )
+    }
+
     /// Initialize hybrid parsing mode, change state to TRANSACTION_START,
     /// and invoke all registered callbacks.
     ///
@@ -643,6 +1435,18 @@ impl ConnectionParser {
             .response_process_body_data(unsafe { &mut *connp_ptr }, data)
     }
 
+    /// Consumes response body data taken as is from the current response chunk,
+    /// at the position of the cursor.
+    pub(crate) fn response_process_body_chunk(&mut self, data: &[u8]) -> Result<()> {
+        let stream_offset = self.response_stream_offset(self.response_curr_data.position());
+        let connp_ptr: *mut Self = self as *mut Self;
+        self.response_mut().response_process_body_data_at(
+            unsafe { &mut *connp_ptr },
+            Some(data),
+            Some(stream_offset),
+        )
+    }
+
     /// Advance state to LINE, or BODY if http version is 0.9.
     ///
     /// Returns HtpStatus::OK on success; HtpStatus::ERROR on error, HtpStatus::STOP
@@ -688,9 +1492,10 @@ impl ConnectionParser {
         Ok(())
     }
 
-    /// Remove the given transaction from the parser
-    pub fn remove_tx(&mut self, tx_id: usize) {
-        self.transactions.remove(tx_id);
+    /// Remove the given transaction from the parser. If the transaction existed,
+    /// it is returned.
+    pub fn remove_tx(&mut self, tx_id: usize) -> Option<Transaction> {
+        self.transactions.remove(tx_id)
     }
 
     /// For each transaction that is started but not completed, invoke the
@@ -699,19 +1504,20 @@ impl ConnectionParser {
     /// This function is meant to be used before dropping the ConnectionParser
     /// so any incomplete transactions can be processed by the caller.
     pub fn flush_incomplete_transactions(&mut self) {
-        let mut to_remove = Vec::<usize>::new();
+        let mut to_remove = Vec::<(usize, TxId)>::new();
         let connp_ptr: *mut Self = self as *mut Self;
         for tx in &mut self.transactions {
             if tx.is_started() && !tx.is_complete() {
-                to_remove.push(tx.index);
+                to_remove.push((tx.index, tx.id));
                 self.cfg
                     .hook_transaction_complete
                     .run_all(unsafe { &*connp_ptr }, tx)
                     .ok();
             }
         }
-        for index in to_remove {
+        for (index, id) in to_remove {
             self.transactions.remove(index);
+            self.push_event(Event::TxComplete(id));
         }
     }
 }
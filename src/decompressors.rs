@@ -1,4 +1,8 @@
 use std::io::{Cursor, Write};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
 use std::time::Instant;
 
 /// Buffer compression output to this chunk size.
@@ -154,9 +158,38 @@ impl Default for Options {
     }
 }
 
+/// Receives the output of a decompression chain: decompressed data, or None once
+/// the end of the stream is reached. Returns the number of bytes it consumed.
+pub type Sink<'a> = dyn FnMut(Option<&[u8]>) -> std::io::Result<usize> + 'a;
+
 /// Describes a decompressor that is able to restart and passthrough data.
-/// Actual decompression is done using the `Write` trait.
-pub trait Decompress: Write {
+/// Decompressed data is passed on to the sink supplied with each call.
+pub trait Decompress: Send + Sync {
+    /// Decompresses `data`, passing any output on to `sink`. Returns the number of
+    /// input bytes consumed.
+    fn write(&mut self, data: &[u8], sink: &mut Sink<'_>) -> std::io::Result<usize>;
+
+    /// Decompresses all of `data`, passing any output on to `sink`.
+    fn write_all(&mut self, mut data: &[u8], sink: &mut Sink<'_>) -> std::io::Result<()> {
+        while !data.is_empty() {
+            match self.write(data, sink) {
+                Ok(0) => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::WriteZero,
+                        "failed to write whole buffer",
+                    ))
+                }
+                Ok(consumed) => data = &data[consumed..],
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Passes any buffered output on to `sink`.
+    fn flush(&mut self, sink: &mut Sink<'_>) -> std::io::Result<()>;
+
     /// Restarts the decompressor to try the same one again or a different one.
     fn restart(&mut self) -> std::io::Result<()>;
 
@@ -166,45 +199,80 @@ pub trait Decompress: Write {
 
     /// Indicates that we have reached the end of data. This would be equivalent
     /// to sending a NULL pointer in C and may be used by the hooks.
-    fn finish(&mut self) -> std::io::Result<()>;
+    fn finish(&mut self, sink: &mut Sink<'_>) -> std::io::Result<()>;
 }
 
 /// Type alias for callback function.
-pub type CallbackFn = Box<dyn FnMut(Option<&[u8]>) -> Result<usize, std::io::Error>>;
-
-/// Simple wrapper around a closure to chain it to the other decompressors
-pub struct CallbackWriter(CallbackFn);
+pub type CallbackFn = Box<dyn FnMut(Option<&[u8]>) -> Result<usize, std::io::Error> + Send + Sync>;
 
-impl CallbackWriter {
-    /// Create a new CallbackWriter.
-    pub fn new(cbk: CallbackFn) -> Self {
-        CallbackWriter(cbk)
-    }
-}
+/// Last decompressor in the chain, which passes the data on to the sink.
+struct SinkWriter;
 
-impl Write for CallbackWriter {
-    fn write(&mut self, data: &[u8]) -> std::result::Result<usize, std::io::Error> {
-        (self.0)(Some(data))
+impl Decompress for SinkWriter {
+    fn write(&mut self, data: &[u8], sink: &mut Sink<'_>) -> std::io::Result<usize> {
+        sink(Some(data))
     }
 
-    fn flush(&mut self) -> std::result::Result<(), std::io::Error> {
+    fn flush(&mut self, _sink: &mut Sink<'_>) -> std::io::Result<()> {
         Ok(())
     }
-}
 
-impl Decompress for CallbackWriter {
     fn restart(&mut self) -> std::io::Result<()> {
         Ok(())
     }
 
     fn set_passthrough(&mut self, _passthrough: bool) {}
 
-    fn finish(&mut self) -> std::io::Result<()> {
-        (self.0)(None)?;
+    fn finish(&mut self, sink: &mut Sink<'_>) -> std::io::Result<()> {
+        sink(None)?;
         Ok(())
     }
 }
 
+/// Passes data on to the next decompressor in the chain, counting the bytes it
+/// accepts.
+struct CountingWriter {
+    /// Next decompressor to call.
+    inner: Box<dyn Decompress>,
+    /// Number of bytes accepted by the next decompressor.
+    count: Arc<AtomicU64>,
+}
+
+impl Decompress for CountingWriter {
+    fn write(&mut self, data: &[u8], sink: &mut Sink<'_>) -> std::io::Result<usize> {
+        let consumed = self.inner.write(data, sink)?;
+        self.count.fetch_add(consumed as u64, Ordering::Relaxed);
+        Ok(consumed)
+    }
+
+    fn flush(&mut self, sink: &mut Sink<'_>) -> std::io::Result<()> {
+        self.inner.flush(sink)
+    }
+
+    fn restart(&mut self) -> std::io::Result<()> {
+        self.inner.restart()
+    }
+
+    fn set_passthrough(&mut self, passthrough: bool) {
+        self.inner.set_passthrough(passthrough)
+    }
+
+    fn finish(&mut self, sink: &mut Sink<'_>) -> std::io::Result<()> {
+        self.inner.finish(sink)
+    }
+}
+
+/// Input and output sizes of one layer of a decompression chain.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LayerSizes {
+    /// Encoding the layer decodes.
+    pub encoding: HtpContentEncoding,
+    /// Number of bytes the layer received.
+    pub input: u64,
+    /// Number of bytes the layer produced.
+    pub output: u64,
+}
+
 /// Type of compression.
 /// cbindgen:rename-all=QualifiedScreamingSnakeCase
 #[repr(C)]
@@ -224,34 +292,104 @@ pub enum HtpContentEncoding {
     ERROR,
 }
 
-/// The outer decompressor tracks the number of callbacks and time spent
-/// decompressing.
-pub struct Decompressor {
-    /// First decompressor to call
-    inner: Box<dyn Decompress>,
+/// Tracks the time spent decompressing.
+struct Timer {
     /// Time we started decompression
     time_before: Option<Instant>,
     /// Time spent decompressing so far in microseconds (usec)
     time_spent: u64,
     /// Number of times the callback was called
     nb_callbacks: u32,
+    /// Time limit in microseconds (usec)
+    time_limit: u32,
+    /// Number of callbacks between checks of the time limit
+    time_test_freq: u32,
 }
 
-impl Decompressor {
-    /// Creates a new decompressor from a struct implementing the Decompress trait.
-    fn new(inner: Box<dyn Decompress>) -> Self {
+impl Timer {
+    /// Creates a new timer with the time limit from `options`.
+    fn new(options: &Options) -> Self {
         Self {
-            inner,
             time_before: None,
             time_spent: 0,
             nb_callbacks: 0,
+            time_limit: options.get_time_limit(),
+            time_test_freq: options.get_time_test_freq(),
+        }
+    }
+
+    /// Starts the decompression timer.
+    fn start(&mut self) {
+        self.time_before.replace(Instant::now());
+    }
+
+    /// Stops the decompression timer, updates and returns the time spent
+    /// decompressing in microseconds (usec).
+    fn reset(&mut self) -> Option<u64> {
+        let now = Instant::now();
+        if let Some(time_before) = self.time_before.replace(now) {
+            // it is unlikely that more than 2^64 will be spent on a single stream
+            self.time_spent += now.duration_since(time_before).as_micros() as u64;
+            Some(self.time_spent)
+        } else {
+            None
+        }
+    }
+
+    /// Increments the number of times the callback was called.
+    fn callback_inc(&mut self) -> u32 {
+        self.nb_callbacks = self.nb_callbacks.wrapping_add(1);
+        self.nb_callbacks
+    }
+
+    /// Counts a callback and, every `time_test_freq` callbacks, fails if the time
+    /// spent decompressing exceeds the time limit.
+    fn check(&mut self) -> std::io::Result<()> {
+        if self.callback_inc() % self.time_test_freq.max(1) == 0 {
+            if let Some(time_spent) = self.reset() {
+                if time_spent > self.time_limit as u64 {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::Other,
+                        "compression_time_limit reached",
+                    ));
+                }
+            }
         }
+        Ok(())
     }
+}
 
-    /// Creates a new decompressor from a callback to call when decompressed
-    /// data is ready.
-    fn callback(callback: CallbackFn) -> Self {
-        Self::new(Box::new(CallbackWriter::new(callback)))
+/// The outer decompressor tracks the number of callbacks and time spent
+/// decompressing.
+pub struct Decompressor {
+    /// First decompressor to call
+    inner: Box<dyn Decompress>,
+    /// Callback to pass decompressed data to when no sink is supplied
+    callback: Option<CallbackFn>,
+    /// Time spent decompressing
+    timer: Timer,
+    /// Encoding of each layer and the number of bytes it received, starting
+    /// with the first decompressor called.
+    layers: Vec<(HtpContentEncoding, Arc<AtomicU64>)>,
+    /// Number of bytes passed to the sink.
+    output: Arc<AtomicU64>,
+}
+
+impl Decompressor {
+    /// Creates a new decompressor that passes its data to the sink supplied with
+    /// each call, or to `callback` when there is none.
+    fn sink(callback: Option<CallbackFn>) -> Self {
+        let output = Arc::new(AtomicU64::new(0));
+        Self {
+            inner: Box::new(CountingWriter {
+                inner: Box::new(SinkWriter),
+                count: Arc::clone(&output),
+            }),
+            callback,
+            timer: Timer::new(&Options::default()),
+            layers: Vec::new(),
+            output,
+        }
     }
 
     /// Prepends a decompressor to this chain by consuming `self.inner`
@@ -283,15 +421,27 @@ impl Decompressor {
     /// // 3. callback
     /// decompressor.decompress(&[]).unwrap();
     /// ```
-    pub fn prepend(self, encoding: HtpContentEncoding, options: Options) -> std::io::Result<Self> {
+    pub fn prepend(
+        mut self,
+        encoding: HtpContentEncoding,
+        options: Options,
+    ) -> std::io::Result<Self> {
         match encoding {
-            HtpContentEncoding::NONE => Ok(Decompressor::new(self.inner)),
+            HtpContentEncoding::NONE => Ok(self),
             HtpContentEncoding::GZIP
             | HtpContentEncoding::DEFLATE
             | HtpContentEncoding::ZLIB
-            | HtpContentEncoding::LZMA => Ok(Decompressor::new(Box::new(InnerDecompressor::new(
-                encoding, self.inner, options,
-            )?))),
+            | HtpContentEncoding::LZMA => {
+                let input = Arc::new(AtomicU64::new(0));
+                self.inner = Box::new(CountingWriter {
+                    inner: Box::new(InnerDecompressor::new(encoding, self.inner, options)?),
+                    count: Arc::clone(&input),
+                });
+                self.layers.insert(0, (encoding, input));
+                self.timer.time_limit = options.get_time_limit();
+                self.timer.time_test_freq = options.get_time_test_freq();
+                Ok(self)
+            }
             HtpContentEncoding::ERROR => Err(std::io::Error::new(
                 std::io::ErrorKind::Other,
                 "expected a valid encoding",
@@ -306,36 +456,49 @@ impl Decompressor {
         callback: CallbackFn,
         options: Options,
     ) -> std::io::Result<Self> {
-        Self::callback(callback).prepend(encoding, options)
+        Self::sink(Some(callback)).prepend(encoding, options)
     }
 
-    /// Starts the decompression timer.
-    fn timer_start(&mut self) {
-        self.time_before.replace(Instant::now());
+    /// Creates a new decompressor with `encoding` that passes its data to the sink
+    /// supplied to `decompress_with` and `finish_with`.
+    pub fn new_with_sink(encoding: HtpContentEncoding, options: Options) -> std::io::Result<Self> {
+        Self::sink(None).prepend(encoding, options)
     }
 
     /// Stops the decompression timer, updates and returns the time spent
     /// decompressing in microseconds (usec).
     pub fn timer_reset(&mut self) -> Option<u64> {
-        let now = Instant::now();
-        if let Some(time_before) = self.time_before.replace(now) {
-            // it is unlikely that more than 2^64 will be spent on a single stream
-            self.time_spent += now.duration_since(time_before).as_micros() as u64;
-            Some(self.time_spent)
-        } else {
-            None
-        }
+        self.timer.reset()
     }
 
     /// Increments the number of times the callback was called.
     pub fn callback_inc(&mut self) -> u32 {
-        self.nb_callbacks = self.nb_callbacks.wrapping_add(1);
-        self.nb_callbacks
+        self.timer.callback_inc()
     }
 
     /// Returns the time spent decompressing in microseconds (usec).
     pub fn time_spent(&self) -> u64 {
-        self.time_spent
+        self.timer.time_spent
+    }
+
+    /// Returns the number of bytes received and produced by each layer, starting
+    /// with the first decompressor called.
+    pub fn layer_sizes(&self) -> Vec<LayerSizes> {
+        self.layers
+            .iter()
+            .enumerate()
+            .map(|(i, (encoding, input))| {
+                let output = self
+                    .layers
+                    .get(i + 1)
+                    .map_or(&self.output, |(_, next)| next);
+                LayerSizes {
+                    encoding: *encoding,
+                    input: input.load(Ordering::Relaxed),
+                    output: output.load(Ordering::Relaxed),
+                }
+            })
+            .collect()
     }
 
     /// Decompress the input `data` by calling the chain of decompressors and
@@ -344,34 +507,68 @@ impl Decompressor {
     /// This will reset the number of callbacks called and restart the
     /// decompression timer.
     pub fn decompress(&mut self, data: &[u8]) -> std::io::Result<()> {
-        self.nb_callbacks = 0;
-        self.timer_start();
+        let mut callback = self.callback.take().ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::Other, "no callback to decompress to")
+        })?;
+        let result = self.decompress_with(data, &mut callback);
+        self.callback.replace(callback);
+        result
+    }
 
-        let result = self.inner.write_all(data).and_then(|_| self.inner.flush());
+    /// Decompress the input `data` by calling the chain of decompressors, passing
+    /// the output on to `sink` as soon as each chunk is ready.
+    ///
+    /// This will reset the number of callbacks called and restart the
+    /// decompression timer. Every `time_test_freq` calls to the sink, decompression
+    /// fails if the time limit was exceeded.
+    pub fn decompress_with(&mut self, data: &[u8], sink: &mut Sink<'_>) -> std::io::Result<()> {
+        self.timer.nb_callbacks = 0;
+        self.timer.start();
+
+        let (inner, timer) = (&mut self.inner, &mut self.timer);
+        let mut checked = |data: Option<&[u8]>| -> std::io::Result<usize> {
+            let consumed = sink(data)?;
+            timer.check()?;
+            Ok(consumed)
+        };
+        let result = inner
+            .write_all(data, &mut checked)
+            .and_then(|_| inner.flush(&mut checked));
 
-        self.timer_reset();
+        self.timer.reset();
         result
     }
 
     /// Notify decompressors that the end of stream as reached. This is equivalent
     /// to sending a NULL data pointer.
     pub fn finish(&mut self) -> std::io::Result<()> {
-        self.inner.finish()
+        let mut callback = self.callback.take().ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::Other, "no callback to decompress to")
+        })?;
+        let result = self.finish_with(&mut callback);
+        self.callback.replace(callback);
+        result
+    }
+
+    /// Notify decompressors that the end of stream as reached, passing any
+    /// remaining output on to `sink`.
+    pub fn finish_with(&mut self, sink: &mut Sink<'_>) -> std::io::Result<()> {
+        self.inner.finish(sink)
     }
 }
 
 impl std::fmt::Debug for Decompressor {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         f.debug_struct("Decompressor")
-            .field("time_spent", &self.time_spent)
-            .field("nb_callbacks", &self.nb_callbacks)
+            .field("time_spent", &self.timer.time_spent)
+            .field("nb_callbacks", &self.timer.nb_callbacks)
             .finish()
     }
 }
 
 /// Trait that represents the decompression writers (gzip, deflate, etc.) and
 /// methods needed to write to a temporary buffer.
-pub trait BufWriter: Write {
+pub trait BufWriter: Write + Send + Sync {
     /// Get a mutable reference to the buffer.
     fn get_mut(&mut self) -> Option<&mut Cursor<Box<[u8]>>>;
     /// Notify end of data.
@@ -716,10 +913,10 @@ impl InnerDecompressor {
     /// Tries to pass data to the callback instead of calling the writers.
     ///
     /// This will set passthrough mode on success or revert on error.
-    fn try_passthrough(&mut self, data: &[u8]) -> std::io::Result<usize> {
+    fn try_passthrough(&mut self, data: &[u8], sink: &mut Sink<'_>) -> std::io::Result<usize> {
         self.set_passthrough(true);
         if let Some(inner) = &mut self.inner {
-            let result = inner.write(data);
+            let result = inner.write(data, sink);
             if result.is_err() {
                 self.set_passthrough(false);
             }
@@ -733,7 +930,11 @@ impl InnerDecompressor {
     ///
     /// The writer should be taken out of its slot and passed directly instead of
     /// `self.writer` to avoid holding multiple mutable references.
-    fn flush_writer(&mut self, writer: &mut Box<dyn BufWriter>) -> std::io::Result<()> {
+    fn flush_writer(
+        &mut self,
+        writer: &mut Box<dyn BufWriter>,
+        sink: &mut Sink<'_>,
+    ) -> std::io::Result<()> {
         if let Some(mut inner) = self.inner.take() {
             while {
                 let result = writer.flush();
@@ -741,7 +942,7 @@ impl InnerDecompressor {
                 // Flush all of the bytes the writer has written to our temporary
                 // buffer of fixed size.
                 if let Some(cursor) = writer.get_mut() {
-                    inner.write_all(&cursor.get_ref()[0..cursor.position() as usize])?;
+                    inner.write_all(&cursor.get_ref()[0..cursor.position() as usize], sink)?;
                     cursor.set_position(0);
                 }
 
@@ -771,12 +972,12 @@ impl InnerDecompressor {
     }
 }
 
-impl Write for InnerDecompressor {
-    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+impl Decompress for InnerDecompressor {
+    fn write(&mut self, data: &[u8], sink: &mut Sink<'_>) -> std::io::Result<usize> {
         // Passthrough mode
         if self.passthrough {
             if let Some(inner) = &mut self.inner {
-                inner.write(data)
+                inner.write(data, sink)
             } else {
                 Ok(data.len())
             }
@@ -802,21 +1003,21 @@ impl Write for InnerDecompressor {
                 Err(e) => {
                     match e.kind() {
                         std::io::ErrorKind::WriteZero => {
-                            self.flush_writer(&mut writer)?;
+                            self.flush_writer(&mut writer, sink)?;
                             // Recursion: the buffer was flushed until `WriteZero`
                             // stopped occuring.
                             self.writer.replace(writer);
-                            self.write(data)
+                            self.write(data, sink)
                         }
                         _ => {
                             // try to restart, any data in the temp buffer will be
                             // discarded
                             if self.restart().is_err() {
-                                self.try_passthrough(data)
+                                self.try_passthrough(data, sink)
                             } else {
                                 // Recursion: restart will fail after a small
                                 // number of attempts
-                                self.write(data)
+                                self.write(data, sink)
                             }
                         }
                     }
@@ -830,20 +1031,18 @@ impl Write for InnerDecompressor {
         }
     }
 
-    fn flush(&mut self) -> std::io::Result<()> {
+    fn flush(&mut self, sink: &mut Sink<'_>) -> std::io::Result<()> {
         if let Some(mut writer) = self.writer.take() {
-            self.flush_writer(&mut writer)?;
+            self.flush_writer(&mut writer, sink)?;
             self.writer.replace(writer);
         }
         if let Some(inner) = &mut self.inner {
-            inner.flush()
+            inner.flush(sink)
         } else {
             Ok(())
         }
     }
-}
 
-impl Decompress for InnerDecompressor {
     fn restart(&mut self) -> std::io::Result<()> {
         if self.restarts < 3 {
             // first retry the same encoding type
@@ -889,9 +1088,9 @@ impl Decompress for InnerDecompressor {
     }
 
     // Tell all decompressors that there is no more data to receive.
-    fn finish(&mut self) -> std::io::Result<()> {
+    fn finish(&mut self, sink: &mut Sink<'_>) -> std::io::Result<()> {
         let output = if let Some(mut writer) = self.writer.take() {
-            self.flush_writer(&mut writer)?;
+            self.flush_writer(&mut writer, sink)?;
             Some(writer.finish()?)
         } else {
             None
@@ -899,15 +1098,35 @@ impl Decompress for InnerDecompressor {
 
         if let Some(mut inner) = self.inner.take() {
             if let Some(output) = output {
-                inner.write_all(&output.get_ref()[..output.position() as usize])?;
+                inner.write_all(&output.get_ref()[..output.position() as usize], sink)?;
             }
-            inner.finish()
+            inner.finish(sink)
         } else {
             Ok(())
         }
     }
 }
 
+#[test]
+fn test_time_limit() {
+    use flate2::{write::GzEncoder, Compression};
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&[0; 1 << 20]).unwrap();
+    let data = encoder.finish().unwrap();
+
+    let mut options = Options::default();
+    options.set_time_limit(0);
+    options.set_time_test_freq(1);
+    let mut decompressor = Decompressor::new_with_sink(HtpContentEncoding::GZIP, options).unwrap();
+    let mut output = 0;
+    let result = decompressor.decompress_with(&data, &mut |data| {
+        output += data.unwrap_or(b"").len();
+        Ok(data.unwrap_or(b"").len())
+    });
+    assert!(result.is_err());
+    assert_eq!(ENCODING_CHUNK_SIZE, output);
+}
+
 #[test]
 fn test_gz_header() {
     // No flags or other bits
@@ -0,0 +1,261 @@
+//! Differential parsing of one connection with two configurations.
+//!
+//! Servers and intermediaries that disagree about where a request ends or what
+//! it addresses are what request smuggling and filter evasion exploit. A
+//! DiffParser feeds identical data to two connection parsers, typically set up
+//! with different server personalities, and reports where their interpretations
+//! diverge.
+
+use crate::{
+    bstr::Bstr,
+    config::Config,
+    connection_parser::{ConnectionParser, Data, HtpStreamState},
+    event::Direction,
+    transaction::{HtpTransferCoding, Transaction},
+};
+use chrono::{DateTime, Utc};
+use std::{net::IpAddr, sync::Arc};
+
+/// A difference between the interpretations of the two parsers of a DiffParser.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Divergence {
+    /// The parsers saw a different number of transactions.
+    TransactionCount {
+        /// Number of transactions seen by the left parser.
+        left: usize,
+        /// Number of transactions seen by the right parser.
+        right: usize,
+    },
+    /// A stream ended up in a different state.
+    StreamState {
+        /// The stream that diverged.
+        direction: Direction,
+        /// State of the stream in the left parser.
+        left: HtpStreamState,
+        /// State of the stream in the right parser.
+        right: HtpStreamState,
+    },
+    /// A message body was framed differently.
+    TransferCoding {
+        /// Index of the transaction.
+        index: usize,
+        /// The message whose framing diverged.
+        direction: Direction,
+        /// Transfer coding determined by the left parser.
+        left: HtpTransferCoding,
+        /// Transfer coding determined by the right parser.
+        right: HtpTransferCoding,
+    },
+    /// A message was given a different Content-Length.
+    ContentLength {
+        /// Index of the transaction.
+        index: usize,
+        /// The message whose length diverged.
+        direction: Direction,
+        /// Content length determined by the left parser.
+        left: i64,
+        /// Content length determined by the right parser.
+        right: i64,
+    },
+    /// A request URI was normalized differently.
+    NormalizedUri {
+        /// Index of the transaction.
+        index: usize,
+        /// Normalized URI (path, query and fragment) of the left parser.
+        left: Option<Bstr>,
+        /// Normalized URI (path, query and fragment) of the right parser.
+        right: Option<Bstr>,
+    },
+}
+
+/// Parses one connection with two configurations side by side.
+#[derive(Debug)]
+pub struct DiffParser {
+    /// The parser using the first configuration.
+    pub left: ConnectionParser,
+    /// The parser using the second configuration.
+    pub right: ConnectionParser,
+}
+
+impl DiffParser {
+    /// Creates a DiffParser whose parsers use the `left` and `right` configurations.
+    pub fn new<C: Into<Arc<Config>>>(left: C, right: C) -> Self {
+        Self {
+            left: ConnectionParser::new(left),
+            right: ConnectionParser::new(right),
+        }
+    }
+
+    /// Opens the connection in both parsers. See ConnectionParser::open.
+    pub fn open(
+        &mut self,
+        client_addr: Option<IpAddr>,
+        client_port: Option<u16>,
+        server_addr: Option<IpAddr>,
+        server_port: Option<u16>,
+        timestamp: Option<DateTime<Utc>>,
+    ) {
+        self.left.open(
+            client_addr,
+            client_port,
+            server_addr,
+            server_port,
+            timestamp,
+        );
+        self.right.open(
+            client_addr,
+            client_port,
+            server_addr,
+            server_port,
+            timestamp,
+        );
+    }
+
+    /// Supplies the same inbound data to both parsers, returning their stream states.
+    pub fn request_data(
+        &mut self,
+        chunk: Data,
+        timestamp: Option<DateTime<Utc>>,
+    ) -> (HtpStreamState, HtpStreamState) {
+        (
+            self.left.request_data(chunk.clone(), timestamp),
+            self.right.request_data(chunk, timestamp),
+        )
+    }
+
+    /// Supplies the same outbound data to both parsers, returning their stream states.
+    pub fn response_data(
+        &mut self,
+        chunk: Data,
+        timestamp: Option<DateTime<Utc>>,
+    ) -> (HtpStreamState, HtpStreamState) {
+        (
+            self.left.response_data(chunk.clone(), timestamp),
+            self.right.response_data(chunk, timestamp),
+        )
+    }
+
+    /// Closes the connection in both parsers.
+    pub fn close(&mut self, timestamp: Option<DateTime<Utc>>) {
+        self.left.close(timestamp);
+        self.right.close(timestamp);
+    }
+
+    /// Compares what the two parsers have seen so far. Transactions are compared
+    /// by index, up to the smaller transaction count.
+    pub fn divergences(&self) -> Vec<Divergence> {
+        let mut divergences = Vec::new();
+        let (left, right) = (self.left.tx_size(), self.right.tx_size());
+        if left != right {
+            divergences.push(Divergence::TransactionCount { left, right });
+        }
+        let streams = [
+            (
+                Direction::Request,
+                self.left.request_stream_state(),
+                self.right.request_stream_state(),
+            ),
+            (
+                Direction::Response,
+                self.left.response_stream_state(),
+                self.right.response_stream_state(),
+            ),
+        ];
+        for (direction, left, right) in streams.iter().cloned() {
+            if left != right {
+                divergences.push(Divergence::StreamState {
+                    direction,
+                    left,
+                    right,
+                });
+            }
+        }
+        for index in 0..std::cmp::min(left, right) {
+            if let (Some(left), Some(right)) = (self.left.tx(index), self.right.tx(index)) {
+                compare_tx(index, left, right, &mut divergences);
+            }
+        }
+        divergences
+    }
+}
+
+/// Appends the differences between two transactions with the same index.
+fn compare_tx(index: usize, left: &Transaction, right: &Transaction, out: &mut Vec<Divergence>) {
+    let framing = [
+        (
+            Direction::Request,
+            (left.request_transfer_coding, right.request_transfer_coding),
+            (left.request_content_length, right.request_content_length),
+        ),
+        (
+            Direction::Response,
+            (
+                left.response_transfer_coding,
+                right.response_transfer_coding,
+            ),
+            (left.response_content_length, right.response_content_length),
+        ),
+    ];
+    for (direction, coding, length) in framing.iter().cloned() {
+        if coding.0 != coding.1 {
+            out.push(Divergence::TransferCoding {
+                index,
+                direction,
+                left: coding.0,
+                right: coding.1,
+            });
+        }
+        if length.0 != length.1 {
+            out.push(Divergence::ContentLength {
+                index,
+                direction,
+                left: length.0,
+                right: length.1,
+            });
+        }
+    }
+    if left.partial_normalized_uri != right.partial_normalized_uri {
+        out.push(Divergence::NormalizedUri {
+            index,
+            left: left.partial_normalized_uri.clone(),
+            right: right.partial_normalized_uri.clone(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::HtpServerPersonality;
+
+    #[test]
+    fn DiffPersonalities() {
+        let mut tomcat = Config::default();
+        tomcat
+            .set_server_personality(HtpServerPersonality::TOMCAT)
+            .unwrap();
+        let mut diff = DiffParser::new(Config::default(), tomcat);
+        diff.open(None, None, None, None, None);
+        diff.request_data(
+            b"GET /a;x=1/b HTTP/1.1\r\nHost: a\r\n\r\nGET /c HTTP/1.1\r\nHost: a\r\n\r\n"[..]
+                .into(),
+            None,
+        );
+        diff.close(None);
+        let divergences = diff.divergences();
+        assert_eq!(1, divergences.len());
+        assert_eq!(
+            Divergence::NormalizedUri {
+                index: 0,
+                left: Some(Bstr::from("/a;x=1/b")),
+                right: Some(Bstr::from("/a/b")),
+            },
+            divergences[0]
+        );
+
+        let mut diff = DiffParser::new(Config::default(), Config::default());
+        diff.open(None, None, None, None, None);
+        diff.request_data(b"GET /a;x=1/b HTTP/1.1\r\nHost: a\r\n\r\n"[..].into(), None);
+        assert!(diff.divergences().is_empty());
+    }
+}
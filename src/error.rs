@@ -1,9 +1,88 @@
-use crate::HtpStatus;
-use std::convert::Into;
+use crate::{connection_parser::State, event::Direction, log::HtpLogCode, HtpStatus};
+use std::{convert::Into, fmt};
 
 /// Alias for libhtp Result type. Result types are classified by `HtpStatus`.
 pub type Result<T> = std::result::Result<T, HtpStatus>;
 
+/// Describes why a request or response stream failed.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Error {
+    /// The parser rejected the data. The context carries the code, message and
+    /// source location of the error the parser logged.
+    Parse(ErrorContext),
+    /// Parsing was aborted without a parser error, typically because a callback
+    /// returned an error.
+    Aborted(ErrorContext),
+}
+
+/// Where and in what state a stream failed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ErrorContext {
+    /// Stream that failed.
+    pub direction: Direction,
+    /// Status that ended parsing.
+    pub status: HtpStatus,
+    /// Parser state when the error occurred.
+    pub state: State,
+    /// Offset in the stream of the first byte that was not consumed.
+    pub offset: u64,
+    /// Code of the logged error, or UNKNOWN if none was logged.
+    pub code: HtpLogCode,
+    /// Message of the logged error.
+    pub message: String,
+    /// File in which the error was logged.
+    pub file: String,
+    /// Line on which the error was logged.
+    pub line: u32,
+}
+
+impl Error {
+    /// Returns the context of the error.
+    pub fn context(&self) -> &ErrorContext {
+        match self {
+            Error::Parse(context) | Error::Aborted(context) => context,
+        }
+    }
+
+    /// Returns the log code of the error, or UNKNOWN if the parser logged none.
+    pub fn code(&self) -> HtpLogCode {
+        self.context().code
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let context = self.context();
+        match self {
+            Error::Parse(_) => write!(
+                f,
+                "{:?} parse error at offset {} in state {:?}: {} ({:?}, {}:{})",
+                context.direction,
+                context.offset,
+                context.state,
+                context.message,
+                context.code,
+                context.file,
+                context.line
+            ),
+            Error::Aborted(_) => write!(
+                f,
+                "{:?} parsing aborted with {:?} at offset {} in state {:?}",
+                context.direction, context.status, context.offset, context.state
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<&Error> for HtpStatus {
+    /// Returns the HtpStatus that ended parsing, for C callers.
+    fn from(error: &Error) -> Self {
+        error.context().status.clone()
+    }
+}
+
 impl<T> Into<HtpStatus> for Result<T> {
     /// Returns HtpStatus from result.
     fn into(self) -> HtpStatus {
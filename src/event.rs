@@ -0,0 +1,51 @@
+//! Pull-based alternative to registering parser callbacks.
+
+use crate::{bstr::Bstr, log::Log, transaction::TxId};
+
+/// Direction of the traffic an event was produced from.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Direction {
+    /// Data sent by the client.
+    Request,
+    /// Data sent by the server.
+    Response,
+}
+
+/// An event queued by the parser when Config::event_queue_limit is set.
+#[derive(Clone)]
+pub enum Event {
+    /// A new transaction was started.
+    TxStarted(TxId),
+    /// The request line of a transaction was parsed.
+    RequestLine {
+        /// Transaction the line belongs to.
+        tx: TxId,
+        /// The raw request line.
+        line: Bstr,
+    },
+    /// A request or response header was parsed. Headers are reported once the
+    /// header block is complete, in the order they appeared.
+    HeaderParsed {
+        /// Transaction the header belongs to.
+        tx: TxId,
+        /// Whether this is a request or response header.
+        direction: Direction,
+        /// Header name.
+        name: Bstr,
+        /// Header value.
+        value: Bstr,
+    },
+    /// A chunk of (decompressed) body data was received.
+    BodyChunk {
+        /// Transaction the data belongs to.
+        tx: TxId,
+        /// Whether this is request or response body data.
+        direction: Direction,
+        /// The body data.
+        data: Bstr,
+    },
+    /// A transaction was completed.
+    TxComplete(TxId),
+    /// The parser logged a message.
+    Log(Log),
+}
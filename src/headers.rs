@@ -13,6 +13,7 @@ use nom::{
     Err::Incomplete,
     IResult, Needed,
 };
+use std::{borrow::Cow, ops::Range};
 
 #[derive(Debug, PartialEq)]
 pub struct Flags;
@@ -51,6 +52,9 @@ pub struct Header {
     pub value: Value,
 }
 
+/// A header with the range of the input it was parsed from.
+pub type HeaderSpan = (Header, Range<usize>);
+
 /// Enumerates possible parser types
 #[derive(PartialEq, Copy, Clone, Debug)]
 pub enum Side {
@@ -309,22 +313,24 @@ impl Parser {
     }
 
     /// Removes trailing unwanted characaters from input.
-    /// If null terminates is set to true, it will remove all characters before the null character
-    fn remove_trailing(&self, input: &mut Vec<u8>, flags: &mut u64) {
+    /// On the request side, a null character in the value sets NULL_TERMINATED; the
+    /// value is kept whole so that the header processors can decide how to handle it.
+    /// The input is shortened in place, so borrowed input is never copied.
+    fn remove_trailing(&self, input: &mut Cow<[u8]>, flags: &mut u64) {
+        let mut len = input.len();
         if self.side == Side::Request {
-            if let Ok((trailing_data, data)) = take_until_null(&input) {
+            if let Ok((trailing_data, _)) = take_until_null(input) {
                 if trailing_data.first() == Some(&b'\0') {
                     flags.set(Flags::NULL_TERMINATED);
                 }
-                *input = data.to_vec();
             }
         }
-        while let Some(end) = input.last() {
-            if is_space(*end) {
-                input.pop();
-            } else {
-                break;
-            }
+        while len > 0 && is_space(input[len - 1]) {
+            len -= 1;
+        }
+        match input {
+            Cow::Borrowed(data) => *data = &data[..len],
+            Cow::Owned(data) => data.truncate(len),
         }
     }
 
@@ -349,7 +355,9 @@ impl Parser {
     fn value(&self) -> impl Fn(&[u8]) -> IResult<&[u8], Value> + '_ {
         move |input| {
             let (rest, (val_bytes, ((_eol, mut flags), fold))) = self.value_bytes()(input)?;
-            let mut value = val_bytes.to_vec();
+            // Only folded values need a buffer of their own; others are copied
+            // once the value is complete.
+            let mut value = Cow::Borrowed(val_bytes);
             if fold.is_some() {
                 let mut i = rest;
                 loop {
@@ -363,6 +371,7 @@ impl Parser {
                                 } else {
                                     self.remove_trailing(&mut value, &mut flags);
                                 }
+                                let value = value.into_owned();
                                 return Ok((rest, Value { value, flags }));
                             }
                             Err(Incomplete(_)) => {
@@ -377,11 +386,12 @@ impl Parser {
                             flags.set(other_flags);
                             //If the value is empty, the value started with a fold and we don't want to push back a space
                             if !value.is_empty() {
-                                value.push(b' ');
+                                value.to_mut().push(b' ');
                             }
-                            value.extend(val_bytes);
+                            value.to_mut().extend_from_slice(val_bytes);
                             if fold.is_none() {
                                 self.remove_trailing(&mut value, &mut flags);
+                                let value = value.into_owned();
                                 return Ok((rest, Value { value, flags }));
                             }
                         }
@@ -394,6 +404,7 @@ impl Parser {
                 } else {
                     self.remove_trailing(&mut value, &mut flags);
                 }
+                let value = value.into_owned();
                 Ok((rest, Value { value, flags }))
             }
         }
@@ -582,10 +593,26 @@ impl Parser {
     /// Parse multiple headers and indicate if end of headers or null was found
     pub fn headers(&self) -> impl Fn(&[u8]) -> IResult<&[u8], (Vec<Header>, bool)> + '_ {
         move |input| {
+            let (rest, (headers, eoh)) = self.headers_with_spans()(input)?;
+            Ok((
+                rest,
+                (headers.into_iter().map(|(head, _)| head).collect(), eoh),
+            ))
+        }
+    }
+
+    /// Same as headers, but also returns the range of the input each header was
+    /// parsed from, including its line terminator.
+    pub fn headers_with_spans(
+        &self,
+    ) -> impl Fn(&[u8]) -> IResult<&[u8], (Vec<HeaderSpan>, bool)> + '_ {
+        move |input| {
+            let span =
+                |start: &[u8], end: &[u8]| input.len() - start.len()..input.len() - end.len();
             let (rest, head) = self.header()(input)?;
             let is_null_terminated = head.value.flags.is_set(Flags::NULL_TERMINATED);
             let mut out = Vec::with_capacity(16);
-            out.push(head);
+            out.push((head, span(input, rest)));
             if is_null_terminated {
                 return Ok((rest, (out, true)));
             }
@@ -596,9 +623,9 @@ impl Parser {
             loop {
                 match self.header()(i) {
                     Ok((rest, head)) => {
-                        i = rest;
                         let is_null_terminated = head.value.flags.is_set(Flags::NULL_TERMINATED);
-                        out.push(head);
+                        out.push((head, span(i, rest)));
+                        i = rest;
                         if is_null_terminated {
                             return Ok((rest, (out, true)));
                         }
@@ -616,6 +643,16 @@ impl Parser {
     }
 }
 
+/// Returns the number of lines in the raw data of a header, counting folded lines.
+/// Bare CR line terminators are counted when there are no LFs.
+pub fn line_count(raw: &[u8]) -> usize {
+    let count = |terminator: u8| raw.iter().filter(|c| **c == terminator).count();
+    match count(b'\n') {
+        0 => std::cmp::max(count(b'\r'), 1),
+        lf => lf,
+    }
+}
+
 /// Parse one null character and return it and the NULL_TERMINATED flag
 fn null(input: &[u8]) -> IResult<&[u8], (&[u8], u64)> {
     map(complete_tag("\0"), |null| (null, Flags::NULL_TERMINATED))(input)
@@ -888,7 +925,7 @@ mod test {
             (
                 vec![
                     header!(b"k1", 0, b"v1", 0),
-                    header!(b"k2", 0, b"v2 before", Flags::NULL_TERMINATED),
+                    header!(b"k2", 0, b"v2 before\0v2 after", Flags::NULL_TERMINATED),
                 ],
                 true,
             ),
@@ -956,7 +993,7 @@ mod test {
                 (
                     vec![
                         header!(b"k1", 0, b"v1", 0),
-                        header!(b"k2", 0, b"v2", Flags::NULL_TERMINATED),
+                        header!(b"k2", 0, b"v2\0v2", Flags::NULL_TERMINATED),
                     ],
                     true,
                 ),
@@ -1125,7 +1162,7 @@ mod test {
             parser.header_with_colon()(b"K: V before\0 V after\r\n\r\n"),
             Ok((
                 b!("\r\n"),
-                header!(b"K", 0, b"V before", Flags::NULL_TERMINATED),
+                header!(b"K", 0, b"V before\0 V after", Flags::NULL_TERMINATED),
             ))
         );
         assert_eq!(
@@ -1245,7 +1282,7 @@ mod test {
         assert_header_result_eq!(
             Ok((
                 b!("\r\n"),
-                header!(b"K", 0, b"V before", Flags::NULL_TERMINATED),
+                header!(b"K", 0, b"V before\0 V after", Flags::NULL_TERMINATED),
             )),
             input,
             req_parser
@@ -1,18 +1,22 @@
 use crate::{
+    config::Config,
+    connection::Connection,
     connection_parser::ConnectionParser,
     error::Result,
     log::Log,
-    transaction::{Data, Transaction},
+    multipart::PartData,
+    transaction::{Data, Param, Transaction},
     util::FileData,
     HtpStatus,
 };
+use std::sync::Arc;
 
 /// External (C) callback function prototype
 pub type TxExternalCallbackFn =
     unsafe extern "C" fn(connp: *const ConnectionParser, tx: *mut Transaction) -> HtpStatus;
 
 /// Native (rust) callback function prototype
-pub type TxNativeCallbackFn = fn(tx: &mut Transaction) -> Result<()>;
+pub type TxNativeCallbackFn = dyn Fn(&mut Transaction) -> Result<()> + Send + Sync;
 
 /// Hook for Transaction
 pub type TxHook = Hook<TxExternalCallbackFn, TxNativeCallbackFn>;
@@ -22,7 +26,7 @@ pub type DataExternalCallbackFn =
     unsafe extern "C" fn(connp: *const ConnectionParser, data: *mut Data) -> HtpStatus;
 
 /// Native (rust) callback function prototype
-pub type DataNativeCallbackFn = fn(data: &mut Data) -> Result<()>;
+pub type DataNativeCallbackFn = dyn Fn(&mut Data) -> Result<()> + Send + Sync;
 
 /// Hook for Data
 pub type DataHook = Hook<DataExternalCallbackFn, DataNativeCallbackFn>;
@@ -31,28 +35,78 @@ pub type DataHook = Hook<DataExternalCallbackFn, DataNativeCallbackFn>;
 pub type FileDataExternalCallbackFn = unsafe extern "C" fn(data: *mut FileData) -> HtpStatus;
 
 /// Native (rust) callback function prototype
-pub type FileDataNativeCallbackFn = fn(data: &mut FileData) -> Result<()>;
+pub type FileDataNativeCallbackFn = dyn Fn(&mut FileData) -> Result<()> + Send + Sync;
 
 /// Hook for htp_tx_filedata_t
 pub type FileDataHook = Hook<FileDataExternalCallbackFn, FileDataNativeCallbackFn>;
 
+/// External (C) callback function prototype
+pub type PartDataExternalCallbackFn = unsafe extern "C" fn(data: *mut PartData) -> HtpStatus;
+
+/// Native (rust) callback function prototype
+pub type PartDataNativeCallbackFn = dyn Fn(&mut PartData) -> Result<()> + Send + Sync;
+
+/// Hook for PartData
+pub type PartDataHook = Hook<PartDataExternalCallbackFn, PartDataNativeCallbackFn>;
+
+/// External (C) callback function prototype
+pub type ParamExternalCallbackFn =
+    unsafe extern "C" fn(tx: *mut Transaction, param: *const Param) -> HtpStatus;
+
+/// Native (rust) callback function prototype
+pub type ParamNativeCallbackFn = dyn Fn(&mut Transaction, &Param) -> Result<()> + Send + Sync;
+
+/// Hook for Param
+pub type ParamHook = Hook<ParamExternalCallbackFn, ParamNativeCallbackFn>;
+
+/// External (C) callback function prototype
+pub type VhostSelectExternalCallbackFn =
+    unsafe extern "C" fn(connp: *const ConnectionParser, tx: *const Transaction) -> *const Config;
+
+/// Native (rust) callback function prototype
+pub type VhostSelectNativeCallbackFn = dyn Fn(&Transaction) -> Option<Arc<Config>> + Send + Sync;
+
+/// Hook for selecting the configuration of a virtual host
+pub type VhostSelectHook = Hook<VhostSelectExternalCallbackFn, VhostSelectNativeCallbackFn>;
+
 /// External (C) callback function prototype
 pub type LogExternalCallbackFn = unsafe extern "C" fn(log: *mut Log) -> HtpStatus;
 
 /// Native (rust) callback function prototype
-pub type LogNativeCallbackFn = fn(log: &mut Log) -> Result<()>;
+pub type LogNativeCallbackFn = dyn Fn(&mut Log) -> Result<()> + Send + Sync;
 
 /// Hook for Log
 pub type LogHook = Hook<LogExternalCallbackFn, LogNativeCallbackFn>;
 
+/// External (C) callback function prototype
+pub type ConnectionFlagExternalCallbackFn =
+    unsafe extern "C" fn(connp: *const ConnectionParser, flag: u8, tx_index: usize) -> HtpStatus;
+
+/// Native (rust) callback function prototype
+pub type ConnectionFlagNativeCallbackFn =
+    dyn Fn(&Connection, u8, usize) -> Result<()> + Send + Sync;
+
+/// Hook for connection flags
+pub type ConnectionFlagHook =
+    Hook<ConnectionFlagExternalCallbackFn, ConnectionFlagNativeCallbackFn>;
+
 /// Callback list
-#[derive(Clone)]
-pub struct Hook<E, N> {
+pub struct Hook<E, N: ?Sized> {
     /// List of all callbacks.
     pub callbacks: Vec<Callback<E, N>>,
 }
 
-impl<E, N> Default for Hook<E, N> {
+impl<E: Copy, N: ?Sized> Clone for Hook<E, N> {
+    /// Copies the callback list. Native callbacks, and any state they carry, are
+    /// shared with the copy.
+    fn clone(&self) -> Self {
+        Hook {
+            callbacks: self.callbacks.clone(),
+        }
+    }
+}
+
+impl<E, N: ?Sized> Default for Hook<E, N> {
     /// Create a new callback list
     fn default() -> Self {
         Hook {
@@ -60,10 +114,13 @@ impl<E, N> Default for Hook<E, N> {
         }
     }
 }
-impl<E, N> Hook<E, N> {
-    /// Register a native (rust) callback function
-    pub fn register(&mut self, cbk_fn: N) {
-        self.callbacks.push(Callback::Native(cbk_fn))
+impl<E, N: ?Sized> Hook<E, N> {
+    /// Register a native (rust) callback, which may be a closure carrying state.
+    /// The callback is shared by every copy of the hook, such as the copies held
+    /// by parsers created from the same configuration, and may run on several
+    /// threads at once: any state it keeps must use interior mutability.
+    pub fn register(&mut self, cbk_fn: Box<N>) {
+        self.callbacks.push(Callback::Native(Arc::from(cbk_fn)))
     }
 
     /// Register an external (C) callback function
@@ -75,40 +132,70 @@ impl<E, N> Hook<E, N> {
 impl TxHook {
     /// Run all callbacks on the list
     ///
-    /// This function will exit early if a callback fails to return HtpStatus::OK
-    /// or HtpStatus::DECLINED.
+    /// This function will exit early if a callback fails to return HtpStatus::OK,
+    /// HtpStatus::DECLINED or HtpStatus::PAUSE, which pauses the transaction.
     pub fn run_all(&self, connp: &ConnectionParser, tx: &mut Transaction) -> Result<()> {
         for cbk_fn in &self.callbacks {
             match cbk_fn {
                 Callback::External(cbk_fn) => {
                     let result = unsafe { cbk_fn(connp, tx) };
-                    if result != HtpStatus::OK && result != HtpStatus::DECLINED {
+                    if result == HtpStatus::PAUSE {
+                        tx.paused = true;
+                    } else if result != HtpStatus::OK && result != HtpStatus::DECLINED {
                         return Err(result);
                     }
                 }
-                Callback::Native(cbk_fn) => {
-                    if let Err(e) = cbk_fn(tx) {
-                        if e != HtpStatus::DECLINED {
-                            return Err(e);
-                        }
+                Callback::Native(cbk_fn) => match cbk_fn(tx) {
+                    Err(HtpStatus::PAUSE) => tx.paused = true,
+                    Err(e) if e != HtpStatus::DECLINED => return Err(e),
+                    _ => {}
+                },
+            };
+        }
+        Ok(())
+    }
+}
+
+impl DataHook {
+    /// Run all callbacks on the list
+    ///
+    /// This function will exit early if a callback fails to return HtpStatus::OK,
+    /// HtpStatus::DECLINED or HtpStatus::PAUSE, which pauses the transaction.
+    ///
+    /// The parser is taken as a raw pointer, as the Data usually borrows one of
+    /// its transactions; it is only handed on to external callbacks.
+    pub fn run_all(&self, connp: *const ConnectionParser, data: &mut Data) -> Result<()> {
+        for cbk_fn in &self.callbacks {
+            match cbk_fn {
+                Callback::External(cbk_fn) => {
+                    let result = unsafe { cbk_fn(connp, data) };
+                    if result == HtpStatus::PAUSE {
+                        data.pause();
+                    } else if result != HtpStatus::OK && result != HtpStatus::DECLINED {
+                        return Err(result);
                     }
                 }
+                Callback::Native(cbk_fn) => match cbk_fn(data) {
+                    Err(HtpStatus::PAUSE) => data.pause(),
+                    Err(e) if e != HtpStatus::DECLINED => return Err(e),
+                    _ => {}
+                },
             };
         }
         Ok(())
     }
 }
 
-impl DataHook {
+impl FileDataHook {
     /// Run all callbacks on the list
     ///
     /// This function will exit early if a callback fails to return HtpStatus::OK
     /// or HtpStatus::DECLINED.
-    pub fn run_all(&self, connp: &ConnectionParser, data: &mut Data) -> Result<()> {
+    pub fn run_all(&self, data: &mut FileData) -> Result<()> {
         for cbk_fn in &self.callbacks {
             match cbk_fn {
                 Callback::External(cbk_fn) => {
-                    let result = unsafe { cbk_fn(connp, data) };
+                    let result = unsafe { cbk_fn(data) };
                     if result != HtpStatus::OK && result != HtpStatus::DECLINED {
                         return Err(result);
                     }
@@ -126,12 +213,12 @@ impl DataHook {
     }
 }
 
-impl FileDataHook {
+impl PartDataHook {
     /// Run all callbacks on the list
     ///
     /// This function will exit early if a callback fails to return HtpStatus::OK
     /// or HtpStatus::DECLINED.
-    pub fn run_all(&self, data: &mut FileData) -> Result<()> {
+    pub fn run_all(&self, data: &mut PartData) -> Result<()> {
         for cbk_fn in &self.callbacks {
             match cbk_fn {
                 Callback::External(cbk_fn) => {
@@ -153,6 +240,54 @@ impl FileDataHook {
     }
 }
 
+impl ParamHook {
+    /// Run all callbacks on the list
+    ///
+    /// This function will exit early if a callback fails to return HtpStatus::OK,
+    /// HtpStatus::DECLINED or HtpStatus::PAUSE, which pauses the transaction.
+    pub fn run_all(&self, tx: &mut Transaction, param: &Param) -> Result<()> {
+        for cbk_fn in &self.callbacks {
+            match cbk_fn {
+                Callback::External(cbk_fn) => {
+                    let result = unsafe { cbk_fn(tx, param) };
+                    if result == HtpStatus::PAUSE {
+                        tx.paused = true;
+                    } else if result != HtpStatus::OK && result != HtpStatus::DECLINED {
+                        return Err(result);
+                    }
+                }
+                Callback::Native(cbk_fn) => match cbk_fn(tx, param) {
+                    Err(HtpStatus::PAUSE) => tx.paused = true,
+                    Err(e) if e != HtpStatus::DECLINED => return Err(e),
+                    _ => {}
+                },
+            };
+        }
+        Ok(())
+    }
+}
+
+impl VhostSelectHook {
+    /// Run the callbacks on the list until one of them selects a configuration.
+    ///
+    /// External callbacks return NULL to decline; the configuration they return
+    /// is copied.
+    pub fn run(&self, connp: &ConnectionParser, tx: &Transaction) -> Option<Arc<Config>> {
+        for cbk_fn in &self.callbacks {
+            let cfg = match cbk_fn {
+                Callback::External(cbk_fn) => {
+                    unsafe { cbk_fn(connp, tx).as_ref() }.map(|cfg| Arc::new(cfg.clone()))
+                }
+                Callback::Native(cbk_fn) => cbk_fn(tx),
+            };
+            if cfg.is_some() {
+                return cfg;
+            }
+        }
+        None
+    }
+}
+
 impl LogHook {
     /// Run all callbacks on the list
     ///
@@ -180,13 +315,48 @@ impl LogHook {
     }
 }
 
+impl ConnectionFlagHook {
+    /// Run all callbacks on the list
+    ///
+    /// This function will exit early if a callback fails to return HtpStatus::OK
+    /// or HtpStatus::DECLINED.
+    pub fn run_all(&self, connp: &ConnectionParser, flag: u8, tx_index: usize) -> Result<()> {
+        for cbk_fn in &self.callbacks {
+            match cbk_fn {
+                Callback::External(cbk_fn) => {
+                    let result = unsafe { cbk_fn(connp, flag, tx_index) };
+                    if result != HtpStatus::OK && result != HtpStatus::DECLINED {
+                        return Err(result);
+                    }
+                }
+                Callback::Native(cbk_fn) => {
+                    if let Err(e) = cbk_fn(&connp.conn, flag, tx_index) {
+                        if e != HtpStatus::DECLINED {
+                            return Err(e);
+                        }
+                    }
+                }
+            };
+        }
+        Ok(())
+    }
+}
+
 /// Type of callbacks
-#[derive(Copy, Clone)]
-pub enum Callback<E, N> {
+pub enum Callback<E, N: ?Sized> {
     /// External (C) callback function
     External(E),
-    /// Native (rust) callback function
-    Native(N),
+    /// Native (rust) callback
+    Native(Arc<N>),
+}
+
+impl<E: Copy, N: ?Sized> Clone for Callback<E, N> {
+    fn clone(&self) -> Self {
+        match self {
+            Callback::External(cbk_fn) => Callback::External(*cbk_fn),
+            Callback::Native(cbk_fn) => Callback::Native(Arc::clone(cbk_fn)),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -202,14 +372,11 @@ mod test {
         let connp = ConnectionParser::new(Config::default());
         let mut hook = DataHook::default();
 
-        hook.register(|_| Ok(()));
+        hook.register(Box::new(|_| Ok(())));
         hook.register_extern(foo);
 
-        assert!(hook
-            .run_all(
-                &connp,
-                &mut Data::new(std::ptr::null_mut(), &ParserData::default(), false)
-            )
-            .is_ok());
+        let data = ParserData::default();
+        let mut data = unsafe { Data::new(std::ptr::null_mut(), &data, false) };
+        assert!(hook.run_all(&connp, &mut data).is_ok());
     }
 }
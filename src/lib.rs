@@ -6,7 +6,7 @@
 #![allow(non_snake_case)]
 #![allow(non_upper_case_globals)]
 #[repr(C)]
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 
 /// Status codes used by LibHTP internally.
 /// cbindgen:rename-all=QualifiedScreamingSnakeCase
@@ -39,28 +39,40 @@ pub enum HtpStatus {
     /// Same as DATA, but indicates that any non-consumed part of the data chunk
     /// should be preserved (buffered) for later.
     DATA_BUFFER = 5,
+    /// Used by transaction, data and parameter callbacks to pause the transaction.
+    /// Parsing stops at the current position and continues when
+    /// ConnectionParser::resume() is called.
+    PAUSE = 6,
     /// The highest value LibHTP will use internally.
     STATUS_RESERVED = 1000,
 }
 
+pub use message::{parse_request_message, parse_response_message};
+
 /// Module for providing logging functions.
 #[macro_use]
 pub mod log;
+/// Module for batch processing of many connections.
+pub mod batch;
 /// Module for bstr functions.
 pub mod bstr;
 /// Module for all functions facing c_api.
 pub mod c_api;
 /// Module for all decompressors functions.
 pub mod decompressors;
+/// Module for differential parsing with two configurations.
+pub mod diff;
 /// Module for all errors.
 pub mod error;
+/// Module for the parser event stream.
+pub mod event;
 /// Module for header parsing.
 mod headers;
 /// Module for hooks.
 pub mod hook;
 /// Module for providing unicode bestfit mappings.
 #[macro_use]
-mod unicode_bestfit_map;
+pub mod unicode_bestfit_map;
 /// Module for libhtp configurations.
 pub mod config;
 /// Module for all connection.
@@ -69,6 +81,10 @@ pub mod connection;
 pub mod connection_parser;
 /// Module for custom list.
 pub mod list;
+/// Module for parsing standalone messages.
+pub mod message;
+/// Module for metrics aggregated across parsers.
+pub mod metrics;
 /// Module for multipart parsing.
 pub mod multipart;
 /// Module for extra utility parsers. (only public for doc tests)
@@ -81,8 +97,12 @@ mod request_generic;
 pub mod response;
 /// Module for response parsing.
 mod response_generic;
+/// Module for content sniffing.
+pub mod sniff;
 /// Module for custom table.
 pub mod table;
+/// Module for recording and replaying input segmentation.
+pub mod trace;
 /// Module for transaction parsing.
 pub mod transaction;
 /// Module to track multiple transactions
@@ -92,6 +112,6 @@ pub mod uri;
 /// Module for url parsing.
 pub mod urlencoded;
 /// Module for utf8 decoding.
-mod utf8_decoder;
+pub mod utf8_decoder;
 /// Module for utility functions.
 pub mod util;
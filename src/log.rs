@@ -1,5 +1,12 @@
-use crate::connection::Connection;
-use std::{net::IpAddr, sync::mpsc::Sender};
+use crate::{connection::Connection, event::Direction, transaction::TxId};
+use std::{
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::Sender,
+        Arc, Mutex,
+    },
+};
 
 /// Different codes used for logging.
 /// cbindgen:rename-all=QualifiedScreamingSnakeCase
@@ -160,6 +167,80 @@ pub enum HtpLogCode {
     COMPRESSION_BOMB_DOUBLE_LZMA,
     /// Invalid content-encoding detected.
     INVALID_CONTENT_ENCODING,
+    /// Sniffed response body content does not match the declared Content-Type.
+    RESPONSE_CONTENT_TYPE_MISMATCH,
+    /// Chunked body framing was lost in a gap and had to be resynchronised.
+    CHUNKED_RESYNC,
+    /// Stream does not carry HTTP.
+    NOT_HTTP,
+    /// Request body exceeded the configured limit.
+    REQUEST_BODY_LIMIT,
+    /// Response body exceeded the configured limit.
+    RESPONSE_BODY_LIMIT,
+    /// Data waiting for the other direction did not fit in the configured buffer.
+    DATA_OTHER_BUFFER_LIMIT,
+    /// Modified headers left the message framing ambiguous.
+    HEADER_MODIFICATION_CONFLICT,
+    /// The request parser skipped data to recover from an error.
+    REQUEST_RESYNC,
+    /// The outbound stream failed before the response to a CONNECT request was seen.
+    CONNECT_RESPONSE_UNAVAILABLE,
+    /// Request line without a protocol was not accepted as HTTP/0.9.
+    REQUEST_LINE_HTTP_0_9_REJECTED,
+    /// Request line with missing or misplaced tokens was repaired.
+    REQUEST_LINE_REPAIRED,
+    /// Request line tokens are separated by more than one SP, or by HTAB.
+    REQUEST_LINE_MULTIPLE_SPACES,
+    /// Request line has whitespace after the protocol.
+    REQUEST_LINE_TRAILING_WHITESPACE,
+    /// Response line contains NUL bytes.
+    RESPONSE_LINE_NUL,
+    /// Response status code is outside 100-599.
+    RESPONSE_LINE_STATUS_OUT_OF_RANGE,
+    /// Response body was not delimited the way its headers declared.
+    RESPONSE_FRAMING_MISMATCH,
+    /// Lines that are not a status line were skipped before the response line.
+    RESPONSE_LEADING_JUNK,
+    /// Response content encoding is not one the request's Accept-Encoding allows.
+    RESPONSE_CONTENT_ENCODING_NOT_ACCEPTED,
+    /// Request URI carries credentials in its userinfo.
+    URI_CREDENTIALS,
+    /// A file was not extracted because an extraction quota was exhausted.
+    FILE_EXTRACTION_QUOTA_EXCEEDED,
+    /// A file was not extracted because its storage backend could not be created.
+    FILE_EXTRACTION_FAILED,
+    /// Request cookies do not follow the RFC 6265 syntax (strict mode).
+    REQUEST_COOKIE_INVALID,
+    /// Request cookies were ignored because they exceed the configured limits.
+    REQUEST_COOKIE_LIMIT,
+    /// A response cookie does not meet the constraints of its __Secure- or __Host- prefix.
+    RESPONSE_COOKIE_PREFIX_VIOLATION,
+    /// Request headers or body conflict with the semantics of the request method.
+    REQUEST_METHOD_SEMANTICS,
+    /// Request Range header has overlapping, descending or too many ranges.
+    REQUEST_RANGE_ABUSE,
+    /// A trailer field carries message framing or routing information.
+    TRAILER_FORBIDDEN_FIELD,
+    /// Charset declared by the response body conflicts with the Content-Type charset.
+    RESPONSE_CHARSET_MISMATCH,
+    /// Request header value contains NUL bytes.
+    REQUEST_FIELD_RAW_NUL,
+    /// Response header value contains NUL bytes.
+    RESPONSE_FIELD_RAW_NUL,
+    /// Decoded path or parameter contains CR or LF.
+    CRLF_INJECTION,
+    /// Response body looks like it contains another response.
+    RESPONSE_SPLITTING,
+    /// Too many empty lines preceded a request line.
+    REQUEST_IGNORED_LINES_LIMIT,
+    /// Summary of the messages suppressed by the log limits.
+    LOG_SUPPRESSED,
+    /// Data was kept for later because its transaction is paused.
+    PARSER_PAUSED,
+    /// A gap was received while data was waiting for the other direction.
+    DATA_OTHER_GAP,
+    /// Data waiting for the other direction was dropped as its stream closed.
+    DATA_OTHER_DROPPED,
     /// Error retrieving a log message's code
     ERROR,
 }
@@ -184,6 +265,166 @@ pub enum HtpLogLevel {
     /// Designated very low priority, often extremely verbose, information.
     DEBUG2,
 }
+
+/// Number of messages logged at each level. Shared by all clones of a Logger, so
+/// the counts cover every message a connection issued, drained or not.
+#[derive(Debug, Default)]
+pub struct LogCounts([AtomicU64; 7]);
+
+impl LogCounts {
+    /// Returns the number of messages logged at the given level.
+    pub fn get(&self, level: HtpLogLevel) -> u64 {
+        self.0[level as usize].load(Ordering::Relaxed)
+    }
+
+    /// Counts a message logged at the given level.
+    fn increment(&self, level: HtpLogLevel) {
+        self.0[level as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Sets all counts back to zero.
+    fn reset(&self) {
+        for count in &self.0 {
+            count.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Per-connection limits on the messages a Logger keeps, with the number of
+/// messages suppressed for each HtpLogCode. Shared by all clones of a Logger.
+#[derive(Debug, Default)]
+pub struct LogLimits {
+    /// Maximum number of messages kept per code.
+    per_code: Option<u64>,
+    /// Maximum number of messages kept in total.
+    total: Option<u64>,
+    /// Counters, updated together.
+    state: Mutex<LogLimitsState>,
+}
+
+#[derive(Debug, Default)]
+struct LogLimitsState {
+    /// Messages kept in total.
+    kept: u64,
+    /// Messages kept, suppressed and already summarized, per code.
+    codes: Vec<LogCodeCount>,
+}
+
+#[derive(Debug)]
+struct LogCodeCount {
+    code: HtpLogCode,
+    kept: u64,
+    suppressed: u64,
+    summarized: u64,
+}
+
+impl LogLimits {
+    /// Creates limits keeping at most `per_code` messages of each code and `total`
+    /// messages overall. None means unlimited.
+    pub fn new(per_code: Option<u64>, total: Option<u64>) -> Self {
+        Self {
+            per_code,
+            total,
+            state: Mutex::new(LogLimitsState::default()),
+        }
+    }
+
+    /// Returns the number of messages with the given code that were suppressed.
+    pub fn suppressed(&self, code: HtpLogCode) -> u64 {
+        self.state.lock().map_or(0, |state| {
+            state
+                .codes
+                .iter()
+                .find(|count| count.code == code)
+                .map_or(0, |count| count.suppressed)
+        })
+    }
+
+    /// Returns the number of messages that were suppressed, across all codes.
+    pub fn suppressed_total(&self) -> u64 {
+        self.state.lock().map_or(0, |state| {
+            state.codes.iter().map(|count| count.suppressed).sum()
+        })
+    }
+
+    /// Decides whether a message with the given code is kept, counting it either way.
+    fn admit(&self, code: HtpLogCode) -> bool {
+        if self.per_code.is_none() && self.total.is_none() {
+            return true;
+        }
+        let mut state = if let Ok(state) = self.state.lock() {
+            state
+        } else {
+            return true;
+        };
+        let total_reached = matches!(self.total, Some(total) if state.kept >= total);
+        let index = if let Some(index) = state.codes.iter().position(|count| count.code == code) {
+            index
+        } else {
+            state.codes.push(LogCodeCount {
+                code,
+                kept: 0,
+                suppressed: 0,
+                summarized: 0,
+            });
+            state.codes.len() - 1
+        };
+        let count = &mut state.codes[index];
+        if total_reached || matches!(self.per_code, Some(limit) if count.kept >= limit) {
+            count.suppressed += 1;
+            return false;
+        }
+        count.kept += 1;
+        state.kept += 1;
+        true
+    }
+
+    /// Returns the codes with messages suppressed since the previous call, and how many.
+    fn take_unsummarized(&self) -> Vec<(HtpLogCode, u64)> {
+        self.state.lock().map_or_else(
+            |_| Vec::new(),
+            |mut state| {
+                state
+                    .codes
+                    .iter_mut()
+                    .filter(|count| count.suppressed > count.summarized)
+                    .map(|count| {
+                        let new = count.suppressed - count.summarized;
+                        count.summarized = count.suppressed;
+                        (count.code, new)
+                    })
+                    .collect()
+            },
+        )
+    }
+
+    /// Sets all counters back to zero.
+    fn reset(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            *state = LogLimitsState::default();
+        }
+    }
+}
+
+/// Where in the connection a message was logged.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LogPosition {
+    /// Direction of the data being parsed.
+    pub direction: Direction,
+    /// Transaction the data belongs to.
+    pub tx_id: Option<TxId>,
+    /// Offset of the parser in the stream of that direction. Only known for
+    /// messages logged by the parser itself, rather than by a transaction.
+    pub offset: Option<u64>,
+}
+
+/// Implemented by the types that log through their `logger` field, to describe
+/// where in the connection they are when a message is logged.
+pub trait LogContext {
+    /// Returns the position to attach to a message logged now.
+    fn log_position(&self) -> Option<LogPosition>;
+}
+
 #[derive(Clone)]
 /// Logger struct
 pub struct Logger {
@@ -192,6 +433,14 @@ pub struct Logger {
     /// Log level used when deciding whether to store or
     /// ignore the messages issued by the parser.
     pub level: HtpLogLevel,
+    /// Number of messages sent at each level.
+    pub counts: Arc<LogCounts>,
+    /// Limits on the messages sent, and counts of those suppressed.
+    pub limits: Arc<LogLimits>,
+    /// The most recent error, kept to describe a stream failure.
+    last_error: Arc<Mutex<Option<Message>>>,
+    /// Position attached to the messages logged through `log`.
+    position: Option<LogPosition>,
 }
 
 impl Logger {
@@ -200,8 +449,58 @@ impl Logger {
         Self {
             sender: sender.clone(),
             level,
+            counts: Arc::new(LogCounts::default()),
+            limits: Arc::new(LogLimits::default()),
+            last_error: Arc::new(Mutex::new(None)),
+            position: None,
+        }
+    }
+
+    /// Returns a clone of this logger that attaches the given position to the
+    /// messages logged through it, for handing to the parsing helpers.
+    pub(crate) fn at(&self, position: Option<LogPosition>) -> Logger {
+        Logger {
+            position,
+            ..self.clone()
+        }
+    }
+
+    /// Takes the most recent error message logged through this logger or any of
+    /// its clones, regardless of the configured log level.
+    pub(crate) fn take_last_error(&self) -> Option<Message> {
+        self.last_error.lock().ok().and_then(|mut last| last.take())
+    }
+
+    /// Clears the message counts and the most recent error, for this logger and
+    /// all of its clones.
+    pub(crate) fn reset(&self) {
+        self.counts.reset();
+        self.limits.reset();
+        self.take_last_error();
+    }
+
+    /// Logs a summary of the messages suppressed by the log limits since the
+    /// previous summary, if any. The summary itself is never suppressed.
+    pub(crate) fn log_suppressed_summary(&mut self) {
+        let suppressed = self.limits.take_unsummarized();
+        if suppressed.is_empty() || HtpLogLevel::WARNING > self.level {
+            return;
         }
+        let total: u64 = suppressed.iter().map(|(_, count)| count).sum();
+        let codes: Vec<String> = suppressed
+            .iter()
+            .map(|(code, count)| format!("{:?} ({})", code, count))
+            .collect();
+        self.counts.increment(HtpLogLevel::WARNING);
+        let _ = self.sender.send(Message::new(
+            file!(),
+            line!(),
+            HtpLogLevel::WARNING,
+            HtpLogCode::LOG_SUPPRESSED,
+            format!("Suppressed {} log messages: {}", total, codes.join(", ")),
+        ));
     }
+
     /// Logs a message to the logger channel.
     pub fn log(
         &mut self,
@@ -211,9 +510,30 @@ impl Logger {
         code: HtpLogCode,
         msg: String,
     ) {
-        // Ignore messages below our log level.
-        if level <= self.level {
-            let _ = self.sender.send(Message::new(file, line, level, code, msg));
+        self.log_at(self.position, file, line, level, code, msg)
+    }
+
+    /// Logs a message issued at the given position to the logger channel.
+    pub fn log_at(
+        &mut self,
+        position: Option<LogPosition>,
+        file: &str,
+        line: u32,
+        level: HtpLogLevel,
+        code: HtpLogCode,
+        msg: String,
+    ) {
+        let mut message = Message::new(file, line, level, code, msg);
+        message.position = position;
+        if level == HtpLogLevel::ERROR {
+            if let Ok(mut last) = self.last_error.lock() {
+                *last = Some(message.clone());
+            }
+        }
+        // Ignore messages below our log level, and those over the limits.
+        if level <= self.level && self.limits.admit(code) {
+            self.counts.increment(level);
+            let _ = self.sender.send(message);
         }
     }
 }
@@ -231,6 +551,8 @@ pub struct Message {
     pub file: String,
     /// Line number on which the code that emitted the message resides.
     pub line: u32,
+    /// Where in the connection the message was logged, if known.
+    pub position: Option<LogPosition>,
 }
 
 impl Message {
@@ -248,6 +570,7 @@ impl Message {
             level,
             code,
             msg,
+            position: None,
         }
     }
 }
@@ -281,9 +604,17 @@ impl Log {
     }
 }
 
-/// Logs a message at the given level.
+/// Logs a message at the given level. When the logger is the `logger` field of a
+/// LogContext, the position of that context is attached to the message.
 #[macro_export]
 macro_rules! htp_log {
+    ($owner:ident . logger, $level:expr, $code:expr, $msg:expr) => {{
+        use $crate::log::{HtpLogCode, HtpLogLevel, LogContext};
+        let position = $owner.log_position();
+        $owner
+            .logger
+            .log_at(position, file!(), line!(), $level, $code, $msg.to_string());
+    }};
     ($logger:expr, $level:expr, $code:expr, $msg:expr) => {{
         use $crate::log::{HtpLogCode, HtpLogLevel};
         $logger.log(file!(), line!(), $level, $code, $msg.to_string());
@@ -293,6 +624,9 @@ macro_rules! htp_log {
 /// Logs a message at the info level.
 #[macro_export]
 macro_rules! htp_info {
+    ($owner:ident . logger, $code:expr, $msg:expr) => {
+        htp_log!($owner.logger, HtpLogLevel::INFO, $code, $msg);
+    };
     ($logger:expr, $code:expr, $msg:expr) => {
         htp_log!($logger, HtpLogLevel::INFO, $code, $msg);
     };
@@ -301,6 +635,9 @@ macro_rules! htp_info {
 /// Logs a message at the debug level.
 #[macro_export]
 macro_rules! htp_debug {
+    ($owner:ident . logger, $code:expr, $msg:expr) => {
+        htp_log!($owner.logger, HtpLogLevel::DEBUG, $code, $msg);
+    };
     ($logger:expr, $code:expr, $msg:expr) => {
         htp_log!($logger, HtpLogLevel::DEBUG, $code, $msg);
     };
@@ -309,6 +646,9 @@ macro_rules! htp_debug {
 /// Logs a message at the warning level.
 #[macro_export]
 macro_rules! htp_warn {
+    ($owner:ident . logger, $code:expr, $msg:expr) => {
+        htp_log!($owner.logger, HtpLogLevel::WARNING, $code, $msg);
+    };
     ($logger:expr, $code:expr, $msg:expr) => {
         htp_log!($logger, HtpLogLevel::WARNING, $code, $msg);
     };
@@ -317,6 +657,9 @@ macro_rules! htp_warn {
 /// Logs a message at the error level.
 #[macro_export]
 macro_rules! htp_error {
+    ($owner:ident . logger, $code:expr, $msg:expr) => {
+        htp_log!($owner.logger, HtpLogLevel::ERROR, $code, $msg);
+    };
     ($logger:expr, $code:expr, $msg:expr) => {
         htp_log!($logger, HtpLogLevel::ERROR, $code, $msg);
     };
@@ -325,6 +668,14 @@ macro_rules! htp_error {
 /// Logs a message at the warning level, ensuring that it ones logs the message once.
 #[macro_export]
 macro_rules! htp_warn_once {
+    ($owner:ident . logger, $code:expr, $msg:expr, $tx_flags:expr, $flags:expr, $flag:expr) => {
+        // Log only once per transaction.
+        if !$tx_flags.is_set($flag) {
+            htp_warn!($owner.logger, $code, $msg);
+        }
+        $tx_flags.set($flag);
+        $flags.set($flag);
+    };
     ($logger:expr, $code:expr, $msg:expr, $tx_flags:expr, $flags:expr, $flag:expr) => {
         // Log only once per transaction.
         if !$tx_flags.is_set($flag) {
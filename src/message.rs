@@ -0,0 +1,60 @@
+//! Parsing of single HTTP messages that are not part of a connection, for
+//! example a request taken from a WAF log.
+
+use crate::{
+    config::Config,
+    connection_parser::{ConnectionParser, HtpStreamState},
+    error::Result,
+    transaction::Transaction,
+    HtpStatus,
+};
+
+/// Parse a complete request message, running the same header, URI and parameter
+/// processing as connection parsing. Any data following the first request is ignored.
+///
+/// Returns the parsed transaction, or HtpStatus::ERROR if the data could not be parsed
+/// as a request.
+pub fn parse_request_message(data: &[u8], cfg: &Config) -> Result<Transaction> {
+    let mut connp = message_parser(cfg);
+    if !data.is_empty() && connp.request_data(data.into(), None) == HtpStreamState::ERROR {
+        return Err(HtpStatus::ERROR);
+    }
+    finish(connp)
+}
+
+/// Parse a complete response message. Since there is no request, the response is
+/// parsed as the response to an unknown request, and a response body without a
+/// Content-Length ends with the data. Any data following the first response is ignored.
+///
+/// Returns the parsed transaction, or HtpStatus::ERROR if the data could not be parsed
+/// as a response.
+pub fn parse_response_message(data: &[u8], cfg: &Config) -> Result<Transaction> {
+    let mut connp = message_parser(cfg);
+    if !data.is_empty() && connp.response_data(data.into(), None) == HtpStreamState::ERROR {
+        return Err(HtpStatus::ERROR);
+    }
+    finish(connp)
+}
+
+fn message_parser(cfg: &Config) -> ConnectionParser {
+    let mut cfg = cfg.clone();
+    // The transaction is handed to the caller.
+    cfg.set_tx_auto_destroy(false);
+    let mut connp = ConnectionParser::new(cfg);
+    connp.open(None, None, None, None, None);
+    connp
+}
+
+fn finish(mut connp: ConnectionParser) -> Result<Transaction> {
+    // Closing the stream completes a message that is delimited by the end of the data.
+    connp.close(None);
+    if connp.request_status == HtpStreamState::ERROR
+        || connp.response_status == HtpStreamState::ERROR
+    {
+        return Err(HtpStatus::ERROR);
+    }
+    match connp.remove_tx(0) {
+        Some(tx) if tx.is_started() => Ok(tx),
+        _ => Err(HtpStatus::ERROR),
+    }
+}
@@ -0,0 +1,168 @@
+//! Counters aggregated across connection parsers.
+//!
+//! A single `Metrics` registry is shared by every parser created from a Config
+//! with Config::set_metrics(), so a monitoring deployment can export totals for
+//! all of its connections without registering callbacks.
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, MutexGuard,
+    },
+    time::Instant,
+};
+
+/// Registry of counters shared by many connection parsers.
+#[derive(Debug)]
+pub struct Metrics {
+    /// Number of transactions started.
+    transactions_started: AtomicU64,
+    /// Number of transactions that completed.
+    transactions_completed: AtomicU64,
+    /// Number of times decompression was aborted as a compression bomb.
+    compression_bombs: AtomicU64,
+    /// Number of completed transactions flagged as request smuggling.
+    request_smuggling: AtomicU64,
+    /// Start time and completed count of the current rate interval.
+    rate_start: Mutex<(Instant, u64)>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            transactions_started: AtomicU64::new(0),
+            transactions_completed: AtomicU64::new(0),
+            compression_bombs: AtomicU64::new(0),
+            request_smuggling: AtomicU64::new(0),
+            rate_start: Mutex::new((Instant::now(), 0)),
+        }
+    }
+}
+
+impl Metrics {
+    /// Returns a new, empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the current counter values, without changing them. The transaction
+    /// rate is computed over the time since the last call to reset_rate(), or since
+    /// the registry was created.
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let started = self.transactions_started.load(Ordering::Relaxed);
+        let completed = self.transactions_completed.load(Ordering::Relaxed);
+        let (start, start_completed) = *self.rate_start();
+        let elapsed = start.elapsed().as_secs_f64();
+        let transactions_per_second = if elapsed > 0.0 {
+            completed.saturating_sub(start_completed) as f64 / elapsed
+        } else {
+            0.0
+        };
+        MetricsSnapshot {
+            transactions_active: started.saturating_sub(completed),
+            transactions_started: started,
+            transactions_completed: completed,
+            transactions_per_second,
+            compression_bombs: self.compression_bombs.load(Ordering::Relaxed),
+            request_smuggling: self.request_smuggling.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Starts a new interval for the transaction rate reported by later snapshots,
+    /// for example after exporting one.
+    pub fn reset_rate(&self) {
+        let completed = self.transactions_completed.load(Ordering::Relaxed);
+        *self.rate_start() = (Instant::now(), completed);
+    }
+
+    fn rate_start(&self) -> MutexGuard<'_, (Instant, u64)> {
+        self.rate_start
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub(crate) fn transaction_started(&self) {
+        self.transactions_started.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn transaction_completed(&self) {
+        self.transactions_completed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn compression_bomb(&self) {
+        self.compression_bombs.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn request_smuggling(&self) {
+        self.request_smuggling.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Point-in-time copy of the counters in a Metrics registry. Formatting it with
+/// Display produces the Prometheus text exposition format.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MetricsSnapshot {
+    /// Number of transactions started and not yet completed. Transactions of
+    /// streams that failed or were closed early are never completed.
+    pub transactions_active: u64,
+    /// Number of transactions started.
+    pub transactions_started: u64,
+    /// Number of transactions that completed.
+    pub transactions_completed: u64,
+    /// Completed transactions per second since the rate was last reset.
+    pub transactions_per_second: f64,
+    /// Number of times decompression was aborted as a compression bomb.
+    pub compression_bombs: u64,
+    /// Number of completed transactions flagged as request smuggling.
+    pub request_smuggling: u64,
+}
+
+impl fmt::Display for MetricsSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let metrics: [(&str, &str, &str, f64); 6] = [
+            (
+                "htp_transactions_active",
+                "gauge",
+                "Transactions started and not yet completed.",
+                self.transactions_active as f64,
+            ),
+            (
+                "htp_transactions_started_total",
+                "counter",
+                "Transactions started.",
+                self.transactions_started as f64,
+            ),
+            (
+                "htp_transactions_completed_total",
+                "counter",
+                "Transactions completed.",
+                self.transactions_completed as f64,
+            ),
+            (
+                "htp_transactions_per_second",
+                "gauge",
+                "Completed transactions per second since the rate was last reset.",
+                self.transactions_per_second,
+            ),
+            (
+                "htp_compression_bombs_total",
+                "counter",
+                "Decompressions aborted as compression bombs.",
+                self.compression_bombs as f64,
+            ),
+            (
+                "htp_request_smuggling_total",
+                "counter",
+                "Completed transactions flagged as request smuggling.",
+                self.request_smuggling as f64,
+            ),
+        ];
+        for (name, kind, help, value) in metrics.iter() {
+            writeln!(f, "# HELP {} {}", name, help)?;
+            writeln!(f, "# TYPE {} {}", name, kind)?;
+            writeln!(f, "{} {}", name, value)?;
+        }
+        Ok(())
+    }
+}
@@ -1,22 +1,23 @@
 use crate::{
     bstr::Bstr,
-    config::{Config, MultipartConfig},
+    config::{Config, HtpMultipartQuoting, MultipartConfig},
     error::Result,
     headers::{Flags as HeaderFlags, Parser as HeadersParser, Side},
-    hook::FileDataHook,
+    hook::{FileDataHook, PartDataHook},
     list::List,
-    parsers::parse_content_type,
+    parsers::{decode_ext_value, parse_content_type},
     table::Table,
     transaction::{Header, Headers},
     util::{
-        is_space, take_ascii_whitespace, take_is_space, take_until_no_case, File, FlagOperations,
-        HtpFileSource,
+        is_space, take_ascii_whitespace, take_is_space, take_until_no_case, ExtractionQuota, File,
+        FileFlags, FlagOperations, HtpFileSource,
     },
     HtpStatus,
 };
+use bstr::{ByteSlice, Finder};
 use nom::{
     branch::alt,
-    bytes::complete::{tag, tag_no_case, take, take_till, take_until, take_while},
+    bytes::complete::{tag, tag_no_case, take, take_till, take_until, take_while, take_while1},
     character::complete::char,
     character::is_space as nom_is_space,
     combinator::{map, not, opt, peek},
@@ -25,7 +26,7 @@ use nom::{
     sequence::tuple,
     IResult,
 };
-use std::rc::Rc;
+use std::sync::Arc;
 
 /// Export Multipart flags.
 #[derive(Debug)]
@@ -102,11 +103,22 @@ impl Flags {
     pub const PART_INCOMPLETE: u64 = 0x20_0000;
     /// A NUL byte was seen in a part header area.
     pub const NUL_BYTE: u64 = 0x40_0000;
+    /// Content-Disposition part header with both the filename and filename* parameters.
+    pub const CD_FILENAME_EXT: u64 = 0x80_0000;
+    /// Content-Disposition extended (RFC 5987) parameter value could not be decoded.
+    pub const CD_PARAM_EXT_INVALID: u64 = 0x100_0000;
+    /// Content-Disposition part header parameter values would be un-escaped differently
+    /// depending on which browser produced them.
+    pub const CD_QUOTING_AMBIGUOUS: u64 = 0x200_0000;
+    /// The value of a part was longer than MultipartConfig::part_value_limit and was
+    /// truncated.
+    pub const PART_VALUE_TRUNCATED: u64 = 0x400_0000;
     /// A collection of flags that all indicate an invalid C-D header.
     pub const CD_INVALID: u64 = (Self::CD_TYPE_INVALID
         | Self::CD_PARAM_REPEATED
         | Self::CD_PARAM_UNKNOWN
-        | Self::CD_SYNTAX_INVALID);
+        | Self::CD_SYNTAX_INVALID
+        | Self::CD_PARAM_EXT_INVALID);
     /// A collection of flags that all indicate an invalid part.
     pub const PART_INVALID: u64 = (Self::CD_INVALID
         | Self::NUL_BYTE
@@ -123,6 +135,8 @@ impl Flags {
     /// A collection of flags that all indicate an unusual Multipart payload.
     pub const UNUSUAL: u64 = (Self::INVALID
         | Self::PART_HEADER_FOLDING
+        | Self::CD_FILENAME_EXT
+        | Self::CD_QUOTING_AMBIGUOUS
         | Self::BBOUNDARY_NLWS_AFTER
         | Self::HAS_EPILOGUE
         | Self::HBOUNDARY_UNUSUAL
@@ -141,8 +155,17 @@ pub struct Parser {
     pub cfg: MultipartConfig,
     /// Request file data hook invoked whenever file data is available.
     pub hook: FileDataHook,
+    /// Request part data hook invoked whenever streamed text part data is available.
+    pub part_hook: PartDataHook,
     /// Number of extracted files.
     pub file_count: u32,
+    /// Number of file bytes extracted from this request so far.
+    pub extracted_len: usize,
+    /// Quotas extracted file data is counted against, besides the request limit.
+    pub quotas: Vec<Arc<ExtractionQuota>>,
+    /// EXTRACTION_SKIPPED and EXTRACTION_FAILED file flags raised since they were
+    /// last taken.
+    extraction_flags: u64,
     // Internal parsing fields; move into a private structure
     /// Parser state; one of MULTIPART_STATE_* constants.
     parser_state: HtpMultipartState,
@@ -151,6 +174,9 @@ pub struct Parser {
     /// When this field reaches boundary_len, we have a boundary match.
     pub boundary_match_pos: usize,
 
+    /// Searches part data for a line ending followed by the boundary.
+    boundary_finder: Finder<'static>,
+
     /// Index of part that is currently being processed.
     pub current_part_idx: Option<usize>,
 
@@ -196,6 +222,9 @@ pub struct Parser {
     /// effectively being buffered. This is probably a case of premature
     /// optimization, but I am going to leave it in for now.
     pub cr_aside: bool,
+
+    /// Number of parts already returned by take_completed_parts().
+    taken_parts: usize,
 }
 
 /// Creates a new multipart/form-data parser.
@@ -204,7 +233,7 @@ pub struct Parser {
 /// Returns New parser instance
 impl Parser {
     /// Create new Parser with `Config`, boundary data and flags.
-    pub fn new(cfg: &Rc<Config>, boundary: &[u8], flags: u64) -> Self {
+    pub fn new(cfg: &Arc<Config>, boundary: &[u8], flags: u64) -> Self {
         Self {
             multipart: Multipart {
                 boundary_len: boundary.len() + 2,
@@ -215,13 +244,18 @@ impl Parser {
             },
             cfg: cfg.multipart_cfg.clone(),
             hook: cfg.hook_request_file_data.clone(),
+            part_hook: cfg.hook_request_part_data.clone(),
             file_count: 0,
+            extracted_len: 0,
+            quotas: Vec::new(),
+            extraction_flags: 0,
             // We're starting in boundary-matching mode. The first boundary can appear without the
             // CRLF, and our starting state expects that. If we encounter non-boundary data, the
             // state will switch to data mode. Then, if the data is CRLF or LF, we will go back
             // to boundary matching. Thus, we handle all the possibilities.
             parser_state: HtpMultipartState::BOUNDARY,
             boundary_match_pos: 0,
+            boundary_finder: Finder::new(&[b"\n--", boundary].concat()).into_owned(),
             current_part_idx: None,
             current_part_mode: HtpMultipartMode::LINE,
             boundary_candidate: Bstr::with_capacity(boundary.len()),
@@ -231,9 +265,30 @@ impl Parser {
             part_data_pieces: Bstr::with_capacity(64),
             boundary_candidate_pos: 0,
             cr_aside: false,
+            taken_parts: 0,
         }
     }
 
+    /// Returns the FileFlags::EXTRACTION_SKIPPED and FileFlags::EXTRACTION_FAILED
+    /// flags raised by files since the last call.
+    pub fn take_extraction_flags(&mut self) -> u64 {
+        std::mem::take(&mut self.extraction_flags)
+    }
+
+    /// Returns the indices of the parts that have been completed since the last
+    /// call. Once parsing is finalized, all remaining parts are returned.
+    pub fn take_completed_parts(&mut self, finalized: bool) -> std::ops::Range<usize> {
+        let end = if finalized {
+            self.multipart.parts.len()
+        } else {
+            self.current_part_idx
+                .unwrap_or_else(|| self.multipart.parts.len())
+        };
+        let start = self.taken_parts.min(end);
+        self.taken_parts = end.max(self.taken_parts);
+        start..end
+    }
+
     /// Returns the part currently being processed.
     pub fn get_current_part(&mut self) -> Result<&mut Part> {
         self.current_part_idx
@@ -300,7 +355,7 @@ impl Parser {
         if self.multipart.flags.is_set(Flags::SEEN_LAST_BOUNDARY)
             && self.get_current_part()?.type_0 == HtpMultipartType::UNKNOWN
         {
-            self.part_data_pieces.add(to_consume);
+            self.buffer_part_data(to_consume);
         }
         if self.current_part_mode == HtpMultipartMode::LINE {
             // Line mode.
@@ -342,7 +397,12 @@ impl Parser {
                     self.current_part_mode = HtpMultipartMode::DATA;
                     self.part_header.clear();
                     let file_count = self.file_count;
+                    let remaining = self
+                        .cfg
+                        .extract_request_bytes_limit
+                        .map(|limit| limit.saturating_sub(self.extracted_len));
                     let cfg = self.cfg.clone();
+                    let quotas = self.quotas.clone();
                     let part = self.get_current_part()?;
                     match &mut part.file {
                         Some(file) => {
@@ -351,7 +411,13 @@ impl Parser {
                             if cfg.extract_request_files
                                 && file_count < cfg.extract_request_files_limit
                             {
-                                file.create(&cfg.tmpdir)?;
+                                // Failures are flagged on the file and reported by
+                                // the transaction.
+                                let _ = file.create(&cfg, remaining, &quotas);
+                                let flags = file.flags
+                                    & (FileFlags::EXTRACTION_SKIPPED
+                                        | FileFlags::EXTRACTION_FAILED);
+                                self.extraction_flags.set(flags);
                                 self.file_count += 1;
                             }
                         }
@@ -374,6 +440,7 @@ impl Parser {
             }
         } else {
             // Data mode; keep the data chunk for later (but not if it is a file).
+            let stream_text_parts = self.cfg.stream_text_parts;
             match self.get_current_part()?.type_0 {
                 HtpMultipartType::FILE => {
                     // Invoke file data callbacks.
@@ -384,15 +451,35 @@ impl Parser {
                         return file.write(to_consume);
                     }
                 }
+                HtpMultipartType::TEXT if stream_text_parts => {
+                    // Invoke part data callbacks instead of keeping the data.
+                    // Ignore error.
+                    let _ = self.run_request_part_data_hook(to_consume);
+                }
                 _ => {
                     // Make a copy of the data in RAM.
-                    self.part_data_pieces.add(to_consume);
+                    self.buffer_part_data(to_consume);
                 }
             }
         }
         Ok(())
     }
 
+    /// Keeps non-file part data until the part is finalized, truncating it at the
+    /// part value limit.
+    fn buffer_part_data(&mut self, data: &[u8]) {
+        let allowed = match self.cfg.part_value_limit {
+            Some(limit) => limit
+                .saturating_sub(self.part_data_pieces.len())
+                .min(data.len()),
+            None => data.len(),
+        };
+        if allowed < data.len() {
+            self.multipart.flags.set(Flags::PART_VALUE_TRUNCATED);
+        }
+        self.part_data_pieces.add(&data[..allowed]);
+    }
+
     /// Processes set-aside data.
     fn process_aside(&mut self, matched: bool) {
         // The stored data pieces can contain up to one line. If we're in data mode and there
@@ -507,6 +594,19 @@ impl Parser {
             // Notify callbacks about the end of the file.
             // Ignore result.
             let _ = self.run_request_file_data_hook(true);
+            // Account for the stored data in the request quota.
+            let mut stored_len = 0;
+            if let Some(file) = &mut self.get_current_part()?.file {
+                file.finalize_hashes();
+                stored_len = file.stored_len;
+            }
+            self.extracted_len += stored_len;
+        } else if self.get_current_part()?.type_0 == HtpMultipartType::TEXT
+            && self.cfg.stream_text_parts
+        {
+            // Notify callbacks about the end of the part.
+            // Ignore result.
+            let _ = self.run_request_part_data_hook(b"");
         } else if !self.part_data_pieces.is_empty() {
             let data = self.part_data_pieces.clone();
             self.get_current_part()?.value.clear();
@@ -524,6 +624,7 @@ impl Parser {
     /// Handle part data. This function will also buffer a CR character if
     /// it is the last byte in the buffer.
     fn parse_state_data<'a>(&mut self, input: &'a [u8]) -> &'a [u8] {
+        let input = self.skip_part_data(input);
         if let Ok((remaining, mut consumed)) = take_till::<_, _, (&[u8], nom::error::ErrorKind)>(
             |c: u8| c == b'\r' || c == b'\n',
         )(input)
@@ -584,6 +685,38 @@ impl Parser {
         }
     }
 
+    /// While in the data of a part, hands everything up to the next line ending that
+    /// could precede a boundary to the part in one piece, instead of line by line.
+    /// Returns the rest of the input, which is then parsed as usual.
+    fn skip_part_data<'a>(&mut self, input: &'a [u8]) -> &'a [u8] {
+        if self.cr_aside
+            || self.current_part_idx.is_none()
+            || self.current_part_mode != HtpMultipartMode::DATA
+        {
+            return input;
+        }
+        let mut end = match self.boundary_finder.find(input) {
+            Some(pos) => pos,
+            // Leave enough to match a boundary that continues in the next chunk.
+            None => input
+                .len()
+                .saturating_sub(self.boundary_finder.needle().len()),
+        };
+        // Keep CRLF together so that the line ending is seen as such.
+        if end > 0 && input[end - 1] == b'\r' {
+            end -= 1;
+        }
+        if end == 0 {
+            return input;
+        }
+        let (data, rest) = input.split_at(end);
+        self.multipart.flags.set(line_flags(data));
+        self.to_consume.add(data);
+        // Ignore result.
+        let _ = self.handle_data(false);
+        rest
+    }
+
     /// Handle possible boundary.
     fn parse_state_boundary<'a>(&mut self, input: &'a [u8]) -> &'a [u8] {
         if self.multipart.boundary.len() < self.boundary_match_pos {
@@ -762,7 +895,7 @@ impl Parser {
                 if let Some((_, h_existing)) = self
                     .get_current_part()?
                     .headers
-                    .get_nocase_mut(header.name.as_slice())
+                    .get_nocase_value_mut(header.name.as_slice())
                 {
                     h_existing.value.extend_from_slice(b", ");
                     h_existing.value.extend_from_slice(header.value.as_slice());
@@ -791,10 +924,32 @@ impl Parser {
     /// Returns OK on success (header found and parsed), DECLINED if there is no C-D header or if
     ///         it could not be processed, and ERROR on fatal error.
     pub fn parse_c_d(&mut self) -> Result<()> {
+        let compute_file_hashes = self.cfg.compute_file_hashes;
+        let quoting = self.cfg.cd_quoting;
+        if quoting == HtpMultipartQuoting::DETECT {
+            // Flag headers that browsers would not all have produced from the same values.
+            if let Some((_, header)) = self
+                .get_current_part()?
+                .headers
+                .get_nocase_nozero("content-disposition")
+            {
+                let value = header.value.as_slice();
+                let generic = content_disposition(value, quoting).ok();
+                if [HtpMultipartQuoting::FIREFOX, HtpMultipartQuoting::CHROME]
+                    .iter()
+                    .any(|quoting| content_disposition(value, *quoting).ok() != generic)
+                {
+                    self.multipart.flags.set(Flags::CD_QUOTING_AMBIGUOUS);
+                }
+            }
+        }
         // Find the C-D header.
         let part = self.get_current_part()?;
         let header = {
-            if let Some((_, header)) = part.headers.get_nocase_nozero_mut("content-disposition") {
+            if let Some((_, header)) = part
+                .headers
+                .get_nocase_nozero_value_mut("content-disposition")
+            {
                 header
             } else {
                 self.multipart.flags.set(Flags::PART_UNKNOWN);
@@ -803,7 +958,9 @@ impl Parser {
         };
 
         // Require "form-data" at the beginning of the header.
-        if let Ok((_, params)) = content_disposition((*header.value).as_slice()) {
+        let parsed = content_disposition((*header.value).as_slice(), quoting);
+        if let Ok((_, params)) = parsed {
+            let mut filename_ext = None;
             for (param_name, param_value) in params {
                 match param_name {
                     b"name" => {
@@ -828,13 +985,30 @@ impl Parser {
                                 return Err(HtpStatus::DECLINED);
                             }
                             None => {
-                                part.file = Some(File::new(
+                                let mut file = File::new(
                                     HtpFileSource::MULTIPART,
                                     Some(Bstr::from(param_value)),
-                                ));
+                                );
+                                if compute_file_hashes {
+                                    file.enable_hashes();
+                                }
+                                part.file = Some(file);
                             }
                         };
                     }
+                    b"filename*" => {
+                        if filename_ext.is_some() {
+                            self.multipart.flags.set(Flags::CD_PARAM_REPEATED);
+                            return Err(HtpStatus::DECLINED);
+                        }
+                        match decode_ext_value(&param_value) {
+                            Some(filename) => filename_ext = Some(filename),
+                            None => {
+                                self.multipart.flags.set(Flags::CD_PARAM_EXT_INVALID);
+                                return Err(HtpStatus::DECLINED);
+                            }
+                        }
+                    }
                     _ => {
                         // Unknown parameter.
                         self.multipart.flags.set(Flags::CD_PARAM_UNKNOWN);
@@ -842,6 +1016,22 @@ impl Parser {
                     }
                 }
             }
+            // The extended filename takes precedence over the plain one.
+            if let Some(filename) = filename_ext {
+                match &mut part.file {
+                    Some(file) => {
+                        file.filename = Some(filename);
+                        self.multipart.flags.set(Flags::CD_FILENAME_EXT);
+                    }
+                    None => {
+                        let mut file = File::new(HtpFileSource::MULTIPART, Some(filename));
+                        if compute_file_hashes {
+                            file.enable_hashes();
+                        }
+                        part.file = Some(file);
+                    }
+                }
+            }
         } else {
             self.multipart.flags.set(Flags::CD_SYNTAX_INVALID);
             return Err(HtpStatus::DECLINED);
@@ -859,12 +1049,42 @@ impl Parser {
             // Combine value pieces into a single buffer.
             // Keep track of the file length.
             Some(file) => {
+                file.update_hashes(data);
                 // Send data to callbacks
                 file.handle_file_data(hook, data.as_ptr(), data.len())
             }
             None => Ok(()),
         }
     }
+
+    /// Send text part data to the request part data callbacks. Empty data marks
+    /// the end of the part.
+    pub fn run_request_part_data_hook(&mut self, data: &[u8]) -> Result<()> {
+        let part = self
+            .current_part_idx
+            .and_then(|idx| self.multipart.parts.get(idx))
+            .ok_or(HtpStatus::ERROR)?;
+        self.part_hook.run_all(&mut PartData::new(part, data))
+    }
+}
+
+/// Returns the LF_LINE and CRLF_LINE flags for the lines ending in data.
+fn line_flags(data: &[u8]) -> u64 {
+    let mut flags = 0;
+    let mut offset = 0;
+    while let Some(pos) = data[offset..].find_byte(b'\n') {
+        let end = offset + pos;
+        if end > 0 && data[end - 1] == b'\r' {
+            flags.set(Flags::CRLF_LINE);
+        } else {
+            flags.set(Flags::LF_LINE);
+        }
+        if flags == Flags::CRLF_LINE | Flags::LF_LINE {
+            break;
+        }
+        offset = end + 1;
+    }
+    flags
 }
 
 /// Holds information related to a part.
@@ -904,10 +1124,25 @@ impl Default for Part {
     }
 }
 
+/// Represents a chunk of text part data.
+pub struct PartData<'a> {
+    /// The part the data belongs to.
+    pub part: &'a Part,
+    /// The data, which is empty at the end of the part.
+    pub data: &'a [u8],
+}
+
+impl<'a> PartData<'a> {
+    /// Construct new PartData.
+    pub fn new(part: &'a Part, data: &'a [u8]) -> Self {
+        PartData { part, data }
+    }
+}
+
 impl Drop for Part {
     fn drop(&mut self) {
         self.file = None;
-        self.headers.elements.clear();
+        self.headers.clear();
     }
 }
 
@@ -971,15 +1206,79 @@ pub struct Multipart {
     pub flags: u64,
 }
 
-/// Extracts and decodes a C-D header param name and value following a form-data. This is impossible to do correctly without a
-/// parsing personality because most browsers are broken:
+/// Reads a quoted C-D header param value, including the opening and closing quotes,
+/// and un-escapes it the way the backend would for the chosen browser personality:
 ///  - Firefox encodes " as \", and \ is not encoded.
 ///  - Chrome encodes " as %22.
 ///  - IE encodes " as \", and \ is not encoded.
 ///  - Opera encodes " as \" and \ as \\.
-fn content_disposition_param() -> impl Fn(&[u8]) -> IResult<&[u8], (&[u8], Vec<u8>)> {
+fn quoted_param_value(quoting: HtpMultipartQuoting) -> impl Fn(&[u8]) -> IResult<&[u8], Vec<u8>> {
     move |input| {
-        let (mut remaining_input, param_name) = map(
+        let (input, _) = char('\"')(input)?; // must start with opening quote
+        let mut param_value = Vec::new();
+        let mut pos = 0;
+        while let Some(c) = input.get(pos) {
+            pos += 1;
+            match (quoting, c) {
+                (_, b'\"') => return Ok((&input[pos..], param_value)),
+                (HtpMultipartQuoting::FIREFOX, b'\\') | (HtpMultipartQuoting::IE, b'\\') => {
+                    // Only an escaped quote is un-escaped
+                    if input.get(pos) == Some(&b'\"') {
+                        pos += 1;
+                        param_value.push(b'\"');
+                    } else {
+                        param_value.push(b'\\');
+                    }
+                }
+                (HtpMultipartQuoting::CHROME, b'%') => {
+                    let decoded = match input.get(pos..pos + 2) {
+                        Some(b"22") => Some(b'\"'),
+                        Some(b"0D") | Some(b"0d") => Some(b'\r'),
+                        Some(b"0A") | Some(b"0a") => Some(b'\n'),
+                        _ => None,
+                    };
+                    if let Some(decoded) = decoded {
+                        pos += 2;
+                        param_value.push(decoded);
+                    } else {
+                        param_value.push(b'%');
+                    }
+                }
+                (HtpMultipartQuoting::CHROME, _)
+                | (HtpMultipartQuoting::FIREFOX, _)
+                | (HtpMultipartQuoting::IE, _) => param_value.push(*c),
+                (_, b'\\') => {
+                    // Unescape any escaped " and \, anything else is invalid
+                    match input.get(pos) {
+                        Some(escaped) if *escaped == b'\"' || *escaped == b'\\' => {
+                            pos += 1;
+                            param_value.push(*escaped);
+                        }
+                        _ => {
+                            return Err(nom::Err::Error((
+                                &input[pos..],
+                                nom::error::ErrorKind::Char,
+                            )))
+                        }
+                    }
+                }
+                _ => param_value.push(*c),
+            }
+        }
+        // Must end with a quote or it is invalid
+        Err(nom::Err::Error((
+            &input[pos..],
+            nom::error::ErrorKind::Char,
+        )))
+    }
+}
+
+/// Extracts and decodes a C-D header param name and value following a form-data.
+fn content_disposition_param(
+    quoting: HtpMultipartQuoting,
+) -> impl Fn(&[u8]) -> IResult<&[u8], (&[u8], Vec<u8>)> {
+    move |input| {
+        let (remaining_input, param_name) = map(
             tuple((
                 take_ascii_whitespace(),
                 char(';'),
@@ -988,47 +1287,34 @@ fn content_disposition_param() -> impl Fn(&[u8]) -> IResult<&[u8], (&[u8], Vec<u
                 take_ascii_whitespace(),
                 char('='),
                 take_ascii_whitespace(),
-                char('\"'), //must start with opening quote
             )),
-            |(_, _, _, param_name, _, _, _, _)| param_name,
+            |(_, _, _, param_name, _, _, _)| param_name,
         )(input)?;
-        // Unescape any escaped " and \ and find the closing "
-        let mut param_value = Vec::new();
-        loop {
-            let (left, (value, to_insert)) = tuple((
-                take_while(|c| c != b'\"' && c != b'\\'),
-                opt(tuple((char('\\'), alt((char('\"'), char('\\')))))),
-            ))(remaining_input)?;
-            remaining_input = left;
-            param_value.extend_from_slice(value);
-            if let Some((_, to_insert)) = to_insert {
-                // Insert the character
-                param_value.push(to_insert as u8);
-            } else {
-                // Must end with a quote or it is invalid
-                let (left, _) = char('\"')(remaining_input)?;
-                remaining_input = left;
-                break;
-            }
+        // Extended (RFC 5987) parameter values are tokens and must not be quoted
+        if param_name.ends_with(b"*") {
+            let (remaining_input, param_value) = take_while1(|c: u8| {
+                !c.is_ascii_whitespace() && c != b';' && c != b',' && c != b'"'
+            })(remaining_input)?;
+            return Ok((remaining_input, (param_name, param_value.to_vec())));
         }
+        let (remaining_input, param_value) = quoted_param_value(quoting)(remaining_input)?;
         Ok((remaining_input, (param_name, param_value)))
     }
 }
 
 /// Extracts and decodes a C-D header param names and values. This is impossible to do correctly without a
-/// parsing personality because most browsers are broken:
-///  - Firefox encodes " as \", and \ is not encoded.
-///  - Chrome encodes " as %22.
-///  - IE encodes " as \", and \ is not encoded.
-///  - Opera encodes " as \" and \ as \\.
-fn content_disposition(input: &[u8]) -> IResult<&[u8], Vec<(&[u8], Vec<u8>)>> {
+/// parsing personality because most browsers are broken, see quoted_param_value.
+fn content_disposition(
+    input: &[u8],
+    quoting: HtpMultipartQuoting,
+) -> IResult<&[u8], Vec<(&[u8], Vec<u8>)>> {
     // Multiple header values are seperated by a ", ": https://tools.ietf.org/html/rfc7230#section-3.2.2
     map(
         tuple((
             tag("form-data"),
             fold_many1(
                 tuple((
-                    content_disposition_param(),
+                    content_disposition_param(quoting),
                     take_ascii_whitespace(),
                     opt(tuple((tag(","), take_ascii_whitespace(), tag("form-data")))),
                     take_ascii_whitespace(),
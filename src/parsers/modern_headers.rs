@@ -0,0 +1,150 @@
+//! Typed parsing of modern headers: Priority (RFC 9218), Early-Data (RFC 8470),
+//! Upgrade-Insecure-Requests and the Sec-Fetch-* fetch metadata.
+
+use super::{trim_ows_end, trim_ows_start};
+use crate::{bstr::Bstr, transaction::Headers};
+
+/// Represents the parameters of a Priority header.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Priority {
+    /// Urgency, from 0 (highest) to 7 (lowest). Defaults to 3.
+    pub urgency: u8,
+    /// Whether the response can be processed incrementally. Defaults to false.
+    pub incremental: bool,
+}
+
+impl Default for Priority {
+    fn default() -> Self {
+        Self {
+            urgency: 3,
+            incremental: false,
+        }
+    }
+}
+
+/// Represents the Sec-Fetch-* request headers sent by browsers.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FetchMetadata {
+    /// Sec-Fetch-Dest token, such as "document" or "image".
+    pub dest: Option<Bstr>,
+    /// Sec-Fetch-Mode token, such as "navigate" or "cors".
+    pub mode: Option<Bstr>,
+    /// Sec-Fetch-Site token, such as "same-origin" or "cross-site".
+    pub site: Option<Bstr>,
+    /// Sec-Fetch-User boolean, which is true for user-activated navigations.
+    pub user: Option<bool>,
+}
+
+impl FetchMetadata {
+    /// Determines if any of the Sec-Fetch-* headers is present.
+    pub fn is_present(&self) -> bool {
+        self.dest.is_some() || self.mode.is_some() || self.site.is_some() || self.user.is_some()
+    }
+}
+
+/// The modern headers of a transaction, as returned by Transaction::modern_headers.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ModernHeaders {
+    /// Priority requested by the client, if the request has a Priority header.
+    pub request_priority: Option<Priority>,
+    /// Priority set by the server, if the response has a Priority header.
+    pub response_priority: Option<Priority>,
+    /// Whether the request was sent in TLS early data (Early-Data: 1).
+    pub early_data: bool,
+    /// Whether the client prefers secure responses (Upgrade-Insecure-Requests: 1).
+    pub upgrade_insecure_requests: bool,
+    /// Fetch metadata sent by the client.
+    pub fetch: FetchMetadata,
+}
+
+impl ModernHeaders {
+    /// Extracts the modern headers from the request and response headers.
+    pub fn new(request: &Headers, response: &Headers) -> Self {
+        let value = |headers: &Headers, name: &str| {
+            headers
+                .get_nocase_nozero(name)
+                .map(|(_, header)| trim(header.value.as_slice()).to_vec())
+        };
+        let token = |name: &str| value(request, name).map(Bstr::from);
+        Self {
+            request_priority: value(request, "priority").map(|v| parse_priority(&v)),
+            response_priority: value(response, "priority").map(|v| parse_priority(&v)),
+            early_data: matches!(value(request, "early-data").as_deref(), Some(b"1")),
+            upgrade_insecure_requests: matches!(
+                value(request, "upgrade-insecure-requests").as_deref(),
+                Some(b"1")
+            ),
+            fetch: FetchMetadata {
+                dest: token("sec-fetch-dest"),
+                mode: token("sec-fetch-mode"),
+                site: token("sec-fetch-site"),
+                user: value(request, "sec-fetch-user").and_then(|v| parse_boolean(&v)),
+            },
+        }
+    }
+}
+
+fn trim(data: &[u8]) -> &[u8] {
+    trim_ows_end(trim_ows_start(data))
+}
+
+/// Parses a structured field boolean: "?1" or "?0".
+fn parse_boolean(value: &[u8]) -> Option<bool> {
+    match value {
+        b"?1" => Some(true),
+        b"?0" => Some(false),
+        _ => None,
+    }
+}
+
+/// Parses a Priority header, a structured field dictionary whose "u" member is the
+/// urgency and "i" member the incremental flag. Members that are unknown or not
+/// valid keep their default value, and parameters are ignored.
+pub fn parse_priority(value: &[u8]) -> Priority {
+    let mut priority = Priority::default();
+    for member in value.split(|c| *c == b',') {
+        let member = trim(member.split(|c| *c == b';').next().unwrap_or_default());
+        let mut parts = member.splitn(2, |c| *c == b'=');
+        let key = parts.next().unwrap_or_default();
+        let value = parts.next();
+        match key {
+            b"u" => {
+                if let Some(urgency) = value
+                    .and_then(|v| std::str::from_utf8(v).ok())
+                    .and_then(|v| v.parse::<u8>().ok())
+                    .filter(|urgency| *urgency <= 7)
+                {
+                    priority.urgency = urgency;
+                }
+            }
+            b"i" => {
+                if let Some(incremental) = value.map_or(Some(true), parse_boolean) {
+                    priority.incremental = incremental;
+                }
+            }
+            _ => {}
+        }
+    }
+    priority
+}
+
+#[test]
+fn ParsePriority() {
+    assert_eq!(Priority::default(), parse_priority(b""));
+    assert_eq!(
+        Priority {
+            urgency: 5,
+            incremental: true
+        },
+        parse_priority(b"u=5, i")
+    );
+    assert_eq!(
+        Priority {
+            urgency: 1,
+            incremental: false
+        },
+        parse_priority(b"i=?1;x=y, u=1, i=?0")
+    );
+    // Out of range and malformed members are ignored.
+    assert_eq!(Priority::default(), parse_priority(b"u=8, i=1, u=-1, x"));
+}
@@ -0,0 +1,305 @@
+//! Typed parsing of response security headers: Strict-Transport-Security,
+//! Content-Security-Policy, X-Frame-Options and X-Content-Type-Options.
+
+use super::{trim_ows_end, trim_ows_start};
+use crate::{
+    bstr::Bstr,
+    transaction::Headers,
+    util::{FlagOperations, HtpFlags},
+};
+
+/// Export security header flags
+#[derive(Debug)]
+pub struct SecurityHeaderFlags;
+
+impl SecurityHeaderFlags {
+    /// Strict-Transport-Security has no max-age directive.
+    pub const MISSING_MAX_AGE: u64 = 0x0001;
+    /// Strict-Transport-Security max-age is not a number of seconds.
+    pub const INVALID_MAX_AGE: u64 = 0x0002;
+    /// A directive appears more than once. The first occurrence is used.
+    pub const DUPLICATE_DIRECTIVE: u64 = 0x0004;
+    /// A directive is not defined for the header.
+    pub const UNKNOWN_DIRECTIVE: u64 = 0x0008;
+    /// The header value is not one of the values defined for it.
+    pub const INVALID_VALUE: u64 = 0x0010;
+    /// The header appears more than once.
+    pub const REPEATED: u64 = 0x0020;
+}
+
+/// Represents a Strict-Transport-Security header.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StrictTransportSecurity {
+    /// Value of the max-age directive, in seconds.
+    pub max_age: Option<u64>,
+    /// Whether the includeSubDomains directive is present.
+    pub include_subdomains: bool,
+    /// Whether the preload directive is present.
+    pub preload: bool,
+    /// Validation flags; see SecurityHeaderFlags.
+    pub flags: u64,
+}
+
+/// Represents one policy of a Content-Security-Policy or
+/// Content-Security-Policy-Report-Only header.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ContentSecurityPolicy {
+    /// Whether the policy is only reported, not enforced.
+    pub report_only: bool,
+    /// Directives with their names lowercased, in the order they appear.
+    pub directives: Vec<(Bstr, Vec<Bstr>)>,
+    /// Validation flags; see SecurityHeaderFlags.
+    pub flags: u64,
+}
+
+impl ContentSecurityPolicy {
+    /// Returns the values of the given directive, if present. The name must be
+    /// lowercase.
+    pub fn directive(&self, name: &str) -> Option<&[Bstr]> {
+        self.directives
+            .iter()
+            .find(|(directive, _)| directive.eq(name))
+            .map(|(_, values)| values.as_slice())
+    }
+}
+
+/// Enumerates the values of the X-Frame-Options header.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum HtpFrameOptions {
+    /// The response must not be framed.
+    DENY,
+    /// The response may only be framed by the same origin.
+    SAMEORIGIN,
+    /// The response may be framed by the given origin; obsolete.
+    ALLOW_FROM,
+    /// The value is not one of the above.
+    INVALID,
+}
+
+/// The security headers of a response, as returned by
+/// Transaction::response_security_headers.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SecurityHeaders {
+    /// Strict-Transport-Security, if present.
+    pub hsts: Option<StrictTransportSecurity>,
+    /// Policies from the Content-Security-Policy headers, followed by those from
+    /// the Content-Security-Policy-Report-Only headers.
+    pub content_security_policy: Vec<ContentSecurityPolicy>,
+    /// X-Frame-Options, if present.
+    pub x_frame_options: Option<HtpFrameOptions>,
+    /// Whether X-Content-Type-Options is "nosniff".
+    pub nosniff: bool,
+    /// All the validation flags raised by the headers; see SecurityHeaderFlags.
+    pub flags: u64,
+}
+
+impl SecurityHeaders {
+    /// Extracts the security headers from the response headers.
+    pub fn new(headers: &Headers) -> Self {
+        let mut security = Self::default();
+        let header = |name: &str| {
+            headers.get_nocase_nozero(name).map(|(_, header)| {
+                let repeated = if header.flags.is_set(HtpFlags::FIELD_REPEATED) {
+                    SecurityHeaderFlags::REPEATED
+                } else {
+                    0
+                };
+                (header.value.as_slice(), repeated)
+            })
+        };
+        if let Some((value, repeated)) = header("strict-transport-security") {
+            let mut hsts = parse_hsts(value);
+            hsts.flags.set(repeated);
+            security.flags.set(hsts.flags);
+            security.hsts = Some(hsts);
+        }
+        for (name, report_only) in &[
+            ("content-security-policy", false),
+            ("content-security-policy-report-only", true),
+        ] {
+            if let Some((value, _)) = header(name) {
+                // Repeated headers are joined with commas, which also separate policies.
+                for policy in value.split(|c| *c == b',') {
+                    let mut policy = parse_csp(policy);
+                    policy.report_only = *report_only;
+                    security.flags.set(policy.flags);
+                    security.content_security_policy.push(policy);
+                }
+            }
+        }
+        if let Some((value, repeated)) = header("x-frame-options") {
+            let value = trim(value);
+            let options = if value.eq_ignore_ascii_case(b"deny") {
+                HtpFrameOptions::DENY
+            } else if value.eq_ignore_ascii_case(b"sameorigin") {
+                HtpFrameOptions::SAMEORIGIN
+            } else if value.len() > 11 && value[..11].eq_ignore_ascii_case(b"allow-from ") {
+                HtpFrameOptions::ALLOW_FROM
+            } else {
+                security.flags.set(SecurityHeaderFlags::INVALID_VALUE);
+                HtpFrameOptions::INVALID
+            };
+            security.flags.set(repeated);
+            security.x_frame_options = Some(options);
+        }
+        if let Some((value, repeated)) = header("x-content-type-options") {
+            security.nosniff = trim(value).eq_ignore_ascii_case(b"nosniff");
+            if !security.nosniff {
+                security.flags.set(SecurityHeaderFlags::INVALID_VALUE);
+            }
+            security.flags.set(repeated);
+        }
+        security
+    }
+}
+
+fn trim(data: &[u8]) -> &[u8] {
+    trim_ows_end(trim_ows_start(data))
+}
+
+/// Parses a Strict-Transport-Security header value.
+pub fn parse_hsts(value: &[u8]) -> StrictTransportSecurity {
+    let mut hsts = StrictTransportSecurity::default();
+    let mut seen: Vec<Vec<u8>> = Vec::new();
+    for directive in value.split(|c| *c == b';') {
+        let directive = trim(directive);
+        if directive.is_empty() {
+            continue;
+        }
+        let mut parts = directive.splitn(2, |c| *c == b'=');
+        let name = trim(parts.next().unwrap_or_default()).to_ascii_lowercase();
+        let value = parts.next().map(trim);
+        if seen.contains(&name) {
+            hsts.flags.set(SecurityHeaderFlags::DUPLICATE_DIRECTIVE);
+            continue;
+        }
+        match name.as_slice() {
+            b"max-age" => {
+                let seconds = value.map(|v| match v {
+                    [b'"', inner @ .., b'"'] => inner,
+                    _ => v,
+                });
+                hsts.max_age = seconds
+                    .filter(|v| !v.is_empty() && v.iter().all(u8::is_ascii_digit))
+                    .and_then(|v| std::str::from_utf8(v).ok())
+                    .and_then(|v| v.parse().ok());
+                if hsts.max_age.is_none() {
+                    hsts.flags.set(SecurityHeaderFlags::INVALID_MAX_AGE);
+                }
+            }
+            b"includesubdomains" => hsts.include_subdomains = true,
+            b"preload" => hsts.preload = true,
+            _ => hsts.flags.set(SecurityHeaderFlags::UNKNOWN_DIRECTIVE),
+        }
+        seen.push(name);
+    }
+    if !seen.iter().any(|name| name == b"max-age") {
+        hsts.flags.set(SecurityHeaderFlags::MISSING_MAX_AGE);
+    }
+    hsts
+}
+
+/// Directives defined by CSP Level 3 and its companion specifications.
+const CSP_DIRECTIVES: &[&[u8]] = &[
+    b"base-uri",
+    b"block-all-mixed-content",
+    b"child-src",
+    b"connect-src",
+    b"default-src",
+    b"fenced-frame-src",
+    b"font-src",
+    b"form-action",
+    b"frame-ancestors",
+    b"frame-src",
+    b"img-src",
+    b"manifest-src",
+    b"media-src",
+    b"navigate-to",
+    b"object-src",
+    b"plugin-types",
+    b"prefetch-src",
+    b"report-to",
+    b"report-uri",
+    b"require-sri-for",
+    b"require-trusted-types-for",
+    b"sandbox",
+    b"script-src",
+    b"script-src-attr",
+    b"script-src-elem",
+    b"style-src",
+    b"style-src-attr",
+    b"style-src-elem",
+    b"trusted-types",
+    b"upgrade-insecure-requests",
+    b"webrtc",
+    b"worker-src",
+];
+
+/// Parses a single Content-Security-Policy policy.
+pub fn parse_csp(value: &[u8]) -> ContentSecurityPolicy {
+    let mut policy = ContentSecurityPolicy::default();
+    for directive in value.split(|c| *c == b';') {
+        let mut tokens = directive
+            .split(|c| c.is_ascii_whitespace())
+            .filter(|token| !token.is_empty());
+        let name = match tokens.next() {
+            Some(name) => name.to_ascii_lowercase(),
+            None => continue,
+        };
+        if policy
+            .directives
+            .iter()
+            .any(|(existing, _)| existing.as_slice() == name.as_slice())
+        {
+            policy.flags.set(SecurityHeaderFlags::DUPLICATE_DIRECTIVE);
+            continue;
+        }
+        if !CSP_DIRECTIVES.contains(&name.as_slice()) {
+            policy.flags.set(SecurityHeaderFlags::UNKNOWN_DIRECTIVE);
+        }
+        policy
+            .directives
+            .push((Bstr::from(name), tokens.map(Bstr::from).collect()));
+    }
+    policy
+}
+
+#[test]
+fn ParseHsts() {
+    let hsts = parse_hsts(b"max-age=31536000; includeSubDomains; preload");
+    assert_eq!(Some(31536000), hsts.max_age);
+    assert!(hsts.include_subdomains && hsts.preload);
+    assert_eq!(0, hsts.flags);
+    assert_eq!(Some(60), parse_hsts(b"Max-Age=\"60\"").max_age);
+
+    let hsts = parse_hsts(b"max-age=-1; max-age=5; foo");
+    assert_eq!(None, hsts.max_age);
+    assert_eq!(
+        SecurityHeaderFlags::INVALID_MAX_AGE
+            | SecurityHeaderFlags::DUPLICATE_DIRECTIVE
+            | SecurityHeaderFlags::UNKNOWN_DIRECTIVE,
+        hsts.flags
+    );
+    assert_eq!(
+        SecurityHeaderFlags::MISSING_MAX_AGE,
+        parse_hsts(b"includeSubDomains").flags
+    );
+}
+
+#[test]
+fn ParseCsp() {
+    let policy = parse_csp(b"default-src 'self'; Script-Src 'self' https://a.example ;; img-src *");
+    assert_eq!(0, policy.flags);
+    assert_eq!(3, policy.directives.len());
+    let script = policy.directive("script-src").unwrap();
+    assert_eq!(2, script.len());
+    assert!(script[1].eq("https://a.example"));
+    assert!(policy.directive("object-src").is_none());
+
+    let policy = parse_csp(b"default-src 'none'; default-src *; bogus-src x");
+    assert!(policy.directive("default-src").unwrap()[0].eq("'none'"));
+    assert_eq!(
+        SecurityHeaderFlags::DUPLICATE_DIRECTIVE | SecurityHeaderFlags::UNKNOWN_DIRECTIVE,
+        policy.flags
+    );
+}
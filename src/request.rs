@@ -1,15 +1,17 @@
 use crate::{
     bstr::Bstr,
     connection::Flags as ConnectionFlags,
-    connection_parser::{ConnectionParser, Data as ParserData, HtpStreamState, State},
+    connection_parser::{Chunk, ConnectionParser, Data as ParserData, HtpStreamState, State},
     error::Result,
+    event::{Direction, Event},
+    headers::Side,
     hook::DataHook,
     parsers::parse_chunked_length,
     transaction::{Data, HtpRequestProgress, HtpResponseProgress, HtpTransferCoding, Transaction},
     util::{
-        chomp, is_line_ignorable, is_space, is_valid_chunked_length_data, nom_take_is_space,
-        take_is_space, take_not_is_space, take_till_eol, take_till_lf, take_till_lf_null,
-        FlagOperations, HtpFlags,
+        chomp, find_chunk_resync_point, is_line_ignorable, is_space, is_valid_chunked_length_data,
+        nom_take_is_space, take_is_space, take_not_is_space, take_till_eol, take_till_lf,
+        take_till_lf_null, FlagOperations, HtpFlags,
     },
     HtpStatus,
 };
@@ -24,6 +26,21 @@ use std::{
     mem::take,
 };
 
+/// Export request method semantics flags
+#[derive(Debug)]
+pub struct MethodFlags;
+
+impl MethodFlags {
+    /// A GET or HEAD request has a message body.
+    pub const GET_WITH_BODY: u64 = 0x0001;
+    /// A CONNECT request has a Content-Length header or a message body.
+    pub const CONNECT_WITH_CONTENT_LENGTH: u64 = 0x0002;
+    /// A TRACE request has a message body.
+    pub const TRACE_WITH_BODY: u64 = 0x0004;
+    /// An OPTIONS request for "*" has a message body.
+    pub const OPTIONS_ASTERISK_WITH_BODY: u64 = 0x0008;
+}
+
 /// Enumerate HTTP methods.
 /// cbindgen:rename-all=QualifiedScreamingSnakeCase
 #[repr(C)]
@@ -133,18 +150,21 @@ impl ConnectionParser {
     fn request_receiver_send_data(&mut self, is_last: bool) -> Result<()> {
         let tx = self.request_mut() as *mut Transaction;
         if let Some(hook) = &self.request_data_receiver_hook {
-            hook.run_all(
-                self,
-                &mut Data::new(
-                    tx,
-                    &ParserData::from(
-                        &self.request_curr_data.get_ref()[self.request_current_receiver_offset
-                            as usize
-                            ..self.request_curr_data.position() as usize],
-                    ),
-                    is_last,
-                ),
-            )?;
+            let data = ParserData::from(
+                &self.request_curr_data.get_ref()[self.request_current_receiver_offset as usize
+                    ..self.request_curr_data.position() as usize],
+            );
+            let stream_offset = self.request_stream_offset(self.request_current_receiver_offset);
+            // The hook receives the parser only as a raw pointer, and nothing else
+            // reaches the transaction until the Data is dropped.
+            let mut data = unsafe { Data::new(tx, &data, is_last) };
+            data.set_position(
+                Direction::Request,
+                Some(stream_offset),
+                Some(self.request_receiver_data_offset),
+            );
+            self.request_receiver_data_offset += data.len() as u64;
+            hook.run_all(self, &mut data)?;
         } else {
             return Ok(());
         };
@@ -157,6 +177,7 @@ impl ConnectionParser {
         // Ignore result.
         let _ = self.request_receiver_finalize_clear();
         self.request_data_receiver_hook = data_receiver_hook;
+        self.request_receiver_data_offset = 0;
         self.request_current_receiver_offset = self.request_curr_data.position();
         Ok(())
     }
@@ -292,6 +313,17 @@ impl ConnectionParser {
     /// Returns OK if the parser can resume parsing, HtpStatus::DATA_OTHER if
     /// it needs to continue waiting.
     pub fn request_connect_wait_response(&mut self) -> Result<()> {
+        // The response will never be seen if the outbound stream failed. Assume
+        // there is no tunnel and carry on with the inbound stream.
+        if self.response_status == HtpStreamState::ERROR {
+            htp_warn!(
+                self.logger,
+                HtpLogCode::CONNECT_RESPONSE_UNAVAILABLE,
+                "Outbound stream failed before the CONNECT response was seen"
+            );
+            self.request_state = State::FINALIZE;
+            return Ok(());
+        }
         // Check that we saw the response line of the current inbound transaction.
         if self.request().response_progress <= HtpResponseProgress::LINE {
             return Err(HtpStatus::DATA_OTHER);
@@ -345,7 +377,7 @@ impl ConnectionParser {
             return Err(HtpStatus::DATA);
         }
         // Consume the data.
-        self.request_process_body_data_ex(Some(&data[0..bytes_to_consume]))?;
+        self.request_process_body_chunk(&data[0..bytes_to_consume])?;
         // Adjust counters.
         self.request_curr_data
             .seek(SeekFrom::Current(bytes_to_consume as i64))?;
@@ -361,6 +393,83 @@ impl ConnectionParser {
         Err(HtpStatus::DATA)
     }
 
+    /// Consumes a gap in a chunked request body. A gap that fits within the current
+    /// chunk is accounted against its length; one that spans the chunk framing
+    /// puts the parser into resynchronisation mode.
+    ///
+    /// Returns HtpStatus::DATA when more data is needed.
+    pub fn request_body_chunked_gap(&mut self, data: &ParserData) -> Result<()> {
+        self.request_mut().request_message_len = self
+            .request()
+            .request_message_len
+            .wrapping_add(data.len() as i64);
+        // Send the gap to the data hooks
+        self.request_run_hook_body_gap(data)?;
+        let left = if self.request_state == State::BODY_CHUNKED_DATA {
+            self.request_chunked_length.unwrap_or(0) as usize
+        } else {
+            0
+        };
+        if data.len() <= left {
+            let left = left - data.len();
+            self.request_chunked_length = Some(left as i32);
+            if left == 0 {
+                self.request_state = State::BODY_CHUNKED_DATA_END;
+            }
+        } else {
+            htp_warn!(
+                self.logger,
+                HtpLogCode::CHUNKED_RESYNC,
+                "Request chunk encoding: Gap spans chunk framing"
+            );
+            self.request_mut()
+                .flags
+                .set(HtpFlags::PARTIALLY_RECONSTRUCTED);
+            self.request_buf.clear();
+            self.request_chunked_resync = true;
+            self.request_state = State::BODY_CHUNKED_LENGTH;
+        }
+        Err(HtpStatus::DATA)
+    }
+
+    /// Looks for the next plausible chunk-size line after a gap disrupted the
+    /// chunked framing. Everything that precedes it is treated as chunk data.
+    ///
+    /// Returns OK once the framing is recovered, ERROR on error, or
+    /// HtpStatus::DATA_BUFFER when more data is needed.
+    pub fn request_body_chunked_resync(&mut self, data: &[u8]) -> Result<()> {
+        let buffered = self.request_buf.len();
+        let mut input = take(&mut self.request_buf);
+        input.add(data);
+        let (data_len, line) = find_chunk_resync_point(input.as_slice());
+        if data_len > 0 {
+            self.request_process_body_data_ex(Some(&input[..data_len]))?;
+        }
+        if let Some(start) = line {
+            self.request_mut().request_message_len = self
+                .request()
+                .request_message_len
+                .wrapping_add(start as i64);
+            if start < buffered {
+                self.request_buf.add(&input[start..buffered]);
+            } else {
+                self.request_curr_data
+                    .seek(SeekFrom::Current((start - buffered) as i64))?;
+            }
+            self.request_chunked_resync = false;
+            Ok(())
+        } else {
+            self.request_mut().request_message_len = self
+                .request()
+                .request_message_len
+                .wrapping_add(data_len as i64);
+            self.request_curr_data.seek(SeekFrom::End(0))?;
+            self.check_request_buffer_limit(input.len() - data_len)?;
+            self.request_buf.add(&input[data_len..]);
+            Err(HtpStatus::DATA_BUFFER)
+        }
+    }
+
     /// Extracts chunk length.
     /// Returns OK on state change, ERROR on error, or HtpStatus::DATA_BUFFER
     /// when more data is needed.
@@ -378,7 +487,7 @@ impl ConnectionParser {
                 //Empty chunk len. Try to continue parsing.
                 return self.request_body_chunked_length(remaining);
             }
-            let mut data = self.request_buf.clone();
+            let mut data = take(&mut self.request_buf);
             data.add(line);
             self.request_mut().request_message_len =
                 (self.request().request_message_len as u64).wrapping_add(data.len() as u64) as i64;
@@ -446,11 +555,10 @@ impl ConnectionParser {
                 .request_message_len
                 .wrapping_add(bytes_to_consume as i64);
             // Send the gap to the data hooks
-            let mut tx_data = Data::new(self.request_mut(), data, false);
-            self.request_run_hook_body_data(&mut tx_data)?;
+            self.request_run_hook_body_gap(data)?;
         } else {
             // Consume the data.
-            self.request_process_body_data_ex(Some(&data.as_slice()[0..bytes_to_consume]))?;
+            self.request_process_body_chunk(&data.as_slice()[0..bytes_to_consume])?;
             self.request_curr_data
                 .seek(SeekFrom::Current(bytes_to_consume as i64))?;
         }
@@ -478,6 +586,7 @@ impl ConnectionParser {
         match self.request().request_transfer_coding {
             HtpTransferCoding::CHUNKED => {
                 self.request_state = State::BODY_CHUNKED_LENGTH;
+                self.request_chunked_resync = false;
                 self.request_mut().request_progress = HtpRequestProgress::BODY
             }
             HtpTransferCoding::IDENTITY => {
@@ -522,6 +631,8 @@ impl ConnectionParser {
             request_header.add(data);
             request_header
         } else {
+            self.request_header_offset =
+                Some(self.request_stream_offset(self.request_curr_data.position()));
             Bstr::from(data)
         };
 
@@ -551,6 +662,10 @@ impl ConnectionParser {
             self.request_curr_data
                 .seek(SeekFrom::Current(data.len() as i64))?;
             self.check_request_buffer_limit(remaining.len())?;
+            let consumed = (request_header.len() - remaining.len()) as u64;
+            self.request_header_offset = self
+                .request_header_offset
+                .map(|offset| offset.wrapping_add(consumed));
             let remaining = Bstr::from(remaining);
             self.request_header.replace(remaining);
             Err(HtpStatus::DATA_BUFFER)
@@ -614,8 +729,20 @@ impl ConnectionParser {
         // Is this a line that should be ignored?
         if is_line_ignorable(self.cfg.server_personality, &line) {
             // We have an empty/whitespace line, which we'll note, ignore and move on.
-            self.request_mut().request_ignored_lines =
-                self.request().request_ignored_lines.wrapping_add(1);
+            let tx = self.request_mut();
+            tx.request_ignored_lines = tx.request_ignored_lines.wrapping_add(1);
+            tx.request_ignored_bytes = tx.request_ignored_bytes.wrapping_add(line.len() as u64);
+            let ignored = tx.request_ignored_lines;
+            if let Some(limit) = self.cfg.request_ignored_lines_limit {
+                if ignored == limit.wrapping_add(1) {
+                    htp_warn!(
+                        self.logger,
+                        HtpLogCode::REQUEST_IGNORED_LINES_LIMIT,
+                        format!("More than {} empty lines before request line", limit)
+                    );
+                    self.set_connection_flag(ConnectionFlags::IGNORED_LINES);
+                }
+            }
             return Ok(());
         }
         // Process request line.
@@ -739,7 +866,7 @@ impl ConnectionParser {
         let bytes_left = self.request_curr_len() - self.request_curr_data.position() as i64;
 
         if bytes_left > 0 {
-            self.conn.flags.set(ConnectionFlags::HTTP_0_9_EXTRA)
+            self.set_connection_flag(ConnectionFlags::HTTP_0_9_EXTRA)
         }
         self.request_curr_data.seek(SeekFrom::End(0))?;
         Err(HtpStatus::DATA)
@@ -758,6 +885,15 @@ impl ConnectionParser {
         if self.request_curr_data.position() as i64 >= self.request_curr_len() {
             return Err(HtpStatus::DATA);
         }
+        let position = self.request_curr_data.position() as usize;
+        let head: Vec<u8> = self.request_curr_data.get_ref()[position..]
+            .iter()
+            .take(8)
+            .copied()
+            .collect();
+        if self.sniff_non_http(&head) {
+            return Ok(());
+        }
         self.request_reset();
         // Change state to TRANSACTION_START
         // Ignore the result.
@@ -774,31 +910,192 @@ impl ConnectionParser {
         Err(HtpStatus::DATA_BUFFER)
     }
 
+    /// Run the REQUEST_BODY_DATA hook with a gap in the request body.
+    fn request_run_hook_body_gap(&mut self, data: &ParserData) -> Result<()> {
+        let tx = self.request_mut() as *mut Transaction;
+        // request_run_hook_body_data only touches the transaction through the Data.
+        let mut tx_data = unsafe { Data::new(tx, data, false) };
+        self.request_run_hook_body_data(&mut tx_data)
+    }
+
     /// Run the REQUEST_BODY_DATA hook.
     pub fn request_run_hook_body_data(&mut self, d: &mut Data) -> Result<()> {
         // Do not invoke callbacks with an empty data chunk
         if !d.data().is_null() && d.is_empty() {
             return Ok(());
         }
-        self.request()
-            .hook_request_body_data
-            .clone()
-            .run_all(self, d)?;
+        let stream_offset = d.as_slice().and(d.stream_offset());
+        d.set_position(Direction::Request, stream_offset, None);
+        let data = d.as_slice();
+        if let Some(tx) = d.transaction_mut() {
+            if data.is_some() {
+                tx.body_utf8_validate(Side::Request, data);
+            }
+            tx.hook_request_body_data.clone().run_all(self, d)?;
+        }
         // Run configuration hooks second
         self.cfg.hook_request_body_data.run_all(self, d)?;
+        if self.events_enabled() {
+            if let (Some(tx), Some(data)) = (d.tx_id(), d.as_slice()) {
+                self.push_event(Event::BodyChunk {
+                    tx,
+                    direction: Direction::Request,
+                    data: Bstr::from(data),
+                });
+            }
+        }
         // Treat request body as file
         if let Some(file) = &mut self.request_file {
             file.handle_file_data(self.cfg.hook_request_file_data.clone(), d.data(), d.len())?;
+            if let Some(data) = d.as_slice() {
+                file.update_hashes(data);
+                file.write(data)?;
+            }
         }
         Ok(())
     }
 
     /// Process a chunk of inbound (client or request) data.
+    ///
+    /// When Config::data_other_buffer_limit is set, data that has to wait for outbound
+    /// data (DATA_OTHER) is kept by the parser and parsed automatically after the next
+    /// response_data call, so it is reported as consumed and callers need not keep it.
     pub fn request_data(
         &mut self,
-        mut chunk: ParserData,
+        chunk: ParserData,
+        timestamp: Option<DateTime<Utc>>,
+    ) -> HtpStreamState {
+        self.request_input(Chunk::Borrowed(chunk), timestamp)
+    }
+
+    /// Process a chunk of inbound data, borrowed or owned. See request_data.
+    pub(crate) fn request_input(
+        &mut self,
+        chunk: Chunk,
+        timestamp: Option<DateTime<Utc>>,
+    ) -> HtpStreamState {
+        self.record_data(true, &chunk.data());
+        let len = chunk.data().len();
+        // Data kept for the outbound stream will not be released by it once it failed.
+        if self.response_status == HtpStreamState::ERROR {
+            self.request_process_pending();
+        }
+        if !self.request_pending.is_empty() && self.request_status != HtpStreamState::CLOSED {
+            let rc = self.request_pend(chunk, timestamp);
+            self.request_consumed = self.request_curr_data.position() as usize;
+            return rc;
+        }
+        let is_gap = chunk.data().is_gap();
+        // These are the states in which the chunk is refused without being looked at.
+        let refused = self.request_paused
+            || self.request_status == HtpStreamState::STOP
+            || self.request_status == HtpStreamState::ERROR
+            || (len == 0 && self.request_status != HtpStreamState::CLOSED);
+        let rc = self.request_data_chunk(chunk, timestamp, false);
+        let rc = self.request_keep_remainder(rc);
+        self.request_consumed = if refused {
+            0
+        } else if is_gap
+            || rc == HtpStreamState::TUNNEL
+            || (rc == HtpStreamState::PAUSED && self.request_paused)
+        {
+            // Gaps and tunnelled data are not parsed, and the rest of a paused
+            // chunk is kept until the parser is resumed.
+            len
+        } else {
+            self.request_curr_data.position() as usize
+        };
+        self.response_process_pending();
+        rc
+    }
+
+    /// Parse inbound data that was kept while waiting for outbound data. Kept data is
+    /// never replayed into a closed stream; it is dropped instead.
+    pub fn request_process_pending(&mut self) {
+        if self.request_pending.is_empty() || self.request_paused {
+            return;
+        }
+        if self.request_status == HtpStreamState::CLOSED {
+            htp_warn!(
+                self.logger,
+                HtpLogCode::DATA_OTHER_DROPPED,
+                "Waiting inbound data dropped as the stream closed"
+            );
+            self.request_pending.clear();
+            return;
+        }
+        let pending = take(&mut self.request_pending);
+        let rc = self.request_data_chunk(Chunk::Owned(pending), None, true);
+        self.request_keep_remainder(rc);
+    }
+
+    /// Queue a chunk behind inbound data that is still waiting for outbound data.
+    fn request_pend(&mut self, chunk: Chunk, timestamp: Option<DateTime<Utc>>) -> HtpStreamState {
+        if let Some(timestamp) = timestamp {
+            self.request_timestamp = timestamp;
+        }
+        let is_gap = chunk.data().is_gap();
+        let data = chunk.into_vec();
+        let mut consumed = 0;
+        if is_gap {
+            htp_warn!(
+                self.logger,
+                HtpLogCode::DATA_OTHER_GAP,
+                "Gap in inbound data while data is waiting for outbound data"
+            );
+        } else if self.request_pending.len() + data.len() <= self.cfg.data_other_buffer_limit {
+            self.request_pending.extend_from_slice(&data);
+            self.conn.track_inbound_data(data.len());
+            consumed = data.len();
+        } else {
+            htp_warn!(
+                self.logger,
+                HtpLogCode::DATA_OTHER_BUFFER_LIMIT,
+                "Waiting inbound data exceeds the buffer limit"
+            );
+        }
+        self.request_curr_data = Cursor::new(data);
+        self.request_curr_data.set_position(consumed as u64);
+        HtpStreamState::DATA_OTHER
+    }
+
+    /// Keep the unprocessed part of the current chunk when parsing has to wait for
+    /// outbound data, if it fits in the configured buffer.
+    fn request_keep_remainder(&mut self, rc: HtpStreamState) -> HtpStreamState {
+        if rc != HtpStreamState::DATA_OTHER
+            || self.cfg.data_other_buffer_limit == 0
+            || self.request_status == HtpStreamState::CLOSED
+        {
+            return rc;
+        }
+        let position = self.request_curr_data.position() as usize;
+        let remainder = &self.request_curr_data.get_ref()[position..];
+        if self.request_pending.len() + remainder.len() > self.cfg.data_other_buffer_limit {
+            htp_warn!(
+                self.logger,
+                HtpLogCode::DATA_OTHER_BUFFER_LIMIT,
+                "Waiting inbound data exceeds the buffer limit"
+            );
+            return rc;
+        }
+        self.request_pending.extend_from_slice(remainder);
+        let end = self.request_curr_data.get_ref().len() as u64;
+        self.request_curr_data.set_position(end);
+        rc
+    }
+
+    /// Process a chunk of inbound data. Replayed data was already counted when it
+    /// was first received.
+    fn request_data_chunk(
+        &mut self,
+        chunk: Chunk,
         timestamp: Option<DateTime<Utc>>,
+        replay: bool,
     ) -> HtpStreamState {
+        let (len, is_gap) = {
+            let data = chunk.data();
+            (data.len(), data.is_gap())
+        };
         // Return if the connection is in stop state.
         if self.request_status == HtpStreamState::STOP {
             htp_info!(
@@ -817,12 +1114,21 @@ impl ConnectionParser {
             );
             return HtpStreamState::ERROR;
         }
+        // Keep the unprocessed data of a paused transaction until it is resumed.
+        if self.request_paused {
+            htp_info!(
+                self.logger,
+                HtpLogCode::PARSER_PAUSED,
+                "Inbound parser is paused"
+            );
+            return HtpStreamState::PAUSED;
+        }
 
         // If the length of the supplied data chunk is zero, proceed
         // only if the stream has been closed. We do not allow zero-sized
         // chunks in the API, but we use them internally to force the parsers
         // to finalize parsing.
-        if chunk.len() == 0 && self.request_status != HtpStreamState::CLOSED {
+        if len == 0 && self.request_status != HtpStreamState::CLOSED {
             htp_error!(
                 self.logger,
                 HtpLogCode::ZERO_LENGTH_DATA_CHUNKS,
@@ -836,7 +1142,7 @@ impl ConnectionParser {
         }
 
         // Store the current chunk information
-        if chunk.is_gap() {
+        if is_gap {
             // Gap
             self.request_mut()
                 .flags
@@ -846,10 +1152,24 @@ impl ConnectionParser {
                 self.request_mut().request_progress = HtpRequestProgress::GAP;
             }
         }
-        self.request_curr_data = Cursor::new(chunk.as_slice().to_vec());
+        let mut chunk = match chunk {
+            Chunk::Borrowed(chunk) => {
+                self.request_curr_data = Cursor::new(chunk.as_slice().to_vec());
+                chunk
+            }
+            Chunk::Owned(data) => {
+                self.request_curr_data = Cursor::new(data);
+                let data = self.request_curr_data.get_ref();
+                // The data is parsed where it is kept. request_curr_data is only
+                // replaced by the next chunk, once this one has been parsed.
+                unsafe { ParserData::from(std::slice::from_raw_parts(data.as_ptr(), data.len())) }
+            }
+        };
         self.request_current_receiver_offset = 0;
         self.request_chunk_count = self.request_chunk_count.wrapping_add(1);
-        self.conn.track_inbound_data(chunk.len());
+        if !replay {
+            self.conn.track_inbound_data(len);
+        }
         // Return without processing any data if the stream is in tunneling
         // mode (which it would be after an initial CONNECT transaction).
         if self.request_status == HtpStreamState::TUNNEL {
@@ -859,9 +1179,12 @@ impl ConnectionParser {
             self.response_status = HtpStreamState::DATA
         }
         //handle gap
-        if chunk.is_gap()
+        if is_gap
             && self.request_state != State::BODY_IDENTITY
             && self.request_state != State::IGNORE_DATA_AFTER_HTTP_0_9
+            && self.request_state != State::BODY_CHUNKED_DATA
+            && self.request_state != State::BODY_CHUNKED_LENGTH
+            && self.request_state != State::BODY_CHUNKED_DATA_END
         {
             // go to request_connect_probe_data ?
             htp_error!(
@@ -871,6 +1194,16 @@ impl ConnectionParser {
             );
             return HtpStreamState::CLOSED;
         }
+        self.request_process(&mut chunk)
+    }
+
+    /// Run the request processors over the current chunk.
+    pub fn request_process(&mut self, chunk: &mut ParserData) -> HtpStreamState {
+        // Only errors logged while processing this chunk describe a failure.
+        self.logger.take_last_error();
+        if self.request_resync && !chunk.is_gap() {
+            self.request_resync_find();
+        }
         loop
         // Invoke a processor, in a loop, until an error
         // occurs or until we run out of data. Many processors
@@ -879,7 +1212,8 @@ impl ConnectionParser {
         // Return if there's been an error or if we've run out of data. We are relying
         // on processors to supply error messages, so we'll keep quiet here.
         {
-            let mut rc = self.handle_request_state(&mut chunk);
+            let request_tx = self.request_index();
+            let mut rc = self.handle_request_state(chunk);
 
             if rc.is_ok() {
                 if self.request_status == HtpStreamState::TUNNEL {
@@ -888,13 +1222,22 @@ impl ConnectionParser {
                 rc = self.request_handle_state_change()
             }
             match rc {
-                // Continue looping.
-                Ok(_) => {}
+                // Continue looping, unless a callback paused the transaction.
+                Ok(_) => {
+                    if self.tx_paused(request_tx) {
+                        self.request_paused = true;
+                        return HtpStreamState::PAUSED;
+                    }
+                }
                 // Do we need more data?
                 Err(HtpStatus::DATA) | Err(HtpStatus::DATA_BUFFER) => {
                     // Ignore result.
                     let _ = self.request_receiver_send_data(false);
                     self.request_status = HtpStreamState::DATA;
+                    if self.tx_paused(request_tx) {
+                        self.request_paused = true;
+                        return HtpStreamState::PAUSED;
+                    }
                     return HtpStreamState::DATA;
                 }
                 // Check for suspended parsing.
@@ -916,7 +1259,11 @@ impl ConnectionParser {
                     return HtpStreamState::STOP;
                 }
                 // Permanent stream error.
-                Err(_) => {
+                Err(HtpStatus::ERROR) if self.cfg.request_error_recovery && !chunk.is_gap() => {
+                    self.request_recover();
+                }
+                Err(status) => {
+                    self.request_error = Some(self.stream_error(Direction::Request, status));
                     self.request_status = HtpStreamState::ERROR;
                     return HtpStreamState::ERROR;
                 }
@@ -924,12 +1271,85 @@ impl ConnectionParser {
         }
     }
 
+    /// Abandon the request that failed to parse and look for the next request line
+    /// in the rest of the data.
+    fn request_recover(&mut self) {
+        self.request_mut().flags.set(HtpFlags::REQUEST_INVALID);
+        // Complete the request the usual way, so that its hooks run. It has failed
+        // already, so an error here only means moving on to the next request.
+        if self.state_request_complete().is_err() {
+            self.request_mut().request_progress = HtpRequestProgress::COMPLETE;
+            self.request_next();
+        }
+        self.request_data_receiver_hook = None;
+        self.request_file = None;
+        self.request_buf.clear();
+        self.request_chunked_resync = false;
+        self.request_state = State::IDLE;
+        self.request_state_previous = State::IDLE;
+        self.set_connection_flag(ConnectionFlags::RESYNCED);
+        htp_warn!(
+            self.logger,
+            HtpLogCode::REQUEST_RESYNC,
+            "Request parsing failed, skipping to the next request"
+        );
+        let mut position = self.request_curr_data.position() as usize;
+        // Skip at least one byte if the request failed where parsing resumed, so
+        // the same data is not parsed over and over.
+        if self.request_resync_resumed == Some((self.request_chunk_count, position as u64)) {
+            position += 1;
+            self.request_curr_data.set_position(position as u64);
+        }
+        self.request_resync_line_start =
+            position > 0 && self.request_curr_data.get_ref()[position - 1] == b'\n';
+        self.request_resync = true;
+        self.request_resync_find();
+    }
+
+    /// Skip data up to the next line that starts with a known method followed by a
+    /// space, or to the end of the current chunk if there is none.
+    fn request_resync_find(&mut self) {
+        let data = self.request_curr_data.get_ref();
+        let start = self.request_curr_data.position() as usize;
+        let found = (start..data.len()).find(|&i| {
+            let line_start = if i == start {
+                self.request_resync_line_start
+            } else {
+                data[i - 1] == b'\n'
+            };
+            line_start && is_plausible_request_line(&data[i..])
+        });
+        let position = match found {
+            Some(position) => {
+                self.request_resync = false;
+                self.request_resync_resumed = Some((self.request_chunk_count, position as u64));
+                position
+            }
+            None => {
+                if data.len() > start {
+                    self.request_resync_line_start = data.last() == Some(&b'\n');
+                }
+                data.len()
+            }
+        };
+        self.request_curr_data.set_position(position as u64);
+        self.request_current_receiver_offset = position as u64;
+    }
+
     /// Return length of the current request chunk.
     pub fn request_curr_len(&self) -> i64 {
         self.request_curr_data.get_ref().len() as i64
     }
 }
 
+/// Returns true if `data` starts with a known request method followed by a space.
+fn is_plausible_request_line(data: &[u8]) -> bool {
+    match data.iter().position(|&c| c == b' ') {
+        Some(end) => HtpMethod::new(&data[..end]) != HtpMethod::UNKNOWN,
+        None => false,
+    }
+}
+
 #[test]
 fn Method() {
     let method = b"GET";
@@ -1,12 +1,14 @@
 use crate::{
     bstr::Bstr,
-    config::HtpUnwanted,
+    config::{HtpHeaderNul, HtpHttp09Mode, HtpServerPersonality, HtpUnwanted},
     connection_parser::ConnectionParser,
     error::Result,
-    headers::Flags as HeaderFlags,
+    event::Direction,
+    headers::{line_count, Flags as HeaderFlags},
+    log::LogContext,
     parsers::{parse_content_length, parse_protocol},
     request::HtpMethod,
-    transaction::{Header, HtpProtocol},
+    transaction::{Header, HtpProtocol, HtpRequestLineRepair, HtpRequestProgress},
     util::{
         is_space, take_ascii_whitespace, take_is_space, take_not_is_space, take_until_null,
         FlagOperations, HtpFlags,
@@ -15,10 +17,24 @@ use crate::{
 use nom::{bytes::complete::take_while, error::ErrorKind, sequence::tuple};
 use std::cmp::Ordering;
 
+/// A repaired request line: the repair, the URI, the delimiter after it and the protocol.
+type RepairedRequestLine<'a> = (HtpRequestLineRepair, &'a [u8], &'a [u8], &'a [u8]);
+
 impl ConnectionParser {
     /// Extract one request header. A header can span multiple lines, in
     /// which case they will be folded into one before parsing is attempted.
     fn process_request_header_generic(&mut self, header: Header) -> Result<()> {
+        let header = if self.request().request_progress > HtpRequestProgress::HEADERS {
+            match self
+                .request_mut()
+                .process_trailer(Direction::Request, header)
+            {
+                Some(header) => header,
+                None => return Ok(()),
+            }
+        } else {
+            header
+        };
         // Try to parse the header.
         let mut repeated = false;
         let reps = self.request().request_header_repetitions;
@@ -27,7 +43,7 @@ impl ConnectionParser {
         if let Some((_, h_existing)) = self
             .request_mut()
             .request_headers
-            .get_nocase_mut(header.name.as_slice())
+            .get_nocase_value_mut(header.name.as_slice())
         {
             // TODO Do we want to have a list of the headers that are
             //      allowed to be combined in this way?
@@ -86,11 +102,23 @@ impl ConnectionParser {
         &mut self,
         data: &'a [u8],
     ) -> Result<(&'a [u8], bool)> {
-        let rc = self.request_mut().request_header_parser.headers()(data);
+        let offset = self.request_header_offset;
+        let rc = self
+            .request_mut()
+            .request_header_parser
+            .headers_with_spans()(data);
         if let Ok((remaining, (headers, eoh))) = rc {
-            for h in headers {
+            for (h, span) in headers {
                 let mut flags = 0;
                 let name_flags = h.name.flags;
+                // Folding is valid but obsolete; keep track of it.
+                if h.value.flags.is_set(HeaderFlags::FOLDING) {
+                    flags.set(HtpFlags::FIELD_FOLDED);
+                    let tx = self.request_mut();
+                    tx.flags
+                        .set(HtpFlags::FIELD_FOLDED | HtpFlags::REQUEST_FIELD_FOLDED);
+                    tx.request_folded_headers = tx.request_folded_headers.wrapping_add(1);
+                }
                 // Ignore LWS after field-name.
                 if name_flags.is_set(HeaderFlags::NAME_TRAILING_WHITESPACE) {
                     // Log only once per transaction.
@@ -156,11 +184,34 @@ impl ConnectionParser {
                         HtpFlags::FIELD_INVALID
                     );
                 }
-                self.process_request_header_generic(Header::new_with_flags(
-                    h.name.name.into(),
-                    h.value.value.into(),
-                    flags,
-                ))?;
+                let null_terminated = h.value.flags.is_set(HeaderFlags::NULL_TERMINATED);
+                let mut header =
+                    Header::new_with_flags(h.name.name.into(), h.value.value.into(), flags);
+                let handling = self.request().cfg.request_header_nul;
+                if header.handle_nul(handling) || null_terminated {
+                    htp_warn_once!(
+                        self.logger,
+                        HtpLogCode::REQUEST_FIELD_RAW_NUL,
+                        "Request field contains NUL bytes",
+                        self.request_mut().flags,
+                        header.flags,
+                        HtpFlags::FIELD_RAW_NUL
+                    );
+                    if handling == HtpHeaderNul::REJECT {
+                        header.flags.set(HtpFlags::FIELD_INVALID);
+                        let tx = self.request_mut();
+                        tx.flags.set(HtpFlags::FIELD_INVALID);
+                        if tx.response_status_expected_number == HtpUnwanted::IGNORE {
+                            tx.response_status_expected_number = HtpUnwanted::CODE_400;
+                        }
+                    }
+                }
+                header.start_offset = offset.map(|offset| offset + span.start as u64);
+                header.end_offset = offset.map(|offset| offset + span.end as u64);
+                let tx = self.request_mut();
+                header.line = tx.request_header_line;
+                tx.request_header_line += line_count(&data[span]);
+                self.process_request_header_generic(header)?;
             }
             Ok((remaining, eoh))
         } else {
@@ -168,6 +219,131 @@ impl ConnectionParser {
         }
     }
 
+    /// Handles a request line without a protocol, which is treated as HTTP/0.9 if
+    /// Config::http_0_9_mode allows it. `malformed` is set when the line would
+    /// only be HTTP/0.9 because it is malformed, for example when the method is
+    /// unknown.
+    fn request_line_0_9(&mut self, malformed: bool) {
+        let accepted = match self.cfg.http_0_9_mode {
+            HtpHttp09Mode::PERMISSIVE => true,
+            HtpHttp09Mode::GET_ONLY => self.request().request_method_number == HtpMethod::GET,
+            HtpHttp09Mode::DISABLED => false,
+        };
+        let tx = self.request_mut();
+        if accepted {
+            tx.is_protocol_0_9 = true;
+            tx.request_protocol_number = HtpProtocol::V0_9;
+            if malformed {
+                tx.flags.set(HtpFlags::HTTP_0_9_MALFORMED);
+            }
+        } else {
+            tx.request_protocol_number = HtpProtocol::INVALID;
+            tx.flags.set(HtpFlags::REQUEST_INVALID);
+            htp_warn!(
+                self.logger,
+                HtpLogCode::REQUEST_LINE_HTTP_0_9_REJECTED,
+                "Request line: missing protocol and HTTP/0.9 not accepted"
+            );
+        }
+    }
+
+    /// Looks for a URI and protocol in the part of the request line after the
+    /// method that the usual parsing would get wrong: a protocol without a URI, or
+    /// a URI with whitespace followed by a protocol. Returns the repair with the URI,
+    /// which is empty if missing, the delimiter after it and the protocol, including
+    /// any trailing whitespace.
+    fn repair_request_line<'a>(&mut self, data: &'a [u8]) -> Option<RepairedRequestLine<'a>> {
+        let end = data.iter().rposition(|c| !is_space(*c))? + 1;
+        let start = data[..end]
+            .iter()
+            .rposition(|c| is_space(*c))
+            .map_or(0, |pos| pos + 1);
+        let protocol = &data[start..end];
+        if protocol.len() <= 5 || !protocol[..5].eq_ignore_ascii_case(b"HTTP/") {
+            return None;
+        }
+        let uri_end = data[..start]
+            .iter()
+            .rposition(|c| !is_space(*c))
+            .map_or(0, |pos| pos + 1);
+        let uri = &data[..uri_end];
+        if uri.is_empty() {
+            return Some((
+                HtpRequestLineRepair::MISSING_URI,
+                uri,
+                &data[..start],
+                &data[start..],
+            ));
+        }
+        let first_space = uri.iter().position(|c| is_space(*c))?;
+        if self.request().cfg.server_personality == HtpServerPersonality::APACHE_2 {
+            // Apache ends the URI at the first whitespace and falls back to
+            // HTTP/1.0 when the rest of the line is not a protocol.
+            let rest = data[first_space..]
+                .iter()
+                .position(|c| !is_space(*c))
+                .map_or(data.len(), |pos| first_space + pos);
+            Some((
+                HtpRequestLineRepair::PROTOCOL_ASSUMED,
+                &data[..first_space],
+                &data[first_space..rest],
+                &data[rest..],
+            ))
+        } else {
+            Some((
+                HtpRequestLineRepair::URI_WITH_SPACES,
+                uri,
+                &data[uri_end..start],
+                &data[start..],
+            ))
+        }
+    }
+
+    /// Splits the part of the request line after the method into the URI, the
+    /// delimiter after it and the protocol.
+    fn split_request_uri<'a>(&mut self, data: &'a [u8]) -> Option<(&'a [u8], &'a [u8], &'a [u8])> {
+        let uri_protocol_parser = tuple::<_, _, (_, ErrorKind), _>
+        // The URI ends with the first whitespace.
+        ((take_while(|c: u8| c != 0x20),
+          // Ignore whitespace after URI.
+          take_is_space)
+        );
+
+        let (mut protocol, (mut uri, mut ws)) = uri_protocol_parser(data).ok()?;
+        if uri.len() == data.len() && uri.iter().any(|&c| is_space(c)) {
+            // warn regardless if we've seen non-compliant chars
+            htp_warn!(
+                self.logger,
+                HtpLogCode::URI_DELIM_NON_COMPLIANT,
+                "Request line: URI contains non-compliant delimiter"
+            );
+            // if we've seen some 'bad' delimiters, we retry with those
+            let uri_protocol_parser2 =
+                tuple::<_, _, (_, ErrorKind), _>((take_not_is_space, take_is_space));
+            if let Ok((protocol2, (uri2, ws2))) = uri_protocol_parser2(data) {
+                uri = uri2;
+                protocol = protocol2;
+                ws = ws2;
+            }
+        }
+        Some((uri, ws, protocol))
+    }
+
+    /// Flags request line tokens that are not separated by a single SP.
+    fn request_line_delimiter(&mut self, delimiter: &[u8]) {
+        if delimiter != b" " {
+            let mut flags = 0;
+            htp_warn_once!(
+                self.logger,
+                HtpLogCode::REQUEST_LINE_MULTIPLE_SPACES,
+                "Request line: tokens not separated by a single SP",
+                self.request_mut().flags,
+                flags,
+                HtpFlags::REQUEST_LINE_MULTIPLE_SPACES
+            );
+        }
+    }
+
     /// Parses a single request line.
     pub fn parse_request_line_generic_ex(
         &mut self,
@@ -201,12 +377,16 @@ impl ConnectionParser {
                     HtpLogCode::REQUEST_LINE_LEADING_WHITESPACE,
                     "Request line: leading whitespace"
                 );
+                self.request_mut()
+                    .flags
+                    .set(HtpFlags::REQUEST_LINE_LEADING_WHITESPACE);
 
                 let requestline_leading_whitespace_unwanted =
-                    self.cfg.requestline_leading_whitespace_unwanted;
+                    self.request().cfg.requestline_leading_whitespace_unwanted;
                 if requestline_leading_whitespace_unwanted != HtpUnwanted::IGNORE {
-                    // reset mstart so that we copy the whitespace into the method
-                    mstart = true;
+                    // reset mstart so that we copy the whitespace into the method,
+                    // unless the personality trims it
+                    mstart = !self.request().cfg.requestline_whitespace_trim;
                     // set expected response code to this anomaly
                     self.request_mut().response_status_expected_number =
                         requestline_leading_whitespace_unwanted
@@ -233,11 +413,11 @@ impl ConnectionParser {
                     "Request line: non-compliant delimiter between Method and URI"
                 );
             }
+            self.request_line_delimiter(ws);
 
             if remaining.is_empty() {
-                // No, this looks like a HTTP/0.9 request.
-                self.request_mut().is_protocol_0_9 = true;
-                self.request_mut().request_protocol_number = HtpProtocol::V0_9;
+                // No, this looks like a HTTP/0.9 request, though without a URI.
+                self.request_line_0_9(true);
                 if self.request().request_method_number == HtpMethod::UNKNOWN {
                     htp_warn!(
                         self.logger,
@@ -248,35 +428,38 @@ impl ConnectionParser {
                 return Ok(());
             }
 
-            let uri_protocol_parser = tuple::<_, _, (_, ErrorKind), _>
-            // The URI ends with the first whitespace.
-            ((take_while(|c: u8| c != 0x20),
-              // Ignore whitespace after URI.
-              take_is_space)
-            );
-
-            if let Ok((mut protocol, (mut uri, _))) = uri_protocol_parser(remaining) {
-                if uri.len() == remaining.len() && uri.iter().any(|&c| is_space(c)) {
-                    // warn regardless if we've seen non-compliant chars
+            let repair = if self.request().cfg.request_line_repair {
+                self.repair_request_line(remaining)
+            } else {
+                None
+            };
+            let tokens = match repair {
+                Some((repair, uri, ws, protocol)) => {
+                    let tx = self.request_mut();
+                    tx.request_line_repair = repair;
+                    tx.flags.set(HtpFlags::REQUEST_LINE_REPAIRED);
                     htp_warn!(
                         self.logger,
-                        HtpLogCode::URI_DELIM_NON_COMPLIANT,
-                        "Request line: URI contains non-compliant delimiter"
+                        HtpLogCode::REQUEST_LINE_REPAIRED,
+                        format!("Request line: repaired ({:?})", repair)
                     );
-                    // if we've seen some 'bad' delimiters, we retry with those
-                    let uri_protocol_parser2 =
-                        tuple::<_, _, (_, ErrorKind), _>((take_not_is_space, take_is_space));
-                    if let Ok((protocol2, (uri2, _))) = uri_protocol_parser2(remaining) {
-                        uri = uri2;
-                        protocol = protocol2;
-                    }
+                    Some((uri, ws, protocol))
                 }
-                self.request_mut().request_uri = Some(Bstr::from(uri));
+                None => self.split_request_uri(remaining),
+            };
+
+            if let Some((uri, ws, mut protocol)) = tokens {
+                let repair = self.request().request_line_repair;
+                self.request_mut().request_uri = if repair == HtpRequestLineRepair::MISSING_URI {
+                    Some(self.request().cfg.request_line_repair_uri.clone())
+                } else {
+                    Some(Bstr::from(uri))
+                };
                 // Is there protocol information available?
                 if protocol.is_empty() {
                     // No, this looks like a HTTP/0.9 request.
-                    self.request_mut().is_protocol_0_9 = true;
-                    self.request_mut().request_protocol_number = HtpProtocol::V0_9;
+                    let malformed = self.request().request_method_number == HtpMethod::UNKNOWN;
+                    self.request_line_0_9(malformed);
                     if self.request().request_method_number == HtpMethod::UNKNOWN {
                         htp_warn!(
                             self.logger,
@@ -286,10 +469,33 @@ impl ConnectionParser {
                     }
                     return Ok(());
                 }
+                // A missing URI leaves no delimiter to check.
+                if !ws.is_empty() {
+                    self.request_line_delimiter(ws);
+                }
+                if let Some(end) = protocol.iter().rposition(|c| !is_space(*c)) {
+                    if end + 1 < protocol.len() {
+                        htp_warn!(
+                            self.logger,
+                            HtpLogCode::REQUEST_LINE_TRAILING_WHITESPACE,
+                            "Request line: trailing whitespace"
+                        );
+                        self.request_mut()
+                            .flags
+                            .set(HtpFlags::REQUEST_LINE_TRAILING_WHITESPACE);
+                        if self.request().cfg.requestline_whitespace_trim {
+                            protocol = &protocol[..=end];
+                        }
+                    }
+                }
                 // The protocol information continues until the end of the line.
                 self.request_mut().request_protocol = Some(Bstr::from(protocol));
                 self.request_mut().request_protocol_number =
-                    parse_protocol(protocol, &mut self.logger);
+                    if repair == HtpRequestLineRepair::PROTOCOL_ASSUMED {
+                        HtpProtocol::V1_0
+                    } else {
+                        parse_protocol(protocol, &mut self.logger.at(self.log_position()))
+                    };
                 if self.request().request_method_number == HtpMethod::UNKNOWN
                     && self.request().request_protocol_number == HtpProtocol::INVALID
                 {
@@ -1,18 +1,22 @@
 use crate::{
     bstr::Bstr,
-    connection_parser::{ConnectionParser, Data as ParserData, HtpStreamState, State},
+    config::HtpUnexpectedBody,
+    connection_parser::{Chunk, ConnectionParser, Data as ParserData, HtpStreamState, State},
     decompressors::HtpContentEncoding,
     error::Result,
+    event::{Direction, Event},
+    headers::Side,
     hook::DataHook,
-    parsers::{parse_chunked_length, parse_content_length},
+    log::LogContext,
+    parsers::{parse_chunked_length, parse_content_length, parse_transfer_encoding},
     request::HtpMethod,
     transaction::{
         Data, HtpProtocol, HtpRequestProgress, HtpResponseProgress, HtpTransferCoding, Transaction,
     },
     uri::Uri,
     util::{
-        chomp, is_line_ignorable, is_space, is_valid_chunked_length_data, take_till_eol,
-        take_till_lf, treat_response_line_as_body, FlagOperations, HtpFlags,
+        chomp, find_chunk_resync_point, is_line_ignorable, is_space, is_valid_chunked_length_data,
+        take_till_eol, take_till_lf, treat_response_line_as_body, FlagOperations, HtpFlags,
     },
     HtpStatus,
 };
@@ -29,18 +33,21 @@ impl ConnectionParser {
     fn response_receiver_send_data(&mut self, is_last: bool) -> Result<()> {
         let tx = self.response_mut() as *mut Transaction;
         if let Some(hook) = &self.response_data_receiver_hook {
-            hook.run_all(
-                self,
-                &mut Data::new(
-                    tx,
-                    &ParserData::from(
-                        &self.response_curr_data.get_ref()[self.response_current_receiver_offset
-                            as usize
-                            ..self.response_curr_data.position() as usize],
-                    ),
-                    is_last,
-                ),
-            )?;
+            let data = ParserData::from(
+                &self.response_curr_data.get_ref()[self.response_current_receiver_offset as usize
+                    ..self.response_curr_data.position() as usize],
+            );
+            let stream_offset = self.response_stream_offset(self.response_current_receiver_offset);
+            // The hook receives the parser only as a raw pointer, and nothing else
+            // reaches the transaction until the Data is dropped.
+            let mut data = unsafe { Data::new(tx, &data, is_last) };
+            data.set_position(
+                Direction::Response,
+                Some(stream_offset),
+                Some(self.response_receiver_data_offset),
+            );
+            self.response_receiver_data_offset += data.len() as u64;
+            hook.run_all(self, &mut data)?;
         } else {
             return Ok(());
         };
@@ -64,6 +71,7 @@ impl ConnectionParser {
         // Ignore result.
         let _ = self.response_receiver_finalize_clear();
         self.response_data_receiver_hook = data_receiver_hook;
+        self.response_receiver_data_offset = 0;
         self.response_current_receiver_offset = self.response_curr_data.position();
         Ok(())
     }
@@ -160,7 +168,7 @@ impl ConnectionParser {
             return Err(HtpStatus::DATA);
         }
         // Consume the data.
-        self.response_process_body_data_ex(Some(&data[0..bytes_to_consume]))?;
+        self.response_process_body_chunk(&data[0..bytes_to_consume])?;
         // Adjust the counters.
         self.response_curr_data
             .seek(SeekFrom::Current(bytes_to_consume as i64))?;
@@ -176,6 +184,83 @@ impl ConnectionParser {
         Err(HtpStatus::DATA)
     }
 
+    /// Consumes a gap in a chunked response body. A gap that fits within the current
+    /// chunk is accounted against its length; one that spans the chunk framing
+    /// puts the parser into resynchronisation mode.
+    ///
+    /// Returns HtpStatus::DATA when more data is needed.
+    pub fn response_body_chunked_gap(&mut self, data: &ParserData) -> Result<()> {
+        self.response_mut().response_message_len = self
+            .response()
+            .response_message_len
+            .wrapping_add(data.len() as i64);
+        // Send the gap to the data hooks
+        self.response_run_hook_body_gap(data)?;
+        let left = if self.response_state == State::BODY_CHUNKED_DATA {
+            self.response_chunked_length.unwrap_or(0) as usize
+        } else {
+            0
+        };
+        if data.len() <= left {
+            let left = left - data.len();
+            self.response_chunked_length = Some(left as i32);
+            if left == 0 {
+                self.response_state = State::BODY_CHUNKED_DATA_END;
+            }
+        } else {
+            htp_warn!(
+                self.logger,
+                HtpLogCode::CHUNKED_RESYNC,
+                "Response chunk encoding: Gap spans chunk framing"
+            );
+            self.response_mut()
+                .flags
+                .set(HtpFlags::PARTIALLY_RECONSTRUCTED);
+            self.response_buf.clear();
+            self.response_chunked_resync = true;
+            self.response_state = State::BODY_CHUNKED_LENGTH;
+        }
+        Err(HtpStatus::DATA)
+    }
+
+    /// Looks for the next plausible chunk-size line after a gap disrupted the
+    /// chunked framing. Everything that precedes it is treated as chunk data.
+    ///
+    /// Returns OK once the framing is recovered, ERROR on error, or
+    /// HtpStatus::DATA_BUFFER when more data is needed.
+    pub fn response_body_chunked_resync(&mut self, data: &[u8]) -> Result<()> {
+        let buffered = self.response_buf.len();
+        let mut input = take(&mut self.response_buf);
+        input.add(data);
+        let (data_len, line) = find_chunk_resync_point(input.as_slice());
+        if data_len > 0 {
+            self.response_process_body_data_ex(Some(&input[..data_len]))?;
+        }
+        if let Some(start) = line {
+            self.response_mut().response_message_len = self
+                .response()
+                .response_message_len
+                .wrapping_add(start as i64);
+            if start < buffered {
+                self.response_buf.add(&input[start..buffered]);
+            } else {
+                self.response_curr_data
+                    .seek(SeekFrom::Current((start - buffered) as i64))?;
+            }
+            self.response_chunked_resync = false;
+            Ok(())
+        } else {
+            self.response_mut().response_message_len = self
+                .response()
+                .response_message_len
+                .wrapping_add(data_len as i64);
+            self.response_curr_data.seek(SeekFrom::End(0))?;
+            self.check_response_buffer_limit(input.len() - data_len)?;
+            self.response_buf.add(&input[data_len..]);
+            Err(HtpStatus::DATA_BUFFER)
+        }
+    }
+
     /// Extracts chunk length.
     ///
     /// Returns Ok(()) on success, Err(HTP_ERROR) on error, or Err(HTP_DATA) when more data is needed.
@@ -194,7 +279,7 @@ impl ConnectionParser {
                     //Empty chunk len. Try to continue parsing.
                     return self.response_body_chunked_length(remaining);
                 }
-                let mut data = self.response_buf.clone();
+                let mut data = take(&mut self.response_buf);
                 data.add(line);
                 self.response_mut().response_message_len =
                     (self.response().response_message_len as u64).wrapping_add(data.len() as u64)
@@ -278,11 +363,12 @@ impl ConnectionParser {
                 .response_message_len
                 .wrapping_add(data.len() as i64);
             // Send the gap to the data hooks
-            let mut tx_data = Data::new(self.response_mut(), data, false);
-            self.response_run_hook_body_data(&mut tx_data)?;
+            self.response_run_hook_body_gap(data)?;
         } else {
             // Consume the data.
-            self.response_process_body_data_ex(Some(&data.as_slice()[0..bytes_to_consume]))?;
+            self.response_mut()
+                .response_splitting_scan(&data.as_slice()[0..bytes_to_consume]);
+            self.response_process_body_chunk(&data.as_slice()[0..bytes_to_consume])?;
             self.response_curr_data
                 .seek(SeekFrom::Current(bytes_to_consume as i64))?;
         }
@@ -307,11 +393,11 @@ impl ConnectionParser {
     pub fn response_body_identity_stream_close(&mut self, data: &ParserData) -> Result<()> {
         if data.is_gap() {
             // Send the gap to the data hooks
-            let mut tx_data = Data::new(self.response_mut(), data, false);
-            self.response_run_hook_body_data(&mut tx_data)?;
+            self.response_run_hook_body_gap(data)?;
         } else if !data.is_empty() {
             // Consume all data from the input buffer.
-            self.response_process_body_data_ex(data.data())?;
+            self.response_mut().response_splitting_scan(data.as_slice());
+            self.response_process_body_chunk(data.as_slice())?;
             // Adjust the counters.
             self.response_curr_data.seek(SeekFrom::End(0))?;
         }
@@ -412,7 +498,7 @@ impl ConnectionParser {
                 return Err(HtpStatus::ERROR);
             }
             // Ignore any response headers seen so far.
-            self.response_mut().response_headers.elements.clear();
+            self.response_mut().response_headers.clear();
             // Expecting to see another response line next.
             self.response_state = State::LINE;
             self.response_mut().response_progress = HtpResponseProgress::LINE;
@@ -447,16 +533,21 @@ impl ConnectionParser {
             || self.response().response_status_number.eq_num(304)
         {
             // There should be no response body
-            // but browsers interpret content sent by the server as such
-            if te_opt.is_none() && cl_opt.is_none() {
+            // but browsers interpret content sent by the server as such.
+            // Like a response to HEAD, a 304 response may describe the length of
+            // the representation it refers to without sending it. A body is only
+            // flagged once its data arrives.
+            let empty = self.response().response_status_number.eq_num(304)
+                || te_opt.is_none()
+                    && match &cl_opt {
+                        Some(cl) => parse_content_length(cl.value.as_slice(), None) == Some(0),
+                        None => true,
+                    };
+            if empty
+                || self.response().cfg.unexpected_response_body == HtpUnexpectedBody::NEXT_RESPONSE
+            {
                 self.response_mut().response_transfer_coding = HtpTransferCoding::NO_BODY;
                 self.response_state = State::FINALIZE
-            } else {
-                htp_warn!(
-                    self.logger,
-                    HtpLogCode::RESPONSE_BODY_UNEXPECTED,
-                    "Unexpected Response body"
-                );
             }
         }
         // Hack condition to check that we do not assume "no body"
@@ -495,6 +586,10 @@ impl ConnectionParser {
             if response_content_type.is_some() {
                 self.response_mut().response_content_type = response_content_type;
             }
+            if let Some(te) = &te_opt {
+                self.response_mut().response_transfer_encoding =
+                    Some(parse_transfer_encoding(te.value.as_slice()));
+            }
             // 2. If a Transfer-Encoding header field (section 14.40) is present and
             //   indicates that the "chunked" transfer coding has been applied, then
             //   the length is defined by the chunked encoding (section 3.6).
@@ -526,6 +621,7 @@ impl ConnectionParser {
                     self.response_mut().flags.set(HtpFlags::REQUEST_SMUGGLING)
                 }
                 self.response_state = State::BODY_CHUNKED_LENGTH;
+                self.response_chunked_resync = false;
                 self.response_mut().response_progress = HtpResponseProgress::BODY
             } else if let Some(cl) = cl_opt {
                 //   value in bytes represents the length of the message-body.
@@ -536,9 +632,10 @@ impl ConnectionParser {
                     self.response_mut().flags.set(HtpFlags::REQUEST_SMUGGLING)
                 }
                 // Get body length
-                if let Some(content_length) =
-                    parse_content_length((*cl.value).as_slice(), Some(&mut self.logger))
-                {
+                if let Some(content_length) = parse_content_length(
+                    (*cl.value).as_slice(),
+                    Some(&mut self.logger.at(self.log_position())),
+                ) {
                     self.response_mut().response_content_length = content_length;
                     self.response_content_length = self.response().response_content_length;
                     self.response_body_data_left = self.response_content_length;
@@ -614,6 +711,8 @@ impl ConnectionParser {
             response_header.add(data);
             response_header
         } else {
+            self.response_header_offset =
+                Some(self.response_stream_offset(self.response_curr_data.position()));
             Bstr::from(data)
         };
 
@@ -655,6 +754,10 @@ impl ConnectionParser {
             self.response_curr_data
                 .seek(SeekFrom::Current(data.len() as i64))?;
             self.check_response_buffer_limit(remaining.len())?;
+            let consumed = (response_header.len() - remaining.len()) as u64;
+            self.response_header_offset = self
+                .response_header_offset
+                .map(|offset| offset.wrapping_add(consumed));
             let remaining = Bstr::from(remaining);
             self.response_header.replace(remaining);
             Err(HtpStatus::DATA_BUFFER)
@@ -717,6 +820,23 @@ impl ConnectionParser {
         // a response line. If it does not look like a line, process the
         // data as a response body because that is what browsers do.
         if treat_response_line_as_body(data) {
+            // Unless it is junk before the status line.
+            if self.response_status != HtpStreamState::CLOSED
+                && !self.response().is_protocol_0_9
+                && self.response().response_junk_lines < self.cfg.response_junk_lines_limit
+            {
+                let mut flags = 0;
+                htp_warn_once!(
+                    self.logger,
+                    HtpLogCode::RESPONSE_LEADING_JUNK,
+                    "Skipping junk before the response line",
+                    self.response_mut().flags,
+                    flags,
+                    HtpFlags::RESPONSE_LEADING_JUNK
+                );
+                self.response_mut().response_junk_lines += 1;
+                return Ok(());
+            }
             self.response_mut().response_content_encoding_processing = HtpContentEncoding::NONE;
             self.response_process_body_data_ex(Some(data))?;
             // Continue to process response body. Because we don't have
@@ -781,13 +901,24 @@ impl ConnectionParser {
             return self.state_response_complete_ex(0);
         }
         if treat_response_line_as_body(&data) {
-            // Interpret remaining bytes as body data
-            htp_warn!(
-                self.logger,
-                HtpLogCode::RESPONSE_BODY_UNEXPECTED,
-                "Unexpected response body"
-            );
-            return self.response_process_body_data_ex(Some(data.as_slice()));
+            let forbidden = self.response().response_body_forbidden();
+            if forbidden {
+                self.response_mut()
+                    .flags
+                    .set(HtpFlags::RESPONSE_BODY_UNEXPECTED);
+            }
+            if !forbidden
+                || self.response().cfg.unexpected_response_body == HtpUnexpectedBody::CONSUME
+            {
+                // Interpret remaining bytes as body data
+                htp_warn!(
+                    self.logger,
+                    HtpLogCode::RESPONSE_BODY_UNEXPECTED,
+                    "Unexpected response body"
+                );
+                self.response_mut().response_unexpected_body_len += data.len() as i64;
+                return self.response_process_body_data_ex(Some(data.as_slice()));
+            }
         }
         // didnt use data, restore
         self.response_buf.add(&data[0..buf_len]);
@@ -815,6 +946,15 @@ impl ConnectionParser {
         if self.response_curr_data.position() as i64 >= self.response_curr_len() {
             return Err(HtpStatus::DATA);
         }
+        let position = self.response_curr_data.position() as usize;
+        let head: Vec<u8> = self.response_curr_data.get_ref()[position..]
+            .iter()
+            .take(8)
+            .copied()
+            .collect();
+        if self.sniff_non_http(&head) {
+            return Ok(());
+        }
 
         // Parsing a new response
         // Log if we have not seen the corresponding request yet
@@ -837,28 +977,183 @@ impl ConnectionParser {
         self.state_response_start()
     }
 
+    /// Run the RESPONSE_BODY_DATA hook with a gap in the response body.
+    fn response_run_hook_body_gap(&mut self, data: &ParserData) -> Result<()> {
+        let tx = self.response_mut() as *mut Transaction;
+        // response_run_hook_body_data only touches the transaction through the Data.
+        let mut tx_data = unsafe { Data::new(tx, data, false) };
+        self.response_run_hook_body_data(&mut tx_data)
+    }
+
     /// Run the RESPONSE_BODY_DATA hook.
     pub fn response_run_hook_body_data(&mut self, d: &mut Data) -> Result<()> {
         // Do not invoke callbacks with an empty data chunk.
         if d.is_empty() {
             return Ok(());
         }
-        // Run transaction hooks first
-        self.response()
-            .hook_response_body_data
-            .clone()
-            .run_all(self, d)?;
+        let stream_offset = d.as_slice().and(d.stream_offset());
+        d.set_position(Direction::Response, stream_offset, None);
+        let data = d.as_slice();
+        if let Some(tx) = d.transaction_mut() {
+            if data.is_some() {
+                tx.response_sniff(data);
+                tx.response_charset_scan(data);
+                tx.body_utf8_validate(Side::Response, data);
+            }
+            // Run transaction hooks first
+            tx.hook_response_body_data.clone().run_all(self, d)?;
+        }
         // Run configuration hooks second
         self.cfg.hook_response_body_data.run_all(self, d)?;
+        if self.events_enabled() {
+            if let (Some(tx), Some(data)) = (d.tx_id(), d.as_slice()) {
+                self.push_event(Event::BodyChunk {
+                    tx,
+                    direction: Direction::Response,
+                    data: Bstr::from(data),
+                });
+            }
+        }
         Ok(())
     }
 
     /// Process a chunk of outbound (server or response) data.
+    ///
+    /// When Config::data_other_buffer_limit is set, data that has to wait for inbound
+    /// data (DATA_OTHER) is kept by the parser and parsed automatically after the next
+    /// request_data call, so it is reported as consumed and callers need not keep it.
     pub fn response_data(
         &mut self,
-        mut chunk: ParserData,
+        chunk: ParserData,
+        timestamp: Option<DateTime<Utc>>,
+    ) -> HtpStreamState {
+        self.response_input(Chunk::Borrowed(chunk), timestamp)
+    }
+
+    /// Process a chunk of outbound data, borrowed or owned. See response_data.
+    pub(crate) fn response_input(
+        &mut self,
+        chunk: Chunk,
+        timestamp: Option<DateTime<Utc>>,
+    ) -> HtpStreamState {
+        self.record_data(false, &chunk.data());
+        let len = chunk.data().len();
+        if !self.response_pending.is_empty() && self.response_status != HtpStreamState::CLOSED {
+            let rc = self.response_pend(chunk, timestamp);
+            self.response_consumed = self.response_curr_data.position() as usize;
+            return rc;
+        }
+        let is_gap = chunk.data().is_gap();
+        // These are the states in which the chunk is refused without being looked at.
+        let refused = self.response_paused
+            || self.response_status == HtpStreamState::STOP
+            || self.response_status == HtpStreamState::ERROR
+            || (len == 0 && self.response_status != HtpStreamState::CLOSED);
+        let rc = self.response_data_chunk(chunk, timestamp, false);
+        let rc = self.response_keep_remainder(rc);
+        self.response_consumed = if refused {
+            0
+        } else if is_gap
+            || rc == HtpStreamState::TUNNEL
+            || (rc == HtpStreamState::PAUSED && self.response_paused)
+        {
+            // Gaps and tunnelled data are not parsed, and the rest of a paused
+            // chunk is kept until the parser is resumed.
+            len
+        } else {
+            self.response_curr_data.position() as usize
+        };
+        self.request_process_pending();
+        rc
+    }
+
+    /// Parse outbound data that was kept while waiting for inbound data. Kept data is
+    /// never replayed into a closed stream; it is dropped instead.
+    pub fn response_process_pending(&mut self) {
+        if self.response_pending.is_empty() || self.response_paused {
+            return;
+        }
+        if self.response_status == HtpStreamState::CLOSED {
+            htp_warn!(
+                self.logger,
+                HtpLogCode::DATA_OTHER_DROPPED,
+                "Waiting outbound data dropped as the stream closed"
+            );
+            self.response_pending.clear();
+            return;
+        }
+        let pending = take(&mut self.response_pending);
+        let rc = self.response_data_chunk(Chunk::Owned(pending), None, true);
+        self.response_keep_remainder(rc);
+    }
+
+    /// Queue a chunk behind outbound data that is still waiting for inbound data.
+    fn response_pend(&mut self, chunk: Chunk, timestamp: Option<DateTime<Utc>>) -> HtpStreamState {
+        if let Some(timestamp) = timestamp {
+            self.response_timestamp = timestamp;
+        }
+        let is_gap = chunk.data().is_gap();
+        let data = chunk.into_vec();
+        let mut consumed = 0;
+        if is_gap {
+            htp_warn!(
+                self.logger,
+                HtpLogCode::DATA_OTHER_GAP,
+                "Gap in outbound data while data is waiting for inbound data"
+            );
+        } else if self.response_pending.len() + data.len() <= self.cfg.data_other_buffer_limit {
+            self.response_pending.extend_from_slice(&data);
+            self.conn.track_outbound_data(data.len());
+            consumed = data.len();
+        } else {
+            htp_warn!(
+                self.logger,
+                HtpLogCode::DATA_OTHER_BUFFER_LIMIT,
+                "Waiting outbound data exceeds the buffer limit"
+            );
+        }
+        self.response_curr_data = Cursor::new(data);
+        self.response_curr_data.set_position(consumed as u64);
+        HtpStreamState::DATA_OTHER
+    }
+
+    /// Keep the unprocessed part of the current chunk when parsing has to wait for
+    /// inbound data, if it fits in the configured buffer.
+    fn response_keep_remainder(&mut self, rc: HtpStreamState) -> HtpStreamState {
+        if rc != HtpStreamState::DATA_OTHER
+            || self.cfg.data_other_buffer_limit == 0
+            || self.response_status == HtpStreamState::CLOSED
+        {
+            return rc;
+        }
+        let position = self.response_curr_data.position() as usize;
+        let remainder = &self.response_curr_data.get_ref()[position..];
+        if self.response_pending.len() + remainder.len() > self.cfg.data_other_buffer_limit {
+            htp_warn!(
+                self.logger,
+                HtpLogCode::DATA_OTHER_BUFFER_LIMIT,
+                "Waiting outbound data exceeds the buffer limit"
+            );
+            return rc;
+        }
+        self.response_pending.extend_from_slice(remainder);
+        let end = self.response_curr_data.get_ref().len() as u64;
+        self.response_curr_data.set_position(end);
+        rc
+    }
+
+    /// Process a chunk of outbound data. Replayed data was already counted when it
+    /// was first received.
+    fn response_data_chunk(
+        &mut self,
+        chunk: Chunk,
         timestamp: Option<DateTime<Utc>>,
+        replay: bool,
     ) -> HtpStreamState {
+        let (len, is_gap) = {
+            let data = chunk.data();
+            (data.len(), data.is_gap())
+        };
         // Return if the connection is in stop state
         if self.response_status == HtpStreamState::STOP {
             htp_info!(
@@ -877,12 +1172,21 @@ impl ConnectionParser {
             );
             return HtpStreamState::ERROR;
         }
+        // Keep the unprocessed data of a paused transaction until it is resumed.
+        if self.response_paused {
+            htp_info!(
+                self.logger,
+                HtpLogCode::PARSER_PAUSED,
+                "Outbound parser is paused"
+            );
+            return HtpStreamState::PAUSED;
+        }
 
         // If the length of the supplied data chunk is zero, proceed
         // only if the stream has been closed. We do not allow zero-sized
         // chunks in the API, but we use it internally to force the parsers
         // to finalize parsing.
-        if chunk.len() == 0 && self.response_status != HtpStreamState::CLOSED {
+        if len == 0 && self.response_status != HtpStreamState::CLOSED {
             htp_error!(
                 self.logger,
                 HtpLogCode::ZERO_LENGTH_DATA_CHUNKS,
@@ -896,7 +1200,7 @@ impl ConnectionParser {
         }
 
         // Store the current chunk information
-        if chunk.is_gap() {
+        if is_gap {
             // Gap
             self.response_mut()
                 .flags
@@ -906,18 +1210,35 @@ impl ConnectionParser {
                 self.response_mut().response_progress = HtpResponseProgress::GAP;
             }
         }
-        self.response_curr_data = Cursor::new(chunk.as_slice().to_vec());
+        let mut chunk = match chunk {
+            Chunk::Borrowed(chunk) => {
+                self.response_curr_data = Cursor::new(chunk.as_slice().to_vec());
+                chunk
+            }
+            Chunk::Owned(data) => {
+                self.response_curr_data = Cursor::new(data);
+                let data = self.response_curr_data.get_ref();
+                // The data is parsed where it is kept. response_curr_data is only
+                // replaced by the next chunk, once this one has been parsed.
+                unsafe { ParserData::from(std::slice::from_raw_parts(data.as_ptr(), data.len())) }
+            }
+        };
         self.response_current_receiver_offset = 0;
-        self.conn.track_outbound_data(chunk.len());
+        if !replay {
+            self.conn.track_outbound_data(len);
+        }
         // Return without processing any data if the stream is in tunneling
         // mode (which it would be after an initial CONNECT transaction.
         if self.response_status == HtpStreamState::TUNNEL {
             return HtpStreamState::TUNNEL;
         }
-        if chunk.is_gap()
+        if is_gap
             && self.response_state != State::BODY_IDENTITY_CL_KNOWN
             && self.response_state != State::BODY_IDENTITY_STREAM_CLOSE
             && self.response_state != State::FINALIZE
+            && self.response_state != State::BODY_CHUNKED_DATA
+            && self.response_state != State::BODY_CHUNKED_LENGTH
+            && self.response_state != State::BODY_CHUNKED_DATA_END
         {
             htp_error!(
                 self.logger,
@@ -926,6 +1247,13 @@ impl ConnectionParser {
             );
             return HtpStreamState::CLOSED;
         }
+        self.response_process(&mut chunk)
+    }
+
+    /// Run the response processors over the current chunk.
+    pub fn response_process(&mut self, chunk: &mut ParserData) -> HtpStreamState {
+        // Only errors logged while processing this chunk describe a failure.
+        self.logger.take_last_error();
         loop
         // Invoke a processor, in a loop, until an error
         // occurs or until we run out of data. Many processors
@@ -936,7 +1264,8 @@ impl ConnectionParser {
         // on processors to add error messages, so we'll
         // keep quiet here.
         {
-            let mut rc = self.handle_response_state(&mut chunk);
+            let response_tx = self.response_index();
+            let mut rc = self.handle_response_state(chunk);
 
             if rc.is_ok() {
                 if self.response_status == HtpStreamState::TUNNEL {
@@ -945,13 +1274,22 @@ impl ConnectionParser {
                 rc = self.response_handle_state_change();
             }
             match rc {
-                // Continue looping.
-                Ok(_) => {}
+                // Continue looping, unless a callback paused the transaction.
+                Ok(_) => {
+                    if self.tx_paused(response_tx) {
+                        self.response_paused = true;
+                        return HtpStreamState::PAUSED;
+                    }
+                }
                 // Do we need more data?
                 Err(HtpStatus::DATA) | Err(HtpStatus::DATA_BUFFER) => {
                     // Ignore result.
                     let _ = self.response_receiver_send_data(false);
                     self.response_status = HtpStreamState::DATA;
+                    if self.tx_paused(response_tx) {
+                        self.response_paused = true;
+                        return HtpStreamState::PAUSED;
+                    }
                     return HtpStreamState::DATA;
                 }
                 // Check for stop
@@ -974,7 +1312,8 @@ impl ConnectionParser {
                     }
                 }
                 // Permanent stream error.
-                Err(_) => {
+                Err(status) => {
+                    self.response_error = Some(self.stream_error(Direction::Response, status));
                     self.response_status = HtpStreamState::ERROR;
                     return HtpStreamState::ERROR;
                 }
@@ -1,10 +1,13 @@
 use crate::{
     bstr::Bstr,
+    config::HtpHeaderNul,
     connection_parser::ConnectionParser,
     error::Result,
-    headers::Flags as HeaderFlags,
-    parsers::{parse_content_length, parse_protocol, parse_status},
-    transaction::{Header, HtpProtocol, HtpResponseNumber},
+    event::Direction,
+    headers::{line_count, Flags as HeaderFlags},
+    log::LogContext,
+    parsers::{parse_content_length, parse_protocol, parse_set_cookie, parse_status},
+    transaction::{CookieFlags, Header, HtpProtocol, HtpResponseNumber, HtpResponseProgress},
     util::{
         take_ascii_whitespace, take_is_space, take_is_space_or_null, take_not_is_space,
         FlagOperations, HtpFlags,
@@ -17,6 +20,14 @@ use std::cmp::Ordering;
 impl ConnectionParser {
     /// Generic response line parser.
     pub fn parse_response_line_generic(&mut self, response_line: &[u8]) -> Result<()> {
+        if response_line.contains(&0) {
+            htp_warn!(
+                self.logger,
+                HtpLogCode::RESPONSE_LINE_NUL,
+                "Response line: contains NUL bytes"
+            );
+            self.response_mut().flags.set(HtpFlags::STATUS_LINE_NUL);
+        }
         let response_tx = self.response_mut();
         response_tx.response_protocol_number = HtpProtocol::INVALID;
         response_tx.response_status = None;
@@ -40,18 +51,36 @@ impl ConnectionParser {
 
             response_tx.response_protocol = Some(Bstr::from(response_protocol));
             self.response_mut().response_protocol_number =
-                parse_protocol(response_protocol, &mut self.logger);
+                parse_protocol(response_protocol, &mut self.logger.at(self.log_position()));
 
             if ws1.is_empty() || status_code.is_empty() {
+                // A missing status code is no more numeric than a malformed one.
+                self.response_mut()
+                    .flags
+                    .set(HtpFlags::STATUS_LINE_NON_NUMERIC);
                 return Ok(());
             }
 
             let response_tx = self.response_mut();
             response_tx.response_status = Some(Bstr::from(status_code));
             response_tx.response_status_number = parse_status(status_code);
+            if !status_code.iter().all(|c| c.is_ascii_digit()) {
+                response_tx.flags.set(HtpFlags::STATUS_LINE_NON_NUMERIC);
+            } else if !response_tx.response_status_number.in_range(100, 599) {
+                response_tx.flags.set(HtpFlags::STATUS_LINE_OUT_OF_RANGE);
+                htp_warn!(
+                    self.logger,
+                    HtpLogCode::RESPONSE_LINE_STATUS_OUT_OF_RANGE,
+                    "Response line: status code outside 100-599"
+                );
+            }
 
-            if ws2.is_empty() {
-                return Ok(());
+            let response_tx = self.response_mut();
+            if ws2.is_empty() || message.is_empty() {
+                response_tx.flags.set(HtpFlags::STATUS_LINE_NO_REASON);
+                if ws2.is_empty() {
+                    return Ok(());
+                }
             }
 
             response_tx.response_message = Some(Bstr::from(message));
@@ -68,12 +97,24 @@ impl ConnectionParser {
         &mut self,
         data: &'a [u8],
     ) -> Result<(&'a [u8], bool)> {
-        let rc = self.response_mut().response_header_parser.headers()(data);
+        let offset = self.response_header_offset;
+        let rc = self
+            .response_mut()
+            .response_header_parser
+            .headers_with_spans()(data);
         if let Ok((remaining, (headers, eoh))) = rc {
-            for h in headers {
+            for (h, span) in headers {
                 let mut flags = 0;
                 let name_flags = &h.name.flags;
                 let value_flags = &h.value.flags;
+                // Folding is valid but obsolete; keep track of it.
+                if value_flags.is_set(HeaderFlags::FOLDING) {
+                    flags.set(HtpFlags::FIELD_FOLDED);
+                    let tx = self.response_mut();
+                    tx.flags
+                        .set(HtpFlags::FIELD_FOLDED | HtpFlags::RESPONSE_FIELD_FOLDED);
+                    tx.response_folded_headers = tx.response_folded_headers.wrapping_add(1);
+                }
                 if value_flags.is_set(HeaderFlags::DEFORMED_EOL)
                     || name_flags.is_set(HeaderFlags::DEFORMED_EOL)
                 {
@@ -144,11 +185,31 @@ impl ConnectionParser {
                         HtpFlags::FIELD_INVALID
                     );
                 }
-                self.process_response_header_generic(Header::new_with_flags(
-                    h.name.name.into(),
-                    h.value.value.into(),
-                    flags,
-                ))?;
+                let null_terminated = h.value.flags.is_set(HeaderFlags::NULL_TERMINATED);
+                let mut header =
+                    Header::new_with_flags(h.name.name.into(), h.value.value.into(), flags);
+                let handling = self.response().cfg.response_header_nul;
+                if header.handle_nul(handling) || null_terminated {
+                    htp_warn_once!(
+                        self.logger,
+                        HtpLogCode::RESPONSE_FIELD_RAW_NUL,
+                        "Response field contains NUL bytes",
+                        self.response_mut().flags,
+                        header.flags,
+                        HtpFlags::FIELD_RAW_NUL
+                    );
+                    if handling == HtpHeaderNul::REJECT {
+                        header.flags.set(HtpFlags::FIELD_INVALID);
+                        let tx = self.response_mut();
+                        tx.flags.set(HtpFlags::FIELD_INVALID);
+                    }
+                }
+                header.start_offset = offset.map(|offset| offset + span.start as u64);
+                header.end_offset = offset.map(|offset| offset + span.end as u64);
+                let tx = self.response_mut();
+                header.line = tx.response_header_line;
+                tx.response_header_line += line_count(&data[span]);
+                self.process_response_header_generic(header)?;
             }
             Ok((remaining, eoh))
         } else {
@@ -159,6 +220,32 @@ impl ConnectionParser {
     /// Generic response header line(s) processor, which assembles folded lines
     /// into a single buffer before invoking the parsing function.
     fn process_response_header_generic(&mut self, header: Header) -> Result<()> {
+        let header = if self.response().response_progress > HtpResponseProgress::HEADERS {
+            match self
+                .response_mut()
+                .process_trailer(Direction::Response, header)
+            {
+                Some(header) => header,
+                None => return Ok(()),
+            }
+        } else {
+            header
+        };
+        // Set-Cookie headers are parsed one by one, as their values cannot be joined.
+        if self.response().cfg.parse_response_cookies
+            && header.name.cmp_nocase("Set-Cookie") == Ordering::Equal
+        {
+            if let Some(cookie) = parse_set_cookie(header.value.as_slice()) {
+                if cookie.flags.is_set(CookieFlags::PREFIX_VIOLATION) {
+                    htp_warn!(
+                        self.logger,
+                        HtpLogCode::RESPONSE_COOKIE_PREFIX_VIOLATION,
+                        "Response cookie does not meet its name prefix constraints"
+                    );
+                }
+                self.response_mut().response_cookies.push(cookie);
+            }
+        }
         let mut repeated = false;
         let reps = self.response().response_header_repetitions;
         let mut update_reps = false;
@@ -166,7 +253,7 @@ impl ConnectionParser {
         if let Some((_, h_existing)) = self
             .response_mut()
             .response_headers
-            .get_nocase_mut(header.name.as_slice())
+            .get_nocase_value_mut(header.name.as_slice())
         {
             // Keep track of repeated same-name headers.
             if !h_existing.flags.is_set(HtpFlags::FIELD_REPEATED) {
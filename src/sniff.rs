@@ -0,0 +1,380 @@
+//! Content sniffing of response bodies and protocol sniffing of streams.
+
+use crate::{bstr::Bstr, util::is_token};
+
+/// Enumerates the content types that can be recognized by sniffing.
+/// cbindgen:rename-all=QualifiedScreamingSnakeCase
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum HtpSniffedType {
+    /// Content could not be recognized.
+    UNKNOWN,
+    /// HTML document.
+    HTML,
+    /// PDF document.
+    PDF,
+    /// Windows (PE/MZ) executable.
+    PE,
+    /// ZIP archive, including formats based on ZIP.
+    ZIP,
+    /// GIF image.
+    GIF,
+    /// PNG image.
+    PNG,
+    /// JPEG image.
+    JPEG,
+}
+
+/// HTML tags that identify a document as HTML when found at its start.
+const HTML_TAGS: [&[u8]; 16] = [
+    b"<!doctype html",
+    b"<html",
+    b"<head",
+    b"<script",
+    b"<iframe",
+    b"<h1",
+    b"<div",
+    b"<font",
+    b"<table",
+    b"<a",
+    b"<style",
+    b"<title",
+    b"<b",
+    b"<body",
+    b"<br",
+    b"<p",
+];
+
+/// Media types that are acceptable for any binary content.
+const GENERIC_BINARY: [&[u8]; 2] = [b"application/octet-stream", b"binary/octet-stream"];
+
+impl HtpSniffedType {
+    /// Determines the content type from the leading bytes of a body.
+    pub fn sniff(data: &[u8]) -> Self {
+        if data.starts_with(b"%PDF-") {
+            HtpSniffedType::PDF
+        } else if data.starts_with(b"MZ") {
+            HtpSniffedType::PE
+        } else if data.starts_with(b"PK\x03\x04")
+            || data.starts_with(b"PK\x05\x06")
+            || data.starts_with(b"PK\x07\x08")
+        {
+            HtpSniffedType::ZIP
+        } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+            HtpSniffedType::GIF
+        } else if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+            HtpSniffedType::PNG
+        } else if data.starts_with(b"\xff\xd8\xff") {
+            HtpSniffedType::JPEG
+        } else if is_html(data) {
+            HtpSniffedType::HTML
+        } else {
+            HtpSniffedType::UNKNOWN
+        }
+    }
+
+    /// Determines whether the declared media type (lowercase, without parameters)
+    /// is consistent with the sniffed type. Unknown content matches anything.
+    pub fn matches(self, content_type: &[u8]) -> bool {
+        let binary = GENERIC_BINARY.contains(&content_type);
+        match self {
+            HtpSniffedType::UNKNOWN => true,
+            HtpSniffedType::HTML => {
+                content_type == b"text/html" || content_type == b"application/xhtml+xml"
+            }
+            HtpSniffedType::PDF => binary || content_type == b"application/pdf",
+            HtpSniffedType::PE => {
+                binary
+                    || [
+                        b"application/x-msdownload".as_ref(),
+                        b"application/x-dosexec",
+                        b"application/x-msdos-program",
+                        b"application/x-executable",
+                        b"application/vnd.microsoft.portable-executable",
+                    ]
+                    .contains(&content_type)
+            }
+            HtpSniffedType::ZIP => {
+                binary
+                    || [
+                        b"application/zip".as_ref(),
+                        b"application/x-zip-compressed",
+                        b"application/java-archive",
+                        b"application/vnd.android.package-archive",
+                    ]
+                    .contains(&content_type)
+                    || content_type.starts_with(b"application/vnd.openxmlformats-")
+                    || content_type.starts_with(b"application/vnd.oasis.opendocument.")
+            }
+            HtpSniffedType::GIF => binary || content_type == b"image/gif",
+            HtpSniffedType::PNG => {
+                binary || content_type == b"image/png" || content_type == b"image/apng"
+            }
+            HtpSniffedType::JPEG => {
+                binary
+                    || content_type == b"image/jpeg"
+                    || content_type == b"image/jpg"
+                    || content_type == b"image/pjpeg"
+            }
+        }
+    }
+}
+
+/// Determines whether the data starts with a recognizable HTML tag, ignoring
+/// leading whitespace and a UTF-8 BOM.
+fn is_html(data: &[u8]) -> bool {
+    let data = data.strip_prefix(b"\xef\xbb\xbf").unwrap_or(data);
+    let start = data
+        .iter()
+        .position(|c| !c.is_ascii_whitespace())
+        .unwrap_or(data.len());
+    let data = &data[start..];
+    if data.starts_with(b"<!--") {
+        return true;
+    }
+    HTML_TAGS.iter().any(|tag| {
+        data.len() > tag.len()
+            && data[..tag.len()].eq_ignore_ascii_case(tag)
+            && (data[tag.len()] == b'>' || data[tag.len()].is_ascii_whitespace())
+    })
+}
+
+/// Finds the value of the first charset parameter in the data, lowercased, such as
+/// "utf-8" in "text/html; charset=\"UTF-8\"".
+pub fn charset_param(data: &[u8]) -> Option<Bstr> {
+    let mut start = 0;
+    while let Some(pos) = data[start..]
+        .windows(7)
+        .position(|w| w.eq_ignore_ascii_case(b"charset"))
+    {
+        let name = start + pos;
+        start = name + 7;
+        if name > 0 && (data[name - 1].is_ascii_alphanumeric() || data[name - 1] == b'-') {
+            continue;
+        }
+        let rest = &data[start..];
+        let rest = &rest[rest.iter().take_while(|c| c.is_ascii_whitespace()).count()..];
+        let rest = match rest.split_first() {
+            Some((b'=', rest)) => rest,
+            _ => continue,
+        };
+        let rest = &rest[rest.iter().take_while(|c| c.is_ascii_whitespace()).count()..];
+        let rest = match rest.first() {
+            Some(b'"') | Some(b'\'') => &rest[1..],
+            _ => rest,
+        };
+        let len = rest
+            .iter()
+            .position(|c| matches!(c, b'"' | b'\'' | b';' | b'>' | b'/') || c.is_ascii_whitespace())
+            .unwrap_or(rest.len());
+        if len > 0 {
+            let mut charset = Bstr::from(&rest[..len]);
+            charset.make_ascii_lowercase();
+            return Some(charset);
+        }
+    }
+    None
+}
+
+/// Extracts the charset declared by the first `<meta charset>` or
+/// `<meta http-equiv="Content-Type" content="...; charset=...">` tag of an HTML
+/// document. The document is not parsed; tags are found by a simple scan.
+pub fn meta_charset(data: &[u8]) -> Option<Bstr> {
+    let mut start = 0;
+    while let Some(pos) = data[start..]
+        .windows(5)
+        .position(|w| w.eq_ignore_ascii_case(b"<meta"))
+    {
+        start += pos + 5;
+        if !matches!(data.get(start), Some(c) if c.is_ascii_whitespace() || *c == b'/') {
+            continue;
+        }
+        let end = data[start..]
+            .iter()
+            .position(|c| *c == b'>')
+            .map_or(data.len(), |end| start + end);
+        if let Some(charset) = charset_param(&data[start..end]) {
+            return Some(charset);
+        }
+    }
+    None
+}
+
+/// Determines whether two charset names denote the same charset, ignoring case and
+/// the punctuation that differs between spellings, as in "UTF8" and "utf-8".
+pub fn same_charset(a: &[u8], b: &[u8]) -> bool {
+    let significant = |name: &[u8]| -> Vec<u8> {
+        name.iter()
+            .filter(|c| !matches!(c, b'-' | b'_' | b' '))
+            .map(u8::to_ascii_lowercase)
+            .collect()
+    };
+    significant(a) == significant(b)
+}
+
+/// Determines whether a line starts like an HTTP/1.x status line, as in
+/// "HTTP/1.1 200". Used to spot responses embedded in a response body.
+pub fn looks_like_status_line(line: &[u8]) -> bool {
+    line.len() >= 12
+        && line[..5].eq_ignore_ascii_case(b"HTTP/")
+        && line[5].is_ascii_digit()
+        && line[6] == b'.'
+        && line[7].is_ascii_digit()
+        && line[8] == b' '
+        && line[9..12].iter().all(u8::is_ascii_digit)
+}
+
+/// Determines whether a line looks like a header field: a token name
+/// immediately followed by a colon and a value.
+pub fn looks_like_header_line(line: &[u8]) -> bool {
+    let colon = if let Some(colon) = line.iter().position(|c| *c == b':') {
+        colon
+    } else {
+        return false;
+    };
+    colon > 0
+        && line[..colon].iter().all(|c| is_token(*c))
+        && line[colon + 1..]
+            .iter()
+            .any(|c| !matches!(c, b' ' | b'\t' | b'\r' | b'\n'))
+}
+
+/// Enumerates the non-HTTP protocols that can be recognized at the start of a stream.
+/// cbindgen:rename-all=QualifiedScreamingSnakeCase
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum HtpSniffedProtocol {
+    /// TLS or SSLv2 record.
+    TLS,
+    /// SSH identification string.
+    SSH,
+}
+
+impl HtpSniffedProtocol {
+    /// Determines whether the first bytes of a stream clearly belong to a protocol
+    /// other than HTTP.
+    ///
+    /// Returns None if the data could be HTTP or is too short to tell.
+    pub fn sniff(data: &[u8]) -> Option<Self> {
+        match data {
+            // TLS record: content type, protocol major version 3, minor version 0-4
+            [0x14..=0x17, 0x03, 0x00..=0x04, ..] => Some(HtpSniffedProtocol::TLS),
+            // SSLv2 client hello with a two byte record header
+            [0x80..=0xff, _, 0x01, 0x00, 0x02..=0x03, ..] | [0x80..=0xff, _, 0x01, 0x03, ..] => {
+                Some(HtpSniffedProtocol::TLS)
+            }
+            _ if data.starts_with(b"SSH-") => Some(HtpSniffedProtocol::SSH),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn Sniff() {
+        assert_eq!(HtpSniffedType::PDF, HtpSniffedType::sniff(b"%PDF-1.4\n"));
+        assert_eq!(HtpSniffedType::PE, HtpSniffedType::sniff(b"MZ\x90\x00\x03"));
+        assert_eq!(
+            HtpSniffedType::ZIP,
+            HtpSniffedType::sniff(b"PK\x03\x04\x14\x00")
+        );
+        assert_eq!(
+            HtpSniffedType::GIF,
+            HtpSniffedType::sniff(b"GIF89a\x01\x00")
+        );
+        assert_eq!(
+            HtpSniffedType::PNG,
+            HtpSniffedType::sniff(b"\x89PNG\r\n\x1a\n\x00")
+        );
+        assert_eq!(
+            HtpSniffedType::JPEG,
+            HtpSniffedType::sniff(b"\xff\xd8\xff\xe0")
+        );
+        assert_eq!(
+            HtpSniffedType::HTML,
+            HtpSniffedType::sniff(b"  \r\n<!DOCTYPE html>")
+        );
+        assert_eq!(
+            HtpSniffedType::HTML,
+            HtpSniffedType::sniff(b"\xef\xbb\xbf<html lang=en>")
+        );
+        assert_eq!(HtpSniffedType::HTML, HtpSniffedType::sniff(b"<b>bold</b>"));
+        assert_eq!(HtpSniffedType::UNKNOWN, HtpSniffedType::sniff(b"<bogus>"));
+        assert_eq!(
+            HtpSniffedType::UNKNOWN,
+            HtpSniffedType::sniff(b"Hello World!")
+        );
+        assert_eq!(HtpSniffedType::UNKNOWN, HtpSniffedType::sniff(b""));
+    }
+
+    #[test]
+    fn SniffProtocol() {
+        assert_eq!(
+            Some(HtpSniffedProtocol::TLS),
+            HtpSniffedProtocol::sniff(b"\x16\x03\x01\x02\x00\x01")
+        );
+        assert_eq!(
+            Some(HtpSniffedProtocol::TLS),
+            HtpSniffedProtocol::sniff(b"\x80\x2e\x01\x03\x01")
+        );
+        assert_eq!(
+            Some(HtpSniffedProtocol::SSH),
+            HtpSniffedProtocol::sniff(b"SSH-2.0-OpenSSH_8.9\r\n")
+        );
+        assert_eq!(None, HtpSniffedProtocol::sniff(b"GET / HTTP/1.1\r\n"));
+        assert_eq!(None, HtpSniffedProtocol::sniff(b"HTTP/1.1 200 OK\r\n"));
+        assert_eq!(None, HtpSniffedProtocol::sniff(b"\x16\x03"));
+        assert_eq!(None, HtpSniffedProtocol::sniff(b"SSH"));
+    }
+
+    #[test]
+    fn Matches() {
+        assert!(HtpSniffedType::UNKNOWN.matches(b"text/html"));
+        assert!(HtpSniffedType::HTML.matches(b"text/html"));
+        assert!(!HtpSniffedType::HTML.matches(b"image/png"));
+        assert!(!HtpSniffedType::HTML.matches(b"application/octet-stream"));
+        assert!(HtpSniffedType::PE.matches(b"application/octet-stream"));
+        assert!(!HtpSniffedType::PE.matches(b"image/jpeg"));
+        assert!(HtpSniffedType::ZIP
+            .matches(b"application/vnd.openxmlformats-officedocument.wordprocessingml.document"));
+        assert!(!HtpSniffedType::PNG.matches(b"image/gif"));
+    }
+
+    #[test]
+    fn MetaCharset() {
+        assert_eq!(
+            Some(Bstr::from("utf-8")),
+            meta_charset(b"<html><head><META charset=\"UTF-8\"/>")
+        );
+        assert_eq!(
+            Some(Bstr::from("shift_jis")),
+            meta_charset(
+                b"<meta name=x><meta http-equiv=Content-Type content='text/html; charset=Shift_JIS'>"
+            )
+        );
+        assert_eq!(None, meta_charset(b"<metadata charset=utf-8><meta name=x>"));
+        assert_eq!(None, meta_charset(b"<meta data-charset=utf-8>"));
+        assert_eq!(
+            Some(Bstr::from("iso-8859-1")),
+            charset_param(b"; Charset = iso-8859-1")
+        );
+        assert!(same_charset(b"UTF8", b"utf-8"));
+        assert!(!same_charset(b"utf-8", b"iso-8859-1"));
+    }
+
+    #[test]
+    fn SplittingLines() {
+        assert!(looks_like_status_line(b"HTTP/1.1 200 OK\r\n"));
+        assert!(looks_like_status_line(b"http/1.0 302"));
+        assert!(!looks_like_status_line(b"HTTP/1.1 2xx OK"));
+        assert!(!looks_like_status_line(b" HTTP/1.1 200 OK"));
+        assert!(looks_like_header_line(b"Set-Cookie: a=1\r\n"));
+        assert!(looks_like_header_line(b"Location:/x\n"));
+        assert!(!looks_like_header_line(b"Set-Cookie: \r\n"));
+        assert!(!looks_like_header_line(b"<html>: a\r\n"));
+        assert!(!looks_like_header_line(b"Hello world: a\r\n"));
+    }
+}
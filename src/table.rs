@@ -2,10 +2,59 @@ use crate::bstr::Bstr;
 use std::{cmp::Ordering, iter::Iterator, ops::Index, slice::SliceIndex};
 
 /// The table structure for key value pairs.
-#[derive(Clone, Debug)]
+///
+/// Case-insensitive lookups compare against a folded copy of each key, made when
+/// the tuple is added. A key that was replaced or resized through elements is
+/// compared directly instead, as are all keys after a mutable tuple was handed out,
+/// until the next add.
+#[derive(Debug)]
 pub struct Table<T> {
     /// Entries in the table.
     pub elements: Vec<(Bstr, T)>,
+    /// Folded keys, in the same order as elements.
+    keys: Vec<FoldedKey>,
+    /// Set when keys may have been changed in place through a mutable tuple.
+    stale: bool,
+}
+
+/// A table key in ascii lowercase with any zeros removed.
+#[derive(Clone, Debug)]
+struct FoldedKey {
+    folded: Vec<u8>,
+    has_zero: bool,
+    /// Address and length of the key that was folded.
+    source: (usize, usize),
+}
+
+impl FoldedKey {
+    fn new(key: &Bstr) -> Self {
+        Self {
+            folded: key
+                .iter()
+                .filter(|c| **c != 0)
+                .map(u8::to_ascii_lowercase)
+                .collect(),
+            has_zero: key.contains(&0),
+            source: (key.as_ptr() as usize, key.len()),
+        }
+    }
+
+    /// Returns true if this was folded from the given key.
+    fn is_for(&self, key: &Bstr) -> bool {
+        self.source == (key.as_ptr() as usize, key.len())
+    }
+}
+
+impl<T: Clone> Clone for Table<T> {
+    fn clone(&self) -> Self {
+        let mut table = Self {
+            elements: self.elements.clone(),
+            keys: Vec::new(),
+            stale: false,
+        };
+        table.refold();
+        table
+    }
 }
 
 impl<T> Index<usize> for Table<T> {
@@ -29,10 +78,24 @@ impl<'a, T> IntoIterator for &'a mut Table<T> {
     type IntoIter = std::slice::IterMut<'a, (Bstr, T)>;
 
     fn into_iter(self) -> std::slice::IterMut<'a, (Bstr, T)> {
+        self.stale = true;
         self.elements.iter_mut()
     }
 }
 
+/// Iterates over the tuples of a table, giving mutable access to the values only.
+pub struct IterMut<'a, T> {
+    inner: std::slice::IterMut<'a, (Bstr, T)>,
+}
+
+impl<'a, T> Iterator for IterMut<'a, T> {
+    type Item = (&'a Bstr, &'a mut T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(key, value)| (&*key, value))
+    }
+}
+
 impl<T> IntoIterator for Table<T> {
     type Item = (Bstr, T);
     type IntoIter = std::vec::IntoIter<(Bstr, T)>;
@@ -47,14 +110,108 @@ impl<T> Table<T> {
     pub fn with_capacity(size: usize) -> Self {
         Self {
             elements: Vec::with_capacity(size),
+            keys: Vec::with_capacity(size),
+            stale: false,
         }
     }
 
     /// Add a new tuple (key, item) to the table
     pub fn add(&mut self, key: Bstr, item: T) {
+        if self.stale || self.keys.len() != self.elements.len() {
+            self.refold();
+        }
+        self.keys.push(FoldedKey::new(&key));
         self.elements.push((key, item));
     }
 
+    /// Fold all the keys again.
+    fn refold(&mut self) {
+        self.keys = self
+            .elements
+            .iter()
+            .map(|(key, _)| FoldedKey::new(key))
+            .collect();
+        self.stale = false;
+    }
+
+    /// Returns an iterator over the tuples, in insertion order.
+    pub fn iter(&self) -> std::slice::Iter<'_, (Bstr, T)> {
+        self.elements.iter()
+    }
+
+    /// Returns an iterator over the tuples, in insertion order, with mutable values.
+    pub fn iter_mut(&mut self) -> IterMut<'_, T> {
+        IterMut {
+            inner: self.elements.iter_mut(),
+        }
+    }
+
+    /// Returns the number of tuples the table can hold without reallocating.
+    pub fn capacity(&self) -> usize {
+        self.elements.capacity()
+    }
+
+    /// Shrinks the allocated capacity of the table, keeping at least room for the
+    /// given number of tuples.
+    pub fn shrink_to(&mut self, capacity: usize) {
+        self.elements.shrink_to(capacity);
+        self.keys.shrink_to(capacity);
+    }
+
+    /// Remove all tuples, keeping the allocated capacity.
+    pub fn clear(&mut self) {
+        self.elements.clear();
+        self.keys.clear();
+        self.stale = false;
+    }
+
+    /// Remove all tuples, returning them in insertion order.
+    pub fn take_all(&mut self) -> Vec<(Bstr, T)> {
+        self.keys.clear();
+        self.stale = false;
+        std::mem::take(&mut self.elements)
+    }
+
+    /// Keep only the tuples for which the predicate returns true.
+    pub fn retain<F: FnMut(&(Bstr, T)) -> bool>(&mut self, f: F) {
+        let keep: Vec<bool> = self.elements.iter().map(f).collect();
+        let mut keep_key = keep.iter();
+        self.keys.retain(|_| *keep_key.next().unwrap_or(&true));
+        let mut keep = keep.into_iter();
+        self.elements.retain(|_| keep.next().unwrap_or(true));
+    }
+
+    /// Returns the folded copy of the key at index, if it is still in sync.
+    fn folded(&self, index: usize, key: &Bstr) -> Option<&FoldedKey> {
+        if self.stale {
+            return None;
+        }
+        self.keys.get(index).filter(|folded| folded.is_for(key))
+    }
+
+    /// Returns the index of the first tuple whose key matches, ignoring ascii case.
+    fn position_nocase(&self, key: &[u8]) -> Option<usize> {
+        self.elements
+            .iter()
+            .enumerate()
+            .position(|(i, (name, _))| match self.folded(i, name) {
+                Some(folded) if !folded.has_zero => folded.folded.eq_ignore_ascii_case(key),
+                _ => name.cmp_nocase(key) == Ordering::Equal,
+            })
+    }
+
+    /// Returns the index of the first tuple whose key matches, ignoring ascii case
+    /// and any zeros in the key.
+    fn position_nocase_nozero(&self, key: &[u8]) -> Option<usize> {
+        self.elements
+            .iter()
+            .enumerate()
+            .position(|(i, (name, _))| match self.folded(i, name) {
+                Some(folded) => folded.folded.eq_ignore_ascii_case(key),
+                None => name.cmp_nocase_nozero(key) == Ordering::Equal,
+            })
+    }
+
     /// Retrieve an element from a specific index.
     pub fn get<I>(&self, index: I) -> Option<&I::Output>
     where
@@ -68,43 +225,90 @@ impl<T> Table<T> {
     where
         I: SliceIndex<[(Bstr, T)]>,
     {
+        self.stale = true;
         self.elements.get_mut(index)
     }
 
+    /// Retrieve the key and a mutable reference to the value of the tuple at a
+    /// specific index.
+    pub fn get_value_mut(&mut self, index: usize) -> Option<(&Bstr, &mut T)> {
+        self.elements
+            .get_mut(index)
+            .map(|(key, value)| (&*key, value))
+    }
+
     /// Search the table for the first tuple with a key matching the given slice, ingnoring ascii case in self
     ///
     /// Returns None if no match is found.
     pub fn get_nocase<K: AsRef<[u8]>>(&self, key: K) -> Option<&(Bstr, T)> {
-        self.elements
-            .iter()
-            .find(|x| x.0.cmp_nocase(key.as_ref()) == Ordering::Equal)
+        let index = self.position_nocase(key.as_ref())?;
+        self.elements.get(index)
     }
 
     /// Search the table for the first tuple with a key matching the given slice, ingnoring ascii case in self
     ///
     /// Returns None if no match is found.
     pub fn get_nocase_mut<K: AsRef<[u8]>>(&mut self, key: K) -> Option<&mut (Bstr, T)> {
+        let index = self.position_nocase(key.as_ref())?;
+        self.get_mut(index)
+    }
+
+    /// Search the table for the first tuple with a key matching the given slice, ingnoring ascii case in self,
+    /// and return its key and a mutable reference to its value.
+    ///
+    /// Returns None if no match is found.
+    pub fn get_nocase_value_mut<K: AsRef<[u8]>>(&mut self, key: K) -> Option<(&Bstr, &mut T)> {
+        let index = self.position_nocase(key.as_ref())?;
+        self.get_value_mut(index)
+    }
+
+    /// Search the table for all tuples with a key matching the given slice, ignoring ascii case in self
+    ///
+    /// Matches are returned in insertion order.
+    pub fn get_all_nocase<'a, K: AsRef<[u8]> + 'a>(
+        &'a self,
+        key: K,
+    ) -> impl Iterator<Item = &'a (Bstr, T)> + 'a {
         self.elements
-            .iter_mut()
-            .find(|x| x.0.cmp_nocase(key.as_ref()) == Ordering::Equal)
+            .iter()
+            .filter(move |x| x.0.cmp_nocase(key.as_ref()) == Ordering::Equal)
     }
 
     /// Search the table for the first tuple with a tuple key matching the given slice, ignoring ascii case and any zeros in self
     ///
     /// Returns None if no match is found.
     pub fn get_nocase_nozero<K: AsRef<[u8]>>(&self, key: K) -> Option<&(Bstr, T)> {
-        self.elements
-            .iter()
-            .find(|x| x.0.cmp_nocase_nozero(key.as_ref()) == Ordering::Equal)
+        let index = self.position_nocase_nozero(key.as_ref())?;
+        self.elements.get(index)
     }
 
     /// Search the table for the first tuple with a tuple key matching the given slice, ignoring ascii case and any zeros in self
     ///
     /// Returns None if no match is found.
     pub fn get_nocase_nozero_mut<K: AsRef<[u8]>>(&mut self, key: K) -> Option<&mut (Bstr, T)> {
-        self.elements
-            .iter_mut()
-            .find(|x| x.0.cmp_nocase_nozero(key.as_ref()) == Ordering::Equal)
+        let index = self.position_nocase_nozero(key.as_ref())?;
+        self.get_mut(index)
+    }
+
+    /// Search the table for the first tuple with a tuple key matching the given slice, ignoring ascii case and any zeros in self,
+    /// and return its key and a mutable reference to its value.
+    ///
+    /// Returns None if no match is found.
+    pub fn get_nocase_nozero_value_mut<K: AsRef<[u8]>>(
+        &mut self,
+        key: K,
+    ) -> Option<(&Bstr, &mut T)> {
+        let index = self.position_nocase_nozero(key.as_ref())?;
+        self.get_value_mut(index)
+    }
+
+    /// Remove all tuples with a key matching the given slice, ignoring ascii case in self
+    ///
+    /// Returns the number of removed tuples.
+    pub fn remove_all_nocase<K: AsRef<[u8]>>(&mut self, key: K) -> usize {
+        let size = self.elements.len();
+        self.retain(|x| x.0.cmp_nocase(key.as_ref()) != Ordering::Equal);
+        size - self.elements.len()
     }
 
     /// Returns the number of elements in the table
@@ -115,6 +319,17 @@ impl<T> Table<T> {
 
 // Tests
 
+#[test]
+fn RemoveAllNocase() {
+    let mut t = Table::with_capacity(3);
+    t.add(Bstr::from("Key"), "Value1");
+    t.add(Bstr::from("Other"), "Value2");
+    t.add(Bstr::from("KEY"), "Value3");
+    assert_eq!(2, t.remove_all_nocase("key"));
+    assert_eq!(1, t.size());
+    assert_eq!(0, t.remove_all_nocase("key"));
+}
+
 #[test]
 fn Add() {
     let mut t = Table::with_capacity(1);
@@ -197,6 +412,12 @@ fn IndexAccess() {
     let mut res_mut = t.get_mut(1).unwrap();
     res_mut.1 = "Value3";
     assert_eq!("Value3", t.get(1).unwrap().1);
+
+    let (key, value) = t.get_value_mut(1).unwrap();
+    assert_eq!(Ordering::Equal, key.cmp("KeY2"));
+    *value = "Value4";
+    assert_eq!("Value4", t.get(1).unwrap().1);
+    assert!(t.get_value_mut(2).is_none());
 }
 
 #[test]
@@ -238,3 +459,45 @@ fn Table_Misc() {
     assert!(t.get_nocase("z").is_none());
     assert_eq!("1", t.get_nocase("p").unwrap().1);
 }
+
+#[test]
+fn FoldedKeys() {
+    let mut t = Table::with_capacity(4);
+    t.add(Bstr::from("Host"), 1);
+    t.add(Bstr::from("X-A\x00"), 2);
+    t.add(Bstr::from("HOST"), 3);
+    assert_eq!(1, t.get_nocase("hOsT").unwrap().1);
+    assert_eq!(2, t.get_nocase("x-a\x00").unwrap().1);
+    assert!(t.get_nocase("x-a").is_none());
+    assert_eq!(2, t.get_nocase_nozero("x-a").unwrap().1);
+
+    t.retain(|(_, item)| *item != 1);
+    assert_eq!(3, t.get_nocase("host").unwrap().1);
+    t.get_nocase_mut("host").unwrap().1 = 4;
+    assert_eq!(4, t.get_nocase_nozero("HOST").unwrap().1);
+    for (_, item) in &mut t {
+        *item += 10;
+    }
+    assert_eq!(14, t.get_nocase("host").unwrap().1);
+    assert_eq!(12, t.get_nocase_nozero("X-A").unwrap().1);
+
+    assert_eq!(2, t.take_all().len());
+    assert_eq!(0, t.size());
+    t.add(Bstr::from("Host"), 6);
+    assert_eq!(6, t.get_nocase("host").unwrap().1);
+
+    // Keys changed through elements or a mutable tuple are seen by lookups.
+    t.add(Bstr::from("Accept"), 7);
+    t.elements[0].0 = Bstr::from("Other");
+    assert!(t.get_nocase("host").is_none());
+    assert_eq!(6, t.get_nocase_nozero("OTHER").unwrap().1);
+    t.elements.swap(0, 1);
+    assert_eq!(7, t.get_nocase("accept").unwrap().1);
+    assert_eq!(6, t.get_nocase("other").unwrap().1);
+    t.get_nocase_mut("accept").unwrap().0[0] = b'E';
+    assert_eq!(7, t.get_nocase("ECCEPT").unwrap().1);
+    assert!(t.get_nocase_nozero("accept").is_none());
+    t.add(Bstr::from("Host"), 8);
+    assert_eq!(7, t.get_nocase("eccept").unwrap().1);
+    assert_eq!(8, t.clone().get_nocase("host").unwrap().1);
+}
@@ -0,0 +1,251 @@
+//! Recording and replaying the segmentation of connection data.
+//!
+//! Parser behavior can depend on how the bytes of a stream are split into
+//! request_data and response_data calls. A parser started with
+//! ConnectionParser::start_recording() keeps a Trace of every call it receives,
+//! which can be saved with Trace::to_bytes() and later replayed against a new
+//! parser to reproduce the exact same segmentation.
+
+use crate::{
+    connection_parser::{ConnectionParser, Data},
+    error::Result,
+    HtpStatus,
+};
+
+/// Identifies a trace produced by Trace::to_bytes, including its format version.
+const MAGIC: &[u8] = b"HTPTRACE1";
+
+/// A single call made to a connection parser.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Segment {
+    /// A request_data call with the given data.
+    Request(Vec<u8>),
+    /// A response_data call with the given data.
+    Response(Vec<u8>),
+    /// A request_data call with a gap of the given length.
+    RequestGap(usize),
+    /// A response_data call with a gap of the given length.
+    ResponseGap(usize),
+    /// A request_close call.
+    RequestClose,
+    /// A close call.
+    Close,
+    /// A resume call.
+    Resume,
+    /// A response_close call.
+    ResponseClose,
+}
+
+impl Segment {
+    /// Returns the tag identifying this kind of segment in a serialized trace.
+    fn tag(&self) -> u8 {
+        match self {
+            Segment::Request(_) => 0,
+            Segment::Response(_) => 1,
+            Segment::RequestGap(_) => 2,
+            Segment::ResponseGap(_) => 3,
+            Segment::RequestClose => 4,
+            Segment::Close => 5,
+            Segment::Resume => 6,
+            Segment::ResponseClose => 7,
+        }
+    }
+}
+
+/// The sequence of calls made to a connection parser. Timestamps and connection
+/// addresses are not recorded.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Trace {
+    /// The calls, in the order they were made.
+    pub segments: Vec<Segment>,
+}
+
+impl Trace {
+    /// Returns a new, empty trace.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a request_data or response_data call.
+    pub(crate) fn push_data(&mut self, request: bool, chunk: &Data) {
+        let segment = match (request, chunk.is_gap()) {
+            (true, false) => Segment::Request(chunk.as_slice().to_vec()),
+            (false, false) => Segment::Response(chunk.as_slice().to_vec()),
+            (true, true) => Segment::RequestGap(chunk.len()),
+            (false, true) => Segment::ResponseGap(chunk.len()),
+        };
+        self.segments.push(segment);
+    }
+
+    /// Serializes the trace into a compact binary form: each segment is a tag
+    /// byte, followed for data and gaps by a variable-length length and the data.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        for segment in &self.segments {
+            out.push(segment.tag());
+            match segment {
+                Segment::Request(data) | Segment::Response(data) => {
+                    write_varint(&mut out, data.len() as u64);
+                    out.extend_from_slice(data);
+                }
+                Segment::RequestGap(len) | Segment::ResponseGap(len) => {
+                    write_varint(&mut out, *len as u64)
+                }
+                _ => {}
+            }
+        }
+        out
+    }
+
+    /// Parses a trace produced by to_bytes.
+    ///
+    /// Returns HtpStatus::ERROR if the data is not a complete trace.
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        if !data.starts_with(MAGIC) {
+            return Err(HtpStatus::ERROR);
+        }
+        let mut rest = &data[MAGIC.len()..];
+        let mut trace = Trace::new();
+        while let Some((&tag, tail)) = rest.split_first() {
+            rest = tail;
+            let segment = match tag {
+                0 | 1 => {
+                    let len = read_varint(&mut rest)? as usize;
+                    if len > rest.len() {
+                        return Err(HtpStatus::ERROR);
+                    }
+                    let (chunk, tail) = rest.split_at(len);
+                    rest = tail;
+                    if tag == 0 {
+                        Segment::Request(chunk.to_vec())
+                    } else {
+                        Segment::Response(chunk.to_vec())
+                    }
+                }
+                2 => Segment::RequestGap(read_varint(&mut rest)? as usize),
+                3 => Segment::ResponseGap(read_varint(&mut rest)? as usize),
+                4 => Segment::RequestClose,
+                5 => Segment::Close,
+                6 => Segment::Resume,
+                7 => Segment::ResponseClose,
+                _ => return Err(HtpStatus::ERROR),
+            };
+            trace.segments.push(segment);
+        }
+        Ok(trace)
+    }
+
+    /// Makes the recorded calls, in order, on a parser that was already opened.
+    pub fn replay(&self, connp: &mut ConnectionParser) {
+        for segment in &self.segments {
+            match segment {
+                Segment::Request(data) => {
+                    connp.request_data(data.into(), None);
+                }
+                Segment::Response(data) => {
+                    connp.response_data(data.into(), None);
+                }
+                Segment::RequestGap(len) => {
+                    connp.request_data((*len).into(), None);
+                }
+                Segment::ResponseGap(len) => {
+                    connp.response_data((*len).into(), None);
+                }
+                Segment::RequestClose => connp.request_close(None),
+                Segment::Close => connp.close(None),
+                Segment::Resume => {
+                    connp.resume();
+                }
+                Segment::ResponseClose => connp.response_close(None),
+            }
+        }
+    }
+}
+
+/// Appends `value` as a little-endian base 128 varint.
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Reads a little-endian base 128 varint from the start of `data`, advancing it.
+fn read_varint(data: &mut &[u8]) -> Result<u64> {
+    let mut value = 0u64;
+    for (i, byte) in data.iter().enumerate().take(10) {
+        value |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            *data = &data[i + 1..];
+            return Ok(value);
+        }
+    }
+    Err(HtpStatus::ERROR)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{config::Config, transaction::HtpResponseProgress};
+
+    #[test]
+    fn RecordReplay() {
+        let mut connp = ConnectionParser::new(Config::default());
+        connp.open(None, None, None, None, None);
+        connp.start_recording();
+        connp.request_data(b"GET / HTTP/1.1\r\nHo"[..].into(), None);
+        connp.request_data(b"st: a\r\n\r\nPOST / HTTP/1.1\r\n"[..].into(), None);
+        connp.request_data(b"Content-Length: 200\r\n\r\n"[..].into(), None);
+        connp.request_data(200.into(), None);
+        connp.response_data(
+            b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nOK"[..].into(),
+            None,
+        );
+        connp.close(None);
+        let trace = connp.take_trace().unwrap();
+        assert_eq!(6, trace.segments.len());
+        assert_eq!(Segment::RequestGap(200), trace.segments[3]);
+        assert_eq!(Segment::Close, trace.segments[5]);
+        assert!(connp.take_trace().is_none());
+
+        let bytes = trace.to_bytes();
+        assert_eq!(Ok(trace.clone()), Trace::from_bytes(&bytes));
+        assert!(Trace::from_bytes(&bytes[..bytes.len() - 3]).is_err());
+        assert!(Trace::from_bytes(b"GET / HTTP/1.1").is_err());
+
+        let mut replayed = ConnectionParser::new(Config::default());
+        replayed.open(None, None, None, None, None);
+        trace.replay(&mut replayed);
+        assert_eq!(connp.tx_size(), replayed.tx_size());
+        let tx = replayed.tx(1).unwrap();
+        assert_eq!(200, tx.request_message_len);
+        assert!(tx.request_method.as_ref().unwrap().eq("POST"));
+    }
+
+    #[test]
+    fn RecordReplayResponseClose() {
+        let mut connp = ConnectionParser::new(Config::default());
+        connp.open(None, None, None, None, None);
+        connp.start_recording();
+        connp.request_data(b"GET / HTTP/1.0\r\n\r\n"[..].into(), None);
+        connp.response_data(
+            b"HTTP/1.0 200 OK\r\nServer: a\r\n\r\nHello"[..].into(),
+            None,
+        );
+        connp.response_close(None);
+        let trace = connp.take_trace().unwrap();
+        assert_eq!(3, trace.segments.len());
+        assert_eq!(Segment::ResponseClose, trace.segments[2]);
+
+        let bytes = trace.to_bytes();
+        assert_eq!(Ok(trace.clone()), Trace::from_bytes(&bytes));
+
+        let mut replayed = ConnectionParser::new(Config::default());
+        replayed.open(None, None, None, None, None);
+        trace.replay(&mut replayed);
+        let tx = replayed.tx(0).unwrap();
+        assert_eq!(HtpResponseProgress::COMPLETE, tx.response_progress);
+        assert_eq!(5, tx.response_message_len);
+    }
+}
@@ -1,32 +1,76 @@
 use crate::{
     bstr::Bstr,
-    config::{Config, HtpUnwanted},
+    config::{
+        Config, HtpBodyLimitAction, HtpHeaderNul, HtpParamDuplicates, HtpTrailerPolicy, HtpUnwanted,
+    },
     connection_parser::{ConnectionParser, Data as ParserData, HtpStreamState, State},
-    decompressors::{Decompressor, HtpContentEncoding},
+    decompressors::{Decompressor, HtpContentEncoding, LayerSizes},
     error::Result,
+    event::{Direction, Event},
     headers::{Parser as HeaderParser, Side},
-    hook::{DataHook, DataNativeCallbackFn},
+    hook::DataHook,
     list::List,
-    log::Logger,
+    log::{LogContext, LogPosition, Logger},
+    metrics::Metrics,
     multipart::{find_boundary, HtpMultipartType, Parser as MultipartParser},
     parsers::{
-        parse_authorization, parse_content_length, parse_content_type, parse_cookies_v0,
-        parse_hostport,
+        modern_headers::ModernHeaders, parse_alt_svc, parse_authenticate, parse_authorization,
+        parse_content_disposition, parse_content_length, parse_content_type, parse_cookies_v0,
+        parse_entity_tag, parse_entity_tag_list, parse_forwarded_headers, parse_hostport,
+        parse_http_date, parse_proxy_authorization, parse_quality_list, parse_range,
+        parse_transfer_encoding, security_headers::SecurityHeaders,
+    },
+    request::{HtpMethod, MethodFlags},
+    sniff::{
+        charset_param, looks_like_header_line, looks_like_status_line, meta_charset, same_charset,
+        HtpSniffedType,
     },
-    request::HtpMethod,
     table::Table,
-    uri::Uri,
-    urlencoded::Parser as UrlEncodedParser,
-    util::{validate_hostname, File, FlagOperations, HtpFileSource, HtpFlags},
+    uri::{split_path_parameters, PathFlags, Uri},
+    urlencoded::{
+        ParamValue as UrlEncodedParamValue, Parser as UrlEncodedParser,
+        RawParam as UrlEncodedRawParam,
+    },
+    utf8_decoder::Utf8Decoder,
+    util::{validate_hostname, File, FileFlags, FlagOperations, HtpFileSource, HtpFlags},
     HtpStatus,
 };
 
-use std::{any::Any, cmp::Ordering, mem::take, rc::Rc};
+use chrono::{DateTime, Utc};
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use std::{
+    any::Any,
+    cmp::Ordering,
+    collections::{hash_map::RandomState, HashMap},
+    hash::{BuildHasher, Hasher},
+    marker::PhantomData,
+    net::IpAddr,
+    sync::Arc,
+};
+
+/// How many leading bytes of each response body line are kept for the response
+/// splitting scan; enough for a status line or a header name and the start of its value.
+const RESPONSE_SPLITTING_LINE_LEN: usize = 256;
 
-/// A collection of possible data sources.
+/// Enumerates how much of a transaction was analyzed under the configured
+/// sampling rate and connection body budget.
 /// cbindgen:rename-all=QualifiedScreamingSnakeCase
 #[repr(C)]
 #[derive(Copy, Clone, PartialEq, Debug)]
+pub enum HtpAnalysisDepth {
+    /// Headers and bodies were analyzed.
+    FULL,
+    /// Body analysis stopped partway because the connection body budget ran out.
+    BODY_TRUNCATED,
+    /// Only headers and framing were analyzed; the bodies were skipped.
+    HEADERS_ONLY,
+}
+
+/// A collection of possible data sources.
+/// cbindgen:rename-all=QualifiedScreamingSnakeCase
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub enum HtpDataSource {
     /// Embedded in the URL.
     URL,
@@ -38,6 +82,32 @@ pub enum HtpDataSource {
     BODY,
 }
 
+/// Export parameter name normalization steps
+#[derive(Debug)]
+pub struct ParamNameNormalization;
+
+impl ParamNameNormalization {
+    /// Truncate the name at the first NUL byte.
+    pub const TRUNCATE_AT_NUL: u64 = 0x0001;
+    /// Remove leading spaces.
+    pub const TRIM_LEADING_SPACES: u64 = 0x0002;
+    /// Replace spaces and dots before the first '[' with underscores.
+    pub const SPACE_DOT_TO_UNDERSCORE: u64 = 0x0004;
+    /// Replace a '[' that has no matching ']' with an underscore.
+    pub const UNMATCHED_BRACKET: u64 = 0x0008;
+    /// Reduce an array name such as "a[b][c]" to its base name "a".
+    pub const ARRAY_BASE: u64 = 0x0010;
+    /// Convert the name to lowercase, for frameworks that look parameters up
+    /// case-insensitively.
+    pub const LOWERCASE: u64 = 0x0020;
+    /// The steps PHP applies when it registers a request variable.
+    pub const PHP: u64 = Self::TRUNCATE_AT_NUL
+        | Self::TRIM_LEADING_SPACES
+        | Self::SPACE_DOT_TO_UNDERSCORE
+        | Self::UNMATCHED_BRACKET
+        | Self::ARRAY_BASE;
+}
+
 /// Represents a single request parameter.
 #[derive(Clone, Debug)]
 pub struct Param {
@@ -47,42 +117,242 @@ pub struct Param {
     pub value: Bstr,
     /// Source of the parameter, for example QUERY_STRING.
     pub source: HtpDataSource,
+    /// Position of the parameter among all the parameters of the transaction,
+    /// in the order they were added.
+    pub ordinal: usize,
+    /// Decoding anomalies seen in this parameter (URLEN_* flags).
+    pub flags: u64,
+    /// The parameter as seen on the wire, with its offsets and UrlencodedFlags,
+    /// for urlencoded parameters. None for parameters from other sources. For a
+    /// joined parameter, this is the first occurrence.
+    pub raw: Option<UrlEncodedRawParam>,
+    /// The raw forms of the later occurrences joined into this parameter, in order,
+    /// when duplicates are joined (see Config::set_param_duplicates).
+    pub joined_raw: Vec<UrlEncodedRawParam>,
+    /// Parameter name after the steps of Config::param_name_normalization, or
+    /// None when no steps are configured.
+    pub normalized_name: Option<Bstr>,
 }
 
 impl Param {
-    /// Make a new owned Param
+    /// Make a new owned Param, without a raw form.
     pub fn new(name: Bstr, value: Bstr, source: HtpDataSource) -> Self {
         Param {
             name,
             value,
             source,
+            ordinal: 0,
+            flags: 0,
+            raw: None,
+            joined_raw: Vec::new(),
+            normalized_name: None,
+        }
+    }
+
+    /// Make a new owned Param from a decoded urlencoded parameter and its raw form.
+    pub fn from_urlencoded(
+        name: Bstr,
+        value: Bstr,
+        raw: UrlEncodedRawParam,
+        source: HtpDataSource,
+    ) -> Self {
+        Param {
+            name,
+            value,
+            source,
+            ordinal: 0,
+            flags: raw.flags,
+            raw: Some(raw),
+            joined_raw: Vec::new(),
+            normalized_name: None,
+        }
+    }
+
+    /// Sets normalized_name by applying the given ParamNameNormalization steps to
+    /// the decoded name, as a server-side framework would.
+    pub fn normalize_name(&mut self, steps: u64) {
+        if steps == 0 {
+            return;
+        }
+        let mut name = self.name.as_slice();
+        if steps.is_set(ParamNameNormalization::TRUNCATE_AT_NUL) {
+            if let Some(nul) = name.iter().position(|c| *c == 0) {
+                name = &name[..nul];
+            }
+        }
+        if steps.is_set(ParamNameNormalization::TRIM_LEADING_SPACES) {
+            let start = name.iter().position(|c| *c != b' ').unwrap_or(name.len());
+            name = &name[start..];
+        }
+        let (base, index) =
+            name.split_at(name.iter().position(|c| *c == b'[').unwrap_or(name.len()));
+        let mut normalized: Vec<u8> = base
+            .iter()
+            .map(|c| match c {
+                b' ' | b'.' if steps.is_set(ParamNameNormalization::SPACE_DOT_TO_UNDERSCORE) => {
+                    b'_'
+                }
+                _ => *c,
+            })
+            .collect();
+        if !index.is_empty() {
+            if index.contains(&b']') {
+                if !steps.is_set(ParamNameNormalization::ARRAY_BASE) {
+                    normalized.extend_from_slice(index);
+                }
+            } else if steps.is_set(ParamNameNormalization::UNMATCHED_BRACKET) {
+                // The remainder of the name is kept as is.
+                normalized.push(b'_');
+                normalized.extend_from_slice(&index[1..]);
+            } else {
+                normalized.extend_from_slice(index);
+            }
         }
+        if steps.is_set(ParamNameNormalization::LOWERCASE) {
+            normalized.make_ascii_lowercase();
+        }
+        self.normalized_name = Some(Bstr::from(normalized));
+    }
+
+    /// Returns normalized_name if set, or the decoded name.
+    pub fn effective_name(&self) -> &Bstr {
+        self.normalized_name.as_ref().unwrap_or(&self.name)
     }
 }
 
-#[derive(Debug, Clone)]
+/// The action recommended for a transaction, based on how the configured server
+/// personality is expected to react to the anomalies seen in the request.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct RecommendedAction {
+    /// HTTP status code the server is expected to respond with.
+    pub status: u16,
+    /// Reason phrase matching the status code.
+    pub reason: &'static str,
+    /// The transaction flags that drove the recommendation. Empty when only
+    /// anomalies without a flag, such as control characters, call for it.
+    pub flags: u64,
+}
+
 /// This structure is used to pass transaction data (for example
 /// request and response body buffers) to callbacks.
 pub struct Data<'a> {
     /// Transaction pointer.
     tx: *mut Transaction,
+    /// Marks the Data as borrowing the transaction for its lifetime.
+    _tx: PhantomData<&'a mut Transaction>,
     /// Ref to the parser data.
     data: &'a ParserData<'a>,
     /// Indicator if this chunk of data is the last in the series. Currently
     /// used only by REQUEST_HEADER_DATA, REQUEST_TRAILER_DATA, RESPONSE_HEADER_DATA,
     /// and RESPONSE_TRAILER_DATA callbacks.
     is_last: bool,
+    /// Direction of the stream the data was taken from.
+    direction: Direction,
+    /// Offset of the data in its direction of the connection stream.
+    stream_offset: Option<u64>,
+    /// Offset of the data among the data of the same kind passed for the transaction.
+    offset: u64,
+    /// Sequence number of the chunk among the chunks passed for the transaction in
+    /// the same direction.
+    sequence: u64,
 }
 
 impl<'a> Data<'a> {
-    /// Construct a new Data.
-    pub fn new(tx: *mut Transaction, data: &'a ParserData<'a>, is_last: bool) -> Self {
-        Self { tx, data, is_last }
+    /// Construct a new Data. This is an unsafe fn, because the Data hands out
+    /// references to the transaction behind `tx`.
+    ///
+    /// # Safety
+    ///
+    /// `tx` must be null or point to a Transaction that stays valid for `'a`.
+    /// While the Data is alive, the transaction must only be accessed through it:
+    /// the caller must not use any other reference or pointer to the transaction,
+    /// including one reached through the ConnectionParser that owns it, until the
+    /// Data is dropped.
+    pub unsafe fn new(tx: *mut Transaction, data: &'a ParserData<'a>, is_last: bool) -> Self {
+        Self {
+            tx,
+            _tx: PhantomData,
+            data,
+            is_last,
+            direction: Direction::Request,
+            stream_offset: None,
+            offset: 0,
+            sequence: 0,
+        }
+    }
+
+    /// Sets the offset of the data in its direction of the connection stream.
+    pub(crate) fn with_stream_offset(mut self, stream_offset: Option<u64>) -> Self {
+        self.stream_offset = stream_offset;
+        self
+    }
+
+    /// Records where the data comes from before it is passed to callbacks. The
+    /// sequence number is taken from the transaction. Body data is given its
+    /// offset by the transaction too, when offset is None.
+    pub(crate) fn set_position(
+        &mut self,
+        direction: Direction,
+        stream_offset: Option<u64>,
+        offset: Option<u64>,
+    ) {
+        let len = self.len() as u64;
+        let (sequence, body_offset) = match self.transaction_mut() {
+            Some(tx) => {
+                let (sequence, body_offset) = match direction {
+                    Direction::Request => (
+                        &mut tx.request_data_sequence,
+                        &mut tx.request_body_data_offset,
+                    ),
+                    Direction::Response => (
+                        &mut tx.response_data_sequence,
+                        &mut tx.response_body_data_offset,
+                    ),
+                };
+                let current = (*sequence, *body_offset);
+                *sequence += 1;
+                if offset.is_none() {
+                    *body_offset += len;
+                }
+                current
+            }
+            None => (0, 0),
+        };
+        self.direction = direction;
+        self.stream_offset = stream_offset;
+        self.offset = offset.unwrap_or(body_offset);
+        self.sequence = sequence;
+    }
+
+    /// Returns the transaction associated with the Data, as transaction() does.
+    /// This no longer returns a raw pointer, so callers need no unsafe code.
+    pub fn tx(&self) -> Option<&Transaction> {
+        self.transaction()
     }
 
     /// Returns the transaction associated with the Data.
-    pub fn tx(&self) -> *mut Transaction {
-        self.tx
+    pub fn transaction(&self) -> Option<&Transaction> {
+        // Data::new requires the pointer to be null or valid and unaliased for 'a.
+        unsafe { self.tx.as_ref() }
+    }
+
+    /// Returns the transaction associated with the Data for modification, for
+    /// example to update its user data.
+    pub fn transaction_mut(&mut self) -> Option<&mut Transaction> {
+        // Data::new requires the pointer to be null or valid and unaliased for 'a.
+        unsafe { self.tx.as_mut() }
+    }
+
+    /// Returns the id of the transaction associated with the Data.
+    pub fn tx_id(&self) -> Option<TxId> {
+        self.transaction().map(Transaction::id)
+    }
+
+    /// Pauses the transaction associated with the Data.
+    pub fn pause(&mut self) {
+        if let Some(tx) = self.transaction_mut() {
+            tx.paused = true;
+        }
     }
 
     /// Returns a pointer to the raw data associated with Data.
@@ -96,7 +366,7 @@ impl<'a> Data<'a> {
     }
 
     /// Return an immutable slice view of the data.
-    pub fn as_slice(&self) -> Option<&[u8]> {
+    pub fn as_slice(&self) -> Option<&'a [u8]> {
         self.data.data()
     }
 
@@ -105,12 +375,49 @@ impl<'a> Data<'a> {
         self.is_last
     }
 
+    /// Returns the direction of the stream the data was taken from.
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    /// Returns the offset of the data in its direction of the connection stream,
+    /// when it is passed as received. Decompressed data and gaps have none.
+    pub fn stream_offset(&self) -> Option<u64> {
+        self.stream_offset
+    }
+
+    /// Returns the offset of the data among the data of the same kind passed for
+    /// the transaction: its raw headers, its raw trailer or its body.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Returns the sequence number of the chunk among all the chunks passed to
+    /// callbacks for the transaction in the same direction, starting at 0.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
     /// Determine whether this data is empty.
     pub fn is_empty(&self) -> bool {
         self.len() == 0
     }
 }
 
+impl std::fmt::Debug for Data<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Data")
+            .field("tx", &self.tx_id())
+            .field("data", &self.data)
+            .field("is_last", &self.is_last)
+            .field("direction", &self.direction)
+            .field("stream_offset", &self.stream_offset)
+            .field("offset", &self.offset)
+            .field("sequence", &self.sequence)
+            .finish()
+    }
+}
+
 /// Enumerates the possible request and response body codings.
 /// cbindgen:rename-all=QualifiedScreamingSnakeCase
 #[repr(C)]
@@ -130,6 +437,23 @@ pub enum HtpTransferCoding {
     ERROR,
 }
 
+/// Enumerates the ways in which the end of a response body was found.
+/// cbindgen:rename-all=QualifiedScreamingSnakeCase
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum HtpResponseDelimiter {
+    /// The response is not complete.
+    UNKNOWN,
+    /// The response has no body.
+    NONE,
+    /// The body ended after the number of bytes in Content-Length.
+    CONTENT_LENGTH,
+    /// The body ended with the last chunk.
+    CHUNKED,
+    /// The body ended when the connection was closed.
+    CLOSE,
+}
+
 /// Enumerates the possible server personalities.
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub enum HtpResponseNumber {
@@ -171,11 +495,61 @@ pub struct Header {
     pub value: Bstr,
     /// Parsing flags; a combination of: HTP_FIELD_INVALID, HTP_FIELD_FOLDED, HTP_FIELD_REPEATED.
     pub flags: u64,
+    /// Index of the line the header starts on, where the request or status line is
+    /// line 0. Trailers are numbered on from the last header line. For repeated
+    /// headers, this is the first occurrence.
+    pub line: usize,
+    /// Offset in the connection stream of the first byte of the header, if known.
+    pub start_offset: Option<u64>,
+    /// Offset in the connection stream just past the line terminator of the header,
+    /// if known.
+    pub end_offset: Option<u64>,
+    /// The value as received, if it contained NUL bytes.
+    pub raw_value: Option<Bstr>,
+}
+
+/// Export trailer flags
+#[derive(Debug)]
+pub struct TrailerFlags;
+
+impl TrailerFlags {
+    /// A trailer field carries message framing (Content-Length or Transfer-Encoding).
+    pub const FRAMING_FIELD: u64 = 0x0001;
+    /// A trailer field carries routing information (Host).
+    pub const ROUTING_FIELD: u64 = 0x0002;
+    /// Trailer fields were discarded, as configured.
+    pub const IGNORED: u64 = 0x0004;
 }
 
 /// Table of request or response headers.
 pub type Headers = Table<Header>;
 
+/// Fingerprint of the order and casing of the header names in a message, which
+/// tends to be characteristic of the client or server software that sent it.
+#[derive(Clone, Debug)]
+pub struct HeaderFingerprint {
+    /// Header names in the order they were first seen, with their original
+    /// casing, separated by commas.
+    pub names: Bstr,
+    /// MD5 of the names, as lowercase hex, for compact comparison.
+    pub hash: String,
+}
+
+impl HeaderFingerprint {
+    /// Compute the fingerprint of the given headers.
+    pub fn new(headers: &Headers) -> Self {
+        let mut names = Bstr::with_capacity(256);
+        for (i, (_, header)) in headers.into_iter().enumerate() {
+            if i > 0 {
+                names.add(",");
+            }
+            names.add(header.name.as_slice());
+        }
+        let hash = format!("{:x}", Md5::digest(names.as_slice()));
+        Self { names, hash }
+    }
+}
+
 impl Header {
     /// Construct a new header.
     pub fn new(name: Bstr, value: Bstr) -> Self {
@@ -184,7 +558,34 @@ impl Header {
 
     /// Construct a new header with flags.
     pub fn new_with_flags(name: Bstr, value: Bstr, flags: u64) -> Self {
-        Self { name, value, flags }
+        Self {
+            name,
+            value,
+            flags,
+            line: 0,
+            start_offset: None,
+            end_offset: None,
+            raw_value: None,
+        }
+    }
+
+    /// Applies the given handling of NUL bytes to the value, keeping the value as
+    /// received in raw_value. Returns true if the value contained a NUL byte.
+    pub(crate) fn handle_nul(&mut self, handling: HtpHeaderNul) -> bool {
+        let nul = if let Some(nul) = self.value.iter().position(|c| *c == 0) {
+            nul
+        } else {
+            return false;
+        };
+        self.raw_value = Some(self.value.clone());
+        if handling != HtpHeaderNul::KEEP {
+            let mut end = nul;
+            while end > 0 && matches!(self.value[end - 1], b' ' | b'\t') {
+                end -= 1;
+            }
+            self.value.truncate(end);
+        }
+        true
     }
 }
 
@@ -257,12 +658,446 @@ pub enum HtpAuthType {
     DIGEST,
     /// HTTP Bearer authentication used.
     BEARER,
+    /// NTLM authentication used.
+    NTLM,
+    /// Negotiate (SPNEGO) authentication used.
+    NEGOTIATE,
     /// Unrecognized authentication method.
     UNRECOGNIZED = 9,
     /// Error retrieving the auth type.
     ERROR,
 }
 
+/// Enumerates the NTLM message types exchanged during an NTLM handshake.
+/// cbindgen:rename-all=QualifiedScreamingSnakeCase
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum HtpNtlmMessageType {
+    /// Type 1 message, sent by the client to start the handshake.
+    NEGOTIATE = 1,
+    /// Type 2 message, the server challenge.
+    CHALLENGE = 2,
+    /// Type 3 message, the client response to the challenge.
+    AUTHENTICATE = 3,
+}
+
+/// Export Digest authentication flags
+#[derive(Debug)]
+pub struct DigestFlags;
+
+impl DigestFlags {
+    /// A required field (username, realm, nonce, uri or response) is missing.
+    pub const MISSING_FIELD: u64 = 0x0001;
+    /// A field appears more than once. The first occurrence is used.
+    pub const DUPLICATE_FIELD: u64 = 0x0002;
+    /// A field that must be a quoted string was not quoted.
+    pub const UNQUOTED_VALUE: u64 = 0x0004;
+    /// A field is not defined by RFC 7616.
+    pub const UNKNOWN_FIELD: u64 = 0x0008;
+}
+
+/// Represents the parameters of a Digest Authorization header (RFC 7616).
+#[derive(Clone, Debug, Default)]
+pub struct DigestAuth {
+    /// User name, or its hash when userhash is true.
+    pub username: Option<Bstr>,
+    /// Protection space the credentials apply to.
+    pub realm: Option<Bstr>,
+    /// Server-specified nonce.
+    pub nonce: Option<Bstr>,
+    /// Effective request URI.
+    pub uri: Option<Bstr>,
+    /// Hex-encoded response digest.
+    pub response: Option<Bstr>,
+    /// Digest algorithm, for example MD5 or SHA-256.
+    pub algorithm: Option<Bstr>,
+    /// Client nonce.
+    pub cnonce: Option<Bstr>,
+    /// Opaque data supplied by the server.
+    pub opaque: Option<Bstr>,
+    /// Quality of protection.
+    pub qop: Option<Bstr>,
+    /// Nonce count.
+    pub nc: Option<Bstr>,
+    /// Whether the username is hashed.
+    pub userhash: Option<Bstr>,
+    /// Parsing flags (DigestFlags).
+    pub flags: u64,
+}
+
+/// Represents the credentials carried by an Authorization or Proxy-Authorization header.
+#[derive(Clone, Debug)]
+pub struct Credentials {
+    /// Authentication type.
+    pub auth_type: HtpAuthType,
+    /// Authentication username.
+    pub username: Option<Bstr>,
+    /// Authentication password. Available only for Basic authentication.
+    pub password: Option<Bstr>,
+    /// Authentication token. Available only for Bearer, NTLM and Negotiate authentication.
+    pub token: Option<Bstr>,
+    /// Digest parameters. Available only for Digest authentication.
+    pub digest: Option<DigestAuth>,
+    /// NTLM message carried in the token, if any.
+    pub ntlm_message: Option<HtpNtlmMessageType>,
+}
+
+impl Default for Credentials {
+    fn default() -> Self {
+        Self {
+            auth_type: HtpAuthType::UNKNOWN,
+            username: None,
+            password: None,
+            token: None,
+            digest: None,
+            ntlm_message: None,
+        }
+    }
+}
+
+/// Represents a single challenge from a WWW-Authenticate or Proxy-Authenticate header.
+#[derive(Clone, Debug)]
+pub struct AuthChallenge {
+    /// Authentication scheme, as seen in the header.
+    pub scheme: Bstr,
+    /// The token68 form of the challenge data (for example an NTLM challenge), if used.
+    pub token: Option<Bstr>,
+    /// The value of the realm parameter, if present.
+    pub realm: Option<Bstr>,
+    /// All the challenge parameters, in the order they were seen.
+    pub params: Table<Bstr>,
+}
+
+/// Export Content-Disposition flags
+#[derive(Debug)]
+pub struct ContentDispositionFlags;
+
+impl ContentDispositionFlags {
+    /// The header does not follow the RFC 6266 syntax.
+    pub const SYNTAX_INVALID: u64 = 0x0001;
+    /// A parameter appears more than once. The first occurrence is used.
+    pub const PARAM_REPEATED: u64 = 0x0002;
+    /// An extended (RFC 5987) parameter value could not be decoded.
+    pub const EXT_VALUE_INVALID: u64 = 0x0004;
+    /// The filename and filename* parameters are both present but disagree.
+    pub const FILENAME_CONFLICT: u64 = 0x0008;
+}
+
+/// Represents a parsed Content-Disposition header (RFC 6266).
+#[derive(Clone, Debug)]
+pub struct ContentDisposition {
+    /// Disposition type (e.g. inline or attachment), converted to lowercase.
+    pub disposition_type: Bstr,
+    /// The filename, taken from the decoded filename* parameter when available
+    /// and from the filename parameter otherwise.
+    pub filename: Option<Bstr>,
+    /// All the parameters, in the order they were seen. Extended parameter values
+    /// are stored decoded, under their name including the trailing asterisk.
+    pub params: Table<Bstr>,
+    /// Parsing flags; a combination of ContentDispositionFlags.
+    pub flags: u64,
+}
+
+/// Enumerates the headers that describe forwarding hops.
+/// cbindgen:rename-all=QualifiedScreamingSnakeCase
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum HtpForwardedSource {
+    /// Forwarded header (RFC 7239).
+    FORWARDED,
+    /// X-Forwarded-For header.
+    X_FORWARDED_FOR,
+    /// X-Real-IP header.
+    X_REAL_IP,
+}
+
+/// Export forwarding hop flags
+#[derive(Debug)]
+pub struct ForwardedFlags;
+
+impl ForwardedFlags {
+    /// The node is not an IP address, "unknown" or an obfuscated identifier.
+    pub const INVALID_NODE: u64 = 0x0001;
+    /// The node port is not a valid port number or an obfuscated identifier.
+    pub const INVALID_PORT: u64 = 0x0002;
+    /// The element is not a well-formed list of name=value pairs.
+    pub const MALFORMED: u64 = 0x0004;
+    /// A parameter appears more than once. The first occurrence is used.
+    pub const DUPLICATE_PARAM: u64 = 0x0008;
+    /// A parameter is not defined by RFC 7239.
+    pub const UNKNOWN_PARAM: u64 = 0x0010;
+}
+
+/// Export request cookie flags
+#[derive(Debug)]
+pub struct CookieFlags;
+
+impl CookieFlags {
+    /// The cookie has no '=' and is taken as a name with an empty value.
+    pub const BARE: u64 = 0x0001;
+    /// The cookie has an empty name. Such cookies are always ignored.
+    pub const EMPTY_NAME: u64 = 0x0002;
+    /// The cookie name is not an RFC 6265 token (strict mode).
+    pub const INVALID_NAME: u64 = 0x0004;
+    /// The cookie value is not made of RFC 6265 cookie-octets (strict mode).
+    pub const INVALID_VALUE: u64 = 0x0008;
+    /// The cookie is surrounded by, or contains, unexpected whitespace (strict mode).
+    pub const WHITESPACE: u64 = 0x0010;
+    /// The cookie name exceeds the configured limit.
+    pub const NAME_TOO_LONG: u64 = 0x0020;
+    /// The cookie value exceeds the configured limit.
+    pub const VALUE_TOO_LONG: u64 = 0x0040;
+    /// A cookie with the same name was seen earlier in the request.
+    pub const DUPLICATE: u64 = 0x0080;
+    /// The cookie was ignored because the configured number of cookies was reached.
+    pub const TOO_MANY: u64 = 0x0100;
+    /// The cookie name starts with "__Secure-", compared case-insensitively.
+    pub const SECURE_PREFIX: u64 = 0x0200;
+    /// The cookie name starts with "__Host-", compared case-insensitively.
+    pub const HOST_PREFIX: u64 = 0x0400;
+    /// A Set-Cookie does not meet the constraints of its name prefix: "__Secure-"
+    /// requires the Secure attribute, and "__Host-" also requires Path=/ and no Domain.
+    pub const PREFIX_VIOLATION: u64 = 0x0800;
+}
+
+/// Export Range flags
+#[derive(Debug)]
+pub struct RangeFlags;
+
+impl RangeFlags {
+    /// The Range header holds more than one range.
+    pub const MULTIPLE: u64 = 0x0001;
+    /// Two or more ranges overlap.
+    pub const OVERLAPPING: u64 = 0x0002;
+    /// A range starts before the range that precedes it.
+    pub const DESCENDING: u64 = 0x0004;
+    /// The number of ranges exceeds the configured limit.
+    pub const TOO_MANY: u64 = 0x0008;
+    /// A range is malformed, or its last position precedes its first.
+    pub const INVALID: u64 = 0x0010;
+    /// The range unit is not "bytes".
+    pub const UNKNOWN_UNIT: u64 = 0x0020;
+}
+
+/// Represents a byte range of a Range request header.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ByteRange {
+    /// A range starting at the first position and ending at the last position,
+    /// inclusive, or at the end of the representation when there is none.
+    FromTo {
+        /// First byte position.
+        first: u64,
+        /// Last byte position, if present.
+        last: Option<u64>,
+    },
+    /// A range made of the given number of bytes at the end of the representation.
+    Suffix(u64),
+}
+
+/// Represents an alternative service advertised by an Alt-Svc response header.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AltService {
+    /// ALPN protocol identifier, percent-decoded, such as "h3".
+    pub protocol_id: Bstr,
+    /// Alternative authority as given, such as ":443" or "alt.example.com:443".
+    pub authority: Bstr,
+    /// Host part of the authority, if not empty.
+    pub host: Option<Bstr>,
+    /// Port part of the authority, if valid.
+    pub port: Option<u16>,
+    /// Number of seconds the alternative is fresh for. Defaults to 86400.
+    pub max_age: u64,
+    /// Whether the alternative persists across network changes (persist=1).
+    pub persist: bool,
+}
+
+/// Represents a cookie set by a Set-Cookie response header.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SetCookie {
+    /// Cookie name.
+    pub name: Bstr,
+    /// Cookie value.
+    pub value: Bstr,
+    /// Value of the Domain attribute, if present and not empty.
+    pub domain: Option<Bstr>,
+    /// Value of the Path attribute, if present.
+    pub path: Option<Bstr>,
+    /// Value of the Expires attribute, if present and a valid HTTP-date.
+    pub expires: Option<DateTime<Utc>>,
+    /// Value of the Max-Age attribute, if present and a valid number.
+    pub max_age: Option<i64>,
+    /// Whether the Secure attribute is present.
+    pub secure: bool,
+    /// Whether the HttpOnly attribute is present.
+    pub http_only: bool,
+    /// Value of the SameSite attribute, if present.
+    pub same_site: Option<Bstr>,
+    /// CookieFlags of the cookie.
+    pub flags: u64,
+}
+
+/// Represents a single hop from a Forwarded, X-Forwarded-For or X-Real-IP header.
+#[derive(Clone, Debug)]
+pub struct ForwardedHop {
+    /// The header this hop was taken from.
+    pub source: HtpForwardedSource,
+    /// The node the request was forwarded for, as seen in the header.
+    pub node: Option<Bstr>,
+    /// The IP address of the node, when it is not "unknown" or obfuscated.
+    pub ip: Option<IpAddr>,
+    /// The port of the node, when present and not obfuscated.
+    pub port: Option<u16>,
+    /// The interface that received the request. Forwarded header only.
+    pub by: Option<Bstr>,
+    /// The Host header the proxy received. Forwarded header only.
+    pub host: Option<Bstr>,
+    /// The protocol the proxy received the request over. Forwarded header only.
+    pub proto: Option<Bstr>,
+    /// Validation flags; a combination of ForwardedFlags.
+    pub flags: u64,
+}
+
+impl ForwardedHop {
+    /// Creates an empty hop taken from the given header.
+    pub fn new(source: HtpForwardedSource) -> Self {
+        Self {
+            source,
+            node: None,
+            ip: None,
+            port: None,
+            by: None,
+            host: None,
+            proto: None,
+            flags: 0,
+        }
+    }
+}
+
+/// Export Transfer-Encoding anomaly flags
+#[derive(Debug)]
+pub struct TransferEncodingFlags;
+
+impl TransferEncodingFlags {
+    /// The value contains NUL bytes.
+    pub const NUL: u64 = 0x0001;
+    /// A coding contains SP or HTAB.
+    pub const WHITESPACE: u64 = 0x0002;
+    /// The value contains whitespace other than SP and HTAB, such as VT or CR.
+    pub const NON_SP_WHITESPACE: u64 = 0x0004;
+    /// A coding is not in lowercase.
+    pub const CASE: u64 = 0x0008;
+    /// The list contains an empty element.
+    pub const EMPTY_CODING: u64 = 0x0010;
+    /// The list contains more than one coding.
+    pub const MULTIPLE_CODINGS: u64 = 0x0020;
+    /// A coding is not one registered for HTTP/1.1.
+    pub const UNKNOWN_CODING: u64 = 0x0040;
+    /// "chunked" is present but is not the final coding.
+    pub const CHUNKED_NOT_LAST: u64 = 0x0080;
+}
+
+/// A parsed Transfer-Encoding header.
+#[derive(Clone, Debug)]
+pub struct TransferEncoding {
+    /// The header value, as seen.
+    pub raw: Bstr,
+    /// The codings, in order, without NUL bytes and surrounding whitespace and in
+    /// lowercase. Empty elements are left out.
+    pub codings: Vec<Bstr>,
+    /// Anomalies found in the value; a combination of TransferEncodingFlags.
+    pub flags: u64,
+}
+
+impl TransferEncoding {
+    /// Determine if "chunked" is the final coding once the value is normalized.
+    pub fn is_chunked(&self) -> bool {
+        match self.codings.last() {
+            Some(coding) => coding.eq("chunked"),
+            None => false,
+        }
+    }
+}
+
+/// An entity tag, as in the ETag and If-None-Match headers.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EntityTag {
+    /// Whether the tag is weak, i.e. prefixed with "W/".
+    pub weak: bool,
+    /// The opaque tag, without the surrounding quotes.
+    pub opaque: Bstr,
+}
+
+impl EntityTag {
+    /// Compares two tags the way If-None-Match does, ignoring weakness.
+    pub fn weak_eq(&self, other: &EntityTag) -> bool {
+        self.opaque == other.opaque
+    }
+
+    /// Compares two tags the way If-Match does. Weak tags never match.
+    pub fn strong_eq(&self, other: &EntityTag) -> bool {
+        !self.weak && !other.weak && self.opaque == other.opaque
+    }
+}
+
+/// A parsed If-None-Match or If-Match header.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EntityTagList {
+    /// Whether the list is "*", which matches any current representation.
+    pub any: bool,
+    /// The tags in the list, in order. Malformed members are left out.
+    pub tags: Vec<EntityTag>,
+}
+
+impl EntityTagList {
+    /// Determine if the list matches the given tag, using the weak comparison.
+    pub fn matches_weak(&self, tag: &EntityTag) -> bool {
+        self.any || self.tags.iter().any(|t| t.weak_eq(tag))
+    }
+}
+
+/// A conditional request seen on a connection, correlated with its response and
+/// with the earlier response that supplied its validator.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ConditionalExchange {
+    /// Index of the transaction carrying the conditional request.
+    pub tx_index: usize,
+    /// Whether the response was 304 Not Modified.
+    pub not_modified: bool,
+    /// Index of the most recent earlier transaction on the connection, for the
+    /// same URI, whose ETag or Last-Modified response header matches the
+    /// validator presented by the request.
+    pub validator_tx: Option<usize>,
+}
+
+/// A member of a header list with quality values, such as Accept-Encoding.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QualityItem {
+    /// The token, media range or language tag, as seen.
+    pub token: Bstr,
+    /// Parameters other than "q", in order, with quoted values unescaped.
+    pub params: Vec<(Bstr, Bstr)>,
+    /// The quality value in thousandths, from 0 to 1000. Defaults to 1000 when
+    /// absent or malformed.
+    pub q: u16,
+}
+
+/// Enumerates the repairs that can be applied to a request line.
+/// cbindgen:rename-all=QualifiedScreamingSnakeCase
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum HtpRequestLineRepair {
+    /// The request line was not repaired.
+    NONE,
+    /// The URI was missing, as in "GET HTTP/1.1", and Config::request_line_repair_uri
+    /// was assumed.
+    MISSING_URI,
+    /// The URI contained whitespace and extends up to the protocol.
+    URI_WITH_SPACES,
+    /// The URI contained whitespace and ends at the first one, as Apache does. The
+    /// rest of the line is not a valid protocol, so HTTP/1.0 was assumed.
+    PROTOCOL_ASSUMED,
+}
+
 /// Protocol version constants.
 /// cbindgen:rename-all=QualifiedScreamingSnakeCase
 #[repr(C)]
@@ -282,20 +1117,49 @@ pub enum HtpProtocol {
     V1_1 = 101,
 }
 
+/// Stable identifier of a transaction on its connection.
+///
+/// Ids are assigned in creation order, starting at zero, and are never reused on a
+/// connection, so they remain valid references after other transactions are destroyed.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TxId(pub u64);
+
+impl std::fmt::Display for TxId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
 /// Represents a single HTTP transaction, which is a combination of a request and a response.
 pub struct Transaction {
     /// The logger structure associated with this transaction
     pub logger: Logger,
     /// The configuration structure associated with this transaction.
-    pub cfg: Rc<Config>,
-    /// Is the configuration structure shared with other transactions or connections? If
-    /// this field is set to HTP_CONFIG_PRIVATE, the transaction owns the configuration.
+    pub cfg: Arc<Config>,
+    /// Is the configuration structure shared with other transactions or connections? This
+    /// field is cleared when the transaction is given its own configuration with set_config.
     pub is_config_shared: bool,
+    /// Registry counting this transaction once it starts, taken from the
+    /// configuration it was created with.
+    metrics: Option<Arc<Metrics>>,
+    /// Set when a callback returned HtpStatus::PAUSE for this transaction. Cleared
+    /// by ConnectionParser::resume().
+    pub paused: bool,
+    /// When set, the request body is only framed: it is not decompressed, parsed
+    /// or passed to body data callbacks.
+    pub request_body_skip: bool,
+    /// When set, the response body is only framed: it is not decompressed, sniffed
+    /// or passed to body data callbacks.
+    pub response_body_skip: bool,
+    /// How much of this transaction was analyzed under the sampling policy.
+    pub analysis_depth: HtpAnalysisDepth,
     /// The user data associated with this transaction.
-    pub user_data: Option<Box<dyn Any>>,
+    pub user_data: Option<Box<dyn Any + Send + Sync>>,
     // Request fields
     /// Contains a count of how many empty lines were skipped before the request line.
     pub request_ignored_lines: u32,
+    /// Total length of the empty lines skipped before the request line.
+    pub request_ignored_bytes: u64,
     /// The first line of this request.
     pub request_line: Option<Bstr>,
     /// Request method.
@@ -318,6 +1182,8 @@ pub struct Transaction {
     /// example, if you submit "GET / HTTP/0.9" to Apache, it will not treat the request
     /// as HTTP/0.9.
     pub is_protocol_0_9: bool,
+    /// Repair applied to the request line, if Config::request_line_repair is enabled.
+    pub request_line_repair: HtpRequestLineRepair,
     /// This structure holds the individual components parsed out of the request URI, with
     /// appropriate normalization and transformation applied, per configuration. No information
     /// is added. In extreme cases when no URI is provided on the request line, all fields
@@ -364,10 +1230,18 @@ pub struct Transaction {
     pub request_entity_len: i64,
     /// Parsed request headers.
     pub request_headers: Headers,
+    /// Request trailer fields, unless Config::trailer_policy discards them.
+    pub request_trailers: Headers,
+    /// TrailerFlags raised by the request trailer.
+    pub request_trailer_flags: u64,
+    /// Fingerprint of the request header ordering, available from REQUEST_HEADERS.
+    pub request_header_fingerprint: Option<HeaderFingerprint>,
     /// Request transfer coding. Can be one of UNKNOWN (body presence not
     /// determined yet), IDENTITY, CHUNKED, NO_BODY,
     /// and UNRECOGNIZED.
     pub request_transfer_coding: HtpTransferCoding,
+    /// The parsed Transfer-Encoding request header, if present.
+    pub request_transfer_encoding: Option<TransferEncoding>,
     /// Request body compression, which indicates if compression is used
     /// for the request body. This field is an interpretation of the information
     /// available in request headers.
@@ -384,6 +1258,12 @@ pub struct Transaction {
     pub request_content_type: Option<Bstr>,
     /// Request decompressor used to decompress request body data.
     pub request_decompressor: Option<Decompressor>,
+    /// Number of bytes received and produced by each layer of request body
+    /// decompression, starting with the outermost content encoding.
+    pub request_decompression_layers: Vec<LayerSizes>,
+    /// Overall request decompression ratio: the bytes produced by the last layer
+    /// divided by the bytes received by the first.
+    pub request_decompression_ratio: Option<f64>,
     /// Contains the value specified in the Content-Length header. The value of this
     /// field will be -1 from the beginning of the transaction and until request
     /// headers are processed. It will stay -1 if the C-L header was not provided,
@@ -403,16 +1283,53 @@ pub struct Transaction {
     pub request_mpartp: Option<MultipartParser>,
     /// Request parameters.
     pub request_params: Table<Param>,
+    /// Positions of the request parameters by source and effective name, used to
+    /// join duplicate parameters without scanning all of them.
+    request_param_positions: HashMap<(HtpDataSource, Vec<u8>), usize>,
+    /// Number of request parameters reflected in request_param_positions.
+    request_param_positions_len: usize,
     /// Request cookies
     pub request_cookies: Table<Bstr>,
+    /// CookieFlags of each cookie in request_cookies, in the same order.
+    pub request_cookie_flags: Vec<u64>,
+    /// CookieFlags raised by all the cookies of the request, including those that
+    /// were ignored.
+    pub request_cookie_header_flags: u64,
+    /// MethodFlags raised by a request whose headers or body conflict with its method.
+    pub request_method_flags: u64,
+    /// Ranges of the Range request header, up to the configured limit.
+    pub request_ranges: Vec<ByteRange>,
+    /// RangeFlags raised by the Range request header.
+    pub request_range_flags: u64,
+    /// UrlencodedFlags raised by the query string and the urlencoded request body.
+    pub request_urlencoded_flags: u64,
     /// Authentication type used in the request.
     pub request_auth_type: HtpAuthType,
     /// Authentication username.
     pub request_auth_username: Option<Bstr>,
     /// Authentication password. Available only when Transaction::request_auth_type is HTP_AUTH_BASIC.
     pub request_auth_password: Option<Bstr>,
-    /// Authentication token. Available only when Transaction::request_auth_type is HTP_AUTH_BEARER.
+    /// Authentication token. Available only when Transaction::request_auth_type is HTP_AUTH_BEARER,
+    /// HTP_AUTH_NTLM or HTP_AUTH_NEGOTIATE. NTLM and Negotiate tokens are kept base64-encoded.
     pub request_auth_token: Option<Bstr>,
+    /// Digest parameters. Available only when Transaction::request_auth_type is HTP_AUTH_DIGEST.
+    pub request_auth_digest: Option<DigestAuth>,
+    /// NTLM message carried in the request Authorization header, if any.
+    pub request_auth_ntlm_message: Option<HtpNtlmMessageType>,
+    /// Credentials from the Proxy-Authorization header. None when the header is absent.
+    pub request_proxy_auth: Option<Credentials>,
+    /// Forwarding hops from the Forwarded, X-Forwarded-For and X-Real-IP request
+    /// headers, in that order. Within a header, the hop closest to the client
+    /// comes first.
+    pub request_forwarded: Vec<ForwardedHop>,
+    /// Parsed request If-None-Match header, if present.
+    pub request_if_none_match: Option<EntityTagList>,
+    /// Parsed request If-Modified-Since header, if present and a valid HTTP-date.
+    pub request_if_modified_since: Option<DateTime<Utc>>,
+    /// Identifies the actor that sent the request, among the actors seen on the
+    /// connection. Available only when actor tracking is enabled and the request
+    /// carries credentials or a session cookie.
+    pub actor_id: Option<usize>,
     /// Request hostname. Per the RFC, the hostname will be taken from the Host header
     /// when available. If the host information is also available in the URI, it is used
     /// instead of whatever might be in the Host header. Can be NULL. This field does
@@ -425,6 +1342,9 @@ pub struct Transaction {
     // Response fields
     /// How many empty lines did we ignore before reaching the status line?
     pub response_ignored_lines: u32,
+    /// How many lines that are not a status line did we skip before reaching it? See
+    /// Config::set_response_junk_lines_limit.
+    pub response_junk_lines: u32,
     /// Response line.
     pub response_line: Option<Bstr>,
     /// Response protocol, as text. Can be NULL.
@@ -441,12 +1361,19 @@ pub struct Transaction {
     /// This field is set by the protocol decoder with it thinks that the
     /// backend server will reject a request with a particular status code.
     pub response_status_expected_number: HtpUnwanted,
-    /// The message associated with the response status code. Can be NULL.
+    /// The message associated with the response status code (the reason phrase), raw. Can
+    /// be NULL, in which case HtpFlags::STATUS_LINE_NO_REASON is set if a status was seen.
     pub response_message: Option<Bstr>,
     /// Have we seen the server respond with a 100 response?
     pub seen_100continue: bool,
     /// Parsed response headers. Contains instances of Header.
     pub response_headers: Headers,
+    /// Response trailer fields, unless Config::trailer_policy discards them.
+    pub response_trailers: Headers,
+    /// TrailerFlags raised by the response trailer.
+    pub response_trailer_flags: u64,
+    /// Fingerprint of the response header ordering, available from RESPONSE_HEADERS.
+    pub response_header_fingerprint: Option<HeaderFingerprint>,
     /// Is this a response a HTTP/2.0 upgrade?
     pub is_http_2_upgrade: bool,
 
@@ -484,9 +1411,19 @@ pub struct Transaction {
     /// headers are processed. It will stay -1 if the C-L header was not provided,
     /// or if the value in it cannot be parsed.
     pub response_content_length: i64,
+    /// How the end of the response body was actually found, available from
+    /// RESPONSE_COMPLETE.
+    pub response_delimiter: HtpResponseDelimiter,
+    /// Number of response body bytes seen minus the number declared in
+    /// Content-Length, available from RESPONSE_COMPLETE. Negative when the
+    /// connection closed early, positive when more data followed the body. Zero
+    /// when the body matched or had no Content-Length.
+    pub response_framing_delta: i64,
     /// Response transfer coding, which indicates if there is a response body,
     /// and how it is transported (e.g., as-is, or chunked).
     pub response_transfer_coding: HtpTransferCoding,
+    /// The parsed Transfer-Encoding response header, if present.
+    pub response_transfer_encoding: Option<TransferEncoding>,
     /// Response body compression, which indicates if compression is used
     /// for the response body. This field is an interpretation of the information
     /// available in response headers.
@@ -497,12 +1434,62 @@ pub struct Transaction {
     /// this field to NONE in a RESPONSE_HEADERS callback will prevent
     /// decompression.
     pub response_content_encoding_processing: HtpContentEncoding,
+    /// NTLM message carried in the response WWW-Authenticate header, if any.
+    pub response_auth_ntlm_message: Option<HtpNtlmMessageType>,
+    /// Challenges from the WWW-Authenticate response header.
+    pub response_auth_challenges: Vec<AuthChallenge>,
+    /// Challenges from the Proxy-Authenticate response header.
+    pub response_proxy_auth_challenges: Vec<AuthChallenge>,
     /// This field will contain the response content type when that information
     /// is available in response headers. The contents of the field will be converted
     /// to lowercase and any parameters (e.g., character set information) removed.
     pub response_content_type: Option<Bstr>,
+    /// Parsed response Content-Disposition header, if present.
+    pub response_content_disposition: Option<ContentDisposition>,
+    /// Parsed response ETag header, if present and well-formed.
+    pub response_etag: Option<EntityTag>,
+    /// Parsed response Last-Modified header, if present and a valid HTTP-date.
+    pub response_last_modified: Option<DateTime<Utc>>,
+    /// Alternative services advertised by the response Alt-Svc header. An empty list
+    /// with the header present means it was "clear".
+    pub response_alt_svc: Option<Vec<AltService>>,
+    /// Cookies set by the response, one for each Set-Cookie header.
+    pub response_cookies: Vec<SetCookie>,
+    /// Content type determined by sniffing the start of the response body, when
+    /// sniffing is enabled and the body has been seen.
+    pub response_sniffed_type: Option<HtpSniffedType>,
+    /// Leading response body data retained until there is enough to sniff.
+    pub response_sniff_buf: Bstr,
+    /// Charset declared by an HTML meta tag at the start of the response body,
+    /// lowercased, when charset scanning is enabled and a declaration was found.
+    pub response_detected_charset: Option<Bstr>,
+    /// Leading response body data retained until there is enough to scan for a charset.
+    response_charset_buf: Bstr,
+    /// Whether the response body has been scanned for a charset declaration.
+    response_charset_scanned: bool,
+    /// Start of the response body line being scanned for response splitting.
+    response_splitting_line: Bstr,
+    /// Number of complete response body lines scanned for response splitting.
+    response_splitting_lines: usize,
+    /// UTF-8 validation flags of the request body, using the HtpFlags::PATH_UTF8_*
+    /// and PATH_HALF_FULL_RANGE bits, when body UTF-8 validation is enabled.
+    /// PATH_UTF8_VALID is only raised once the body is complete.
+    pub request_body_utf8_flags: u64,
+    /// UTF-8 validation flags of the decompressed response body, as for
+    /// request_body_utf8_flags.
+    pub response_body_utf8_flags: u64,
+    /// Validates the request body as it is seen.
+    request_body_utf8: Option<Utf8Decoder>,
+    /// Validates the response body as it is seen.
+    response_body_utf8: Option<Utf8Decoder>,
     /// Response decompressor used to decompress response body data.
     pub response_decompressor: Option<Decompressor>,
+    /// Number of bytes received and produced by each layer of response body
+    /// decompression, starting with the outermost content encoding.
+    pub response_decompression_layers: Vec<LayerSizes>,
+    /// Overall response decompression ratio: the bytes produced by the last layer
+    /// divided by the bytes received by the first.
+    pub response_decompression_ratio: Option<f64>,
 
     // Common fields
     /// Parsing flags; a combination of: HTP_REQUEST_INVALID_T_E, HTP_INVALID_FOLDING,
@@ -514,14 +1501,42 @@ pub struct Transaction {
     pub response_progress: HtpResponseProgress,
     /// Transaction index on the connection.
     pub index: usize,
+    /// Stable transaction id on the connection.
+    pub id: TxId,
     /// Total repetitions for headers in request.
     pub request_header_repetitions: u16,
     /// Total repetitions for headers in response.
     pub response_header_repetitions: u16,
+    /// Number of folded (obs-fold) headers in request.
+    pub request_folded_headers: u32,
+    /// Number of folded (obs-fold) headers in response.
+    pub response_folded_headers: u32,
+    /// Number of data chunks passed to request callbacks.
+    pub(crate) request_data_sequence: u64,
+    /// Line index of the next request header or trailer.
+    pub(crate) request_header_line: usize,
+    /// Line index of the next response header or trailer.
+    pub(crate) response_header_line: usize,
+    /// Number of data chunks passed to response callbacks.
+    pub(crate) response_data_sequence: u64,
+    /// Number of request body bytes passed to callbacks.
+    pub(crate) request_body_data_offset: u64,
+    /// Number of response body bytes passed to callbacks.
+    pub(crate) response_body_data_offset: u64,
+    /// Number of response bytes consumed as an unexpected response body.
+    pub(crate) response_unexpected_body_len: i64,
     /// Request header parser
     pub request_header_parser: HeaderParser,
     /// Response header parser
     pub response_header_parser: HeaderParser,
+    /// Timestamp of the data chunk in which the request started.
+    pub request_start_timestamp: Option<DateTime<Utc>>,
+    /// Timestamp of the data chunk in which the request completed.
+    pub request_complete_timestamp: Option<DateTime<Utc>>,
+    /// Timestamp of the data chunk in which the response started.
+    pub response_start_timestamp: Option<DateTime<Utc>>,
+    /// Timestamp of the data chunk in which the response completed.
+    pub response_complete_timestamp: Option<DateTime<Utc>>,
 }
 
 /// Type alias for list of transactions.
@@ -529,13 +1544,32 @@ pub type Transactions = List<Transaction>;
 
 impl Transaction {
     /// Construct a new transaction.
-    pub fn new(cfg: &Rc<Config>, logger: &Logger, index: usize) -> Self {
+    pub fn new(cfg: &Arc<Config>, logger: &Logger, index: usize, id: TxId) -> Self {
+        Self::with_tables(cfg, logger, index, id, None)
+    }
+
+    /// Construct a new transaction, reusing the tables of a freed transaction if
+    /// given.
+    pub(crate) fn with_tables(
+        cfg: &Arc<Config>,
+        logger: &Logger,
+        index: usize,
+        id: TxId,
+        tables: Option<TxTables>,
+    ) -> Self {
+        let tables = tables.unwrap_or_else(|| TxTables::new(cfg));
         Self {
             logger: logger.clone(),
-            cfg: Rc::clone(&cfg),
+            cfg: Arc::clone(&cfg),
             is_config_shared: true,
+            metrics: cfg.metrics.clone(),
+            paused: false,
+            request_body_skip: false,
+            response_body_skip: false,
+            analysis_depth: HtpAnalysisDepth::FULL,
             user_data: None,
             request_ignored_lines: 0,
+            request_ignored_bytes: 0,
             request_line: None,
             request_method: None,
             request_method_number: HtpMethod::UNKNOWN,
@@ -543,32 +1577,55 @@ impl Transaction {
             request_protocol: None,
             request_protocol_number: HtpProtocol::UNKNOWN,
             is_protocol_0_9: false,
+            request_line_repair: HtpRequestLineRepair::NONE,
             parsed_uri: None,
             parsed_uri_raw: None,
             complete_normalized_uri: None,
             partial_normalized_uri: None,
             request_message_len: 0,
             request_entity_len: 0,
-            request_headers: Table::with_capacity(32),
+            request_headers: tables.request_headers,
+            request_trailers: Table::with_capacity(4),
+            request_trailer_flags: 0,
+            request_header_fingerprint: None,
             request_transfer_coding: HtpTransferCoding::UNKNOWN,
+            request_transfer_encoding: None,
             request_content_encoding: HtpContentEncoding::NONE,
             request_content_encoding_processing: HtpContentEncoding::NONE,
             request_content_type: None,
             request_content_length: -1,
             request_decompressor: None,
+            request_decompression_layers: Vec::new(),
+            request_decompression_ratio: None,
             hook_request_body_data: DataHook::default(),
             hook_response_body_data: DataHook::default(),
             request_urlenp_body: None,
             request_mpartp: None,
-            request_params: Table::with_capacity(32),
-            request_cookies: Table::with_capacity(32),
+            request_params: tables.request_params,
+            request_param_positions: HashMap::new(),
+            request_param_positions_len: 0,
+            request_cookies: tables.request_cookies,
+            request_cookie_flags: Vec::new(),
+            request_cookie_header_flags: 0,
+            request_method_flags: 0,
+            request_ranges: Vec::new(),
+            request_range_flags: 0,
+            request_urlencoded_flags: 0,
             request_auth_type: HtpAuthType::UNKNOWN,
             request_auth_username: None,
             request_auth_password: None,
             request_auth_token: None,
+            request_auth_digest: None,
+            request_auth_ntlm_message: None,
+            request_proxy_auth: None,
+            request_forwarded: Vec::new(),
+            request_if_none_match: None,
+            request_if_modified_since: None,
+            actor_id: None,
             request_hostname: None,
             request_port_number: None,
             response_ignored_lines: 0,
+            response_junk_lines: 0,
             response_line: None,
             response_protocol: None,
             response_protocol_number: HtpProtocol::UNKNOWN,
@@ -577,30 +1634,102 @@ impl Transaction {
             response_status_expected_number: HtpUnwanted::IGNORE,
             response_message: None,
             seen_100continue: false,
-            response_headers: Table::with_capacity(32),
+            response_headers: tables.response_headers,
+            response_trailers: Table::with_capacity(4),
+            response_trailer_flags: 0,
+            response_header_fingerprint: None,
             is_http_2_upgrade: false,
             response_message_len: 0,
             response_entity_len: 0,
             response_content_length: -1,
+            response_delimiter: HtpResponseDelimiter::UNKNOWN,
+            response_framing_delta: 0,
             response_transfer_coding: HtpTransferCoding::UNKNOWN,
+            response_transfer_encoding: None,
             response_content_encoding: HtpContentEncoding::NONE,
             response_content_encoding_processing: HtpContentEncoding::NONE,
+            response_auth_ntlm_message: None,
+            response_auth_challenges: Vec::new(),
+            response_proxy_auth_challenges: Vec::new(),
             response_content_type: None,
+            response_content_disposition: None,
+            response_etag: None,
+            response_last_modified: None,
+            response_alt_svc: None,
+            response_cookies: Vec::new(),
+            response_sniffed_type: None,
+            response_sniff_buf: Bstr::new(),
+            response_detected_charset: None,
+            response_charset_buf: Bstr::new(),
+            response_charset_scanned: false,
+            response_splitting_line: Bstr::new(),
+            response_splitting_lines: 0,
+            request_body_utf8_flags: 0,
+            response_body_utf8_flags: 0,
+            request_body_utf8: None,
+            response_body_utf8: None,
             response_decompressor: None,
+            response_decompression_layers: Vec::new(),
+            response_decompression_ratio: None,
             flags: 0,
             request_progress: HtpRequestProgress::NOT_STARTED,
             response_progress: HtpResponseProgress::NOT_STARTED,
             index,
+            id,
             request_header_repetitions: 0,
             response_header_repetitions: 0,
+            request_folded_headers: 0,
+            response_folded_headers: 0,
+            request_data_sequence: 0,
+            request_header_line: 1,
+            response_header_line: 1,
+            response_data_sequence: 0,
+            request_body_data_offset: 0,
+            response_body_data_offset: 0,
+            response_unexpected_body_len: 0,
             request_header_parser: HeaderParser::new(Side::Request),
             response_header_parser: HeaderParser::new(Side::Response),
+            request_start_timestamp: None,
+            request_complete_timestamp: None,
+            response_start_timestamp: None,
+            response_complete_timestamp: None,
         }
     }
 
     /// Register callback for the transaction-specific REQUEST_BODY_DATA hook.
-    pub fn register_request_body_data(&mut self, cbk_fn: DataNativeCallbackFn) {
-        self.hook_request_body_data.register(cbk_fn)
+    pub fn register_request_body_data(
+        &mut self,
+        cbk_fn: impl Fn(&mut Data) -> Result<()> + Send + Sync + 'static,
+    ) {
+        self.hook_request_body_data.register(Box::new(cbk_fn))
+    }
+
+    /// Returns the stable id of this transaction.
+    pub fn id(&self) -> TxId {
+        self.id
+    }
+
+    /// Stops processing the request body of this transaction. The body is still
+    /// framed and counted in request_message_len, but it is no longer decompressed,
+    /// parsed as urlencoded or multipart data, extracted or passed to body data
+    /// callbacks. Call this at the latest from a REQUEST_HEADERS callback to skip
+    /// the whole body.
+    pub fn set_request_body_skip(&mut self) {
+        self.request_body_skip = true;
+        self.request_content_encoding_processing = HtpContentEncoding::NONE;
+        self.request_decompressor = None;
+        self.request_urlenp_body = None;
+        self.request_mpartp = None;
+    }
+
+    /// Stops processing the response body of this transaction. The body is still
+    /// framed and counted in response_message_len, but it is no longer decompressed,
+    /// sniffed or passed to body data callbacks. Call this at the latest from a
+    /// RESPONSE_HEADERS callback to skip the whole body.
+    pub fn set_response_body_skip(&mut self) {
+        self.response_body_skip = true;
+        self.response_content_encoding_processing = HtpContentEncoding::NONE;
+        self.response_decompressor = None;
     }
 
     /// Has this transaction started?
@@ -609,8 +1738,19 @@ impl Transaction {
             && self.response_progress == HtpResponseProgress::NOT_STARTED)
     }
 
+    /// Replaces the configuration used for the rest of this transaction, for example to
+    /// apply a virtual host specific personality from a REQUEST_LINE hook. Only processing
+    /// that has not happened yet is affected: the request URI has already been normalized
+    /// by the time REQUEST_LINE hooks run, while headers, cookies and bodies will be parsed
+    /// and decoded with the new configuration. Hooks are always taken from the connection
+    /// parser's configuration.
+    pub fn set_config(&mut self, cfg: Arc<Config>) {
+        self.cfg = cfg;
+        self.is_config_shared = false;
+    }
+
     /// Set the user data.
-    pub fn set_user_data(&mut self, data: Box<dyn Any + 'static>) {
+    pub fn set_user_data(&mut self, data: Box<dyn Any + Send + Sync + 'static>) {
         self.user_data = Some(data);
     }
 
@@ -629,49 +1769,312 @@ impl Transaction {
     }
 
     /// Adds one parameter to the request. This function will take over the
-    /// responsibility for the provided Param structure.
+    /// responsibility for the provided Param structure, and assigns its ordinal.
     pub fn request_add_param(&mut self, mut param: Param) -> Result<()> {
+        param.normalize_name(self.cfg.param_name_normalization);
         if let Some(parameter_processor_fn) = self.cfg.parameter_processor {
             parameter_processor_fn(&mut param)?
         }
+        if param.flags.is_set(HtpFlags::CRLF_INJECTION) {
+            htp_warn_once!(
+                self.logger,
+                HtpLogCode::CRLF_INJECTION,
+                "Decoded parameter contains CR or LF",
+                self.flags,
+                param.flags,
+                HtpFlags::CRLF_INJECTION
+            );
+        }
+        let cfg = self.cfg.clone();
+        if cfg.param_duplicates == HtpParamDuplicates::JOIN {
+            let existing = self
+                .joined_param_position(&param)
+                .and_then(|index| self.request_params.get_value_mut(index))
+                .map(|(_, existing)| existing);
+            if let Some(existing) = existing {
+                existing.value.add(",");
+                existing.value.add(param.value.as_slice());
+                existing.flags.set(param.flags);
+                existing.joined_raw.extend(param.raw);
+                return Ok(());
+            }
+        }
+        param.ordinal = self.request_params.size();
+        cfg.hook_request_param.run_all(self, &param)?;
         self.request_params.add(param.name.clone(), param);
         Ok(())
     }
 
-    /// Determine if the request has a body.
-    pub fn request_has_body(&self) -> bool {
-        self.request_transfer_coding == HtpTransferCoding::IDENTITY
-            || self.request_transfer_coding == HtpTransferCoding::CHUNKED
+    /// Returns the position of the request parameter with the same source and
+    /// effective name as the given one. Positions are indexed as parameters are
+    /// added, and reindexed if request_params was changed some other way.
+    fn joined_param_position(&mut self, param: &Param) -> Option<usize> {
+        let key = (param.source, param.effective_name().to_vec());
+        if self.request_param_positions_len > self.request_params.size() {
+            self.reindex_param_positions(0);
+        } else {
+            self.reindex_param_positions(self.request_param_positions_len);
+        }
+        let matches = |index: usize, params: &Table<Param>| {
+            matches!(params.get(index), Some((_, existing))
+                if existing.source == param.source
+                    && existing.effective_name() == param.effective_name())
+        };
+        match self.request_param_positions.get(&key) {
+            Some(index) if matches(*index, &self.request_params) => Some(*index),
+            Some(_) => {
+                self.reindex_param_positions(0);
+                let index = *self.request_param_positions.get(&key)?;
+                Some(index).filter(|index| matches(*index, &self.request_params))
+            }
+            None => None,
+        }
     }
 
-    /// Determine if we have a request body, and how it is packaged.
-    pub fn process_request_headers(&mut self, connp: &mut ConnectionParser) -> Result<()> {
-        // Determine if we have a request body, and how it is packaged.
-        let cl_opt = self.request_headers.get_nocase_nozero("content-length");
-        // Check for the Transfer-Encoding header, which would indicate a chunked request body.
-        if let Some((_, te)) = self.request_headers.get_nocase_nozero("transfer-encoding") {
-            // Make sure it contains "chunked" only.
-            // TODO The HTTP/1.1 RFC also allows the T-E header to contain "identity", which
-            //      presumably should have the same effect as T-E header absence. However, Apache
-            //      (2.2.22 on Ubuntu 12.04 LTS) instead errors out with "Unknown Transfer-Encoding: identity".
-            //      And it behaves strangely, too, sending a 501 and proceeding to process the request
-            //      (e.g., PHP is run), but without the body. It then closes the connection.
-            if te.value.cmp_nocase("chunked") != Ordering::Equal {
-                // Invalid T-E header value.
-                self.request_transfer_coding = HtpTransferCoding::INVALID;
-                self.flags.set(HtpFlags::REQUEST_INVALID_T_E);
-                self.flags.set(HtpFlags::REQUEST_INVALID)
-            } else {
-                // Chunked encoding is a HTTP/1.1 feature, so check that an earlier protocol
-                // version is not used. The flag will also be set if the protocol could not be parsed.
-                //
-                // TODO IIS 7.0, for example, would ignore the T-E header when it
-                //      it is used with a protocol below HTTP 1.1. This should be a
-                //      personality trait.
-                if self.request_protocol_number < HtpProtocol::V1_1 {
-                    self.flags.set(HtpFlags::REQUEST_INVALID_T_E);
-                    self.flags.set(HtpFlags::REQUEST_SMUGGLING);
-                }
+    /// Indexes the positions of the request parameters from the given one onwards,
+    /// discarding all the positions when starting from the first parameter.
+    fn reindex_param_positions(&mut self, from: usize) {
+        if from == 0 {
+            self.request_param_positions.clear();
+        }
+        for index in from..self.request_params.size() {
+            if let Some((_, existing)) = self.request_params.get(index) {
+                self.request_param_positions
+                    .entry((existing.source, existing.effective_name().to_vec()))
+                    .or_insert(index);
+            }
+        }
+        self.request_param_positions_len = self.request_params.size();
+    }
+
+    /// Collects the keys that identify the actor sending this request: Basic
+    /// credentials, a hash of the Bearer token and configured session cookies.
+    fn actor_keys(&self) -> Vec<Vec<u8>> {
+        let mut keys = Vec::new();
+        match self.request_auth_type {
+            HtpAuthType::BASIC => {
+                if let Some(username) = &self.request_auth_username {
+                    keys.push([b"basic:", username.as_slice()].concat());
+                }
+            }
+            HtpAuthType::BEARER => {
+                if let Some(token) = &self.request_auth_token {
+                    keys.push([b"bearer:".as_ref(), &Sha256::digest(token.as_slice())].concat());
+                }
+            }
+            _ => {}
+        }
+        for (name, value) in &self.request_cookies {
+            if !value.is_empty()
+                && self
+                    .cfg
+                    .actor_session_cookies
+                    .iter()
+                    .any(|cookie| name.eq_nocase(cookie))
+            {
+                // Cookie names are matched case-insensitively.
+                let name = name.to_ascii_lowercase();
+                keys.push([b"cookie:", name.as_slice(), b"=", value.as_slice()].concat());
+            }
+        }
+        keys
+    }
+
+    /// Produce the recommended action for this transaction, derived from the
+    /// reactions the parser recorded and the anomaly flags it carries. Returns None
+    /// if the server is not expected to reject the request.
+    pub fn recommended_action(&self) -> Option<RecommendedAction> {
+        let cfg = &self.cfg.decoder_cfg;
+        // Map the flags to the configured reactions that could have raised them.
+        let candidates = [
+            (
+                HtpFlags::PATH_ENCODED_NUL | HtpFlags::URLEN_ENCODED_NUL,
+                cfg.nul_encoded_unwanted,
+            ),
+            (
+                HtpFlags::PATH_RAW_NUL | HtpFlags::URLEN_RAW_NUL,
+                cfg.nul_raw_unwanted,
+            ),
+            (
+                HtpFlags::PATH_INVALID_ENCODING | HtpFlags::URLEN_INVALID_ENCODING,
+                cfg.url_encoding_invalid_unwanted,
+            ),
+            (
+                HtpFlags::PATH_OVERLONG_U | HtpFlags::URLEN_OVERLONG_U,
+                cfg.u_encoding_unwanted,
+            ),
+            (
+                HtpFlags::PATH_ENCODED_SEPARATOR,
+                cfg.path_separators_encoded_unwanted,
+            ),
+            (HtpFlags::PATH_UTF8_INVALID, cfg.utf8_invalid_unwanted),
+            (
+                HtpFlags::REQUEST_LINE_LEADING_WHITESPACE,
+                self.cfg.requestline_leading_whitespace_unwanted,
+            ),
+        ];
+        let raised = candidates.iter().filter(|(flags, unwanted)| {
+            self.flags.is_set(*flags) && *unwanted != HtpUnwanted::IGNORE
+        });
+        // Some reactions, such as those to control characters or to method
+        // semantics, have no transaction flag and are only recorded in
+        // response_status_expected_number. The strongest reaction wins.
+        let unwanted = raised
+            .clone()
+            .map(|(_, unwanted)| *unwanted)
+            .chain(std::iter::once(self.response_status_expected_number))
+            .max_by_key(|unwanted| unwanted.status_code())?;
+        Some(RecommendedAction {
+            status: unwanted.status_code()?,
+            reason: unwanted.reason()?,
+            flags: raised
+                .filter(|(_, reaction)| *reaction == unwanted)
+                .fold(0, |acc, (flags, _)| acc | (self.flags & flags)),
+        })
+    }
+
+    /// Parses the named request header as a list with quality values. Returns an
+    /// empty list if the header is absent.
+    fn request_quality_list(&self, name: &str) -> Vec<QualityItem> {
+        match self.request_headers.get_nocase_nozero(name) {
+            Some((_, header)) => parse_quality_list(header.value.as_slice()),
+            None => Vec::new(),
+        }
+    }
+
+    /// Returns the media ranges in the Accept request header.
+    pub fn request_accept(&self) -> Vec<QualityItem> {
+        self.request_quality_list("accept")
+    }
+
+    /// Returns the content codings in the Accept-Encoding request header.
+    pub fn request_accept_encodings(&self) -> Vec<QualityItem> {
+        self.request_quality_list("accept-encoding")
+    }
+
+    /// Returns the language ranges in the Accept-Language request header.
+    pub fn request_accept_languages(&self) -> Vec<QualityItem> {
+        self.request_quality_list("accept-language")
+    }
+
+    /// Returns the transfer codings in the TE request header.
+    pub fn request_te(&self) -> Vec<QualityItem> {
+        self.request_quality_list("te")
+    }
+
+    /// Determine if the client accepts a response in the given content coding,
+    /// named by all of its aliases. A request without Accept-Encoding accepts any
+    /// coding. An explicit entry for an alias takes precedence over the "*"
+    /// wildcard.
+    pub fn request_accepts_encoding(&self, aliases: &[&str]) -> bool {
+        if self
+            .request_headers
+            .get_nocase_nozero("accept-encoding")
+            .is_none()
+        {
+            return true;
+        }
+        let accepted = self.request_accept_encodings();
+        let explicit: Vec<&QualityItem> = accepted
+            .iter()
+            .filter(|item| aliases.iter().any(|alias| item.token.eq_nocase(alias)))
+            .collect();
+        if !explicit.is_empty() {
+            return explicit.iter().any(|item| item.q > 0);
+        }
+        accepted.iter().any(|item| item.token.eq("*") && item.q > 0)
+    }
+
+    /// Returns the typed values of the Priority, Early-Data, Upgrade-Insecure-Requests
+    /// and Sec-Fetch-* headers of the transaction.
+    pub fn modern_headers(&self) -> ModernHeaders {
+        ModernHeaders::new(&self.request_headers, &self.response_headers)
+    }
+
+    /// Returns the typed values of the Strict-Transport-Security,
+    /// Content-Security-Policy, X-Frame-Options and X-Content-Type-Options
+    /// response headers, with their validation flags.
+    pub fn response_security_headers(&self) -> SecurityHeaders {
+        SecurityHeaders::new(&self.response_headers)
+    }
+
+    /// Determine if the request carries an If-None-Match or a valid
+    /// If-Modified-Since header.
+    pub fn request_is_conditional(&self) -> bool {
+        self.request_if_none_match.is_some() || self.request_if_modified_since.is_some()
+    }
+
+    /// Determine if the response of this transaction supplied the validator that
+    /// a later request presents.
+    pub fn response_validates(&self, request: &Transaction) -> bool {
+        if let (Some(inm), Some(etag)) = (&request.request_if_none_match, &self.response_etag) {
+            if inm.matches_weak(etag) {
+                return true;
+            }
+        }
+        match (
+            request.request_if_modified_since,
+            self.response_last_modified,
+        ) {
+            (Some(since), Some(modified)) => since == modified,
+            _ => false,
+        }
+    }
+
+    /// Determine if the request has a body.
+    pub fn request_has_body(&self) -> bool {
+        self.request_transfer_coding == HtpTransferCoding::IDENTITY
+            || self.request_transfer_coding == HtpTransferCoding::CHUNKED
+    }
+
+    /// Determine if the response must not have a body, because it answers a HEAD
+    /// request or its status is 1xx, 204 or 304.
+    pub fn response_body_forbidden(&self) -> bool {
+        self.request_method_number == HtpMethod::HEAD
+            || self.response_status_number.in_range(100, 199)
+            || self.response_status_number.eq_num(204)
+            || self.response_status_number.eq_num(304)
+    }
+
+    /// Determine if we have a request body, and how it is packaged.
+    pub fn process_request_headers(&mut self, connp: &mut ConnectionParser) -> Result<()> {
+        // Determine if we have a request body, and how it is packaged.
+        let cl_opt = self.request_headers.get_nocase_nozero("content-length");
+        // Check for the Transfer-Encoding header, which would indicate a chunked request body.
+        if let Some((_, te)) = self.request_headers.get_nocase_nozero("transfer-encoding") {
+            // Make sure it ends with "chunked", without anomalies the server rejects.
+            // TODO The HTTP/1.1 RFC also allows the T-E header to contain "identity", which
+            //      presumably should have the same effect as T-E header absence. However, Apache
+            //      (2.2.22 on Ubuntu 12.04 LTS) instead errors out with "Unknown Transfer-Encoding: identity".
+            //      And it behaves strangely, too, sending a 501 and proceeding to process the request
+            //      (e.g., PHP is run), but without the body. It then closes the connection.
+            let te = parse_transfer_encoding(te.value.as_slice());
+            let chunked = te.is_chunked();
+            let rejected = te.flags & self.cfg.transfer_encoding_reject;
+            self.request_transfer_encoding = Some(te);
+            if !chunked || rejected != 0 {
+                if chunked {
+                    htp_warn!(
+                        self.logger,
+                        HtpLogCode::INVALID_TRANSFER_ENCODING_VALUE_IN_REQUEST,
+                        format!("Request T-E rejected for anomalies 0x{:x}", rejected)
+                    );
+                }
+                // Invalid T-E header value.
+                self.request_transfer_coding = HtpTransferCoding::INVALID;
+                self.flags.set(HtpFlags::REQUEST_INVALID_T_E);
+                self.flags.set(HtpFlags::REQUEST_INVALID)
+            } else {
+                // Chunked encoding is a HTTP/1.1 feature, so check that an earlier protocol
+                // version is not used. The flag will also be set if the protocol could not be parsed.
+                //
+                // TODO IIS 7.0, for example, would ignore the T-E header when it
+                //      it is used with a protocol below HTTP 1.1. This should be a
+                //      personality trait.
+                if self.request_protocol_number < HtpProtocol::V1_1 {
+                    self.flags.set(HtpFlags::REQUEST_INVALID_T_E);
+                    self.flags.set(HtpFlags::REQUEST_SMUGGLING);
+                }
                 // If the T-E header is present we are going to use it.
                 self.request_transfer_coding = HtpTransferCoding::CHUNKED;
                 // We are still going to check for the presence of C-L.
@@ -700,9 +2103,10 @@ impl Transaction {
                 //      which is bound to fail (because it will contain commas).
             }
             // Get the body length.
-            if let Some(content_length) =
-                parse_content_length((*(*cl).value).as_slice(), Some(&mut self.logger))
-            {
+            if let Some(content_length) = parse_content_length(
+                (*(*cl).value).as_slice(),
+                Some(&mut self.logger.at(self.log_position())),
+            ) {
                 // We have a request body of known length.
                 self.request_content_length = content_length;
                 self.request_transfer_coding = HtpTransferCoding::IDENTITY
@@ -722,10 +2126,20 @@ impl Transaction {
             self.request_transfer_coding = HtpTransferCoding::INVALID;
             self.flags.set(HtpFlags::REQUEST_INVALID)
         }
-        // Check for body data to treat as file uploads.
-        if self.request_has_body() {
-            // Prepare to treat request body as a file.
-            connp.request_file = Some(File::new(HtpFileSource::REQUEST_BODY, None));
+        self.check_method_semantics();
+        if let Some((_, range)) = self.request_headers.get_nocase_nozero("range") {
+            let (ranges, flags) =
+                parse_range(range.value.as_slice(), self.cfg.request_ranges_limit);
+            self.request_ranges = ranges;
+            self.request_range_flags = flags;
+            if flags.is_set(RangeFlags::OVERLAPPING | RangeFlags::DESCENDING | RangeFlags::TOO_MANY)
+            {
+                htp_warn!(
+                    self.logger,
+                    HtpLogCode::REQUEST_RANGE_ABUSE,
+                    "Range header with overlapping, descending or too many ranges"
+                );
+            }
         }
         // Determine hostname.
         // Use the hostname from the URI, when available.
@@ -737,7 +2151,7 @@ impl Transaction {
             self.request_port_number = Some(*port_number);
         }
         // Examine the Host header.
-        if let Some((_, header)) = self.request_headers.get_nocase_nozero_mut("host") {
+        if let Some((_, header)) = self.request_headers.get_nocase_nozero_value_mut("host") {
             // Host information available in the headers.
             if let Ok((_, (hostname, port_nmb, valid))) = parse_hostport(&header.value) {
                 if !valid {
@@ -783,6 +2197,27 @@ impl Transaction {
                 self.flags.set(HtpFlags::HOST_MISSING)
             }
         }
+        // Let the application choose the configuration of the virtual host.
+        if let Some(cfg) = connp.cfg.hook_vhost_select.run(connp, self) {
+            self.set_config(cfg);
+        }
+        // Check for body data to treat as file uploads.
+        if self.request_has_body() {
+            // Prepare to treat request body as a file.
+            let mut file = File::new(HtpFileSource::REQUEST_BODY, None);
+            let cfg = Arc::clone(&self.cfg);
+            let cfg = &cfg.multipart_cfg;
+            if cfg.extract_put_files && self.request_method_number == HtpMethod::PUT {
+                let quotas = connp.extraction_quotas(cfg);
+                // Failures are flagged on the file and reported below.
+                let _ = file.create(cfg, cfg.extract_request_bytes_limit, &quotas);
+                self.log_extraction_flags(file.flags);
+            }
+            if cfg.compute_file_hashes {
+                file.enable_hashes();
+            }
+            connp.request_file = Some(file);
+        }
         // Determine Content-Type.
         if let Some((_, ct)) = self.request_headers.get_nocase_nozero("content-type") {
             self.request_content_type = Some(parse_content_type(ct.value.as_slice())?);
@@ -792,23 +2227,48 @@ impl Transaction {
                 && ct.value.starts_with("application/x-www-form-urlencoded")
             {
                 // Create parser instance.
-                self.request_urlenp_body = Some(UrlEncodedParser::new(self.cfg.decoder_cfg));
+                self.request_urlenp_body =
+                    Some(UrlEncodedParser::new(self.cfg.decoder_cfg.clone()));
             } else if self.cfg.parse_multipart {
                 if let Some(boundary) = find_boundary(ct.value.as_slice(), &mut flags) {
                     if !boundary.is_empty() {
                         // Create a Multipart parser instance.
-                        self.request_mpartp =
-                            Some(MultipartParser::new(&self.cfg, boundary, flags));
+                        let mut mpartp = MultipartParser::new(&self.cfg, boundary, flags);
+                        mpartp.quotas = connp.extraction_quotas(&self.cfg.multipart_cfg);
+                        self.request_mpartp = Some(mpartp);
                     }
                 }
             }
         }
         // Parse cookies.
-        if connp.cfg.parse_request_cookies {
+        if self.cfg.parse_request_cookies {
             parse_cookies_v0(self)?;
+            let flags = self.request_cookie_header_flags;
+            if flags.is_set(
+                CookieFlags::BARE
+                    | CookieFlags::INVALID_NAME
+                    | CookieFlags::INVALID_VALUE
+                    | CookieFlags::WHITESPACE,
+            ) && self.cfg.request_cookies_strict
+            {
+                htp_warn!(
+                    self.logger,
+                    HtpLogCode::REQUEST_COOKIE_INVALID,
+                    "Request cookies do not follow RFC 6265"
+                );
+            }
+            if flags.is_set(
+                CookieFlags::NAME_TOO_LONG | CookieFlags::VALUE_TOO_LONG | CookieFlags::TOO_MANY,
+            ) {
+                htp_warn!(
+                    self.logger,
+                    HtpLogCode::REQUEST_COOKIE_LIMIT,
+                    "Request cookies exceed the configured limits"
+                );
+            }
         }
         // Parse authentication information.
-        if connp.cfg.parse_request_auth {
+        if self.cfg.parse_request_auth {
             parse_authorization(self).or_else(|rc| {
                 if rc == HtpStatus::DECLINED {
                     // Don't fail the stream if an authorization header is invalid, just set a flag.
@@ -818,6 +2278,31 @@ impl Transaction {
                     Err(rc)
                 }
             })?;
+            parse_proxy_authorization(self).or_else(|rc| {
+                if rc == HtpStatus::DECLINED {
+                    self.flags.set(HtpFlags::PROXY_AUTH_INVALID);
+                    Ok(())
+                } else {
+                    Err(rc)
+                }
+            })?;
+            if let Some(message) = self.request_auth_ntlm_message {
+                connp.conn.ntlm_handshake = Some(message);
+            }
+        }
+        self.request_forwarded = parse_forwarded_headers(&self.request_headers);
+        if let Some((_, inm)) = self.request_headers.get_nocase_nozero("if-none-match") {
+            self.request_if_none_match = Some(parse_entity_tag_list(inm.value.as_slice()));
+        }
+        self.request_if_modified_since = self
+            .request_headers
+            .get_nocase_nozero("if-modified-since")
+            .and_then(|(_, ims)| parse_http_date(ims.value.as_slice()));
+        if self.request_forwarded.iter().any(|hop| hop.flags != 0) {
+            self.flags.set(HtpFlags::FORWARDED_INVALID);
+        }
+        if self.cfg.track_actors {
+            self.actor_id = connp.conn.actors.identify(&self.actor_keys());
         }
 
         let ce = (*self)
@@ -856,8 +2341,16 @@ impl Transaction {
             slow_path = false;
             HtpContentEncoding::NONE
         };
+        self.request_header_fingerprint = Some(HeaderFingerprint::new(&self.request_headers));
         // Run hook REQUEST_HEADERS.
         connp.cfg.hook_request_headers.run_all(connp, self)?;
+        self.push_header_events(connp, Direction::Request);
+        // Only the framing of a skipped body is followed.
+        if self.request_body_skip {
+            connp.request_file = None;
+            self.request_content_encoding_processing = HtpContentEncoding::NONE;
+            slow_path = false;
+        }
 
         // Initialize the decompression engine as necessary. We can deal with three
         // scenarios:
@@ -875,7 +2368,7 @@ impl Transaction {
             | HtpContentEncoding::DEFLATE
             | HtpContentEncoding::ZLIB
             | HtpContentEncoding::LZMA => {
-                self.request_prepend_decompressor(connp, self.request_content_encoding_processing)?;
+                self.request_prepend_decompressor(self.request_content_encoding_processing)?;
             }
             HtpContentEncoding::NONE => {
                 if slow_path {
@@ -948,7 +2441,7 @@ impl Transaction {
                                 );
                                 HtpContentEncoding::NONE
                             };
-                            self.request_prepend_decompressor(connp, encoding)?;
+                            self.request_prepend_decompressor(encoding)?;
                         }
                     }
                 }
@@ -983,16 +2476,16 @@ impl Transaction {
         } else {
             // Finalize parsing.
             urlenp.finalize();
-            let elements = take(&mut urlenp.params.elements);
-            // Add all parameters to the transaction.
-            for (name, value) in elements.iter() {
-                let param = Param::new(
-                    Bstr::from((*name).as_slice()),
-                    Bstr::from((*value).as_slice()),
-                    HtpDataSource::BODY,
-                );
-                self.request_add_param(param)?;
-            }
+        }
+        if urlenp.flags.is_set(HtpFlags::URLEN_ALT_SEPARATOR) {
+            self.flags.set(HtpFlags::URLEN_ALT_SEPARATOR);
+        }
+        self.request_urlencoded_flags.set(urlenp.urlencoded_flags);
+        let elements = urlenp.params.take_all();
+        // Add the parameters parsed so far to the transaction.
+        for (name, UrlEncodedParamValue { value, raw }) in elements {
+            let param = Param::from_urlencoded(name, value, raw, HtpDataSource::BODY);
+            self.request_add_param(param)?;
         }
         Ok(())
     }
@@ -1003,35 +2496,159 @@ impl Transaction {
     fn request_process_multipart_data(&mut self, data: Option<&[u8]>) -> Result<()> {
         let mpartp = self.request_mpartp.as_mut().ok_or(HtpStatus::DECLINED)?;
 
-        if let Some(data) = data {
+        let finalized = if let Some(data) = data {
             // Process one chunk of data.
             mpartp.parse(data);
+            false
         } else {
             // Finalize parsing.
             // Ignore result.
             let _ = mpartp.finalize();
+            true
+        };
+        let extraction_flags = mpartp.take_extraction_flags();
+        // Use text parameters from the parts completed so far.
+        let completed = mpartp.take_completed_parts(finalized);
+        let parts = &mpartp.get_multipart().parts;
+        let params: Vec<Param> = completed
+            .filter_map(|i| parts.get(i))
+            .filter(|part| part.type_0 == HtpMultipartType::TEXT)
+            .map(|part| {
+                Param::new(
+                    Bstr::from((*part.name).as_slice()),
+                    Bstr::from((*part.value).as_slice()),
+                    HtpDataSource::BODY,
+                )
+            })
+            .collect();
+        self.log_extraction_flags(extraction_flags);
+        for param in params {
+            self.request_add_param(param)?;
+        }
+        Ok(())
+    }
 
-            // take ownership of the parts to iterate over
-            let parts = take(&mut mpartp.get_multipart().parts);
-            for part in &parts {
-                // Use text parameters.
-                if part.type_0 == HtpMultipartType::TEXT {
-                    let param = Param::new(
-                        Bstr::from((*part.name).as_slice()),
-                        Bstr::from((*part.value).as_slice()),
-                        HtpDataSource::BODY,
-                    );
-                    self.request_add_param(param)?;
-                }
+    /// Records a request or response trailer field as configured by
+    /// Config::trailer_policy, flagging fields that are not allowed in trailers.
+    ///
+    /// Returns the field if it is also to be merged into the headers.
+    pub(crate) fn process_trailer(
+        &mut self,
+        direction: Direction,
+        header: Header,
+    ) -> Option<Header> {
+        let name = header.name.as_slice();
+        let mut flags = 0;
+        if name.eq_ignore_ascii_case(b"content-length")
+            || name.eq_ignore_ascii_case(b"transfer-encoding")
+        {
+            flags.set(TrailerFlags::FRAMING_FIELD);
+        } else if name.eq_ignore_ascii_case(b"host") {
+            flags.set(TrailerFlags::ROUTING_FIELD);
+        }
+        if flags != 0 {
+            htp_warn!(
+                self.logger,
+                HtpLogCode::TRAILER_FORBIDDEN_FIELD,
+                "Trailer field not allowed in trailers"
+            );
+        }
+        let policy = self.cfg.trailer_policy;
+        if policy == HtpTrailerPolicy::IGNORE {
+            flags.set(TrailerFlags::IGNORED);
+        }
+        let (trailers, trailer_flags) = match direction {
+            Direction::Request => (&mut self.request_trailers, &mut self.request_trailer_flags),
+            Direction::Response => (
+                &mut self.response_trailers,
+                &mut self.response_trailer_flags,
+            ),
+        };
+        trailer_flags.set(flags);
+        if policy == HtpTrailerPolicy::IGNORE {
+            return None;
+        }
+        if let Some((_, existing)) = trailers.get_nocase_value_mut(name) {
+            existing.flags.set(HtpFlags::FIELD_REPEATED);
+            existing.value.extend_from_slice(b", ");
+            existing.value.extend_from_slice(header.value.as_slice());
+        } else {
+            trailers.add(header.name.clone(), header.clone());
+        }
+        if policy == HtpTrailerPolicy::MERGE {
+            Some(header)
+        } else {
+            None
+        }
+    }
+
+    /// Flag request bodies and headers that the method does not allow, and
+    /// set the expected response status when the configuration rejects them.
+    fn check_method_semantics(&mut self) {
+        let has_body = match self.request_transfer_coding {
+            HtpTransferCoding::CHUNKED => true,
+            HtpTransferCoding::IDENTITY => self.request_content_length > 0,
+            _ => false,
+        };
+        let cfg = Arc::clone(&self.cfg);
+        let (flag, unwanted, msg) = match self.request_method_number {
+            HtpMethod::GET | HtpMethod::HEAD if has_body => (
+                MethodFlags::GET_WITH_BODY,
+                cfg.get_body_unwanted,
+                "GET or HEAD request with a body",
+            ),
+            HtpMethod::CONNECT
+                if has_body
+                    || self
+                        .request_headers
+                        .get_nocase_nozero("content-length")
+                        .is_some() =>
+            {
+                (
+                    MethodFlags::CONNECT_WITH_CONTENT_LENGTH,
+                    cfg.connect_content_length_unwanted,
+                    "CONNECT request with Content-Length",
+                )
             }
-            // Put the parts back
-            self.request_mpartp
-                .as_mut()
-                .ok_or(HtpStatus::DECLINED)?
-                .get_multipart()
-                .parts = parts;
+            HtpMethod::TRACE if has_body => (
+                MethodFlags::TRACE_WITH_BODY,
+                cfg.trace_body_unwanted,
+                "TRACE request with a body",
+            ),
+            HtpMethod::OPTIONS
+                if has_body && matches!(&self.request_uri, Some(uri) if uri.eq("*")) =>
+            {
+                (
+                    MethodFlags::OPTIONS_ASTERISK_WITH_BODY,
+                    cfg.options_asterisk_body_unwanted,
+                    "OPTIONS * request with a body",
+                )
+            }
+            _ => return,
+        };
+        self.request_method_flags.set(flag);
+        htp_warn!(self.logger, HtpLogCode::REQUEST_METHOD_SEMANTICS, msg);
+        if unwanted != HtpUnwanted::IGNORE {
+            self.response_status_expected_number = unwanted;
+        }
+    }
+
+    /// Logs the reasons files could not be extracted, given their FileFlags.
+    fn log_extraction_flags(&mut self, flags: u64) {
+        if flags.is_set(FileFlags::EXTRACTION_SKIPPED) {
+            htp_warn!(
+                self.logger,
+                HtpLogCode::FILE_EXTRACTION_QUOTA_EXCEEDED,
+                "File not extracted: extraction quota exhausted"
+            );
+        }
+        if flags.is_set(FileFlags::EXTRACTION_FAILED) {
+            htp_warn!(
+                self.logger,
+                HtpLogCode::FILE_EXTRACTION_FAILED,
+                "File not extracted: storage could not be created"
+            );
         }
-        Ok(())
     }
 
     /// Process a chunk of request body data. This function assumes that
@@ -1045,11 +2662,25 @@ impl Transaction {
         &mut self,
         connp: &mut ConnectionParser,
         data: Option<&[u8]>,
+    ) -> Result<()> {
+        self.request_process_body_data_at(connp, data, None)
+    }
+
+    /// Process a chunk of request body data, given its offset in the request stream
+    /// when it was taken as is from the current request chunk.
+    pub(crate) fn request_process_body_data_at(
+        &mut self,
+        connp: &mut ConnectionParser,
+        data: Option<&[u8]>,
+        stream_offset: Option<u64>,
     ) -> Result<()> {
         // None data is used to indicate the end of request body.
         // Keep track of body size before decompression.
         self.request_message_len =
             (self.request_message_len as u64).wrapping_add(data.unwrap_or(b"").len() as u64) as i64;
+        if self.request_body_skip || self.body_budget_exhausted(connp, Side::Request, data) {
+            return Ok(());
+        }
         match self.request_content_encoding_processing {
             HtpContentEncoding::GZIP
             | HtpContentEncoding::DEFLATE
@@ -1059,14 +2690,26 @@ impl Transaction {
                 if self.request_decompressor.is_none() && data.is_none() {
                     return Ok(());
                 }
+                // Once the limit is reached there is no point decompressing more data.
+                if data.is_some() && self.body_limit_skipping(HtpFlags::REQUEST_BODY_LIMIT) {
+                    return Ok(());
+                }
                 let mut decompressor = self.request_decompressor.take().ok_or(HtpStatus::ERROR)?;
                 if let Some(data) = data {
-                    decompressor
-                        .decompress(data)
-                        .map_err(|_| HtpStatus::ERROR)?;
+                    let mut status = Ok(());
+                    let result = decompressor.decompress_with(data, &mut |data| {
+                        self.request_decompressor_callback(connp, data)
+                            .map_err(|e| {
+                                status = Err(e);
+                                std::io::Error::new(std::io::ErrorKind::Other, "hook failed")
+                            })
+                            .map(|_| data.unwrap_or(b"").len())
+                    });
+                    status?;
                     if decompressor.time_spent()
                         > self.cfg.compression_options.get_time_limit() as u64
                     {
+                        self.count_compression_bomb();
                         htp_log!(
                             self.logger,
                             HtpLogLevel::ERROR,
@@ -1078,12 +2721,21 @@ impl Transaction {
                         );
                         return Err(HtpStatus::ERROR);
                     }
+                    result.map_err(|_| HtpStatus::ERROR)?;
+                    self.record_request_decompression(&decompressor);
                     // put the decompressor back in its slot
                     self.request_decompressor.replace(decompressor);
                 } else {
                     // don't put the decompressor back in its slot
                     // ignore errors
-                    let _ = decompressor.finish();
+                    let _ = decompressor.finish_with(&mut |data| {
+                        self.request_decompressor_callback(connp, data)
+                            .map_err(|_| {
+                                std::io::Error::new(std::io::ErrorKind::Other, "hook failed")
+                            })
+                            .map(|_| data.unwrap_or(b"").len())
+                    });
+                    self.record_request_decompression(&decompressor);
                 }
             }
             HtpContentEncoding::NONE => {
@@ -1091,15 +2743,23 @@ impl Transaction {
                 // is identical to request_message_len.
                 // None data is used to indicate the end of request body.
                 // Keep track of the body length.
-                self.request_entity_len = (self.request_entity_len as u64)
-                    .wrapping_add(data.unwrap_or(b"").len() as u64)
-                    as i64;
+                let len = data.unwrap_or(b"").len();
+                let allowed = self.request_body_allowance(len)?;
+                self.request_entity_len =
+                    (self.request_entity_len as u64).wrapping_add(len as u64) as i64;
+                if allowed == 0 && len > 0 {
+                    return Ok(());
+                }
+                let data = data.map(|data| &data[..allowed]);
                 let _ = self.request_process_multipart_data(data);
                 let _ = self.request_process_urlencoded_data(data);
                 // Send data to the callbacks.
                 let data = ParserData::from(data);
-                let mut data = Data::new(self, &data, false);
-                connp.request_run_hook_body_data(&mut data).map_err(|e| {
+                // self is not used again until the hooks have run.
+                let mut data =
+                    unsafe { Data::new(self, &data, false) }.with_stream_offset(stream_offset);
+                let result = connp.request_run_hook_body_data(&mut data);
+                result.map_err(|e| {
                     htp_error!(
                         self.logger,
                         HtpLogCode::REQUEST_BODY_DATA_CALLBACK_ERROR,
@@ -1159,12 +2819,38 @@ impl Transaction {
         &mut self,
         connp: &mut ConnectionParser,
         data: Option<&[u8]>,
+    ) -> Result<()> {
+        self.response_process_body_data_at(connp, data, None)
+    }
+
+    /// Process a chunk of response body data, given its offset in the response stream
+    /// when it was taken as is from the current response chunk.
+    pub(crate) fn response_process_body_data_at(
+        &mut self,
+        connp: &mut ConnectionParser,
+        data: Option<&[u8]>,
+        stream_offset: Option<u64>,
     ) -> Result<()> {
         // None data is used to indicate the end of response body.
         // Keep track of body size before decompression.
         self.response_message_len = (self.response_message_len as u64)
             .wrapping_add(data.unwrap_or(b"").len() as u64)
             as i64;
+        // Flag body data sent with a response that must not have a body.
+        if !data.unwrap_or(b"").is_empty()
+            && self.response_body_forbidden()
+            && !self.flags.is_set(HtpFlags::RESPONSE_BODY_UNEXPECTED)
+        {
+            htp_warn!(
+                self.logger,
+                HtpLogCode::RESPONSE_BODY_UNEXPECTED,
+                "Unexpected response body"
+            );
+            self.flags.set(HtpFlags::RESPONSE_BODY_UNEXPECTED);
+        }
+        if self.response_body_skip || self.body_budget_exhausted(connp, Side::Response, data) {
+            return Ok(());
+        }
 
         match self.response_content_encoding_processing {
             HtpContentEncoding::GZIP
@@ -1175,15 +2861,26 @@ impl Transaction {
                 if self.response_decompressor.is_none() && data.is_none() {
                     return Ok(());
                 }
+                // Once the limit is reached there is no point decompressing more data.
+                if data.is_some() && self.body_limit_skipping(HtpFlags::RESPONSE_BODY_LIMIT) {
+                    return Ok(());
+                }
                 let mut decompressor = self.response_decompressor.take().ok_or(HtpStatus::ERROR)?;
                 if let Some(data) = data {
-                    decompressor
-                        .decompress(data)
-                        .map_err(|_| HtpStatus::ERROR)?;
-
+                    let mut status = Ok(());
+                    let result = decompressor.decompress_with(data, &mut |data| {
+                        self.response_decompressor_callback(connp, data)
+                            .map_err(|e| {
+                                status = Err(e);
+                                std::io::Error::new(std::io::ErrorKind::Other, "hook failed")
+                            })
+                            .map(|_| data.unwrap_or(b"").len())
+                    });
+                    status?;
                     if decompressor.time_spent()
                         > self.cfg.compression_options.get_time_limit() as u64
                     {
+                        self.count_compression_bomb();
                         htp_log!(
                             self.logger,
                             HtpLogLevel::ERROR,
@@ -1195,25 +2892,37 @@ impl Transaction {
                         );
                         return Err(HtpStatus::ERROR);
                     }
+                    result.map_err(|_| HtpStatus::ERROR)?;
+                    self.record_response_decompression(&decompressor);
                     // put the decompressor back in its slot
                     self.response_decompressor.replace(decompressor);
                 } else {
                     // don't put the decompressor back in its slot
                     // ignore errors
-                    let _ = decompressor.finish();
+                    let _ = decompressor.finish_with(&mut |data| {
+                        self.response_decompressor_callback(connp, data)
+                            .map_err(|_| {
+                                std::io::Error::new(std::io::ErrorKind::Other, "hook failed")
+                            })
+                            .map(|_| data.unwrap_or(b"").len())
+                    });
+                    self.record_response_decompression(&decompressor);
                 }
             }
             HtpContentEncoding::NONE => {
                 // When there's no decompression, response_entity_len.
                 // is identical to response_message_len.
-                let data = ParserData::from(data);
-                let mut tx_data = Data {
-                    tx: self,
-                    data: &data,
-                    is_last: false,
-                };
+                let len = data.unwrap_or(b"").len();
+                let allowed = self.response_body_allowance(len)?;
                 self.response_entity_len =
-                    (self.response_entity_len as u64).wrapping_add(tx_data.len() as u64) as i64;
+                    (self.response_entity_len as u64).wrapping_add(len as u64) as i64;
+                if allowed == 0 && len > 0 {
+                    return Ok(());
+                }
+                let data = ParserData::from(data.map(|data| &data[..allowed]));
+                // self is not used again until the hooks have run.
+                let mut tx_data =
+                    unsafe { Data::new(self, &data, false) }.with_stream_offset(stream_offset);
                 connp.response_run_hook_body_data(&mut tx_data)?;
             }
             HtpContentEncoding::ERROR => {
@@ -1234,7 +2943,13 @@ impl Transaction {
         if self.request_has_body() {
             self.request_process_body_data(connp, None)?;
         }
+        self.body_utf8_validate(Side::Request, None);
+        if let Some(file) = &mut connp.request_file {
+            file.finalize_hashes();
+        }
         self.request_progress = HtpRequestProgress::COMPLETE;
+        self.request_complete_timestamp = Some(connp.request_timestamp);
+        connp.conn.stats.requests += 1;
         // Run hook REQUEST_COMPLETE.
         connp.cfg.hook_request_complete.run_all(connp, self)?;
         Ok(())
@@ -1262,8 +2977,16 @@ impl Transaction {
     /// Initialize hybrid parsing mode, change state to TRANSACTION_START,
     /// and invoke all registered callbacks.
     pub fn state_request_start(&mut self, connp: &mut ConnectionParser) -> Result<()> {
+        if self.request_start_timestamp.is_none() {
+            if let Some(metrics) = &self.metrics {
+                metrics.transaction_started();
+            }
+            self.apply_sampling(connp);
+        }
+        self.request_start_timestamp = Some(connp.request_timestamp);
         // Run hook REQUEST_START.
         connp.cfg.hook_request_start.run_all(connp, self)?;
+        connp.push_event(Event::TxStarted(self.id));
         // Change state into request line parsing.
         connp.request_state = State::LINE;
         self.request_progress = HtpRequestProgress::LINE;
@@ -1313,7 +3036,7 @@ impl Transaction {
     ///         callbacks does not want to follow the transaction any more.
     pub fn state_request_line(&mut self, connp: &mut ConnectionParser) -> Result<()> {
         // Determine how to process the request URI.
-        let mut parsed_uri = Uri::with_config(connp.cfg.decoder_cfg);
+        let mut parsed_uri = Uri::with_config(self.cfg.decoder_cfg.clone());
         if self.request_method_number == HtpMethod::CONNECT {
             // When CONNECT is used, the request URI contains an authority string.
             parsed_uri.parse_uri_hostport(
@@ -1323,6 +3046,14 @@ impl Transaction {
         } else if let Some(uri) = self.request_uri.as_ref() {
             parsed_uri.parse_uri(uri.as_slice());
         }
+        if parsed_uri.username.is_some() || parsed_uri.password.is_some() {
+            self.flags.set(HtpFlags::URI_CREDENTIALS);
+            htp_warn!(
+                self.logger,
+                HtpLogCode::URI_CREDENTIALS,
+                "Request URI carries credentials"
+            );
+        }
         self.parsed_uri_raw = Some(parsed_uri);
         // Parse the request URI into Transaction::parsed_uri_raw.
         // Build Transaction::parsed_uri, but only if it was not explicitly set already.
@@ -1337,16 +3068,25 @@ impl Transaction {
                 .and_then(|parsed_uri| parsed_uri.query.clone())
             {
                 // We have a non-zero length query string.
-                let mut urlenp = UrlEncodedParser::new(self.cfg.decoder_cfg);
+                let mut urlenp = UrlEncodedParser::new(self.cfg.decoder_cfg.clone());
+                // The query string follows the first '?' of the request URI.
+                if let Some(question) = self
+                    .request_uri
+                    .as_ref()
+                    .and_then(|uri| uri.iter().position(|c| *c == b'?'))
+                {
+                    urlenp.source_offset = question + 1;
+                }
                 urlenp.parse_complete(query.as_slice());
+                if urlenp.flags.is_set(HtpFlags::URLEN_ALT_SEPARATOR) {
+                    self.flags.set(HtpFlags::URLEN_ALT_SEPARATOR);
+                }
+                self.request_urlencoded_flags.set(urlenp.urlencoded_flags);
 
                 // Add all parameters to the transaction.
-                for (name, value) in urlenp.params.elements.iter() {
-                    let param = Param::new(
-                        Bstr::from(name.as_slice()),
-                        Bstr::from(value.as_slice()),
-                        HtpDataSource::QUERY_STRING,
-                    );
+                for (name, UrlEncodedParamValue { value, raw }) in urlenp.params {
+                    let param =
+                        Param::from_urlencoded(name, value, raw, HtpDataSource::QUERY_STRING);
                     self.request_add_param(param)?;
                 }
             }
@@ -1362,12 +3102,27 @@ impl Transaction {
         connp.cfg.hook_request_uri_normalize.run_all(connp, self)?;
         // Run hook REQUEST_LINE.
         connp.cfg.hook_request_line.run_all(connp, self)?;
+        if connp.events_enabled() {
+            connp.push_event(Event::RequestLine {
+                tx: self.id,
+                line: self.request_line.clone().unwrap_or_default(),
+            });
+        }
         if let Some(parsed_uri) = self.parsed_uri.as_mut() {
             let (partial_normalized_uri, complete_normalized_uri) =
                 parsed_uri.generate_normalized_uri(Some(self.logger.clone()));
             self.partial_normalized_uri = partial_normalized_uri;
             self.complete_normalized_uri = complete_normalized_uri;
         }
+        // Run hook REQUEST_URI_NORMALIZED. Returning STOP marks the transaction
+        // as uninteresting, so that its bodies are not processed.
+        match connp.cfg.hook_request_uri_normalized.run_all(connp, self) {
+            Err(HtpStatus::STOP) => {
+                self.set_request_body_skip();
+                self.set_response_body_skip();
+            }
+            rc => rc?,
+        }
         // Move on to the next phase.
         connp.request_state = State::PROTOCOL;
         Ok(())
@@ -1387,11 +3142,45 @@ impl Transaction {
         if !self.is_complete() {
             return Ok(());
         }
+        if let Some(metrics) = &self.metrics {
+            metrics.transaction_completed();
+            if self.flags.is_set(HtpFlags::REQUEST_SMUGGLING) {
+                metrics.request_smuggling();
+            }
+        }
+        let stats = &mut connp.conn.stats;
+        stats.track_flags(self.flags);
+        if self.request_content_encoding_processing != HtpContentEncoding::NONE {
+            stats.request_decompressed_bytes += self.request_entity_len.max(0) as u64;
+        }
+        if self.response_content_encoding_processing != HtpContentEncoding::NONE {
+            stats.response_decompressed_bytes += self.response_entity_len.max(0) as u64;
+        }
         // Run hook TRANSACTION_COMPLETE.
         connp.cfg.hook_transaction_complete.run_all(connp, self)?;
+        connp.push_event(Event::TxComplete(self.id));
         Ok(())
     }
 
+    /// Queue a HeaderParsed event for each request or response header.
+    fn push_header_events(&self, connp: &mut ConnectionParser, direction: Direction) {
+        if !connp.events_enabled() {
+            return;
+        }
+        let headers = match direction {
+            Direction::Request => &self.request_headers,
+            Direction::Response => &self.response_headers,
+        };
+        for (_, header) in headers {
+            connp.push_event(Event::HeaderParsed {
+                tx: self.id,
+                direction,
+                name: header.name.clone(),
+                value: header.value.clone(),
+            });
+        }
+    }
+
     /// Change transaction state to RESPONSE and invoke registered callbacks.
     pub fn state_response_complete_ex(
         &mut self,
@@ -1400,10 +3189,17 @@ impl Transaction {
     ) -> Result<()> {
         if self.response_progress != HtpResponseProgress::COMPLETE {
             self.response_progress = HtpResponseProgress::COMPLETE;
+            self.response_complete_timestamp = Some(connp.response_timestamp);
+            connp.conn.stats.responses += 1;
             // Run the last RESPONSE_BODY_DATA HOOK, but only if there was a response body present.
             if self.response_transfer_coding != HtpTransferCoding::NO_BODY {
                 let _ = self.response_process_body_data(connp, None);
             }
+            // Sniff bodies that were shorter than the sniffing window.
+            self.response_sniff(None);
+            self.response_charset_scan(None);
+            self.body_utf8_validate(Side::Response, None);
+            self.response_framing_check(connp);
             // Run hook RESPONSE_COMPLETE.
             connp.cfg.hook_response_complete.run_all(connp, self)?;
         }
@@ -1438,47 +3234,249 @@ impl Transaction {
         Ok(())
     }
 
+    /// Compare how the response body was delimited with what the headers declared:
+    /// a body shorter or longer than Content-Length, or a body ended by closing a
+    /// connection the headers declared persistent.
+    fn response_framing_check(&mut self, connp: &ConnectionParser) {
+        let closed = connp.response_status == HtpStreamState::CLOSED;
+        self.response_delimiter = match self.response_transfer_coding {
+            HtpTransferCoding::NO_BODY => HtpResponseDelimiter::NONE,
+            HtpTransferCoding::CHUNKED => HtpResponseDelimiter::CHUNKED,
+            HtpTransferCoding::IDENTITY if self.response_content_length >= 0 => {
+                if closed && self.response_message_len < self.response_content_length {
+                    HtpResponseDelimiter::CLOSE
+                } else {
+                    HtpResponseDelimiter::CONTENT_LENGTH
+                }
+            }
+            _ => HtpResponseDelimiter::CLOSE,
+        };
+        if self.response_transfer_coding == HtpTransferCoding::IDENTITY
+            && self.response_content_length >= 0
+        {
+            self.response_framing_delta = self.response_message_len - self.response_content_length;
+            // Data after the body that was already logged as an unexpected
+            // response body is not reported again.
+            if self.response_framing_delta != 0
+                && !(0..=self.response_unexpected_body_len).contains(&self.response_framing_delta)
+            {
+                self.flags.set(HtpFlags::RESPONSE_FRAMING_MISMATCH);
+                htp_warn!(
+                    self.logger,
+                    HtpLogCode::RESPONSE_FRAMING_MISMATCH,
+                    format!(
+                        "Response body of {} bytes does not match Content-Length {}",
+                        self.response_message_len, self.response_content_length
+                    )
+                );
+            }
+        } else if self.response_delimiter == HtpResponseDelimiter::CLOSE
+            && self.response_message_len > 0
+            && self.response_connection_persistent()
+        {
+            self.flags.set(HtpFlags::RESPONSE_FRAMING_MISMATCH);
+            htp_warn!(
+                self.logger,
+                HtpLogCode::RESPONSE_FRAMING_MISMATCH,
+                "Response body delimited by connection close on a persistent connection"
+            );
+        }
+    }
+
+    /// Determine if the response headers declare the connection persistent: HTTP/1.1
+    /// without "Connection: close", or HTTP/1.0 with "Connection: keep-alive".
+    fn response_connection_persistent(&self) -> bool {
+        let connection = self
+            .response_headers
+            .get_nocase_nozero("connection")
+            .map(|(_, h)| h.value.clone());
+        let has = |token: &str| match &connection {
+            Some(value) => value.index_of_nocase_nozero(token).is_some(),
+            None => false,
+        };
+        if self.response_protocol_number >= HtpProtocol::V1_1 {
+            !has("close")
+        } else {
+            has("keep-alive")
+        }
+    }
+
+    /// Feed response body data to the content sniffer, passing None at the end of
+    /// the body. Once enough data is seen, the sniffed type is stored and compared
+    /// against the declared Content-Type.
+    pub fn response_sniff(&mut self, data: Option<&[u8]>) {
+        let limit = if let Some(limit) = self.cfg.response_body_sniff_len {
+            limit
+        } else {
+            return;
+        };
+        if self.response_sniffed_type.is_some() {
+            return;
+        }
+        if let Some(data) = data {
+            let len = std::cmp::min(limit - self.response_sniff_buf.len(), data.len());
+            self.response_sniff_buf.add(&data[..len]);
+            if self.response_sniff_buf.len() < limit {
+                return;
+            }
+        } else if self.response_sniff_buf.is_empty() {
+            // No response body.
+            return;
+        }
+        let sniffed = HtpSniffedType::sniff(self.response_sniff_buf.as_slice());
+        self.response_sniffed_type = Some(sniffed);
+        self.response_sniff_buf.clear();
+        if let Some(content_type) = &self.response_content_type {
+            if !sniffed.matches(content_type.as_slice()) {
+                self.flags.set(HtpFlags::RESPONSE_CONTENT_TYPE_MISMATCH);
+                htp_warn!(
+                    self.logger,
+                    HtpLogCode::RESPONSE_CONTENT_TYPE_MISMATCH,
+                    format!(
+                        "Response body sniffed as {:?} but declared as {}",
+                        sniffed,
+                        String::from_utf8_lossy(content_type.as_slice())
+                    )
+                );
+            }
+        }
+    }
+
+    /// Feed body data to the UTF-8 validator of the given side, passing None at
+    /// the end of the body.
+    pub(crate) fn body_utf8_validate(&mut self, side: Side, data: Option<&[u8]>) {
+        if !self.cfg.body_utf8_validation {
+            return;
+        }
+        let bestfit_map = &self.cfg.decoder_cfg.bestfit_map;
+        let (decoder, flags) = match side {
+            Side::Request => (
+                &mut self.request_body_utf8,
+                &mut self.request_body_utf8_flags,
+            ),
+            Side::Response => (
+                &mut self.response_body_utf8,
+                &mut self.response_body_utf8_flags,
+            ),
+        };
+        if let Some(data) = data {
+            let decoder = decoder.get_or_insert_with(|| Utf8Decoder::new(bestfit_map.clone()));
+            decoder.decode_chunk(data);
+            decoder.decoded_bytes.clear();
+            *flags = decoder.flags;
+        } else if let Some(mut decoder) = decoder.take() {
+            decoder.finish();
+            *flags = decoder.flags;
+        }
+    }
+
+    /// Feed response body data to the charset scanner, passing None at the end of
+    /// the body. Once enough data is seen, the charset declared by a meta tag is
+    /// stored and compared against the charset of the Content-Type header.
+    pub fn response_charset_scan(&mut self, data: Option<&[u8]>) {
+        let limit = if let Some(limit) = self.cfg.response_charset_scan_len {
+            limit
+        } else {
+            return;
+        };
+        if self.response_charset_scanned {
+            return;
+        }
+        if let Some(data) = data {
+            let len = std::cmp::min(limit - self.response_charset_buf.len(), data.len());
+            self.response_charset_buf.add(&data[..len]);
+            if self.response_charset_buf.len() < limit {
+                return;
+            }
+        } else if self.response_charset_buf.is_empty() {
+            // No response body.
+            return;
+        }
+        self.response_charset_scanned = true;
+        self.response_detected_charset = meta_charset(self.response_charset_buf.as_slice());
+        self.response_charset_buf.clear();
+        let declared = self
+            .response_headers
+            .get_nocase_nozero("content-type")
+            .and_then(|(_, header)| {
+                let value = header.value.as_slice();
+                let params = value.iter().position(|c| *c == b';')?;
+                charset_param(&value[params..])
+            });
+        if let (Some(detected), Some(declared)) = (&self.response_detected_charset, declared) {
+            if !same_charset(detected.as_slice(), declared.as_slice()) {
+                self.flags.set(HtpFlags::RESPONSE_CHARSET_MISMATCH);
+                htp_warn!(
+                    self.logger,
+                    HtpLogCode::RESPONSE_CHARSET_MISMATCH,
+                    format!(
+                        "Response body declares charset {} but Content-Type declares {}",
+                        String::from_utf8_lossy(detected.as_slice()),
+                        String::from_utf8_lossy(declared.as_slice())
+                    )
+                );
+            }
+        }
+    }
+
+    /// Feed raw identity response body data to the response splitting scan. Each
+    /// body line is checked for a status line, and the first one for a header field.
+    pub(crate) fn response_splitting_scan(&mut self, mut data: &[u8]) {
+        if !self.cfg.response_splitting_detection || self.flags.is_set(HtpFlags::RESPONSE_SPLITTING)
+        {
+            return;
+        }
+        while !data.is_empty() {
+            let end = data.iter().position(|c| *c == b'\n').map(|pos| pos + 1);
+            let line = &data[..end.unwrap_or(data.len())];
+            data = &data[line.len()..];
+            let len = std::cmp::min(
+                RESPONSE_SPLITTING_LINE_LEN - self.response_splitting_line.len(),
+                line.len(),
+            );
+            self.response_splitting_line.add(&line[..len]);
+            if end.is_none() {
+                return;
+            }
+            let prefix = self.response_splitting_line.as_slice();
+            let split = looks_like_status_line(prefix)
+                || (self.response_splitting_lines == 0 && looks_like_header_line(prefix));
+            self.response_splitting_line.clear();
+            self.response_splitting_lines += 1;
+            if split {
+                self.flags.set(HtpFlags::RESPONSE_SPLITTING);
+                htp_warn!(
+                    self.logger,
+                    HtpLogCode::RESPONSE_SPLITTING,
+                    "Response body looks like it contains another response"
+                );
+                return;
+            }
+        }
+    }
+
     fn response_decompressor_callback(
         &mut self,
         connp: &mut ConnectionParser,
         data: Option<&[u8]>,
-    ) -> std::io::Result<usize> {
-        // If no data is passed, call the hooks with NULL to signify the end of the
-        // response body.
-        let mut tx_data = Data {
-            tx: self,
-            data: &ParserData::from(data),
-            // is_last is not used in this callback
-            is_last: false,
-        };
-
+    ) -> Result<()> {
+        let len = data.unwrap_or(b"").len();
+        let allowed = self.response_body_allowance(len)?;
         // Keep track of actual response body length.
         self.response_entity_len =
-            (self.response_entity_len as u64).wrapping_add(tx_data.len() as u64) as i64;
-
-        // Invoke all callbacks.
-        connp
-            .response_run_hook_body_data(&mut tx_data)
-            .map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "body data hook failed"))?;
+            (self.response_entity_len as u64).wrapping_add(len as u64) as i64;
 
-        if let Some(decompressor) = &mut self.response_decompressor {
-            if decompressor.callback_inc() % self.cfg.compression_options.get_time_test_freq() == 0
-            {
-                if let Some(time_spent) = decompressor.timer_reset() {
-                    if time_spent > self.cfg.compression_options.get_time_limit() as u64 {
-                        htp_log!(
-                            self.logger,
-                            HtpLogLevel::ERROR,
-                            HtpLogCode::COMPRESSION_BOMB,
-                            format!("Compression bomb: spent {} us decompressing", time_spent)
-                        );
-                        return Err(std::io::Error::new(
-                            std::io::ErrorKind::Other,
-                            "compression_time_limit reached",
-                        ));
-                    }
-                }
-            }
+        // Invoke all callbacks, unless the body limit leaves nothing to pass on.
+        if allowed > 0 || len == 0 {
+            // If no data is passed, call the hooks with NULL to signify the end of the
+            // response body.
+            let data = ParserData::from(data.map(|data| &data[..allowed]));
+            // is_last is not used in this callback. self is not used again until the
+            // hooks have run.
+            let mut tx_data = unsafe { Data::new(self, &data, false) };
+            connp
+                .response_run_hook_body_data(&mut tx_data)
+                .map_err(|_| HtpStatus::ERROR)?;
         }
 
         // output > ratio * input ?
@@ -1492,6 +3490,7 @@ impl Transaction {
 
         let bomb_limit = self.cfg.compression_options.get_bomb_limit();
         if self.response_entity_len > bomb_limit as i64 && exceeds_ratio {
+            self.count_compression_bomb();
             htp_log!(
                 self.logger,
                 HtpLogLevel::ERROR,
@@ -1501,55 +3500,32 @@ impl Transaction {
                     self.response_entity_len, self.response_message_len,
                 )
             );
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "compression_bomb_limit reached",
-            ));
+            return Err(HtpStatus::ERROR);
         }
-        Ok(tx_data.len())
+        Ok(())
     }
 
     fn request_decompressor_callback(
         &mut self,
         connp: &mut ConnectionParser,
         data: Option<&[u8]>,
-    ) -> std::io::Result<usize> {
-        // If no data is passed, call the hooks with NULL to signify the end of the
-        // request body.
-        let mut tx_data = Data {
-            tx: self,
-            data: &ParserData::from(data),
-            // is_last is not used in this callback
-            is_last: false,
-        };
-
+    ) -> Result<()> {
+        let len = data.unwrap_or(b"").len();
+        let allowed = self.request_body_allowance(len)?;
         // Keep track of actual request body length.
-        self.request_entity_len =
-            (self.request_entity_len as u64).wrapping_add(tx_data.len() as u64) as i64;
-
-        // Invoke all callbacks.
-        connp
-            .request_run_hook_body_data(&mut tx_data)
-            .map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "body data hook failed"))?;
+        self.request_entity_len = (self.request_entity_len as u64).wrapping_add(len as u64) as i64;
 
-        if let Some(decompressor) = &mut self.request_decompressor {
-            if decompressor.callback_inc() % self.cfg.compression_options.get_time_test_freq() == 0
-            {
-                if let Some(time_spent) = decompressor.timer_reset() {
-                    if time_spent > self.cfg.compression_options.get_time_limit() as u64 {
-                        htp_log!(
-                            self.logger,
-                            HtpLogLevel::ERROR,
-                            HtpLogCode::COMPRESSION_BOMB,
-                            format!("Compression bomb: spent {} us decompressing", time_spent)
-                        );
-                        return Err(std::io::Error::new(
-                            std::io::ErrorKind::Other,
-                            "compression_time_limit reached",
-                        ));
-                    }
-                }
-            }
+        // Invoke all callbacks, unless the body limit leaves nothing to pass on.
+        if allowed > 0 || len == 0 {
+            // If no data is passed, call the hooks with NULL to signify the end of the
+            // request body.
+            let data = ParserData::from(data.map(|data| &data[..allowed]));
+            // is_last is not used in this callback. self is not used again until the
+            // hooks have run.
+            let mut tx_data = unsafe { Data::new(self, &data, false) };
+            connp
+                .request_run_hook_body_data(&mut tx_data)
+                .map_err(|_| HtpStatus::ERROR)?;
         }
 
         // output > ratio * input ?
@@ -1563,6 +3539,7 @@ impl Transaction {
 
         let bomb_limit = self.cfg.compression_options.get_bomb_limit();
         if self.request_entity_len > bomb_limit as i64 && exceeds_ratio {
+            self.count_compression_bomb();
             htp_log!(
                 self.logger,
                 HtpLogLevel::ERROR,
@@ -1572,19 +3549,12 @@ impl Transaction {
                     self.request_entity_len, self.request_message_len,
                 )
             );
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "compression_bomb_limit reached",
-            ));
+            return Err(HtpStatus::ERROR);
         }
-        Ok(tx_data.len())
+        Ok(())
     }
 
-    fn request_prepend_decompressor(
-        &mut self,
-        connp: &mut ConnectionParser,
-        encoding: HtpContentEncoding,
-    ) -> Result<()> {
+    fn request_prepend_decompressor(&mut self, encoding: HtpContentEncoding) -> Result<()> {
         if encoding != HtpContentEncoding::NONE {
             if let Some(decompressor) = self.request_decompressor.take() {
                 self.request_decompressor
@@ -1596,29 +3566,17 @@ impl Transaction {
                 // Add the callback first because it will be called last in
                 // the chain of writers
 
-                // TODO: fix lifetime error and remove this line!
-                let tx = self as *mut Self;
-                let connp_ptr: *mut ConnectionParser = connp as *mut ConnectionParser;
-
-                self.request_decompressor.replace(unsafe {
-                    Decompressor::new_with_callback(
+                self.request_decompressor
+                    .replace(Decompressor::new_with_sink(
                         encoding,
-                        Box::new(move |data: Option<&[u8]>| -> std::io::Result<usize> {
-                            (*tx).request_decompressor_callback(&mut *connp_ptr, data)
-                        }),
                         self.cfg.compression_options,
-                    )?
-                });
+                    )?);
             }
         }
         Ok(())
     }
 
-    fn response_prepend_decompressor(
-        &mut self,
-        connp: &mut ConnectionParser,
-        encoding: HtpContentEncoding,
-    ) -> Result<()> {
+    fn response_prepend_decompressor(&mut self, encoding: HtpContentEncoding) -> Result<()> {
         if encoding != HtpContentEncoding::NONE {
             if let Some(decompressor) = self.response_decompressor.take() {
                 self.response_decompressor
@@ -1630,19 +3588,11 @@ impl Transaction {
                 // Add the callback first because it will be called last in
                 // the chain of writers
 
-                // TODO: fix lifetime error and remove this line!
-                let tx = self as *mut Self;
-                let connp_ptr: *mut ConnectionParser = connp as *mut ConnectionParser;
-
-                self.response_decompressor.replace(unsafe {
-                    Decompressor::new_with_callback(
+                self.response_decompressor
+                    .replace(Decompressor::new_with_sink(
                         encoding,
-                        Box::new(move |data: Option<&[u8]>| -> std::io::Result<usize> {
-                            (*tx).response_decompressor_callback(&mut *connp_ptr, data)
-                        }),
                         self.cfg.compression_options,
-                    )?
-                });
+                    )?);
             }
         }
         Ok(())
@@ -1681,6 +3631,19 @@ impl Transaction {
         } else {
             HtpContentEncoding::NONE
         };
+        let codings: &[&str] = match self.response_content_encoding {
+            HtpContentEncoding::GZIP => &["gzip", "x-gzip"],
+            HtpContentEncoding::DEFLATE => &["deflate", "x-deflate"],
+            HtpContentEncoding::LZMA => &["lzma"],
+            _ => &[],
+        };
+        if !codings.is_empty() && !self.request_accepts_encoding(codings) {
+            htp_warn!(
+                self.logger,
+                HtpLogCode::RESPONSE_CONTENT_ENCODING_NOT_ACCEPTED,
+                "Response content encoding not accepted by the request"
+            );
+        }
 
         // Configure decompression, if enabled in the configuration.
         self.response_content_encoding_processing = if self.cfg.response_decompression_enabled {
@@ -1690,10 +3653,49 @@ impl Transaction {
             HtpContentEncoding::NONE
         };
 
+        // Follow connection-oriented authentication handshakes.
+        if self.cfg.parse_request_auth {
+            parse_authenticate(self);
+            if let Some(message) = self.response_auth_ntlm_message {
+                connp.conn.ntlm_handshake = Some(message);
+            }
+        }
+
+        if let Some((_, cd)) = self
+            .response_headers
+            .get_nocase_nozero("content-disposition")
+        {
+            self.response_content_disposition =
+                Some(parse_content_disposition(cd.value.as_slice()));
+        }
+        self.response_etag = self
+            .response_headers
+            .get_nocase_nozero("etag")
+            .and_then(|(_, etag)| parse_entity_tag(etag.value.as_slice()));
+        self.response_last_modified = self
+            .response_headers
+            .get_nocase_nozero("last-modified")
+            .and_then(|(_, lm)| parse_http_date(lm.value.as_slice()));
+        self.response_alt_svc = self
+            .response_headers
+            .get_nocase_nozero("alt-svc")
+            .map(|(_, alt_svc)| parse_alt_svc(alt_svc.value.as_slice()));
+        if let Some(alt_svc) = &self.response_alt_svc {
+            // A new advertisement replaces the alternatives known for the service.
+            connp.conn.alt_svc = alt_svc.clone();
+        }
+
+        self.response_header_fingerprint = Some(HeaderFingerprint::new(&self.response_headers));
         // Run hook RESPONSE_HEADERS.
         //TODO: remove clone
         let hook_response_headers = self.cfg.hook_response_headers.clone();
         hook_response_headers.run_all(connp, self)?;
+        self.push_header_events(connp, Direction::Response);
+        // Only the framing of a skipped body is followed.
+        if self.response_body_skip {
+            self.response_content_encoding_processing = HtpContentEncoding::NONE;
+            return Ok(());
+        }
 
         // Initialize the decompression engine as necessary. We can deal with three
         // scenarios:
@@ -1711,10 +3713,7 @@ impl Transaction {
             | HtpContentEncoding::DEFLATE
             | HtpContentEncoding::ZLIB
             | HtpContentEncoding::LZMA => {
-                self.response_prepend_decompressor(
-                    connp,
-                    self.response_content_encoding_processing,
-                )?;
+                self.response_prepend_decompressor(self.response_content_encoding_processing)?;
                 Ok(())
             }
             HtpContentEncoding::NONE => {
@@ -1789,7 +3788,7 @@ impl Transaction {
                                 HtpContentEncoding::NONE
                             };
 
-                            self.response_prepend_decompressor(connp, encoding)?;
+                            self.response_prepend_decompressor(encoding)?;
                         }
                     }
                 }
@@ -1811,6 +3810,7 @@ impl Transaction {
     /// Returns OK on success; ERROR on error, HTP_STOP if one of the
     ///         callbacks does not want to follow the transaction any more.
     pub fn state_response_start(&mut self, connp: &mut ConnectionParser) -> Result<()> {
+        self.response_start_timestamp = Some(connp.response_timestamp);
         // Run hook RESPONSE_START.
         connp.cfg.hook_response_start.run_all(connp, self)?;
         // Change state into response line parsing, except if we're following
@@ -1841,6 +3841,132 @@ impl Transaction {
         Ok(())
     }
 
+    /// Returns how many of the next `len` decompressed request body bytes may be
+    /// processed under the configured request body limit.
+    fn request_body_allowance(&mut self, len: usize) -> Result<usize> {
+        self.body_allowance(Side::Request, len)
+    }
+
+    /// Returns how many of the next `len` decompressed response body bytes may be
+    /// processed under the configured response body limit.
+    fn response_body_allowance(&mut self, len: usize) -> Result<usize> {
+        self.body_allowance(Side::Response, len)
+    }
+
+    fn body_allowance(&mut self, side: Side, len: usize) -> Result<usize> {
+        let (limit, seen, flag) = match side {
+            Side::Request => (
+                self.cfg.request_body_limit,
+                self.request_entity_len,
+                HtpFlags::REQUEST_BODY_LIMIT,
+            ),
+            Side::Response => (
+                self.cfg.response_body_limit,
+                self.response_entity_len,
+                HtpFlags::RESPONSE_BODY_LIMIT,
+            ),
+        };
+        let limit = match limit {
+            Some(limit) => limit,
+            None => return Ok(len),
+        };
+        let seen = seen.max(0) as u64;
+        if len == 0 || seen.saturating_add(len as u64) <= limit {
+            return Ok(len);
+        }
+        if !self.flags.is_set(flag) {
+            self.flags.set(flag);
+            let msg = format!("Body exceeds the configured limit of {} bytes", limit);
+            match side {
+                Side::Request => htp_warn!(self.logger, HtpLogCode::REQUEST_BODY_LIMIT, msg),
+                Side::Response => htp_warn!(self.logger, HtpLogCode::RESPONSE_BODY_LIMIT, msg),
+            }
+        }
+        match self.cfg.body_limit_action {
+            HtpBodyLimitAction::SKIP => Ok(limit.saturating_sub(seen) as usize),
+            HtpBodyLimitAction::FLAG => Ok(len),
+            HtpBodyLimitAction::ERROR => Err(HtpStatus::ERROR),
+        }
+    }
+
+    /// Decides, when the transaction starts, whether its bodies are analyzed under
+    /// the configured sampling rate and connection body budget.
+    fn apply_sampling(&mut self, connp: &ConnectionParser) {
+        let sampled_out = match self.cfg.analysis_sample_rate {
+            Some(rate) if rate > 1 => {
+                let mut hasher = RandomState::new().build_hasher();
+                hasher.write_usize(self.index);
+                hasher.finish() >= u64::MAX / u64::from(rate)
+            }
+            _ => false,
+        };
+        let exhausted = matches!(
+            self.cfg.connection_body_budget,
+            Some(budget) if connp.body_analysis_used >= budget
+        );
+        if sampled_out || exhausted {
+            self.set_request_body_skip();
+            self.set_response_body_skip();
+            self.analysis_depth = HtpAnalysisDepth::HEADERS_ONLY;
+        }
+    }
+
+    /// Charges body data to the connection body budget. Returns true, after
+    /// skipping the rest of the body on this side, if the budget is already spent.
+    fn body_budget_exhausted(
+        &mut self,
+        connp: &mut ConnectionParser,
+        side: Side,
+        data: Option<&[u8]>,
+    ) -> bool {
+        let (budget, data) = match (self.cfg.connection_body_budget, data) {
+            (Some(budget), Some(data)) => (budget, data),
+            _ => return false,
+        };
+        if connp.body_analysis_used < budget {
+            connp.body_analysis_used = connp.body_analysis_used.saturating_add(data.len() as u64);
+            return false;
+        }
+        match side {
+            Side::Request => {
+                self.set_request_body_skip();
+                self.set_response_body_skip();
+            }
+            Side::Response => self.set_response_body_skip(),
+        }
+        if self.analysis_depth == HtpAnalysisDepth::FULL {
+            self.analysis_depth = HtpAnalysisDepth::BODY_TRUNCATED;
+        }
+        true
+    }
+
+    /// Records the layer sizes and overall ratio of request decompression.
+    fn record_request_decompression(&mut self, decompressor: &Decompressor) {
+        let layers = decompressor.layer_sizes();
+        self.request_decompression_ratio = decompression_ratio(&layers);
+        self.request_decompression_layers = layers;
+    }
+
+    /// Records the layer sizes and overall ratio of response decompression.
+    fn record_response_decompression(&mut self, decompressor: &Decompressor) {
+        let layers = decompressor.layer_sizes();
+        self.response_decompression_ratio = decompression_ratio(&layers);
+        self.response_decompression_layers = layers;
+    }
+
+    /// Counts a compression bomb in the metrics registry, if there is one.
+    fn count_compression_bomb(&self) {
+        if let Some(metrics) = &self.metrics {
+            metrics.compression_bomb();
+        }
+    }
+
+    /// Returns true if the body limit identified by `flag` was reached and the rest
+    /// of the body is being skipped.
+    fn body_limit_skipping(&self, flag: u64) -> bool {
+        self.flags.is_set(flag) && self.cfg.body_limit_action == HtpBodyLimitAction::SKIP
+    }
+
     /// Determines if both request and response are complete.
     pub fn is_complete(&self) -> bool {
         // A transaction is considered complete only when both the request and
@@ -1873,7 +3999,7 @@ impl Transaction {
 
     /// Normalize a previously-parsed request URI.
     pub fn normalize_parsed_uri(&mut self) {
-        let mut uri = Uri::with_config(self.cfg.decoder_cfg);
+        let mut uri = Uri::with_config(self.cfg.decoder_cfg.clone());
         if let Some(incomplete) = &self.parsed_uri_raw {
             uri.scheme = incomplete.normalized_scheme();
             uri.username = incomplete.normalized_username(&mut self.flags);
@@ -1882,11 +4008,342 @@ impl Transaction {
             uri.port_number = incomplete.normalized_port(&mut self.flags);
             uri.query = incomplete.query.clone();
             uri.fragment = incomplete.normalized_fragment(&mut self.flags);
-            uri.path = incomplete
-                .normalized_path(&mut self.flags, &mut self.response_status_expected_number);
+            let mut path = incomplete.path.clone();
+            if let Some(raw) = &incomplete.path {
+                let (stripped, parameters, path_flags) = split_path_parameters(raw.as_slice());
+                uri.path_parameters = parameters;
+                uri.path_flags.set(path_flags);
+                if self.cfg.path_parameters_strip {
+                    path = Some(stripped);
+                }
+            }
+            if let Some(path) = path {
+                uri.path = Some(incomplete.normalize_path(
+                    path,
+                    &mut self.flags,
+                    &mut self.response_status_expected_number,
+                ));
+            }
+            uri.path_encoding = incomplete.path_encoding.clone();
+            uri.query_encoding = incomplete.query_encoding.clone();
+            if let (Some(raw), Some(path)) = (&incomplete.path, &uri.path) {
+                if raw.starts_with("//") && !path.starts_with("//") {
+                    uri.path_flags.set(PathFlags::LEADING_SLASHES_COLLAPSED);
+                }
+            }
+            if self.cfg.path_cookieless_session_strip {
+                uri.strip_cookieless_session();
+            }
+            uri.split_path_info(&self.cfg.path_info_extensions);
+            if self.cfg.uri_userinfo_strip {
+                uri.username = None;
+                uri.password = None;
+            }
+            if self.cfg.uri_fragment_strip {
+                uri.fragment = None;
+            }
         }
         self.parsed_uri = Some(uri);
     }
+
+    /// Serializes the request line and headers of this transaction back to wire
+    /// format, ending with the empty line that terminates the headers. Bodies are
+    /// not retained by the parser, so a caller forwarding the request appends the
+    /// body itself. Headers are written from the header table, one per line and
+    /// without folding.
+    ///
+    /// Returns None if a header field was repeated: repeated fields are merged into
+    /// one table entry (or dropped, for Content-Length), so the original lines cannot
+    /// be reproduced. Replacing such a header makes the transaction serializable again.
+    ///
+    /// When `normalized` is set, the request line is rebuilt from the method, the
+    /// normalized URI in origin form and the protocol instead of being copied as
+    /// received.
+    pub fn to_request_bytes(&self, normalized: bool) -> Option<Vec<u8>> {
+        let mut out = Vec::new();
+        if normalized {
+            let uri = self
+                .partial_normalized_uri
+                .as_ref()
+                .or(self.request_uri.as_ref());
+            let protocol = protocol_name(self.request_protocol_number)
+                .or_else(|| self.request_protocol.as_ref().map(|p| p.as_slice()));
+            let parts = [
+                self.request_method.as_ref().map(|m| m.as_slice()),
+                uri.map(|u| u.as_slice()),
+                protocol,
+            ];
+            join_line(&mut out, &parts);
+        } else if let Some(line) = &self.request_line {
+            out.extend_from_slice(line.as_slice());
+        }
+        out.extend_from_slice(b"\r\n");
+        // HTTP/0.9 requests consist of the request line alone.
+        if !self.is_protocol_0_9 {
+            write_headers(&mut out, &self.request_headers)?;
+        }
+        Some(out)
+    }
+
+    /// Serializes the status line and headers of this transaction back to wire
+    /// format. See to_request_bytes.
+    ///
+    /// When `normalized` is set, the status line is rebuilt from the protocol, the
+    /// status code and the reason phrase instead of being copied as received.
+    pub fn to_response_bytes(&self, normalized: bool) -> Option<Vec<u8>> {
+        let mut out = Vec::new();
+        if normalized {
+            let protocol = protocol_name(self.response_protocol_number)
+                .or_else(|| self.response_protocol.as_ref().map(|p| p.as_slice()));
+            let status = match self.response_status_number {
+                HtpResponseNumber::VALID(status) => Some(Bstr::from(status.to_string().as_str())),
+                _ => self.response_status.clone(),
+            };
+            let parts = [
+                protocol,
+                status.as_ref().map(|s| s.as_slice()),
+                self.response_message.as_ref().map(|m| m.as_slice()),
+            ];
+            join_line(&mut out, &parts);
+        } else if let Some(line) = &self.response_line {
+            out.extend_from_slice(line.as_slice());
+        }
+        out.extend_from_slice(b"\r\n");
+        write_headers(&mut out, &self.response_headers)?;
+        Some(out)
+    }
+
+    /// Appends a request header after the existing ones, then re-validates the
+    /// request framing. See check_modified_framing.
+    pub fn add_request_header(&mut self, name: &[u8], value: &[u8]) {
+        add_header(&mut self.request_headers, name, value);
+        self.check_modified_framing(Side::Request);
+    }
+
+    /// Removes all request headers with the given name, ignoring case, then
+    /// re-validates the request framing. Returns the number of removed headers.
+    pub fn remove_request_header(&mut self, name: &[u8]) -> usize {
+        let removed = self.request_headers.remove_all_nocase(name);
+        self.check_modified_framing(Side::Request);
+        removed
+    }
+
+    /// Replaces the value of the first request header with the given name,
+    /// keeping its position and flags other than FIELD_REPEATED, and removes any
+    /// later headers with the same name. The header is appended if it is not
+    /// present. The request framing is re-validated afterwards.
+    pub fn replace_request_header(&mut self, name: &[u8], value: &[u8]) {
+        replace_header(&mut self.request_headers, name, value);
+        self.check_modified_framing(Side::Request);
+    }
+
+    /// Appends a response header after the existing ones, then re-validates the
+    /// response framing.
+    pub fn add_response_header(&mut self, name: &[u8], value: &[u8]) {
+        add_header(&mut self.response_headers, name, value);
+        self.check_modified_framing(Side::Response);
+    }
+
+    /// Removes all response headers with the given name, ignoring case, then
+    /// re-validates the response framing. Returns the number of removed headers.
+    pub fn remove_response_header(&mut self, name: &[u8]) -> usize {
+        let removed = self.response_headers.remove_all_nocase(name);
+        self.check_modified_framing(Side::Response);
+        removed
+    }
+
+    /// Replaces the value of the first response header with the given name. See
+    /// replace_request_header.
+    pub fn replace_response_header(&mut self, name: &[u8], value: &[u8]) {
+        replace_header(&mut self.response_headers, name, value);
+        self.check_modified_framing(Side::Response);
+    }
+
+    /// Checks that modified headers still frame the message unambiguously: either
+    /// Transfer-Encoding or Content-Length may be present, but not both, and all
+    /// Content-Length headers must be valid and agree. A conflict raises
+    /// HEADER_MODIFICATION_CONFLICT, which stays set even if a later modification
+    /// resolves it.
+    ///
+    /// Returns true if the framing is coherent.
+    fn check_modified_framing(&mut self, side: Side) -> bool {
+        let headers = match side {
+            Side::Request => &self.request_headers,
+            Side::Response => &self.response_headers,
+        };
+        let mut lengths = headers
+            .get_all_nocase("content-length")
+            .map(|(_, header)| parse_content_length(header.value.as_slice(), None));
+        let first = lengths.next();
+        let coherent = match first {
+            None => true,
+            Some(None) => false,
+            Some(Some(len)) => {
+                lengths.all(|other| other == Some(len))
+                    && headers.get_nocase("transfer-encoding").is_none()
+            }
+        };
+        if !coherent && !self.flags.is_set(HtpFlags::HEADER_MODIFICATION_CONFLICT) {
+            self.flags.set(HtpFlags::HEADER_MODIFICATION_CONFLICT);
+            htp_warn!(
+                self.logger,
+                HtpLogCode::HEADER_MODIFICATION_CONFLICT,
+                "Modified headers have conflicting C-L and T-E"
+            );
+        }
+        coherent
+    }
+}
+
+/// Appends a header marked as modified.
+fn add_header(headers: &mut Headers, name: &[u8], value: &[u8]) {
+    headers.add(
+        Bstr::from(name),
+        Header::new_with_flags(
+            Bstr::from(name),
+            Bstr::from(value),
+            HtpFlags::FIELD_MODIFIED,
+        ),
+    );
+}
+
+/// Replaces the value of the first header with the given name and drops the rest,
+/// or appends the header if there is none.
+fn replace_header(headers: &mut Headers, name: &[u8], value: &[u8]) {
+    let index = headers
+        .iter()
+        .position(|(key, _)| key.cmp_nocase(name) == Ordering::Equal);
+    match index {
+        Some(index) => {
+            if let Some((_, header)) = headers.get_value_mut(index) {
+                header.value = Bstr::from(value);
+                header.flags.set(HtpFlags::FIELD_MODIFIED);
+                header.flags.unset(HtpFlags::FIELD_REPEATED);
+            }
+            let mut i = 0;
+            headers.retain(|(key, _)| {
+                i += 1;
+                i - 1 <= index || key.cmp_nocase(name) != Ordering::Equal
+            });
+        }
+        None => add_header(headers, name, value),
+    }
+}
+
+/// The larger tables of a transaction, taken from a freed transaction so that a
+/// later one can reuse their allocations.
+pub(crate) struct TxTables {
+    request_headers: Headers,
+    response_headers: Headers,
+    request_params: Table<Param>,
+    request_cookies: Table<Bstr>,
+}
+
+impl TxTables {
+    /// Allocates empty tables with the capacities hinted by the configuration.
+    fn new(cfg: &Config) -> Self {
+        let (headers, params, cookies) = Self::capacities(cfg);
+        Self {
+            request_headers: Table::with_capacity(headers),
+            response_headers: Table::with_capacity(headers),
+            request_params: Table::with_capacity(params),
+            request_cookies: Table::with_capacity(cookies),
+        }
+    }
+
+    /// Takes the tables of a transaction that is being freed, emptied and shrunk
+    /// back to the capacities hinted by the configuration, so that one large
+    /// transaction does not keep its allocations for the rest of the connection.
+    pub(crate) fn take(tx: &mut Transaction, cfg: &Config) -> Self {
+        let (headers, params, cookies) = Self::capacities(cfg);
+        Self {
+            request_headers: take_table(&mut tx.request_headers, headers),
+            response_headers: take_table(&mut tx.response_headers, headers),
+            request_params: take_table(&mut tx.request_params, params),
+            request_cookies: take_table(&mut tx.request_cookies, cookies),
+        }
+    }
+
+    /// Returns the header, parameter and cookie table capacities.
+    fn capacities(cfg: &Config) -> (usize, usize, usize) {
+        (
+            cfg.expected_headers.unwrap_or(32),
+            cfg.expected_params.unwrap_or(32),
+            cfg.expected_cookies.unwrap_or(32),
+        )
+    }
+}
+
+/// Returns the bytes produced by the last decompression layer divided by the
+/// bytes received by the first, if any were received.
+fn decompression_ratio(layers: &[LayerSizes]) -> Option<f64> {
+    match (layers.first(), layers.last()) {
+        (Some(first), Some(last)) if first.input > 0 => {
+            Some(last.output as f64 / first.input as f64)
+        }
+        _ => None,
+    }
+}
+
+/// Takes a table, emptied and shrunk to the given capacity, leaving an empty one
+/// that has not allocated.
+fn take_table<T>(table: &mut Table<T>, capacity: usize) -> Table<T> {
+    let mut table = std::mem::replace(table, Table::with_capacity(0));
+    table.clear();
+    table.shrink_to(capacity);
+    table
+}
+
+/// Returns the canonical name of a recognised protocol version.
+fn protocol_name(protocol: HtpProtocol) -> Option<&'static [u8]> {
+    match protocol {
+        HtpProtocol::V1_0 => Some(b"HTTP/1.0"),
+        HtpProtocol::V1_1 => Some(b"HTTP/1.1"),
+        _ => None,
+    }
+}
+
+/// Writes the present parts separated by single spaces.
+fn join_line(out: &mut Vec<u8>, parts: &[Option<&[u8]>]) {
+    for (i, part) in parts.iter().flatten().enumerate() {
+        if i > 0 {
+            out.push(b' ');
+        }
+        out.extend_from_slice(part);
+    }
+}
+
+/// Writes headers followed by the empty line that terminates them. Returns None,
+/// leaving `out` incomplete, if a header was merged from repeated fields.
+fn write_headers(out: &mut Vec<u8>, headers: &Headers) -> Option<()> {
+    for (_, header) in headers {
+        if header.flags.is_set(HtpFlags::FIELD_REPEATED) {
+            return None;
+        }
+        out.extend_from_slice(header.name.as_slice());
+        out.extend_from_slice(b": ");
+        out.extend_from_slice(header.value.as_slice());
+        out.extend_from_slice(b"\r\n");
+    }
+    out.extend_from_slice(b"\r\n");
+    Some(())
+}
+
+impl LogContext for Transaction {
+    /// Returns the id of the transaction. Its offset in the stream is only known to
+    /// the parser, and the direction is inferred from the progress of the response.
+    fn log_position(&self) -> Option<LogPosition> {
+        let direction = if self.response_progress > HtpResponseProgress::NOT_STARTED {
+            Direction::Response
+        } else {
+            Direction::Request
+        };
+        Some(LogPosition {
+            direction,
+            tx_id: Some(self.id),
+            offset: None,
+        })
+    }
 }
 
 impl PartialEq for Transaction {
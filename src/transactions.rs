@@ -1,27 +1,37 @@
-use crate::{config::Config, log::Logger, transaction::Transaction};
+use crate::{
+    config::Config,
+    log::Logger,
+    transaction::{Transaction, TxId, TxTables},
+    util::FlagOperations,
+};
 use std::collections::BTreeMap;
-use std::rc::Rc;
+use std::sync::Arc;
 
 /// Transaction is a structure which tracks request and response
 /// transactions, and guarantees that the current request or
 /// response transaction will always exist.
 pub struct Transactions {
-    config: Rc<Config>,
+    config: Arc<Config>,
     logger: Logger,
     request: usize,
     response: usize,
+    next_id: u64,
     transactions: BTreeMap<usize, Transaction>,
+    /// Tables of freed transactions, kept for reuse.
+    pool: Vec<TxTables>,
 }
 
 impl Transactions {
     /// Make a new Transactions struct with the given config
-    pub fn new(cfg: &Rc<Config>, logger: &Logger) -> Self {
+    pub fn new(cfg: &Arc<Config>, logger: &Logger) -> Self {
         Self {
-            config: Rc::clone(cfg),
+            config: Arc::clone(cfg),
             logger: logger.clone(),
             request: 0,
             response: 0,
+            next_id: 0,
             transactions: BTreeMap::default(),
+            pool: Vec::new(),
         }
     }
 
@@ -62,9 +72,16 @@ impl Transactions {
 
     /// Get the current request transaction
     pub fn request_mut(&mut self) -> &mut Transaction {
+        let (config, logger, next_id, pool) = (
+            &self.config,
+            &self.logger,
+            &mut self.next_id,
+            &mut self.pool,
+        );
+        let index = self.request;
         self.transactions
-            .entry(self.request)
-            .or_insert(Transaction::new(&self.config, &self.logger, self.request))
+            .entry(index)
+            .or_insert_with(|| Self::create(config, logger, index, next_id, pool))
     }
 
     /// Get the current response transaction index
@@ -79,9 +96,16 @@ impl Transactions {
 
     /// Get the current response transaction
     pub fn response_mut(&mut self) -> &mut Transaction {
+        let (config, logger, next_id, pool) = (
+            &self.config,
+            &self.logger,
+            &mut self.next_id,
+            &mut self.pool,
+        );
+        let index = self.response;
         self.transactions
-            .entry(self.response)
-            .or_insert(Transaction::new(&self.config, &self.logger, self.response))
+            .entry(index)
+            .or_insert_with(|| Self::create(config, logger, index, next_id, pool))
     }
 
     /// Increment the request transaction number.
@@ -102,6 +126,27 @@ impl Transactions {
         self.response
     }
 
+    /// Create the transaction at the given index, assigning it the next id and
+    /// reusing pooled tables if there are any
+    fn create(
+        config: &Arc<Config>,
+        logger: &Logger,
+        index: usize,
+        next_id: &mut u64,
+        pool: &mut Vec<TxTables>,
+    ) -> Transaction {
+        let id = TxId(*next_id);
+        *next_id = next_id.wrapping_add(1);
+        Transaction::with_tables(config, logger, index, id, pool.pop())
+    }
+
+    /// Keeps the tables of a freed transaction for reuse, unless the pool is full.
+    fn recycle(&mut self, mut tx: Transaction) {
+        if self.pool.len() < self.config.expected_transactions.unwrap_or(2) {
+            self.pool.push(TxTables::take(&mut tx, &self.config));
+        }
+    }
+
     /// Check if any old transactions can be freed
     fn check_free(&mut self, index: usize) {
         if self.config.tx_auto_destroy {
@@ -110,8 +155,20 @@ impl Transactions {
                     return;
                 }
             }
-            self.transactions.remove(&index);
+            if let Some(tx) = self.transactions.remove(&index) {
+                self.recycle(tx);
+            }
+        }
+    }
+
+    /// Frees all transactions and starts numbering them from zero again.
+    pub fn clear(&mut self) {
+        for (_, tx) in std::mem::take(&mut self.transactions) {
+            self.recycle(tx);
         }
+        self.request = 0;
+        self.response = 0;
+        self.next_id = 0;
     }
 
     /// Remove the transaction at the given index. If the transaction
@@ -125,10 +182,52 @@ impl Transactions {
         self.transactions.get(&index)
     }
 
+    /// Get the given transaction by id
+    pub fn get_by_id(&self, id: TxId) -> Option<&Transaction> {
+        self.transactions.values().find(|tx| tx.id == id)
+    }
+
+    /// Get the given transaction by id
+    pub fn get_by_id_mut(&mut self, id: TxId) -> Option<&mut Transaction> {
+        self.transactions.values_mut().find(|tx| tx.id == id)
+    }
+
     /// Get the given transaction by index number
     pub fn get_mut(&mut self, index: usize) -> Option<&mut Transaction> {
         self.transactions.get_mut(&index)
     }
+
+    /// Iterate over the started transactions that have not been freed, in order.
+    pub fn iter(&self) -> TransactionsIter<'_> {
+        TransactionsIter {
+            iter: self.transactions.values(),
+        }
+    }
+}
+
+/// An iterator over the started transactions of a Transactions, in order.
+/// Transactions that were freed, for example by auto-destroy, are skipped.
+pub struct TransactionsIter<'a> {
+    iter: std::collections::btree_map::Values<'a, usize, Transaction>,
+}
+
+impl<'a> TransactionsIter<'a> {
+    /// Only yield transactions whose request or response is not complete.
+    pub fn incomplete(self) -> impl Iterator<Item = &'a Transaction> {
+        self.filter(|tx| !tx.is_complete())
+    }
+
+    /// Only yield transactions with any of the given HtpFlags set.
+    pub fn flagged(self, mask: u64) -> impl Iterator<Item = &'a Transaction> {
+        self.filter(move |tx| tx.flags.is_set(mask))
+    }
+}
+
+impl<'a> Iterator for TransactionsIter<'a> {
+    type Item = &'a Transaction;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.by_ref().find(|tx| tx.is_started())
+    }
 }
 
 /// An iterator over Transactions
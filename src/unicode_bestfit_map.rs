@@ -1,25 +1,97 @@
+use crate::{error::Result, HtpStatus};
 use lazy_static::lazy_static;
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc};
 
-#[derive(Copy, Clone)]
+/// Enumerates the code pages with a built-in best-fit map.
+/// cbindgen:rename-all=QualifiedScreamingSnakeCase
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum HtpBestfitCodepage {
+    /// Windows 1252 (Western European), the default.
+    CP1252,
+    /// Windows 932 (Japanese Shift-JIS). Only the single-byte part of the code page
+    /// is mapped; characters that best-fit to two bytes decode to the replacement byte.
+    CP932,
+}
+
+/// Best-fit mapping of Unicode code points to single bytes, which is used to decode
+/// characters the way a Windows server running with a given code page does.
+#[derive(Clone, Debug)]
 pub struct UnicodeBestfitMap {
     // Best-fit mapping options.
     /// The best-fit map to use to decode %u-encoded characters.
-    pub map: &'static HashMap<u32, u8>,
+    pub map: Arc<HashMap<u32, u8>>,
     /// The replacement byte used when there is no best-fit mapping.
     pub replacement_byte: u8,
 }
 
 impl Default for UnicodeBestfitMap {
     fn default() -> Self {
+        Self::for_codepage(HtpBestfitCodepage::CP1252)
+    }
+}
+
+impl UnicodeBestfitMap {
+    /// Returns the built-in best-fit map of a code page.
+    pub fn for_codepage(codepage: HtpBestfitCodepage) -> Self {
+        let map = match codepage {
+            HtpBestfitCodepage::CP1252 => &*bestfit_1252,
+            HtpBestfitCodepage::CP932 => &*bestfit_932,
+        };
         Self {
-            map: &bestfit_1252,
+            map: Arc::clone(map),
             replacement_byte: b'?',
         }
     }
-}
 
-impl UnicodeBestfitMap {
+    /// Loads a best-fit map from text in the format of the Microsoft bestfitNNNN.txt
+    /// files: lines holding a code point and a byte as hexadecimal numbers, with
+    /// comments starting with ';'. When the text has section headers, only the
+    /// WCTABLE section is read. Mappings to more than one byte are skipped.
+    ///
+    /// Returns an error if a mapping line is malformed.
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let hex = |field: &str| {
+            let digits = field
+                .strip_prefix("0x")
+                .or_else(|| field.strip_prefix("0X"))
+                .unwrap_or(field);
+            u32::from_str_radix(digits, 16).map_err(|_| HtpStatus::ERROR)
+        };
+        let text = std::str::from_utf8(data).map_err(|_| HtpStatus::ERROR)?;
+        let sectioned = text.lines().any(|line| {
+            matches!(line.split_whitespace().next(), Some(word) if word.eq_ignore_ascii_case("WCTABLE"))
+        });
+        let mut in_table = !sectioned;
+        let mut map = HashMap::new();
+        for line in text.lines() {
+            let line = line.split(';').next().unwrap_or_default();
+            let mut fields = line.split_whitespace();
+            let first = match fields.next() {
+                Some(first) => first,
+                None => continue,
+            };
+            if !first.starts_with(|c: char| c.is_ascii_digit()) {
+                // A section header, such as "CODEPAGE 1252" or "MBTABLE 123".
+                in_table = first.eq_ignore_ascii_case("WCTABLE");
+                continue;
+            }
+            if !in_table {
+                continue;
+            }
+            let unicode = hex(first)?;
+            let byte = hex(fields.next().ok_or(HtpStatus::ERROR)?)?;
+            if byte <= 0xff {
+                map.insert(unicode, byte as u8);
+            }
+        }
+        Ok(Self {
+            map: Arc::new(map),
+            replacement_byte: b'?',
+        })
+    }
+
+    /// Returns the byte a code point maps to, or the replacement byte.
     pub fn get(&self, unicode: u32) -> u8 {
         self.map
             .get(&unicode)
@@ -37,7 +109,7 @@ macro_rules! bestfit_key {
 }
 
 lazy_static! {
-    pub static ref bestfit_1252: HashMap<u32, u8> = [
+    static ref bestfit_1252: Arc<HashMap<u32, u8>> = Arc::new([
         (bestfit_key!(0x01, 0), 0x41),
         (bestfit_key!(0x1, 0x1), 0x61),
         (bestfit_key!(0x1, 0x2), 0x41),
@@ -431,5 +503,12 @@ lazy_static! {
     ]
     .iter()
     .cloned()
-    .collect();
+    .collect());
+    static ref bestfit_932: Arc<HashMap<u32, u8>> = Arc::new({
+        // Half-width katakana are single bytes in code page 932.
+        let mut map: HashMap<u32, u8> = (0xff61..=0xff9f).map(|c| (c, (c - 0xfec0) as u8)).collect();
+        // The overline takes the place of the tilde.
+        map.insert(0x203e, 0x7e);
+        map
+    });
 }
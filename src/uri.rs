@@ -9,6 +9,185 @@ use crate::{
     },
 };
 use nom::{combinator::opt, sequence::tuple};
+use std::ops::Range;
+
+/// Export percent-encoding flags of a URI component
+#[derive(Debug)]
+pub struct UriEncodingFlags;
+
+impl UriEncodingFlags {
+    /// Some bytes are percent-encoded.
+    pub const ENCODED: u64 = 0x0001;
+    /// An unreserved character, such as a letter or a digit, is percent-encoded.
+    pub const ENCODED_UNRESERVED: u64 = 0x0002;
+    /// A path separator, '/' or '\\', is percent-encoded.
+    pub const ENCODED_SEPARATOR: u64 = 0x0004;
+    /// A '%' is percent-encoded.
+    pub const ENCODED_PERCENT: u64 = 0x0008;
+    /// A %u sequence is used.
+    pub const U_ENCODED: u64 = 0x0010;
+    /// A '%' does not start a valid sequence.
+    pub const INVALID: u64 = 0x0020;
+    /// A '?' is percent-encoded. Servers such as Apache decode it into the path
+    /// rather than treating it as the start of the query string.
+    pub const ENCODED_QUESTION_MARK: u64 = 0x0040;
+}
+
+/// Export flags of a normalized URI path
+#[derive(Debug)]
+pub struct PathFlags;
+
+impl PathFlags {
+    /// The path started with more than one slash, which were collapsed.
+    pub const LEADING_SLASHES_COLLAPSED: u64 = 0x0001;
+    /// The path was split into a script path and PATH_INFO.
+    pub const PATH_INFO: u64 = 0x0002;
+    /// An ASP.NET cookieless session segment was removed from the path.
+    pub const COOKIELESS_SESSION: u64 = 0x0004;
+    /// A path segment has ";name=value" parameters.
+    pub const PATH_PARAMETERS: u64 = 0x0008;
+    /// A path segment is "." or ".." once its parameters are removed, as in "/..;/",
+    /// which hides a traversal from parsers that do not remove them.
+    pub const PATH_PARAMETER_TRAVERSAL: u64 = 0x0010;
+}
+
+/// Splits the ";name=value" parameters off the segments of a path as received, as
+/// Tomcat does before decoding it.
+///
+/// Returns the path without parameters, the parameters in order, and PathFlags.
+pub fn split_path_parameters(path: &[u8]) -> (Bstr, Vec<(Bstr, Bstr)>, u64) {
+    let mut stripped = Vec::with_capacity(path.len());
+    let mut parameters = Vec::new();
+    let mut flags = 0;
+    for (i, segment) in path.split(|c| *c == b'/').enumerate() {
+        if i > 0 {
+            stripped.push(b'/');
+        }
+        let mut parts = segment.split(|c| *c == b';');
+        let name = parts.next().unwrap_or_default();
+        stripped.extend_from_slice(name);
+        if name.len() == segment.len() {
+            continue;
+        }
+        flags.set(PathFlags::PATH_PARAMETERS);
+        // Other parsers see a segment such as "..;x" as a regular name.
+        let mut dots = name.to_ascii_lowercase();
+        while let Some(encoded) = dots.windows(3).position(|w| w == b"%2e") {
+            dots.splice(encoded..encoded + 3, Some(b'.'));
+        }
+        if dots == b"." || dots == b".." {
+            flags.set(PathFlags::PATH_PARAMETER_TRAVERSAL);
+        }
+        for parameter in parts {
+            let (name, value) = match parameter.iter().position(|c| *c == b'=') {
+                Some(eq) => (&parameter[..eq], &parameter[eq + 1..]),
+                None => (parameter, &b""[..]),
+            };
+            parameters.push((Bstr::from(name), Bstr::from(value)));
+        }
+    }
+    (Bstr::from(stripped), parameters, flags)
+}
+
+/// Records which bytes of a URI component were percent-encoded.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct UriEncoding {
+    /// Ranges of the component, as received, holding percent-encoded sequences.
+    pub ranges: Vec<Range<usize>>,
+    /// One entry per byte of the percent-decoded component, set when the byte was
+    /// encoded. Normalization beyond percent-decoding, such as the removal of dot
+    /// segments, is not reflected.
+    pub mask: Vec<bool>,
+    /// A combination of UriEncodingFlags.
+    pub flags: u64,
+}
+
+impl UriEncoding {
+    /// Scans a component as received. %u sequences are recognized when u_encoding
+    /// is set, and are decoded to a single byte as the path decoder does.
+    pub fn new(input: &[u8], u_encoding: bool) -> Self {
+        let mut encoding = Self::default();
+        let mut i = 0;
+        while i < input.len() {
+            if input[i] != b'%' {
+                encoding.mask.push(false);
+                i += 1;
+                continue;
+            }
+            let rest = &input[i + 1..];
+            let (len, byte) = if u_encoding && matches!(rest.first(), Some(b'u') | Some(b'U')) {
+                match hex_value(rest.get(1..5)) {
+                    Some(c) => {
+                        encoding.flags.set(UriEncodingFlags::U_ENCODED);
+                        (6, if c < 0x80 { Some(c as u8) } else { None })
+                    }
+                    None => (0, None),
+                }
+            } else {
+                match hex_value(rest.get(0..2)) {
+                    Some(c) => (3, Some(c as u8)),
+                    None => (0, None),
+                }
+            };
+            if len == 0 {
+                encoding.flags.set(UriEncodingFlags::INVALID);
+                encoding.mask.push(false);
+                i += 1;
+                continue;
+            }
+            encoding.flags.set(UriEncodingFlags::ENCODED);
+            match byte {
+                Some(c) if c.is_ascii_alphanumeric() || matches!(c, b'-' | b'.' | b'_' | b'~') => {
+                    encoding.flags.set(UriEncodingFlags::ENCODED_UNRESERVED)
+                }
+                Some(b'/') | Some(b'\\') => encoding.flags.set(UriEncodingFlags::ENCODED_SEPARATOR),
+                Some(b'%') => encoding.flags.set(UriEncodingFlags::ENCODED_PERCENT),
+                Some(b'?') => encoding.flags.set(UriEncodingFlags::ENCODED_QUESTION_MARK),
+                _ => {}
+            }
+            encoding.ranges.push(i..i + len);
+            encoding.mask.push(true);
+            i += len;
+        }
+        encoding
+    }
+
+    /// Determine if the byte at the given offset of the percent-decoded component
+    /// was encoded.
+    pub fn is_encoded(&self, offset: usize) -> bool {
+        self.mask.get(offset).copied().unwrap_or(false)
+    }
+}
+
+/// Parses hexadecimal digits, as in a percent-encoded sequence.
+fn hex_value(digits: Option<&[u8]>) -> Option<u32> {
+    let digits = digits?;
+    if !digits.iter().all(u8::is_ascii_hexdigit) {
+        return None;
+    }
+    u32::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()
+}
+
+/// Determines if a path segment is an ASP.NET cookieless session segment, made of
+/// one or more letter-prefixed values in parentheses, such as "(S(id)F(ticket))".
+fn is_cookieless_session(segment: &[u8]) -> bool {
+    let mut inner = match segment {
+        [b'(', inner @ .., b')'] if !inner.is_empty() => inner,
+        _ => return false,
+    };
+    while !inner.is_empty() {
+        inner = match inner {
+            [c, b'(', rest @ ..] if c.is_ascii_alphabetic() => {
+                match rest.iter().position(|c| *c == b')') {
+                    Some(end) => &rest[end + 1..],
+                    None => return false,
+                }
+            }
+            _ => return false,
+        };
+    }
+    true
+}
 
 /// URI structure. Each of the fields provides access to a single
 /// URI element. Where an element is not present in a URI, the
@@ -39,6 +218,24 @@ pub struct Uri {
     /// Fragment identifier. This field will rarely be available in a server-side
     /// setting, but it's not impossible to see it.
     pub fragment: Option<Bstr>,
+    /// Which bytes of the path were percent-encoded as received.
+    pub path_encoding: Option<UriEncoding>,
+    /// Which bytes of the query string were percent-encoded as received.
+    pub query_encoding: Option<UriEncoding>,
+    /// Leading part of the normalized path that names the script, when the path
+    /// was split by Config::path_info_extensions.
+    pub script_path: Option<Bstr>,
+    /// Remainder of the normalized path after the script path, as in the CGI
+    /// PATH_INFO variable.
+    pub path_info: Option<Bstr>,
+    /// Parameters of the path segments, such as ("jsessionid", "1234") for
+    /// "/a;jsessionid=1234", in order.
+    pub path_parameters: Vec<(Bstr, Bstr)>,
+    /// ASP.NET cookieless session segment removed from the normalized path, such
+    /// as "(S(id))".
+    pub cookieless_session: Option<Bstr>,
+    /// A combination of PathFlags.
+    pub path_flags: u64,
 }
 
 impl std::fmt::Debug for Uri {
@@ -53,6 +250,13 @@ impl std::fmt::Debug for Uri {
             .field("path", &self.path)
             .field("query", &self.query)
             .field("fragment", &self.fragment)
+            .field("path_encoding", &self.path_encoding)
+            .field("query_encoding", &self.query_encoding)
+            .field("script_path", &self.script_path)
+            .field("path_info", &self.path_info)
+            .field("path_parameters", &self.path_parameters)
+            .field("cookieless_session", &self.cookieless_session)
+            .field("path_flags", &self.path_flags)
             .finish()
     }
 }
@@ -71,6 +275,13 @@ impl Uri {
             path: None,
             query: None,
             fragment: None,
+            path_encoding: None,
+            query_encoding: None,
+            script_path: None,
+            path_info: None,
+            path_parameters: Vec::new(),
+            cookieless_session: None,
+            path_flags: 0,
         }
     }
     /// Create a new Uri struct from given values.
@@ -96,6 +307,13 @@ impl Uri {
             path,
             query,
             fragment,
+            path_encoding: None,
+            query_encoding: None,
+            script_path: None,
+            path_info: None,
+            path_parameters: Vec::new(),
+            cookieless_session: None,
+            path_flags: 0,
         }
     }
 
@@ -112,6 +330,13 @@ impl Uri {
             path: None,
             query: None,
             fragment: None,
+            path_encoding: None,
+            query_encoding: None,
+            script_path: None,
+            path_info: None,
+            path_parameters: Vec::new(),
+            cookieless_session: None,
+            path_flags: 0,
         }
     }
 
@@ -187,18 +412,82 @@ impl Uri {
 
     /// Normalize uri path.
     pub fn normalized_path(&self, flags: &mut u64, status: &mut HtpUnwanted) -> Option<Bstr> {
-        if let Some(mut path) = self.path.clone() {
-            // Decode URL-encoded (and %u-encoded) characters, as well as lowercase,
-            // compress separators and convert backslashes.
-            // Ignore result.
-            decode_uri_path_inplace(&self.cfg, flags, status, &mut path);
-            // Handle UTF-8 in the path. Validate it first, and only save it if cfg specifies it
-            utf8_decode_and_validate_uri_path_inplace(&self.cfg, flags, status, &mut path);
-            // RFC normalization.
-            normalize_uri_path_inplace(&mut path);
-            Some(path)
-        } else {
-            None
+        self.path
+            .clone()
+            .map(|path| self.normalize_path(path, flags, status))
+    }
+
+    /// Normalizes a path with the decoder configuration of this URI, as
+    /// normalized_path() does with the path of this URI.
+    pub fn normalize_path(
+        &self,
+        mut path: Bstr,
+        flags: &mut u64,
+        status: &mut HtpUnwanted,
+    ) -> Bstr {
+        // Decode URL-encoded (and %u-encoded) characters, as well as lowercase,
+        // compress separators and convert backslashes.
+        // Ignore result.
+        decode_uri_path_inplace(&self.cfg, flags, status, &mut path);
+        // Handle UTF-8 in the path. Validate it first, and only save it if cfg specifies it
+        utf8_decode_and_validate_uri_path_inplace(&self.cfg, flags, status, &mut path);
+        // RFC normalization.
+        normalize_uri_path_inplace(&mut path);
+        path
+    }
+
+    /// Removes the first ASP.NET cookieless session segment from the path, keeping
+    /// it in cookieless_session.
+    pub fn strip_cookieless_session(&mut self) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+        let mut offset = 0;
+        let found = path.split(|c| *c == b'/').find_map(|segment| {
+            let start = offset;
+            offset += segment.len() + 1;
+            if is_cookieless_session(segment) {
+                Some(start..start + segment.len())
+            } else {
+                None
+            }
+        });
+        if let Some(segment) = found {
+            // Remove the separator that follows the segment too, if any.
+            let mut stripped = path[..segment.start].to_vec();
+            stripped.extend_from_slice(&path[(segment.end + 1).min(path.len())..]);
+            self.cookieless_session = Some(Bstr::from(&path[segment]));
+            self.path = Some(Bstr::from(stripped));
+            self.path_flags.set(PathFlags::COOKIELESS_SESSION);
+        }
+    }
+
+    /// Splits the path after the first segment that ends with one of the given
+    /// script extensions, as Apache does to compute PATH_INFO. The path is not
+    /// split when that segment is the last one.
+    pub fn split_path_info(&mut self, extensions: &[Bstr]) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+        let is_script = |script: &[u8]| {
+            extensions.iter().any(|extension| {
+                script.len() > extension.len()
+                    && script.ends_with(extension.as_slice())
+                    && script[script.len() - extension.len() - 1] != b'/'
+            })
+        };
+        let split = path
+            .iter()
+            .enumerate()
+            .skip(1)
+            .find(|(i, c)| **c == b'/' && is_script(&path[..*i]))
+            .map(|(i, _)| i);
+        if let Some(split) = split {
+            self.script_path = Some(Bstr::from(&path[..split]));
+            self.path_info = Some(Bstr::from(&path[split..]));
+            self.path_flags.set(PathFlags::PATH_INFO);
         }
     }
 
@@ -220,9 +509,11 @@ impl Uri {
         if let Ok((_, (scheme_authority, path, query, fragment))) = res {
             if let Some(path) = path {
                 self.path = Some(Bstr::from(path));
+                self.path_encoding = Some(UriEncoding::new(path, self.cfg.u_encoding_decode));
             }
             if let Some(query) = query {
                 self.query = Some(Bstr::from(query));
+                self.query_encoding = Some(UriEncoding::new(query, self.cfg.u_encoding_decode));
             }
             if let Some(fragment) = fragment {
                 self.fragment = Some(Bstr::from(fragment));
@@ -671,3 +962,76 @@ fn NormalizeUriPath() {
     normalize_uri_path_inplace(&mut s);
     assert!(s.eq("/images.gif"));
 }
+
+#[test]
+fn UriEncodingMap() {
+    let encoding = UriEncoding::new(b"/%61dmin%2f%u0041%zz", true);
+    assert_eq!(vec![1..4, 8..11, 11..17], encoding.ranges);
+    assert_eq!(
+        UriEncodingFlags::ENCODED
+            | UriEncodingFlags::ENCODED_UNRESERVED
+            | UriEncodingFlags::ENCODED_SEPARATOR
+            | UriEncodingFlags::U_ENCODED
+            | UriEncodingFlags::INVALID,
+        encoding.flags
+    );
+    // "/admin/A%zz"
+    assert_eq!(11, encoding.mask.len());
+    let encoded: Vec<usize> = (0..11).filter(|i| encoding.is_encoded(*i)).collect();
+    assert_eq!(vec![1, 6, 7], encoded);
+
+    // %u is left alone unless it is decoded.
+    let encoding = UriEncoding::new(b"%u0041%25", false);
+    assert_eq!(vec![6..9], encoding.ranges);
+    assert_eq!(
+        UriEncodingFlags::ENCODED | UriEncodingFlags::ENCODED_PERCENT | UriEncodingFlags::INVALID,
+        encoding.flags
+    );
+    assert_eq!(UriEncoding::default(), UriEncoding::new(b"", true));
+}
+
+#[test]
+fn StripCookielessSession() {
+    let mut uri = Uri::default();
+    uri.path = Some(Bstr::from("/app/(S(x1y2)F(t))/default.aspx"));
+    uri.strip_cookieless_session();
+    assert_eq!(Some(Bstr::from("/app/default.aspx")), uri.path);
+    assert_eq!(Some(Bstr::from("(S(x1y2)F(t))")), uri.cookieless_session);
+    assert!(uri.path_flags.is_set(PathFlags::COOKIELESS_SESSION));
+
+    let mut uri = Uri::default();
+    uri.path = Some(Bstr::from("/app/(S(x))"));
+    uri.strip_cookieless_session();
+    assert_eq!(Some(Bstr::from("/app/")), uri.path);
+
+    for path in &["/(x)/a", "/(S(x)/a", "/()/a", "/(S(x)1)/a"] {
+        let mut uri = Uri::default();
+        uri.path = Some(Bstr::from(*path));
+        uri.strip_cookieless_session();
+        assert_eq!(Some(Bstr::from(*path)), uri.path);
+        assert!(uri.cookieless_session.is_none());
+    }
+}
+
+#[test]
+fn SplitPathParameters() {
+    let (path, parameters, flags) = split_path_parameters(b"/app;v=1/%2E.;x/b;jsessionid=12;c");
+    assert_eq!(Bstr::from("/app/%2E./b"), path);
+    assert_eq!(
+        vec![
+            (Bstr::from("v"), Bstr::from("1")),
+            (Bstr::from("x"), Bstr::from("")),
+            (Bstr::from("jsessionid"), Bstr::from("12")),
+            (Bstr::from("c"), Bstr::from("")),
+        ],
+        parameters
+    );
+    assert_eq!(
+        PathFlags::PATH_PARAMETERS | PathFlags::PATH_PARAMETER_TRAVERSAL,
+        flags
+    );
+    let (path, parameters, flags) = split_path_parameters(b"/a/../b");
+    assert_eq!(Bstr::from("/a/../b"), path);
+    assert!(parameters.is_empty());
+    assert_eq!(0, flags);
+}
@@ -1,8 +1,8 @@
 use crate::{
     bstr::Bstr,
-    config::{DecoderConfig, HtpUnwanted},
+    config::{DecoderConfig, HtpArgumentSeparators, HtpBareParam, HtpUnwanted},
     table::Table,
-    util::{urldecode_ex, FlagOperations},
+    util::{urldecode_ex, FlagOperations, HtpFlags},
 };
 use nom::{
     bytes::complete::{take, take_till},
@@ -11,6 +11,61 @@ use nom::{
     sequence::tuple,
     IResult,
 };
+use std::ops::Deref;
+
+/// Export urlencoded structure flags
+#[derive(Debug)]
+pub struct UrlencodedFlags;
+
+impl UrlencodedFlags {
+    /// A parameter has no '=', such as `?a`. See Config::set_bare_param.
+    pub const BARE: u64 = 0x0001;
+    /// A parameter has an '=' but an empty name, such as `?=b`.
+    pub const EMPTY_NAME: u64 = 0x0002;
+    /// A parameter has an '=' but an empty value, such as `?a=`.
+    pub const EMPTY_VALUE: u64 = 0x0004;
+    /// Separators are repeated, or lead the data, such as `?a&&b`. The empty
+    /// parameters between them are ignored.
+    pub const REPEATED_SEPARATOR: u64 = 0x0008;
+}
+
+/// The raw (undecoded) form of a parsed parameter, along with the
+/// anomalies seen while decoding it.
+#[derive(Clone, Debug, Default)]
+pub struct RawParam {
+    /// Parameter name, as seen on the wire.
+    pub name: Bstr,
+    /// Parameter value, as seen on the wire.
+    pub value: Bstr,
+    /// Decoding flags raised by this parameter alone.
+    pub flags: u64,
+    /// UrlencodedFlags raised by this parameter alone.
+    pub urlencoded_flags: u64,
+    /// Offset of the name in the source data: the request URI for a query
+    /// string, and the request body, once dechunked and decompressed, for a
+    /// body.
+    pub name_offset: usize,
+    /// Offset of the value in the source data, if the parameter has an '='.
+    pub value_offset: Option<usize>,
+}
+
+/// A decoded parameter value, stored with the raw form of its parameter.
+#[derive(Clone, Debug, Default)]
+pub struct ParamValue {
+    /// The decoded value.
+    pub value: Bstr,
+    /// The parameter as seen on the wire.
+    pub raw: RawParam,
+}
+
+/// Let callers use the decoded value directly
+impl Deref for ParamValue {
+    type Target = Bstr;
+
+    fn deref(&self) -> &Self::Target {
+        &self.value
+    }
+}
 
 /// This is the main URLENCODED parser structure. It is used to store
 /// parser configuration, temporary parsing data, as well as the parameters.
@@ -18,21 +73,29 @@ use nom::{
 pub struct Parser {
     /// The configuration structure associated with this parser
     pub cfg: DecoderConfig,
-    /// The character used to separate parameters. Defaults to & and should
-    /// not be changed without good reason.
-    pub argument_separator: u8,
+    /// The characters used to separate parameters. Defaults to the separators
+    /// from the decoder configuration.
+    pub argument_separators: HtpArgumentSeparators,
     /// Whether to perform URL-decoding on parameters. Defaults to true.
     pub decode_url_encoding: bool,
-    /// This table contains the list of parameters, indexed by name.
-    pub params: Table<Bstr>,
+    /// This table contains the list of parameters, indexed by name. Each
+    /// decoded value is stored with the raw form of its parameter.
+    pub params: Table<ParamValue>,
     /// Contains parsing flags
     pub flags: u64,
+    /// UrlencodedFlags raised by the data parsed so far.
+    pub urlencoded_flags: u64,
     /// This field is set if the parser thinks that the
     /// backend server will reject a request with a particular status code.
     pub response_status_expected_number: HtpUnwanted,
+    /// Offset in the source data, such as the request URI, of the first byte
+    /// given to the parser. Parameter offsets are relative to the source.
+    pub source_offset: usize,
     // Private fields; these are used during the parsing process only
     complete: bool,
+    consumed: usize,
     saw_data: bool,
+    saw_param: bool,
     field: Bstr,
 }
 
@@ -40,14 +103,18 @@ impl Parser {
     /// Construct new Parser with provided decoder configuration
     pub fn new(cfg: DecoderConfig) -> Self {
         Self {
+            argument_separators: cfg.argument_separators,
             cfg,
-            argument_separator: b'&',
             decode_url_encoding: true,
             params: Table::with_capacity(32),
             flags: 0,
+            urlencoded_flags: 0,
             response_status_expected_number: HtpUnwanted::IGNORE,
+            source_offset: 0,
             complete: false,
+            consumed: 0,
             saw_data: false,
+            saw_param: false,
             field: Bstr::with_capacity(64),
         }
     }
@@ -77,16 +144,29 @@ impl Parser {
         let input = self.field.clone();
         let mut input = input.as_slice();
         if input.is_empty() {
-            if self.complete && self.params.size() == 0 && self.saw_data {
-                self.params.add(Bstr::new(), Bstr::new());
+            if self.complete && !self.saw_param && self.saw_data {
+                let raw = RawParam {
+                    name_offset: self.source_offset,
+                    ..Default::default()
+                };
+                self.params.add(
+                    Bstr::new(),
+                    ParamValue {
+                        value: Bstr::new(),
+                        raw,
+                    },
+                );
             }
             return;
         }
         let mut remaining: &[u8] = b"";
-        let sep = self.argument_separator;
+        let seps = self.argument_separators;
+        if input.contains(&seps.alternative()) {
+            self.flags.set(HtpFlags::URLEN_ALT_SEPARATOR);
+        }
         self.saw_data = true;
         if !self.complete {
-            let data: Vec<&[u8]> = input.rsplitn(2, |c| *c == sep).collect();
+            let data: Vec<&[u8]> = input.rsplitn(2, |c| seps.is_separator(*c)).collect();
             if data.len() == 2 {
                 input = data[1];
                 remaining = data[0];
@@ -94,8 +174,55 @@ impl Parser {
                 return;
             }
         }
-        input.split(|c| *c == sep).for_each(|segment| {
+        let base = self.source_offset + self.consumed;
+        let mut position = 0;
+        let complete = self.complete;
+        input.split(|c| seps.is_separator(*c)).for_each(|segment| {
+            let segment_offset = base + position;
+            position += segment.len() + 1;
+            // A trailing separator is common; only flag empty parameters that
+            // precede another separator.
+            if segment.is_empty() && !(complete && position > input.len()) {
+                self.urlencoded_flags
+                    .set(UrlencodedFlags::REPEATED_SEPARATOR);
+            }
             if let Ok((value, name)) = name_value(segment) {
+                let has_equals = name.len() < segment.len();
+                let mut raw = RawParam {
+                    name: Bstr::from(name),
+                    value: Bstr::from(value),
+                    flags: 0,
+                    urlencoded_flags: 0,
+                    name_offset: segment_offset,
+                    value_offset: if has_equals {
+                        Some(segment_offset + name.len() + 1)
+                    } else {
+                        None
+                    },
+                };
+                let (name, value) = if !has_equals {
+                    raw.urlencoded_flags.set(UrlencodedFlags::BARE);
+                    if self.cfg.bare_param == HtpBareParam::VALUE {
+                        raw.name = Bstr::new();
+                        raw.value = Bstr::from(name);
+                        raw.value_offset = Some(segment_offset);
+                        (&b""[..], name)
+                    } else {
+                        (name, value)
+                    }
+                } else {
+                    if name.is_empty() {
+                        raw.urlencoded_flags.set(UrlencodedFlags::EMPTY_NAME);
+                    }
+                    if value.is_empty() {
+                        raw.urlencoded_flags.set(UrlencodedFlags::EMPTY_VALUE);
+                    }
+                    (name, value)
+                };
+                self.urlencoded_flags.set(raw.urlencoded_flags);
+                if segment.contains(&seps.alternative()) {
+                    raw.flags.set(HtpFlags::URLEN_ALT_SEPARATOR);
+                }
                 let mut name = Bstr::from(name);
                 let mut value = Bstr::from(value);
                 if self.decode_url_encoding {
@@ -103,6 +230,7 @@ impl Parser {
                         urldecode_ex(name.as_slice(), &self.cfg)
                     {
                         self.flags.set(flags);
+                        raw.flags.set(flags);
                         self.response_status_expected_number = expected_status;
                         name.clear();
                         name.add(consumed);
@@ -111,14 +239,17 @@ impl Parser {
                         urldecode_ex(value.as_slice(), &self.cfg)
                     {
                         self.flags.set(flags);
+                        raw.flags.set(flags);
                         self.response_status_expected_number = expected_status;
                         value.clear();
                         value.add(consumed);
                     }
                 }
-                self.params.add(name, value);
+                self.params.add(name, ParamValue { value, raw });
+                self.saw_param = true;
             }
         });
+        self.consumed += self.field.len() - remaining.len();
         self.field.clear();
         self.field.add(remaining);
     }
@@ -139,13 +270,17 @@ impl Default for Parser {
     fn default() -> Self {
         Self {
             cfg: DecoderConfig::default(),
-            argument_separator: b'&',
+            argument_separators: HtpArgumentSeparators::AMPERSAND,
             decode_url_encoding: true,
             params: Table::with_capacity(32),
             flags: 0,
+            urlencoded_flags: 0,
             response_status_expected_number: HtpUnwanted::IGNORE,
+            source_offset: 0,
             complete: false,
+            consumed: 0,
             saw_data: false,
+            saw_param: false,
             field: Bstr::with_capacity(64),
         }
     }
@@ -368,3 +503,155 @@ fn Partial6() {
     assert!(urlenp.params.get_nocase("qzn").unwrap().1.eq("23"));
     assert_eq!(2, urlenp.params.size());
 }
+
+#[test]
+fn RawParams() {
+    let mut urlenp = Parser::default();
+    urlenp.parse_complete(b"p=%41&q%20x=2&p=%zz");
+
+    assert_eq!(3, urlenp.params.size());
+    assert!(urlenp.params[0].1.raw.name.eq("p"));
+    assert!(urlenp.params[0].1.raw.value.eq("%41"));
+    assert_eq!(0, urlenp.params[0].1.raw.flags);
+    assert!(urlenp.params[1].1.raw.name.eq("q%20x"));
+    assert!(urlenp.params[1].0.eq("q x"));
+    assert!(urlenp.params[2].1.raw.value.eq("%zz"));
+    assert!(urlenp.params[2]
+        .1
+        .raw
+        .flags
+        .is_set(HtpFlags::URLEN_INVALID_ENCODING));
+}
+
+#[test]
+fn ArgumentSeparators() {
+    let mut urlenp = Parser::default();
+    urlenp.parse_complete(b"p=1;q=2&r=3");
+    assert_eq!(2, urlenp.params.size());
+    assert!(urlenp.params.get_nocase("p").unwrap().1.eq("1;q=2"));
+    assert!(urlenp.flags.is_set(HtpFlags::URLEN_ALT_SEPARATOR));
+    assert!(urlenp.params[0]
+        .1
+        .raw
+        .flags
+        .is_set(HtpFlags::URLEN_ALT_SEPARATOR));
+    assert!(!urlenp.params[1]
+        .1
+        .raw
+        .flags
+        .is_set(HtpFlags::URLEN_ALT_SEPARATOR));
+
+    let mut urlenp = Parser::default();
+    urlenp.argument_separators = HtpArgumentSeparators::BOTH;
+    urlenp.parse_partial(b"p=1;q");
+    urlenp.parse_partial(b"=2&r=3");
+    urlenp.finalize();
+    assert_eq!(3, urlenp.params.size());
+    assert!(urlenp.params.get_nocase("q").unwrap().1.eq("2"));
+    assert!(urlenp.flags.is_set(HtpFlags::URLEN_ALT_SEPARATOR));
+
+    let mut urlenp = Parser::default();
+    urlenp.argument_separators = HtpArgumentSeparators::SEMICOLON;
+    urlenp.parse_complete(b"p=1;q=2");
+    assert_eq!(2, urlenp.params.size());
+    assert!(!urlenp.flags.is_set(HtpFlags::URLEN_ALT_SEPARATOR));
+}
+
+#[test]
+fn ParamOffsets() {
+    let mut urlenp = Parser::default();
+    urlenp.source_offset = 10;
+    urlenp.parse_partial(b"p=1&qq");
+    urlenp.parse_partial(b"=%32&r&");
+    urlenp.parse_partial(b"=3");
+    urlenp.finalize();
+    let offsets: Vec<(usize, Option<usize>)> = urlenp
+        .params
+        .iter()
+        .map(|(_, value)| (value.raw.name_offset, value.raw.value_offset))
+        .collect();
+    assert_eq!(
+        vec![(10, Some(12)), (14, Some(17)), (21, None), (23, Some(24))],
+        offsets
+    );
+    assert!(urlenp.params[1].1.raw.value.eq("%32"));
+}
+
+#[test]
+fn BareParam() {
+    let mut urlenp = Parser::default();
+    urlenp.parse_complete(b"a&b=1");
+
+    assert!(urlenp.params.get_nocase("a").unwrap().1.eq(""));
+    assert_eq!(UrlencodedFlags::BARE, urlenp.urlencoded_flags);
+    assert_eq!(
+        UrlencodedFlags::BARE,
+        urlenp.params[0].1.raw.urlencoded_flags
+    );
+    assert_eq!(0, urlenp.params[1].1.raw.urlencoded_flags);
+
+    let mut cfg = DecoderConfig::default();
+    cfg.bare_param = HtpBareParam::VALUE;
+    let mut urlenp = Parser::new(cfg);
+    urlenp.source_offset = 1;
+    urlenp.parse_complete(b"a&b=1");
+
+    assert!(urlenp.params.get_nocase("").unwrap().1.eq("a"));
+    assert!(urlenp.params.get_nocase("b").unwrap().1.eq("1"));
+    assert_eq!(2, urlenp.params.size());
+    assert!(urlenp.params[0].1.raw.name.is_empty());
+    assert!(urlenp.params[0].1.raw.value.eq("a"));
+    assert_eq!(Some(1), urlenp.params[0].1.raw.value_offset);
+}
+
+#[test]
+fn EmptyNameAndValue() {
+    let mut urlenp = Parser::default();
+    urlenp.parse_complete(b"=b&a=");
+
+    assert_eq!(
+        UrlencodedFlags::EMPTY_NAME,
+        urlenp.params[0].1.raw.urlencoded_flags
+    );
+    assert_eq!(
+        UrlencodedFlags::EMPTY_VALUE,
+        urlenp.params[1].1.raw.urlencoded_flags
+    );
+    assert_eq!(
+        UrlencodedFlags::EMPTY_NAME | UrlencodedFlags::EMPTY_VALUE,
+        urlenp.urlencoded_flags
+    );
+}
+
+#[test]
+fn RepeatedSeparators() {
+    for (data, params, repeated) in &[
+        (&b"a=1&b=2&"[..], 2, false),
+        (b"a=1&&b=2", 2, true),
+        (b"&a=1", 1, true),
+        (b"a=1&&", 1, true),
+    ] {
+        let mut urlenp = Parser::default();
+        urlenp.parse_complete(data);
+        assert_eq!(
+            *repeated,
+            urlenp
+                .urlencoded_flags
+                .is_set(UrlencodedFlags::REPEATED_SEPARATOR)
+        );
+        assert_eq!(*params, urlenp.params.size());
+
+        // Streaming the data byte by byte gives the same result.
+        let mut urlenp = Parser::default();
+        for byte in data.chunks(1) {
+            urlenp.parse_partial(byte);
+        }
+        urlenp.finalize();
+        assert_eq!(
+            *repeated,
+            urlenp
+                .urlencoded_flags
+                .is_set(UrlencodedFlags::REPEATED_SEPARATOR)
+        );
+    }
+}
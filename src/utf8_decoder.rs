@@ -55,14 +55,23 @@ static utf8d_allow_overlong: [u8; 400] = [
     1, 1, 3, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
 ];
 
-#[derive(Clone)]
+/// Decodes UTF-8 into single bytes using best-fit mapping, validating it on the way.
+///
+/// A decoder can be given a whole string with decode_and_validate(), or a stream
+/// split across chunks with decode_chunk() followed by finish(). Characters that
+/// span chunks are decoded as if the data were contiguous.
+#[derive(Clone, Debug)]
 pub struct Utf8Decoder {
     bestfit_map: UnicodeBestfitMap,
     state: u32,
     seq: u32,
     codepoint: u32,
+    /// HtpFlags::PATH_UTF8_VALID, PATH_UTF8_INVALID, PATH_UTF8_OVERLONG and
+    /// PATH_HALF_FULL_RANGE flags raised by the data decoded so far.
     pub flags: u64,
+    /// Whether any valid multi-byte character has been seen.
     pub seen_valid: bool,
+    /// The decoded bytes.
     pub decoded_bytes: Vec<u8>,
 }
 
@@ -148,18 +157,50 @@ impl Utf8Decoder {
     /// the replacement byte specified in the bestfit_map. Best-fit mapping will be used
     /// to convert UTF-8 into a single-byte stream.
     pub fn decode_and_validate(&mut self, input: &[u8]) {
-        //Reset all internals
+        self.reset();
+        self.decoded_bytes.reserve(input.len());
+        for byte in input {
+            self.decode_byte(*byte);
+        }
+        self.set_valid_flag();
+    }
+
+    /// Reset the decoder so that it can be used for another stream.
+    pub fn reset(&mut self) {
         self.state = 0;
         self.seq = 0;
         self.codepoint = 0;
         self.flags = 0;
         self.decoded_bytes.clear();
-        self.decoded_bytes.reserve(input.len());
         self.seen_valid = false;
+    }
+
+    /// Decode the next chunk of a stream, continuing any character left incomplete
+    /// by the previous chunk. The decoded bytes are appended to self.decoded_bytes,
+    /// which the caller may drain between chunks.
+    pub fn decode_chunk(&mut self, input: &[u8]) {
+        self.decoded_bytes.reserve(input.len());
         for byte in input {
             self.decode_byte(*byte);
         }
-        // Did the input stream seem like a valid UTF-8 string?
+    }
+
+    /// Finish a stream decoded with decode_chunk(). A character left incomplete at
+    /// the end of the stream is invalid and replaced with the replacement byte.
+    /// PATH_UTF8_VALID is raised if valid characters and no invalid ones were seen.
+    pub fn finish(&mut self) {
+        if self.seq != 0 {
+            self.flags.set(HtpFlags::PATH_UTF8_INVALID);
+            self.decoded_bytes.push(self.bestfit_map.replacement_byte);
+            self.state = 0;
+            self.seq = 0;
+            self.codepoint = 0;
+        }
+        self.set_valid_flag();
+    }
+
+    /// Did the input stream seem like a valid UTF-8 string?
+    fn set_valid_flag(&mut self) {
         if self.seen_valid && !self.flags.is_set(HtpFlags::PATH_UTF8_INVALID) {
             self.flags.set(HtpFlags::PATH_UTF8_VALID)
         }
@@ -194,3 +235,37 @@ impl Utf8Decoder {
         self.bestfit_map.get(self.codepoint)
     }
 }
+
+// Tests
+
+#[test]
+fn DecodeChunks() {
+    let input = "caf\u{e9} \u{20ac}100 \u{1f600}".as_bytes();
+    let mut whole = Utf8Decoder::new(UnicodeBestfitMap::default());
+    whole.decode_and_validate(input);
+    assert!(whole.flags.is_set(HtpFlags::PATH_UTF8_VALID));
+    for split in 1..input.len() {
+        let mut decoder = Utf8Decoder::new(UnicodeBestfitMap::default());
+        decoder.decode_chunk(&input[..split]);
+        decoder.decode_chunk(&input[split..]);
+        decoder.finish();
+        assert_eq!(whole.flags, decoder.flags);
+        assert_eq!(whole.decoded_bytes, decoder.decoded_bytes);
+    }
+
+    // A character cut off at the end of the stream is invalid.
+    let mut decoder = Utf8Decoder::new(UnicodeBestfitMap::default());
+    decoder.decode_chunk(b"caf\xc3\xa9");
+    decoder.decode_chunk(b" \xe2\x82");
+    assert!(!decoder.flags.is_set(HtpFlags::PATH_UTF8_INVALID));
+    decoder.finish();
+    assert!(decoder.flags.is_set(HtpFlags::PATH_UTF8_INVALID));
+    assert!(!decoder.flags.is_set(HtpFlags::PATH_UTF8_VALID));
+
+    decoder.reset();
+    decoder.decode_chunk(b"\xc0");
+    decoder.decode_chunk(b"\xaf");
+    decoder.finish();
+    assert!(decoder.flags.is_set(HtpFlags::PATH_UTF8_OVERLONG));
+    assert_eq!(b"/", decoder.decoded_bytes.as_slice());
+}
@@ -2,12 +2,15 @@
 
 use crate::{
     bstr::Bstr,
-    config::{DecoderConfig, HtpServerPersonality, HtpUnwanted, HtpUrlEncodingHandling},
+    config::{
+        DecoderConfig, HtpServerPersonality, HtpUnwanted, HtpUrlEncodingHandling, MultipartConfig,
+    },
     error::Result,
     hook::FileDataHook,
     utf8_decoder::Utf8Decoder,
     HtpStatus,
 };
+use md5::Md5;
 use nom::{
     branch::alt,
     bytes::complete::{
@@ -26,7 +29,15 @@ use nom::{
     Err::Incomplete,
     IResult, Needed,
 };
-use std::{io::Write, rc::Rc, sync::Mutex};
+use sha2::{Digest, Sha256};
+use std::{
+    io::Write,
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
 use tempfile::{Builder, NamedTempFile};
 
 /// String for the libhtp version.
@@ -80,6 +91,8 @@ impl FlagOperations<u64> for u64 {
 pub struct HtpFlags;
 
 impl HtpFlags {
+    /// Field was added or changed through the header modification API.
+    pub const FIELD_MODIFIED: u64 = 0x0000_0000_0001;
     /// Field cannot be parsed.
     pub const FIELD_UNPARSEABLE: u64 = 0x0000_0000_0004;
     /// Field is invalid.
@@ -154,6 +167,58 @@ impl HtpFlags {
     pub const REQUEST_MISSING_BYTES: u64 = (0x0010_0000_0000 | Self::MISSING_BYTES);
     /// Missing bytes in the response data.
     pub const RESPONSE_MISSING_BYTES: u64 = (0x0020_0000_0000 | Self::MISSING_BYTES);
+    /// Folded field in request headers.
+    pub const REQUEST_FIELD_FOLDED: u64 = 0x0040_0000_0000;
+    /// Folded field in response headers.
+    pub const RESPONSE_FIELD_FOLDED: u64 = 0x0080_0000_0000;
+    /// Sniffed response body content conflicts with the declared Content-Type.
+    pub const RESPONSE_CONTENT_TYPE_MISMATCH: u64 = 0x0100_0000_0000;
+    /// Urlencoded data contains the alternative parameter separator.
+    pub const URLEN_ALT_SEPARATOR: u64 = 0x0200_0000_0000;
+    /// Invalid Proxy-Authorization header.
+    pub const PROXY_AUTH_INVALID: u64 = 0x0400_0000_0000;
+    /// Body was partially reconstructed after a gap disrupted chunked framing.
+    pub const PARTIALLY_RECONSTRUCTED: u64 = 0x0800_0000_0000;
+    /// Invalid Forwarded, X-Forwarded-For or X-Real-IP header.
+    pub const FORWARDED_INVALID: u64 = 0x1000_0000_0000;
+    /// Request body exceeded the configured request_body_limit.
+    pub const REQUEST_BODY_LIMIT: u64 = 0x2000_0000_0000;
+    /// Response body exceeded the configured response_body_limit.
+    pub const RESPONSE_BODY_LIMIT: u64 = 0x4000_0000_0000;
+    /// Modified headers left the message framing ambiguous.
+    pub const HEADER_MODIFICATION_CONFLICT: u64 = 0x8000_0000_0000;
+    /// Request was treated as HTTP/0.9 only because its request line is malformed.
+    pub const HTTP_0_9_MALFORMED: u64 = 0x0001_0000_0000_0000;
+    /// Request line was repaired; see Transaction::request_line_repair.
+    pub const REQUEST_LINE_REPAIRED: u64 = 0x0002_0000_0000_0000;
+    /// Request line starts with whitespace.
+    pub const REQUEST_LINE_LEADING_WHITESPACE: u64 = 0x0004_0000_0000_0000;
+    /// Request line tokens are separated by something other than a single SP.
+    pub const REQUEST_LINE_MULTIPLE_SPACES: u64 = 0x0008_0000_0000_0000;
+    /// Request line has whitespace after the protocol.
+    pub const REQUEST_LINE_TRAILING_WHITESPACE: u64 = 0x0010_0000_0000_0000;
+    /// Status line has a status code but no reason phrase.
+    pub const STATUS_LINE_NO_REASON: u64 = 0x0020_0000_0000_0000;
+    /// Status code is missing or contains characters other than digits.
+    pub const STATUS_LINE_NON_NUMERIC: u64 = 0x0040_0000_0000_0000;
+    /// Status line contains NUL bytes.
+    pub const STATUS_LINE_NUL: u64 = 0x0080_0000_0000_0000;
+    /// Status code is numeric but outside 100-599.
+    pub const STATUS_LINE_OUT_OF_RANGE: u64 = 0x0100_0000_0000_0000;
+    /// Server sent a body with a response that must not have one.
+    pub const RESPONSE_BODY_UNEXPECTED: u64 = 0x0200_0000_0000_0000;
+    /// Response body was not delimited the way its headers declared.
+    pub const RESPONSE_FRAMING_MISMATCH: u64 = 0x0400_0000_0000_0000;
+    /// Lines that are not a status line were skipped before the response line.
+    pub const RESPONSE_LEADING_JUNK: u64 = 0x0800_0000_0000_0000;
+    /// Request URI carries credentials in its userinfo.
+    pub const URI_CREDENTIALS: u64 = 0x1000_0000_0000_0000;
+    /// Charset declared by the response body conflicts with the Content-Type charset.
+    pub const RESPONSE_CHARSET_MISMATCH: u64 = 0x2000_0000_0000_0000;
+    /// Decoded path or parameter contains CR or LF that could inject a header.
+    pub const CRLF_INJECTION: u64 = 0x4000_0000_0000_0000;
+    /// Identity response body contains a status line or starts with a header field.
+    pub const RESPONSE_SPLITTING: u64 = 0x8000_0000_0000_0000;
 }
 
 /// Enumerates file sources.
@@ -182,6 +247,205 @@ pub enum Eol {
     LFCR,
 }
 
+/// Flags that describe an extracted file.
+pub struct FileFlags;
+
+impl FileFlags {
+    /// The file name is empty.
+    pub const FILENAME_EMPTY: u64 = 0x0001;
+    /// The file name contains a path separator ('/' or '\\').
+    pub const FILENAME_PATH: u64 = 0x0002;
+    /// The file name contains a ".." path component.
+    pub const FILENAME_TRAVERSAL: u64 = 0x0004;
+    /// The file name contains NUL or other control characters.
+    pub const FILENAME_CONTROL_CHARS: u64 = 0x0008;
+    /// The file data exceeded the extraction limits and the stored copy is truncated.
+    pub const STORAGE_TRUNCATED: u64 = 0x0010;
+    /// The file was not extracted because an extraction quota was exhausted.
+    pub const EXTRACTION_SKIPPED: u64 = 0x0020;
+    /// The file was not extracted because its storage backend could not be created.
+    pub const EXTRACTION_FAILED: u64 = 0x0040;
+    /// A collection of flags that all indicate a file name that is unsafe to use as-is.
+    pub const FILENAME_UNSAFE: u64 = (Self::FILENAME_EMPTY
+        | Self::FILENAME_PATH
+        | Self::FILENAME_TRAVERSAL
+        | Self::FILENAME_CONTROL_CHARS);
+}
+
+/// A backend that receives the data of an extracted file.
+pub trait FileStorage: std::fmt::Debug + Send {
+    /// Store a chunk of file data.
+    fn write(&mut self, data: &[u8]) -> Result<()>;
+    /// Location of the stored file on disk, if the backend uses one.
+    fn path(&self) -> Option<&Path> {
+        None
+    }
+    /// Stored file contents, if the backend keeps them in memory.
+    fn data(&self) -> Option<&[u8]> {
+        None
+    }
+}
+
+impl<T: FileStorage + ?Sized> FileStorage for Box<T> {
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        (**self).write(data)
+    }
+
+    fn path(&self) -> Option<&Path> {
+        (**self).path()
+    }
+
+    fn data(&self) -> Option<&[u8]> {
+        (**self).data()
+    }
+}
+
+/// Stores file data in a temporary file, which is removed when the storage is dropped.
+#[derive(Debug)]
+pub struct TempFileStorage {
+    file: NamedTempFile,
+}
+
+impl TempFileStorage {
+    /// Create a new temporary file in the given directory.
+    pub fn new(tmpdir: &str) -> Result<Self> {
+        Ok(Self {
+            file: Builder::new()
+                .prefix("libhtp-multipart-file-")
+                .rand_bytes(16)
+                .tempfile_in(tmpdir)?,
+        })
+    }
+}
+
+impl FileStorage for TempFileStorage {
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.file.write_all(data)?;
+        Ok(())
+    }
+
+    fn path(&self) -> Option<&Path> {
+        Some(self.file.path())
+    }
+}
+
+/// Stores file data in memory, keeping at most `limit` bytes.
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    data: Vec<u8>,
+    limit: usize,
+}
+
+impl MemoryStorage {
+    /// Create a new in-memory storage that holds up to `limit` bytes.
+    pub fn new(limit: usize) -> Self {
+        Self {
+            data: Vec::new(),
+            limit,
+        }
+    }
+}
+
+impl FileStorage for MemoryStorage {
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        let available = self.limit.saturating_sub(self.data.len());
+        self.data
+            .extend_from_slice(&data[..std::cmp::min(available, data.len())]);
+        Ok(())
+    }
+
+    fn data(&self) -> Option<&[u8]> {
+        Some(&self.data)
+    }
+}
+
+/// A byte quota on extracted file data, shared by all the files that draw from it.
+/// Bytes count against the quota for as long as the file holding them exists.
+#[derive(Debug)]
+pub struct ExtractionQuota {
+    /// Maximum number of bytes held at once.
+    limit: usize,
+    /// Number of bytes currently held.
+    used: AtomicUsize,
+}
+
+impl ExtractionQuota {
+    /// Create a quota that allows up to `limit` bytes to be held at once.
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            used: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the number of bytes currently held.
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of bytes that may still be extracted.
+    pub fn remaining(&self) -> usize {
+        self.limit.saturating_sub(self.used())
+    }
+
+    /// Takes up to `len` bytes from the quota, returning how many were granted.
+    fn reserve(&self, len: usize) -> usize {
+        let mut granted = 0;
+        let _ = self
+            .used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                granted = std::cmp::min(len, self.limit.saturating_sub(used));
+                Some(used + granted)
+            });
+        granted
+    }
+
+    /// Returns `len` bytes to the quota.
+    fn release(&self, len: usize) {
+        self.used.fetch_sub(len, Ordering::Relaxed);
+    }
+}
+
+/// The bytes a file holds against an ExtractionQuota, returned when it is dropped.
+#[derive(Debug)]
+struct QuotaLease {
+    quota: Arc<ExtractionQuota>,
+    held: AtomicUsize,
+}
+
+impl QuotaLease {
+    fn reserve(&self, len: usize) -> usize {
+        let granted = self.quota.reserve(len);
+        self.held.fetch_add(granted, Ordering::Relaxed);
+        granted
+    }
+
+    fn release(&self, len: usize) {
+        self.quota.release(len);
+        self.held.fetch_sub(len, Ordering::Relaxed);
+    }
+}
+
+impl Drop for QuotaLease {
+    fn drop(&mut self) {
+        self.quota.release(*self.held.get_mut());
+    }
+}
+
+/// Function used to create a user-supplied storage backend for a file.
+pub type FileStorageFactory = fn(file: &File) -> Result<Box<dyn FileStorage>>;
+
+/// Enumerates the backends available for storing extracted files.
+#[derive(Copy, Clone)]
+pub enum HtpFileStorage {
+    /// Store files as temporary files in the configured directory.
+    TEMPFILE,
+    /// Keep files in memory, storing at most the given number of bytes per file.
+    MEMORY(usize),
+    /// Hand files to a sink created by the given function.
+    CUSTOM(FileStorageFactory),
+}
+
 /// Used to represent files that are seen during the processing of HTTP traffic. Most
 /// commonly this refers to files seen in multipart/form-data payloads. In addition, PUT
 /// request bodies can be treated as files.
@@ -193,43 +457,183 @@ pub struct File {
     pub filename: Option<Bstr>,
     /// File length.
     pub len: usize,
-    /// The file used for external storage.
+    /// Flags; see FileFlags.
+    pub flags: u64,
+    /// Number of bytes handed to the storage backend.
+    pub stored_len: usize,
+    /// Maximum number of bytes that may be handed to the storage backend.
+    pub storage_limit: Option<usize>,
+    /// Quotas the stored bytes are counted against.
+    quotas: Vec<Arc<QuotaLease>>,
+    /// The backend used for external storage.
     //TODO: Remove this mem management by making File not cloneable
-    pub tmpfile: Option<Rc<Mutex<NamedTempFile>>>,
+    pub storage: Option<Arc<Mutex<dyn FileStorage>>>,
+    /// SHA-256 digest of the file data, available once the file is complete.
+    pub sha256: Option<[u8; 32]>,
+    /// MD5 digest of the file data, available once the file is complete.
+    pub md5: Option<[u8; 16]>,
+    /// Rolling hashes, present while the file is being hashed.
+    hashers: Option<(Sha256, Md5)>,
 }
 
 impl File {
     /// Construct new File.
     pub fn new(source: HtpFileSource, filename: Option<Bstr>) -> File {
+        let flags = filename
+            .as_ref()
+            .map(|name| filename_flags(name.as_slice()))
+            .unwrap_or(0);
         File {
             source,
             filename,
             len: 0,
-            tmpfile: None,
+            flags,
+            stored_len: 0,
+            storage_limit: None,
+            quotas: Vec::new(),
+            storage: None,
+            sha256: None,
+            md5: None,
+            hashers: None,
         }
     }
 
-    /// Set new tmpfile.
-    pub fn create(&mut self, tmpfile: &str) -> Result<()> {
-        self.tmpfile = Some(Rc::new(Mutex::new(
-            Builder::new()
-                .prefix("libhtp-multipart-file-")
-                .rand_bytes(5)
-                .tempfile_in(tmpfile)?,
-        )));
+    /// Start computing hashes of the file data as it streams through.
+    pub fn enable_hashes(&mut self) {
+        self.hashers = Some((Sha256::new(), Md5::new()));
+    }
+
+    /// Feed a chunk of file data to the rolling hashes, if enabled.
+    pub fn update_hashes(&mut self, data: &[u8]) {
+        if let Some((sha256, md5)) = &mut self.hashers {
+            sha256.update(data);
+            md5.update(data);
+        }
+    }
+
+    /// Finish hashing and expose the digests. Has no effect if hashing
+    /// was not enabled or the digests have already been computed.
+    pub fn finalize_hashes(&mut self) {
+        if let Some((sha256, md5)) = self.hashers.take() {
+            self.sha256 = Some(sha256.finalize().into());
+            self.md5 = Some(md5.finalize().into());
+        }
+    }
+
+    /// Attach a storage backend, as selected by the configuration. At most `limit`
+    /// bytes will be stored, if a limit is provided, and the stored bytes are counted
+    /// against the given quotas. No backend is attached when nothing could be written
+    /// to it, because the limit or a quota is exhausted (FileFlags::EXTRACTION_SKIPPED),
+    /// or when the backend cannot be created (FileFlags::EXTRACTION_FAILED, with the
+    /// error returned).
+    pub fn create(
+        &mut self,
+        cfg: &MultipartConfig,
+        limit: Option<usize>,
+        quotas: &[Arc<ExtractionQuota>],
+    ) -> Result<()> {
+        let cap = match cfg.file_storage {
+            HtpFileStorage::MEMORY(cap) => Some(cap),
+            _ => None,
+        };
+        let exhausted = quotas.iter().any(|quota| quota.remaining() == 0);
+        if limit == Some(0) || cap == Some(0) || exhausted {
+            self.flags.set(FileFlags::EXTRACTION_SKIPPED);
+            return Ok(());
+        }
+        let storage: Result<Arc<Mutex<dyn FileStorage>>> = match cfg.file_storage {
+            HtpFileStorage::TEMPFILE => TempFileStorage::new(&cfg.tmpdir)
+                .map(|storage| Arc::new(Mutex::new(storage)) as Arc<Mutex<dyn FileStorage>>),
+            HtpFileStorage::MEMORY(cap) => {
+                self.storage_limit = Some(cap);
+                Ok(Arc::new(Mutex::new(MemoryStorage::new(cap))))
+            }
+            HtpFileStorage::CUSTOM(factory) => {
+                factory(self).map(|storage| Arc::new(Mutex::new(storage)) as _)
+            }
+        };
+        if storage.is_err() {
+            self.flags.set(FileFlags::EXTRACTION_FAILED);
+        }
+        let storage = storage?;
+        self.quotas = quotas
+            .iter()
+            .map(|quota| {
+                Arc::new(QuotaLease {
+                    quota: Arc::clone(quota),
+                    held: AtomicUsize::new(0),
+                })
+            })
+            .collect();
+        if let Some(limit) = limit {
+            self.storage_limit = Some(self.storage_limit.map_or(limit, |cap| cap.min(limit)));
+        }
+        self.storage = Some(storage);
         Ok(())
     }
 
-    /// Write data to tmpfile.
+    /// Write data to the storage backend, honouring the storage limit.
     pub fn write(&mut self, data: &[u8]) -> Result<()> {
-        if let Some(mutex) = &self.tmpfile {
-            if let Ok(mut tmpfile) = mutex.lock() {
-                tmpfile.write_all(data)?;
+        if let Some(mutex) = &self.storage {
+            let mut len = match self.storage_limit {
+                Some(limit) => {
+                    let available = limit.saturating_sub(self.stored_len);
+                    if data.len() > available {
+                        self.flags.set(FileFlags::STORAGE_TRUNCATED);
+                    }
+                    std::cmp::min(available, data.len())
+                }
+                None => data.len(),
+            };
+            for (i, lease) in self.quotas.iter().enumerate() {
+                let granted = lease.reserve(len);
+                if granted < len {
+                    for previous in &self.quotas[..i] {
+                        previous.release(len - granted);
+                    }
+                    self.flags.set(FileFlags::STORAGE_TRUNCATED);
+                    len = granted;
+                }
+            }
+            if len > 0 {
+                let written = match mutex.lock() {
+                    Ok(mut storage) => storage.write(&data[..len]).map(|_| true),
+                    Err(_) => Ok(false),
+                };
+                if let Ok(true) = written {
+                    self.stored_len += len;
+                } else {
+                    // Return the quota reserved for data that was not stored.
+                    for lease in &self.quotas {
+                        lease.release(len);
+                    }
+                }
+                written?;
             }
         }
         Ok(())
     }
 
+    /// Returns the file name reduced to its last path component, with control
+    /// characters replaced by '_'. Returns None if nothing usable remains.
+    pub fn sanitized_filename(&self) -> Option<Bstr> {
+        let filename = self.filename.as_ref()?;
+        let basename = filename
+            .as_slice()
+            .rsplit(|c| *c == b'/' || *c == b'\\')
+            .next()
+            .unwrap_or_default();
+        if basename.is_empty() || basename == b"." || basename == b".." {
+            return None;
+        }
+        Some(Bstr::from(
+            basename
+                .iter()
+                .map(|c| if c.is_ascii_control() { b'_' } else { *c })
+                .collect::<Vec<u8>>(),
+        ))
+    }
+
     /// Update file length and invoke any file data callbacks on the provided cfg
     pub fn handle_file_data(
         &mut self,
@@ -245,6 +649,27 @@ impl File {
     }
 }
 
+/// Determines the FileFlags that apply to the provided file name.
+pub fn filename_flags(filename: &[u8]) -> u64 {
+    let mut flags: u64 = 0;
+    if filename.is_empty() {
+        flags.set(FileFlags::FILENAME_EMPTY);
+    }
+    if filename.iter().any(|c| *c == b'/' || *c == b'\\') {
+        flags.set(FileFlags::FILENAME_PATH);
+    }
+    if filename
+        .split(|c| *c == b'/' || *c == b'\\')
+        .any(|component| component == b"..")
+    {
+        flags.set(FileFlags::FILENAME_TRAVERSAL);
+    }
+    if filename.iter().any(|c| c.is_ascii_control()) {
+        flags.set(FileFlags::FILENAME_CONTROL_CHARS);
+    }
+    flags
+}
+
 /// Represents a chunk of file data.
 pub struct FileData<'a> {
     /// File information.
@@ -494,7 +919,7 @@ pub fn utf8_decode_and_validate_uri_path_inplace(
     status: &mut HtpUnwanted,
     path: &mut Bstr,
 ) {
-    let mut decoder = Utf8Decoder::new(cfg.bestfit_map);
+    let mut decoder = Utf8Decoder::new(cfg.bestfit_map.clone());
     decoder.decode_and_validate(path.as_slice());
     if cfg.utf8_convert_bestfit {
         path.clear();
@@ -816,13 +1241,24 @@ fn path_decode<'a>(
                     acc.0.push(byte);
                 }
             }
+            if insert && is_crlf_injection(byte, cfg) {
+                acc.1.set(HtpFlags::CRLF_INJECTION);
+            }
             acc.1.set(flag);
-            acc.2 = code;
+            // Keep the reaction to an earlier byte.
+            if code != HtpUnwanted::IGNORE {
+                acc.2 = code;
+            }
             acc
         },
     )(input)
 }
 
+/// Returns true if a decoded byte is CR or LF and the configuration asks for them to be flagged.
+fn is_crlf_injection(byte: u8, cfg: &DecoderConfig) -> bool {
+    cfg.crlf_injection_detection && (byte == b'\r' || byte == b'\n')
+}
+
 /// Decode the parsed uri path inplace according to the settings in the
 /// transaction configuration structure.
 pub fn decode_uri_path_inplace(
@@ -860,6 +1296,9 @@ pub fn urldecode_uri_inplace(
         if f.is_set(HtpFlags::URLEN_RAW_NUL) {
             flags.set(HtpFlags::PATH_RAW_NUL);
         }
+        if f.is_set(HtpFlags::CRLF_INJECTION) {
+            flags.set(HtpFlags::CRLF_INJECTION);
+        }
         Ok(())
     } else {
         Err(HtpStatus::ERROR)
@@ -1090,6 +1529,9 @@ pub fn urldecode_ex<'a>(
         |mut acc: (Vec<_>, u64, HtpUnwanted), (byte, code, flag, insert)| {
             if insert {
                 acc.0.push(byte);
+                if is_crlf_injection(byte, cfg) {
+                    acc.1.set(HtpFlags::CRLF_INJECTION);
+                }
             }
             acc.1.set(flag);
             if code != HtpUnwanted::IGNORE {
@@ -1289,13 +1731,75 @@ pub fn is_valid_chunked_length_data(data: &[u8]) -> bool {
     .is_err()
 }
 
+/// Searches for the next plausible chunk-size line in data that follows a gap
+/// in a chunked body. A candidate line starts either at the beginning of the
+/// data or right after a LF, holds 1 to 8 hex digits optionally followed by
+/// a chunk extension, and is terminated by a LF within a few bytes.
+///
+/// Returns the number of leading bytes that can safely be treated as chunk
+/// data and, if a candidate was found, the offset at which it starts. When no
+/// candidate is found, any trailing bytes that may still turn out to be the
+/// start of one are excluded from the safe length.
+pub fn find_chunk_resync_point(data: &[u8]) -> (usize, Option<usize>) {
+    const MAX_EXTENSION_LEN: usize = 64;
+    let mut start = 0;
+    loop {
+        let digits = data[start..]
+            .iter()
+            .take_while(|c| c.is_ascii_hexdigit())
+            .count();
+        let rest = &data[start + digits..];
+        // The data that precedes the candidate is chunk data minus the CRLF
+        // that terminated the chunk.
+        let mut data_end = start;
+        if data_end > 0 {
+            data_end -= 1;
+            if data_end > 0 && data[data_end - 1] == b'\r' {
+                data_end -= 1;
+            }
+        }
+        if digits > 0 && digits <= 8 {
+            let valid = std::str::from_utf8(&data[start..start + digits])
+                .ok()
+                .and_then(|len| i32::from_str_radix(len, 16).ok())
+                .is_some();
+            if valid {
+                match rest.first() {
+                    Some(b'\r') | Some(b'\n') | Some(b';') | Some(b' ') | Some(b'\t') => match rest
+                        .iter()
+                        .position(|&c| c == b'\n')
+                    {
+                        Some(pos) if pos <= MAX_EXTENSION_LEN => return (data_end, Some(start)),
+                        None if rest.len() <= MAX_EXTENSION_LEN => return (data_end, None),
+                        _ => {}
+                    },
+                    None => return (data_end, None),
+                    _ => {}
+                }
+            }
+        } else if rest.is_empty() {
+            // Nothing after the last LF yet; keep the line ending around in
+            // case the next candidate arrives with the following data.
+            return (data_end, None);
+        }
+        match data[start..].iter().position(|&c| c == b'\n') {
+            Some(pos) => start += pos + 1,
+            None => return (data.len(), None),
+        }
+    }
+}
+
 fn is_chunked_ctl_char(c: u8) -> bool {
     matches!(c, 0x0d | 0x0a | 0x20 | 0x09 | 0x0b | 0x0c)
 }
 
 #[cfg(test)]
 mod test {
-    use crate::{config::Config, util::*};
+    use crate::{
+        config::Config,
+        unicode_bestfit_map::{HtpBestfitCodepage, UnicodeBestfitMap},
+        util::*,
+    };
     use nom::{
         error::ErrorKind::TakeUntil,
         Err::{Error, Incomplete},
@@ -1710,6 +2214,25 @@ mod test {
         ));
     }
 
+    #[test]
+    fn FindChunkResyncPoint() {
+        assert_eq!((0, Some(0)), find_chunk_resync_point(b"4\r\ntail"));
+        assert_eq!((3, Some(5)), find_chunk_resync_point(b"bar\r\n4\r\ntail"));
+        assert_eq!((3, Some(5)), find_chunk_resync_point(b"bar\r\n4;ext=1\r\n"));
+        assert_eq!((3, Some(4)), find_chunk_resync_point(b"bar\na\n"));
+        // Hex-looking chunk data is not a chunk-size line
+        assert_eq!((13, None), find_chunk_resync_point(b"bar\r\nfeedback"));
+        // A candidate might still be completed by the next data chunk
+        assert_eq!((3, None), find_chunk_resync_point(b"bar\r\n"));
+        assert_eq!((3, None), find_chunk_resync_point(b"bar\r\n1f"));
+        assert_eq!((3, None), find_chunk_resync_point(b"bar\r\n1f;ext"));
+        // Too many digits for a chunk length
+        assert_eq!(
+            (19, None),
+            find_chunk_resync_point(b"bar\r\n123456789\r\nxyz")
+        );
+    }
+
     #[test]
     fn TakeUntilNoCase() {
         let (remaining, consumed) = take_until_no_case(b"TAG")(
@@ -2443,4 +2966,28 @@ mod test {
         urldecode_inplace(&cfg.decoder_cfg, &mut s).unwrap();
         assert_eq!(e, s);
     }
+
+    #[test]
+    fn DecodeUrlencodedBestfitCodepage() {
+        let mut cfg = Config::default();
+        cfg.set_u_encoding_decode(true);
+        cfg.set_bestfit_replacement_byte(b'#');
+        let i = Bstr::from("/%uFF0F%uFF71");
+        assert_eq!(
+            urldecode_ex(&i, &cfg.decoder_cfg).unwrap().1 .0,
+            b"//#".as_ref()
+        );
+        cfg.set_bestfit_codepage(HtpBestfitCodepage::CP932);
+        assert_eq!(
+            urldecode_ex(&i, &cfg.decoder_cfg).unwrap().1 .0,
+            b"/#\xb1".as_ref()
+        );
+        let map = UnicodeBestfitMap::from_bytes(
+            b"CODEPAGE 1252\nMBTABLE 1\n0x41 0x0041\nWCTABLE 2\n0xff0f 0x2f ; slash\n0x00a5 0x815f\nENDCODEPAGE\n",
+        )
+        .unwrap();
+        assert_eq!(1, map.map.len());
+        assert_eq!(b'/', map.get(0xff0f));
+        assert!(UnicodeBestfitMap::from_bytes(b"0xff0f\n").is_err());
+    }
 }
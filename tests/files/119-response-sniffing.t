@@ -0,0 +1,18 @@
+>>>
+GET /logo.png HTTP/1.1
+Host: www.example.com
+
+GET /page HTTP/1.1
+Host: www.example.com
+
+
+<<<
+HTTP/1.1 200 OK
+Content-Type: image/png
+Content-Length: 38
+
+<html><body>Not an image</body></html>HTTP/1.1 200 OK
+Content-Type: text/html; charset=UTF-8
+Content-Length: 6
+
+<html>
\ No newline at end of file
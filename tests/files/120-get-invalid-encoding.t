@@ -0,0 +1,9 @@
+>>>
+GET /%zzimages.gif HTTP/1.1
+Host: www.example.com
+
+
+<<<
+HTTP/1.1 400 Bad Request
+Content-Length: 0
+
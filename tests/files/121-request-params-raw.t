@@ -0,0 +1,9 @@
+>>>
+GET /?a=%41&c%20d=%zz&a=b HTTP/1.1
+Host: www.example.com
+
+
+<<<
+HTTP/1.1 200 OK
+Content-Length: 0
+
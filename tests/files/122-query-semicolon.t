@@ -0,0 +1,9 @@
+>>>
+GET /?p=1;q=2&r=3 HTTP/1.1
+Host: www.example.com
+
+
+<<<
+HTTP/1.1 200 OK
+Content-Length: 0
+
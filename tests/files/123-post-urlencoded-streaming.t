@@ -0,0 +1,13 @@
+>>>
+POST /?q=1 HTTP/1.1
+Host: www.example.com
+Content-Type: application/x-www-form-urlencoded
+Content-Length: 11
+
+p=1&r=2
+>>>
+&s=3
+<<<
+HTTP/1.1 200 OK
+Content-Length: 0
+
@@ -0,0 +1,34 @@
+>>>
+GET /secure HTTP/1.1
+Host: www.example.com
+
+
+<<<
+HTTP/1.1 401 Unauthorized
+WWW-Authenticate: Negotiate
+WWW-Authenticate: NTLM
+Content-Length: 0
+
+
+>>>
+GET /secure HTTP/1.1
+Host: www.example.com
+Authorization: NTLM TlRMTVNTUAABAAAAB4IIAAAAAAAAAAAAAAAAAAAAAAA=
+
+
+<<<
+HTTP/1.1 401 Unauthorized
+WWW-Authenticate: NTLM TlRMTVNTUAACAAAAAAAAADAAAAAFgggAASNFZ4mrze8AAAAAAAAAAAAAAAAwAAAA
+Content-Length: 0
+
+
+>>>
+GET /secure HTTP/1.1
+Host: www.example.com
+Authorization: NTLM TlRMTVNTUAADAAAAAAAAAE4AAAAAAAAATgAAAAgACABAAAAABgAGAEgAAAAAAAAATgAAAAAAAABOAAAABYIIAEMATwBSAFAAYgBvAGIA
+
+
+<<<
+HTTP/1.1 200 OK
+Content-Length: 0
+
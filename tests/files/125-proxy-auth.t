@@ -0,0 +1,12 @@
+>>>
+GET http://www.example.com/ HTTP/1.1
+Host: www.example.com
+Proxy-Authorization: Basic aXZhbnI6c2VjcmV0
+
+
+<<<
+HTTP/1.1 407 Proxy Authentication Required
+Proxy-Authenticate: Basic realm="proxy", Digest realm="proxy", nonce="abc", qop="auth"
+WWW-Authenticate: Bearer realm="example"
+Content-Length: 0
+
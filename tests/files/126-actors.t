@@ -0,0 +1,56 @@
+>>>
+GET /1 HTTP/1.1
+Host: www.example.com
+Cookie: PHPSESSID=a
+
+
+<<<
+HTTP/1.1 200 OK
+Content-Length: 0
+
+
+>>>
+GET /2 HTTP/1.1
+Host: www.example.com
+Authorization: Basic aXZhbnI6c2VjcmV0
+Cookie: theme=dark; phpsessid=a
+
+
+<<<
+HTTP/1.1 200 OK
+Content-Length: 0
+
+
+>>>
+GET /3 HTTP/1.1
+Host: www.example.com
+Authorization: Basic aXZhbnI6c2VjcmV0
+
+
+<<<
+HTTP/1.1 200 OK
+Content-Length: 0
+
+
+>>>
+GET /4 HTTP/1.1
+Host: www.example.com
+Authorization: Bearer mF_9.B5f-4.1JqM
+Cookie: PHPSESSID=b
+
+
+<<<
+HTTP/1.1 200 OK
+Content-Length: 0
+
+
+>>>
+GET /5 HTTP/1.1
+Host: www.example.com
+Cookie: theme=dark
+
+
+<<<
+HTTP/1.1 200 OK
+Content-Length: 0
+
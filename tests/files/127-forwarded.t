@@ -0,0 +1,13 @@
+>>>
+GET / HTTP/1.1
+Host: www.example.com
+Forwarded: for=192.0.2.60;proto=http;by=203.0.113.43, for="[2001:db8:cafe::17]:4711"
+Forwarded: For=unknown;host=example.com, for=_hidden:_port
+X-Forwarded-For: 198.51.100.7, 2001:db8::1, bogus
+X-Real-IP: 198.51.100.7
+
+
+<<<
+HTTP/1.1 200 OK
+Content-Length: 0
+
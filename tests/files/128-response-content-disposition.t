@@ -0,0 +1,10 @@
+>>>
+GET /download HTTP/1.1
+Host: www.example.com
+
+
+<<<
+HTTP/1.1 200 OK
+Content-Disposition: Attachment; filename="EURO rates.txt"; filename*=utf-8''%e2%82%ac%20rates.txt
+Content-Length: 0
+
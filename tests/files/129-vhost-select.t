@@ -0,0 +1,24 @@
+>>>
+POST /a HTTP/1.1
+Host: www.Example.com
+Content-Type: application/x-www-form-urlencoded
+Cookie: session=1
+Content-Length: 3
+
+p=1
+<<<
+HTTP/1.1 200 OK
+Content-Length: 0
+
+>>>
+POST /b HTTP/1.1
+Host: other.example.com
+Content-Type: application/x-www-form-urlencoded
+Cookie: session=2
+Content-Length: 3
+
+p=2
+<<<
+HTTP/1.1 200 OK
+Content-Length: 0
+
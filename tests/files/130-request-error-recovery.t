@@ -0,0 +1,21 @@
+>>>
+POST / HTTP/1.1
+Transfer-Encoding: ABC
+Host: www.example.com
+
+b
+p=012345678
+0
+
+GET /next HTTP/1.1
+Host: www.example.com
+
+
+<<<
+HTTP/1.1 400 Bad Request
+Content-Length: 0
+
+HTTP/1.1 200 OK
+Content-Length: 2
+
+OK
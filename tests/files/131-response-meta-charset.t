@@ -0,0 +1,18 @@
+>>>
+GET /latin1 HTTP/1.1
+Host: www.example.com
+
+GET /utf8 HTTP/1.1
+Host: www.example.com
+
+
+<<<
+HTTP/1.1 200 OK
+Content-Type: text/html; charset=ISO-8859-1
+Content-Length: 48
+
+<html><head><meta charset="utf-8"></head></html>HTTP/1.1 200 OK
+Content-Type: text/html; charset="utf8"
+Content-Length: 67
+
+<META HTTP-EQUIV="Content-Type" CONTENT="text/html; charset=UTF-8">
\ No newline at end of file
@@ -0,0 +1,22 @@
+>>>
+POST / HTTP/1.1
+Host: www.example.com
+Content-Length:
+ 12
+
+Hello World!
+<<<
+HTTP/1.1 200 OK
+Content-Length: 0
+
+
+>>>
+POST / HTTP/1.1
+Host: www.example.com
+Content-Length: 12
+
+Hello World!
+<<<
+HTTP/1.1 200 OK
+Content-Length: 0
+
@@ -25,7 +25,8 @@ enum TestError {
 }
 
 fn GUnzip_decompressor_callback(d: &mut Data) -> HtpStatus {
-    unsafe { (*d.tx()).set_user_data(Box::new(Bstr::from(d.as_slice().unwrap()))) };
+    let data = Bstr::from(d.as_slice().unwrap());
+    d.transaction_mut().unwrap().set_user_data(Box::new(data));
     HtpStatus::OK
 }
 
@@ -36,24 +37,14 @@ impl Test {
             .unwrap();
         // The default bomb limit may be slow in some development environments causing tests to fail.
         cfg.compression_options.set_time_limit(std::u32::MAX);
-        let mut connp = ConnectionParser::new(cfg);
+        let connp = ConnectionParser::new(cfg);
 
         let expected = Bstr::from("The five boxing wizards jump quickly.");
-        let tx = connp.request_mut() as *mut Transaction;
         Test {
             connp,
             expected,
-            decompressor: Decompressor::new_with_callback(
-                HtpContentEncoding::GZIP,
-                Box::new(move |data: Option<&[u8]>| {
-                    let data = ParserData::from(data);
-                    let mut tx_data = Data::new(tx, &data, false);
-                    GUnzip_decompressor_callback(&mut tx_data);
-                    Ok(tx_data.len())
-                }),
-                Default::default(),
-            )
-            .unwrap(),
+            decompressor: Decompressor::new_with_sink(HtpContentEncoding::GZIP, Default::default())
+                .unwrap(),
         }
     }
 
@@ -71,8 +62,14 @@ impl Test {
         filepath.push(filename);
 
         let data = std::fs::read(filepath).map_err(TestError::Io)?;
+        let tx = self.connp.request_mut() as *mut Transaction;
         self.decompressor
-            .decompress(&data)
+            .decompress_with(&data, &mut |data: Option<&[u8]>| {
+                let data = ParserData::from(data);
+                let mut tx_data = unsafe { Data::new(tx, &data, false) };
+                GUnzip_decompressor_callback(&mut tx_data);
+                Ok(tx_data.len())
+            })
             .map(|_| ())
             .map_err(|_| TestError::Htp(HtpStatus::ERROR))
     }
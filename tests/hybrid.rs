@@ -108,18 +108,18 @@ fn HybridParsing_Get_Callback_TRANSACTION_COMPLETE(tx: &mut Transaction) -> Resu
 }
 
 fn HybridParsing_Get_Callback_RESPONSE_BODY_DATA(d: &mut Data) -> Result<()> {
-    let user_data = unsafe {
-        (*d.tx())
-            .user_data_mut::<HybridParsing_Get_User_Data>()
-            .unwrap()
-    };
+    let data = d.as_slice().unwrap_or(b"");
+    let user_data = d
+        .transaction_mut()
+        .unwrap()
+        .user_data_mut::<HybridParsing_Get_User_Data>()
+        .unwrap();
 
     // Don't do anything if in errored state.
     if user_data.response_body_correctly_received == -1 {
         return Err(HtpStatus::ERROR);
     }
 
-    let data: &[u8] = unsafe { std::slice::from_raw_parts(d.data(), d.len()) };
     match user_data.response_body_chunks_seen {
         0 => {
             if data == b"<h1>Hello" {
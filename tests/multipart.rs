@@ -1,17 +1,17 @@
 #![allow(non_snake_case)]
 use htp::{
     bstr::Bstr,
-    config::{Config, HtpServerPersonality},
+    config::{Config, HtpMultipartQuoting, HtpServerPersonality},
     connection_parser::ConnectionParser,
     multipart::*,
     transaction::{Header, Transaction},
-    util::FlagOperations,
+    util::{FileFlags, FlagOperations, HtpFileStorage},
     HtpStatus,
 };
 use std::{
     fs,
     net::{IpAddr, Ipv4Addr},
-    rc::Rc,
+    sync::Arc,
 };
 
 // import common testing utilities
@@ -929,14 +929,15 @@ fn WithFileExternallyStored() {
         assert!(filename.eq("test.bin"));
         assert_eq!(6, file.len);
 
-        assert!(file.tmpfile.is_some());
+        assert!(file.storage.is_some());
         let name = file
-            .tmpfile
+            .storage
             .as_ref()
             .unwrap()
             .lock()
             .unwrap()
             .path()
+            .unwrap()
             .to_path_buf();
 
         let contents = fs::read_to_string(&name).unwrap();
@@ -947,6 +948,87 @@ fn WithFileExternallyStored() {
     assert!(!tmpfile.exists());
 }
 
+#[test]
+fn WithFileStorageQuota() {
+    let mut cfg = TestConfig();
+    cfg.set_extract_request_files(true, false);
+    cfg.set_extract_request_limits(16, Some(8));
+    cfg.set_file_storage(HtpFileStorage::MEMORY(1024));
+    let mut t = Test::new(cfg);
+    let parts = vec![
+        "--0123456789\r\n\
+         Content-Disposition: form-data; name=\"field1\"; filename=\"../../etc/passwd\"\r\n\
+         \r\n\
+         ABCDEF\
+         \r\n--0123456789\r\n\
+         Content-Disposition: form-data; name=\"field2\"; filename=\"test.bin\"\r\n\
+         \r\n\
+         GHIJKL\
+         \r\n--0123456789--",
+    ];
+
+    t.parseParts(&parts);
+
+    assert_eq!(2, t.body().parts.len());
+
+    let file = t.body().parts.get(0).unwrap().file.as_ref().unwrap();
+    assert!(file.flags.is_set(FileFlags::FILENAME_PATH));
+    assert!(file.flags.is_set(FileFlags::FILENAME_TRAVERSAL));
+    assert!(!file.flags.is_set(FileFlags::STORAGE_TRUNCATED));
+    assert_eq!(Some(Bstr::from("passwd")), file.sanitized_filename());
+    assert_eq!(
+        Some(b"ABCDEF".as_ref()),
+        file.storage.as_ref().unwrap().lock().unwrap().data()
+    );
+
+    let file = t.body().parts.get(1).unwrap().file.as_ref().unwrap();
+    assert_eq!(0, file.flags & FileFlags::FILENAME_UNSAFE);
+    assert!(file.flags.is_set(FileFlags::STORAGE_TRUNCATED));
+    assert_eq!(6, file.len);
+    assert_eq!(2, file.stored_len);
+    assert_eq!(
+        Some(b"GH".as_ref()),
+        file.storage.as_ref().unwrap().lock().unwrap().data()
+    );
+}
+
+#[test]
+fn WithFileHashes() {
+    let mut cfg = TestConfig();
+    cfg.set_compute_file_hashes(true);
+    let mut t = Test::new(cfg);
+    let parts = vec![
+        "--0123456789\r\n\
+         Content-Disposition: form-data; name=\"field1\"\r\n\
+         \r\n\
+         ABCDEF\
+         \r\n--0123456789\r\n\
+         Content-Disposition: form-data; name=\"field2\"; filename=\"test.bin\"\r\n\
+         \r\n\
+         GHIJKL\
+         \r\n--0123456789--",
+    ];
+
+    t.parseParts(&parts);
+
+    let file = t.body().parts.get(1).unwrap().file.as_ref().unwrap();
+    assert_eq!(
+        Some([
+            0xd7, 0xd3, 0x07, 0x2b, 0xc4, 0x87, 0x83, 0xfb, 0x61, 0x7b, 0x61, 0xdb, 0xc4, 0xe9,
+            0xed, 0x88, 0xa6, 0x5e, 0x9f, 0xb3, 0xde, 0xda, 0xb3, 0x9d, 0x81, 0x34, 0xac, 0xc3,
+            0x05, 0x85, 0x30, 0xb8
+        ]),
+        file.sha256
+    );
+    assert_eq!(
+        Some([
+            0x31, 0x17, 0xe6, 0xe7, 0x3d, 0x88, 0x31, 0x18, 0xb2, 0x61, 0x2b, 0x3d, 0x59, 0x83,
+            0xdc, 0x0c
+        ]),
+        file.md5
+    );
+}
+
 #[test]
 fn PartHeadersEmptyLineBug() {
     let mut t = Test::new(TestConfig());
@@ -1717,7 +1799,7 @@ fn InvalidContentDispositionSyntax() {
         // Incomplete header.
         "form-data; name= ",
     ];
-    let cfg = Rc::new(Config::default());
+    let cfg = Arc::new(Config::default());
     for input in inputs {
         let parser = &mut Parser::new(&cfg, b"123", 0);
         parser.multipart.parts.push(Part::default());
@@ -1731,6 +1813,116 @@ fn InvalidContentDispositionSyntax() {
     }
 }
 
+#[test]
+fn ContentDispositionExtendedFilename() {
+    let cfg = Arc::new(Config::default());
+    let parse = |input: &str| {
+        let mut parser = Parser::new(&cfg, b"123", 0);
+        parser.multipart.parts.push(Part::default());
+        parser.current_part_idx = Some(0);
+        let part = parser.get_current_part().unwrap();
+        let header = Header::new(b"Content-Disposition".to_vec().into(), input.into());
+        part.headers.add(header.name.clone(), header);
+        let rc = parser.parse_c_d();
+        (rc, parser)
+    };
+
+    let (rc, mut parser) = parse("form-data; name=\"file1\"; filename*=UTF-8''%e2%82%ac.txt");
+    assert!(rc.is_ok());
+    assert_eq!(0, parser.multipart.flags);
+    let part = parser.get_current_part().unwrap();
+    assert!(part.name.eq("file1"));
+    assert!(part
+        .file
+        .as_ref()
+        .unwrap()
+        .filename
+        .as_ref()
+        .unwrap()
+        .eq("\u{20ac}.txt"));
+
+    // The extended filename wins regardless of order, but the combination is flagged.
+    for input in &[
+        "form-data; name=\"file1\"; filename=\"euro.txt\"; filename*=utf-8''%e2%82%ac.txt",
+        "form-data; name=\"file1\"; filename*=utf-8''%e2%82%ac.txt; filename=\"euro.txt\"",
+    ] {
+        let (rc, mut parser) = parse(input);
+        assert!(rc.is_ok());
+        assert!(parser.multipart.flags.is_set(Flags::CD_FILENAME_EXT));
+        assert!(!parser.multipart.flags.is_set(Flags::CD_INVALID));
+        let part = parser.get_current_part().unwrap();
+        assert!(part
+            .file
+            .as_ref()
+            .unwrap()
+            .filename
+            .as_ref()
+            .unwrap()
+            .eq("\u{20ac}.txt"));
+    }
+
+    let (rc, parser) = parse("form-data; name=\"file1\"; filename*=bogus");
+    assert_err!(rc, HtpStatus::DECLINED);
+    assert!(parser.multipart.flags.is_set(Flags::CD_PARAM_EXT_INVALID));
+    assert!(parser.multipart.flags.is_set(Flags::CD_INVALID));
+
+    let (rc, parser) =
+        parse("form-data; name=\"file1\"; filename*=utf-8''a.txt; filename*=utf-8''b.txt");
+    assert_err!(rc, HtpStatus::DECLINED);
+    assert!(parser.multipart.flags.is_set(Flags::CD_PARAM_REPEATED));
+
+    // Extended values are tokens, not quoted strings.
+    let (rc, parser) = parse("form-data; name=\"file1\"; filename*=\"utf-8''a.txt\"");
+    assert_err!(rc, HtpStatus::DECLINED);
+    assert!(parser.multipart.flags.is_set(Flags::CD_SYNTAX_INVALID));
+}
+
+#[test]
+fn ContentDispositionQuoting() {
+    let parse = |input: &str, quoting: HtpMultipartQuoting| {
+        let mut cfg = Config::default();
+        cfg.set_multipart_quoting(quoting);
+        let cfg = Arc::new(cfg);
+        let mut parser = Parser::new(&cfg, b"123", 0);
+        parser.multipart.parts.push(Part::default());
+        parser.current_part_idx = Some(0);
+        let part = parser.get_current_part().unwrap();
+        let header = Header::new(b"Content-Disposition".to_vec().into(), input.into());
+        part.headers.add(header.name.clone(), header);
+        let rc = parser.parse_c_d();
+        let name = parser.get_current_part().unwrap().name.clone();
+        (rc.map(|_| name), parser.multipart.flags)
+    };
+
+    let input = "form-data; name=\"a\\\"b\\\\c%22d\"";
+    for quoting in &[HtpMultipartQuoting::GENERIC, HtpMultipartQuoting::OPERA] {
+        let (name, flags) = parse(input, *quoting);
+        assert!(name.unwrap().eq("a\"b\\c%22d"));
+        assert_eq!(0, flags);
+    }
+    for quoting in &[HtpMultipartQuoting::FIREFOX, HtpMultipartQuoting::IE] {
+        let (name, _) = parse(input, *quoting);
+        assert!(name.unwrap().eq("a\"b\\\\c%22d"));
+    }
+    let (name, flags) = parse(input, HtpMultipartQuoting::CHROME);
+    assert_err!(name, HtpStatus::DECLINED);
+    assert!(flags.is_set(Flags::CD_SYNTAX_INVALID));
+    let (name, flags) = parse(input, HtpMultipartQuoting::DETECT);
+    assert!(name.unwrap().eq("a\"b\\c%22d"));
+    assert!(flags.is_set(Flags::CD_QUOTING_AMBIGUOUS));
+    assert!(!flags.is_set(Flags::CD_INVALID));
+
+    let (name, _) = parse(
+        "form-data; name=\"a%22b%0D%0A%zz\\\"",
+        HtpMultipartQuoting::CHROME,
+    );
+    assert!(name.unwrap().eq("a\"b\r\n%zz\\"));
+
+    let (name, flags) = parse("form-data; name=\"plain\"", HtpMultipartQuoting::DETECT);
+    assert!(name.unwrap().eq("plain"));
+    assert_eq!(0, flags);
+}
+
 #[test]
 fn ParamValueEscaping() {
     let mut t = Test::new(TestConfig());
@@ -1802,3 +1994,171 @@ fn HeaderValueTrim() {
         .1;
     assert_eq!(header.value, "form-data; name=\"field1\"");
 }
+
+#[test]
+fn TakeCompletedParts() {
+    let mut t = Test::new(TestConfig());
+    t.set_mpartp(b"BBB");
+
+    t.mpartp()
+        .parse(b"--BBB\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n1\r\n--BBB\r\n");
+    assert_eq!(0..1, t.mpartp().take_completed_parts(false));
+    t.mpartp()
+        .parse(b"Content-Disposition: form-data; name=\"b\"\r\n\r\n2");
+    assert_eq!(1..1, t.mpartp().take_completed_parts(false));
+    t.mpartp().parse(b"\r\n--BBB--\r\n");
+    t.mpartp().finalize().unwrap();
+    assert_eq!(1..2, t.mpartp().take_completed_parts(true));
+    assert_eq!(2..2, t.mpartp().take_completed_parts(true));
+}
+
+#[test]
+fn LargePartBoundarySearch() {
+    let mut body = b"--0123456789\r\n\
+        Content-Disposition: form-data; name=\"field1\"\r\n\r\n"
+        .to_vec();
+    for i in 0..200 {
+        body.extend_from_slice(format!("line {} --012345678 ", i).as_bytes());
+        body.extend_from_slice(if i % 7 == 3 { b"\n" } else { b"\r\n" });
+    }
+    body.extend_from_slice(b"END\r\n--0123456789 \r\n");
+    body.extend_from_slice(b"Content-Disposition: form-data; name=\"field2\"\r\n\r\n");
+    body.extend_from_slice(&[b'x'; 5000]);
+    body.extend_from_slice(b"\r\n--0123456789--\r\n");
+
+    let mut results = Vec::new();
+    for size in &[body.len(), 1000, 64] {
+        let mut t = Test::new(TestConfig());
+        t.set_mpartp(b"0123456789");
+        for chunk in body.chunks(*size) {
+            t.mpartp().parse(chunk);
+        }
+        t.mpartp().finalize().unwrap();
+        let multipart = t.body();
+        let parts: Vec<(usize, Bstr)> = multipart
+            .parts
+            .into_iter()
+            .map(|part| (part.len, part.value.clone()))
+            .collect();
+        results.push((multipart.flags, parts));
+    }
+    let (flags, parts) = &results[0];
+    assert_eq!(
+        Flags::LF_LINE | Flags::CRLF_LINE | Flags::BBOUNDARY_LWS_AFTER | Flags::SEEN_LAST_BOUNDARY,
+        *flags
+    );
+    assert_eq!(2, parts.len());
+    assert!(parts[0].1.ends_with(b"END"));
+    assert_eq!(5000, parts[1].1.len());
+    for result in &results[1..] {
+        assert_eq!(results[0], *result);
+    }
+}
+
+#[test]
+fn PartValueLimit() {
+    let mut cfg = TestConfig();
+    cfg.set_multipart_part_value_limit(Some(10));
+    let mut t = Test::new(cfg);
+    t.set_mpartp(b"0123456789");
+    t.mpartp().parse(
+        b"--0123456789\r\n\
+        Content-Disposition: form-data; name=\"short\"\r\n\r\n\
+        ABCDEFGHIJ\r\n\
+        --0123456789\r\n\
+        Content-Disposition: form-data; name=\"long\"\r\n\r\n\
+        ABCDEFGHIJKLMNOPQRSTUVWXYZ\r\n\
+        --0123456789--\r\n",
+    );
+    t.mpartp().finalize().unwrap();
+
+    let body = t.body();
+    assert!(body.flags.is_set(Flags::PART_VALUE_TRUNCATED));
+    assert_eq!(2, body.parts.len());
+    assert!(body.parts.get(0).unwrap().value.eq("ABCDEFGHIJ"));
+    assert!(body.parts.get(1).unwrap().value.eq("ABCDEFGHIJ"));
+
+    let mut t = Test::new(TestConfig());
+    t.set_mpartp(b"0123456789");
+    t.mpartp().parse(
+        b"--0123456789\r\n\
+        Content-Disposition: form-data; name=\"long\"\r\n\r\n\
+        ABCDEFGHIJKLMNOPQRSTUVWXYZ\r\n\
+        --0123456789--\r\n",
+    );
+    t.mpartp().finalize().unwrap();
+    assert!(!t.body().flags.is_set(Flags::PART_VALUE_TRUNCATED));
+    assert!(t
+        .body()
+        .parts
+        .get(0)
+        .unwrap()
+        .value
+        .eq("ABCDEFGHIJKLMNOPQRSTUVWXYZ"));
+}
+
+#[test]
+fn StreamTextParts() {
+    let body = b"--0123456789\r\n\
+        Content-Disposition: form-data; name=\"field1\"\r\n\r\n\
+        first value\r\nwith two lines\r\n\
+        --0123456789\r\n\
+        Content-Disposition: form-data; name=\"file1\"; filename=\"a.txt\"\r\n\r\n\
+        FILE\r\n\
+        --0123456789\r\n\
+        Content-Disposition: form-data; name=\"field2\"\r\n\r\n\
+        second\r\n\
+        --0123456789--\r\n";
+
+    for size in &[body.len(), 7, 1] {
+        let seen: Arc<std::sync::Mutex<Vec<(Bstr, Vec<u8>, usize)>>> = Arc::default();
+        let mut cfg = TestConfig();
+        cfg.set_multipart_stream_text_parts(true);
+        let sink = Arc::clone(&seen);
+        cfg.register_request_part_data(move |data: &mut PartData| {
+            let mut seen = sink.lock().unwrap();
+            match seen.last_mut() {
+                Some((name, value, ends)) if *name == data.part.name && *ends == 0 => {
+                    value.extend_from_slice(data.data);
+                    if data.data.is_empty() {
+                        *ends += 1;
+                    }
+                }
+                _ => seen.push((
+                    data.part.name.clone(),
+                    data.data.to_vec(),
+                    data.data.is_empty() as usize,
+                )),
+            }
+            Ok(())
+        });
+        let mut t = Test::new(cfg);
+        t.set_mpartp(b"0123456789");
+        for chunk in body.chunks(*size) {
+            t.mpartp().parse(chunk);
+        }
+        t.mpartp().finalize().unwrap();
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(2, seen.len());
+        assert!(seen[0].0.eq("field1"));
+        assert_eq!(b"first value\r\nwith two lines".to_vec(), seen[0].1);
+        assert_eq!(1, seen[0].2);
+        assert!(seen[1].0.eq("field2"));
+        assert_eq!(b"second".to_vec(), seen[1].1);
+        assert_eq!(1, seen[1].2);
+
+        let multipart = t.body();
+        assert_eq!(3, multipart.parts.len());
+        assert_eq!(
+            HtpMultipartType::TEXT,
+            multipart.parts.get(0).unwrap().type_0
+        );
+        assert!(multipart.parts.get(0).unwrap().value.is_empty());
+        assert_eq!(
+            HtpMultipartType::FILE,
+            multipart.parts.get(1).unwrap().type_0
+        );
+        assert!(multipart.parts.get(2).unwrap().value.is_empty());
+    }
+}